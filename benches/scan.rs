@@ -0,0 +1,76 @@
+use best_practices::cli::bench::{bench_hashing, HashMode, SyntheticTreeBuilder};
+use best_practices::fs::{Algorithm, TreeIndex};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use std::fmt::Write;
+use std::io::Cursor;
+
+// hashes one tree of 4 MiB files in every mode, fast digests only read the
+// first and last MiB of them
+fn hashing(c: &mut Criterion) {
+    let tree = SyntheticTreeBuilder::new()
+        .files(16)
+        .file_size("4MiB")
+        .build()
+        .unwrap();
+    let mut group = c.benchmark_group("hashing");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(tree.bytes()));
+    for mode in HashMode::all() {
+        group.bench_with_input(BenchmarkId::from_parameter(mode), &mode, |b, mode| {
+            b.iter(|| bench_hashing(tree.path(), *mode, 1).unwrap())
+        });
+    }
+    group.finish();
+}
+
+// walks a deep tree of empty files so the time goes to the walk and not
+// the hashing
+fn walking(c: &mut Criterion) {
+    let tree = SyntheticTreeBuilder::new()
+        .files(5000)
+        .file_size(0u64)
+        .fanout(6)
+        .depth(3)
+        .build()
+        .unwrap();
+    let mode = HashMode { algorithm: Algorithm::Blake2b, mmap: false, fast: false };
+    let mut group = c.benchmark_group("walking");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(tree.files() as u64));
+    for threads in [1, 4] {
+        group.bench_with_input(BenchmarkId::new("threads", threads), &threads, |b, threads| {
+            b.iter(|| bench_hashing(tree.path(), mode, *threads).unwrap())
+        });
+    }
+    group.finish();
+}
+
+// a text index of 100,000 entries with two dupes each
+fn index_text() -> String {
+    let mut s = String::from("#treeindex v2 os=linux\n");
+    for n in 0..100_000u64 {
+        let digest: String = (0..4).map(|i| format!("{:016x}", n.wrapping_mul(0x9e37_79b9_7f4a_7c15).rotate_left(i * 16))).collect();
+        writeln!(s, "{} {} /data/photos/{}/img_{}.jpg", digest, n * 1000, n % 100, n).unwrap();
+        writeln!(s, "- /backup/a/photos/{}/img_{}.jpg", n % 100, n).unwrap();
+        writeln!(s, "- /backup/b/photos/{}/img_{}.jpg", n % 100, n).unwrap();
+    }
+    s
+}
+
+// reads the same index with both text parsers
+fn parsing(c: &mut Criterion) {
+    let text = index_text();
+    let mut group = c.benchmark_group("parsing");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(text.len() as u64));
+    group.bench_function("read", |b| {
+        b.iter(|| TreeIndex::read(Cursor::new(text.as_bytes()), true).unwrap())
+    });
+    group.bench_function("read_fast", |b| {
+        b.iter(|| TreeIndex::read_fast(Cursor::new(text.as_bytes()), true).unwrap())
+    });
+    group.finish();
+}
+
+criterion_group!(benches, hashing, walking, parsing);
+criterion_main!(benches);
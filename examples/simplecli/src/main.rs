@@ -1,11 +1,9 @@
 extern crate structopt;
 use best_practices::{
-    error::Error,
-    cli::io::*,
+    cli::{io::*, logging},
     Result
 };
 use clap::{
-    crate_authors,
     crate_description,
     crate_name,
     crate_version
@@ -19,7 +17,7 @@ use structopt::StructOpt;
 #[structopt(
     name = crate_name!(),
     version = crate_version!(),
-    author = crate_authors!("\n"),
+    author = env!("CARGO_PKG_AUTHORS"),
     about = crate_description!(),
 )]
 struct Opt {
@@ -59,12 +57,7 @@ fn main() -> Result<()> {
     let opt = Opt::from_args();
 
     // set up the logger
-    match stderrlog::new().quiet(opt.quiet).verbosity(opt.verbosity).init() {
-        Err(e) => {
-            return Err(Error::LogError(e.to_string()));
-        }
-        _ => {}
-    }
+    logging::init(opt.quiet, opt.verbosity)?;
 
     match opt.cmd {
         Command::Echo { output, input } => {
@@ -1,28 +1,109 @@
 use best_practices::{
     error::Error,
     cli::io::*,
-    cli::fs::{
+    fs::{
+        Action,
+        ActionLog,
+        ActionRecord,
+        ActionResult,
+        apply_action,
+        dir_pairs,
+        dir_similarity,
+        Algorithm,
+        AppleDoublePolicy,
+        ChecksumFormat,
+        common_root,
+        ConfirmMode,
+        CopyLayout,
+        CopyTargets,
+        DedupExecutorBuilder,
+        DedupPlanner,
+        DedupPlannerBuilder,
+        DigestEncoding,
+        DirIndex,
+        ExecutionMode,
+        IndexFormat,
+        IoPriority,
+        MemoryMetrics,
+        MemoryUsage,
+        INDEX_VERSION,
+        prune_empty_dirs,
+        read_action_log_with_header,
+        serve,
+        serve_tcp,
+        subdirs,
+        PreserveOptions,
+        ReparsePolicy,
+        SavingsReport,
+        ScanSummary,
+        ScriptShell,
+        similar_files,
+        similar_images,
+        SizeMode,
+        TraversalOrder,
+        SpecialFilePolicy,
+        SyncPlannerBuilder,
+        TempFile,
+        TreeIndex,
         TreeIndexBuilder,
-        TreeListBuilder
+        TreeIndexDiff,
+        TreeItemBuilder,
+        TreeList,
+        TreeListBuilder,
+        write_ndjson_item
     },
+    cli::report::{DupeReport, ReportFormat},
+    cancel::CancellationToken,
+    cli::logging::{self, LogFormat},
+    cli::signals,
+    cli::term::{
+        color_writer,
+        terminal_width,
+        ColorChoice
+    },
+    cli::bench::{bench_hashing, HashMode, SyntheticTreeBuilder},
+    units::{format_bytes, parse_age, parse_bytes, Units},
     Result,
 };
+#[cfg(feature = "sign")]
+use best_practices::{
+    cli::keys::{read_signing_key, read_verifying_key},
+    fs::{generate_key, key_hex}
+};
+#[cfg(feature = "media")]
+use best_practices::fs::ImageHashKind;
+#[cfg(feature = "s3")]
+use best_practices::fs::S3SourceBuilder;
+#[cfg(feature = "sqlite")]
+use best_practices::fs::{IndexStore, SqliteStore};
+#[cfg(all(feature = "service", unix))]
+use best_practices::fs::{bind_socket, IndexService};
+#[cfg(feature = "watch")]
+use best_practices::fs::{TreeWatcher, TreeWatcherBuilder};
 use clap::{
-    crate_authors,
     crate_description,
     crate_name,
     crate_version
 };
 use log::*;
 use std::collections::HashSet;
-use std::path::PathBuf;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+#[cfg(feature = "watch")]
+use std::time::Duration;
+use std::time::SystemTime;
 use structopt::StructOpt;
 
+#[cfg(feature = "tui")]
+mod tui;
+
 #[derive(Debug, StructOpt)]
 #[structopt(
     name = crate_name!(),
     version = crate_version!(),
-    author = crate_authors!("\n"),
+    author = env!("CARGO_PKG_AUTHORS"),
     about = crate_description!(),
 )]
 struct Opt {
@@ -35,11 +116,459 @@ struct Opt {
     #[structopt(long = "verbose", short = "v", parse(from_occurrences))]
     verbosity: usize,
 
+    /// How log records are written: text, or json for one structured
+    /// record per line that log pipelines can ingest
+    #[structopt(long, default_value = "text")]
+    log_format: LogFormat,
+
+    /// Write the counts of files scanned, bytes hashed and actions done to
+    /// this file in the Prometheus text format when the command finishes,
+    /// and after every batch of changes while watching, for the node
+    /// exporter's textfile collector
+    #[structopt(long, parse(from_os_str))]
+    metrics_file: Option<PathBuf>,
+
+    /// Sync the plans and logs of files acted on to the disk as they are
+    /// written so they survive a crash or power cut, at the cost of speed
+    #[structopt(long)]
+    sync: bool,
+
+    #[structopt(flatten)]
+    input: InputOptions,
+
     /// Subcommand
     #[structopt(subcommand)]
     cmd: Command
 }
 
+#[derive(Debug, StructOpt)]
+struct ScanOptions {
+
+    /// Additional root directories to scan
+    #[structopt(long = "root", parse(from_os_str), number_of_values = 1)]
+    roots: Vec<PathBuf>,
+
+    /// Digest the files listed in this file, or stdin for -, instead of
+    /// scanning the roots
+    #[structopt(long, parse(from_os_str))]
+    paths_from: Option<PathBuf>,
+
+    /// The listed paths are separated by NUL bytes, e.g. from find -print0
+    #[structopt(short = "0", long)]
+    null: bool,
+
+    /// Ignore files smaller than this, e.g. 64KiB
+    #[structopt(long, parse(try_from_str = parse_bytes))]
+    min_size: Option<u64>,
+
+    /// Ignore files larger than this, e.g. 4GiB
+    #[structopt(long, parse(try_from_str = parse_bytes))]
+    max_size: Option<u64>,
+
+    /// Only include files modified within this long ago, e.g. 30d
+    #[structopt(long, parse(try_from_str = parse_age))]
+    newer_than: Option<SystemTime>,
+
+    /// Only include files modified longer ago than this, e.g. 1y
+    #[structopt(long, parse(try_from_str = parse_age))]
+    older_than: Option<SystemTime>,
+
+    /// Only include files with these extensions, e.g. jpg,png
+    #[structopt(long = "ext", use_delimiter = true, number_of_values = 1)]
+    extensions: Vec<String>,
+
+    /// Only include files whose detected MIME type starts with this, e.g. image/
+    #[structopt(long)]
+    mime: Option<String>,
+
+    /// Skip files matched by .gitignore, .ignore and global git excludes
+    #[structopt(long)]
+    respect_ignore: bool,
+
+    /// Skip hidden files and directories
+    #[structopt(long)]
+    skip_hidden: bool,
+
+    /// Descend at most this many directories deep, 1 is only the root
+    #[structopt(long)]
+    max_depth: Option<usize>,
+
+    /// Don't cross file system boundaries
+    #[structopt(short = "x", long)]
+    one_file_system: bool,
+
+    /// The order to walk the tree in: bfs, dfs or sorted
+    #[structopt(long, default_value = "bfs")]
+    order: TraversalOrder,
+
+    /// Number of threads to scan and hash with, 0 means one per CPU
+    #[structopt(short = "j", long, default_value = "1")]
+    threads: usize,
+
+    /// Memory map files to hash them, faster for large files on fast disks.
+    /// Files on network file systems and files modified in the last few
+    /// seconds are still read.
+    #[structopt(long)]
+    mmap: bool,
+
+    /// Digest at most this many files a second, 0 is no limit
+    #[structopt(long, default_value = "0")]
+    max_files_per_sec: u64,
+
+    /// Read at most this much a second, e.g. 20MiB, 0 is no limit
+    #[structopt(long, parse(try_from_str = parse_bytes), default_value = "0")]
+    max_bytes_per_sec: u64,
+
+    /// The IO priority to scan with: normal or background, which only
+    /// gets the disk when nothing else wants it
+    #[structopt(long, default_value = "normal")]
+    io_priority: IoPriority,
+
+    /// The digest algorithm: blake2b or sha256, match uses the index's
+    #[structopt(long, default_value = "blake2b")]
+    algo: Algorithm,
+
+    /// Write digests as self-describing multibase multihashes
+    #[structopt(long)]
+    multihash: bool,
+
+    /// Also keep digests of each chunk of this size, e.g. 4MiB, so verify can
+    /// tell which parts of a file changed
+    #[structopt(long, parse(try_from_str = parse_bytes))]
+    chunks: Option<u64>,
+
+    /// Also keep digests of chunks cut by the content with this average size,
+    /// e.g. 16KiB, so dupes partial can find files that are mostly the same
+    #[structopt(long, parse(try_from_str = parse_bytes), conflicts_with = "chunks")]
+    cdc: Option<u64>,
+
+    /// Also keep a fuzzy digest of every file so dupes fuzzy can find files
+    /// with minor edits, this can't be used with --fast
+    #[structopt(long)]
+    fuzzy: bool,
+
+    /// Key the digests with the secret in this file, "-" prompts for it
+    #[structopt(long, parse(try_from_str = read_hash_key))]
+    hash_key: Option<HashKey>,
+
+    /// Keep the mtime, permissions, owner and group of each file so verify
+    /// finds metadata only changes
+    #[structopt(long)]
+    metadata: bool,
+
+    /// What to do with FIFOs, sockets, devices and dangling symlinks: skip,
+    /// record or error
+    #[structopt(long, default_value = "skip")]
+    special: SpecialFilePolicy,
+
+    /// What to do with Windows junctions, mount points and cloud
+    /// placeholders: skip, follow or record
+    #[structopt(long, default_value = "skip")]
+    reparse_points: ReparsePolicy,
+
+    /// What to do with the ._name AppleDouble and .DS_Store files macOS
+    /// leaves on shares: keep or ignore, pair ignores them too
+    #[structopt(long, default_value = "keep")]
+    apple_double: AppleDoublePolicy,
+
+    /// Print the scan stats to stderr as JSON when the scan is done
+    #[structopt(long)]
+    stats_json: bool,
+
+    /// Also list the files inside zip, tar and tar.gz archives as
+    /// archive.zip!inner/path
+    #[cfg(feature = "archive")]
+    #[structopt(long)]
+    archives: bool,
+
+    /// Also keep a perceptual hash of every image, ahash or phash, so dupes
+    /// similar-images can find resized and re-saved copies
+    #[cfg(feature = "media")]
+    #[structopt(long)]
+    image_hash: Option<ImageHashKind>,
+
+    /// Normalize the paths of the scanned files to Unicode NFC, macOS
+    /// stores them decomposed and Linux usually doesn't
+    #[cfg(feature = "unicode")]
+    #[structopt(long)]
+    nfc: bool,
+}
+
+#[derive(Debug, StructOpt)]
+struct InputOptions {
+    /// Rewrite the paths of indexes read as input from OLD to NEW, e.g.
+    /// /mnt/backup=/media/backup, may be given more than once
+    #[structopt(long, parse(try_from_str = parse_prefix_map), number_of_values = 1)]
+    map_prefix: Vec<PrefixMap>,
+
+    /// Normalize the paths of indexes read as input to Unicode NFC so they
+    /// compare equal to paths scanned with --nfc
+    #[cfg(feature = "unicode")]
+    #[structopt(long)]
+    nfc: bool,
+}
+
+#[derive(Debug, StructOpt)]
+struct PlanOptions {
+    /// Only act on groups with at least this many dupes
+    #[structopt(long, default_value = "0")]
+    min_dupes: usize,
+
+    /// Only act on groups that free at least this many bytes, e.g. 100MiB
+    #[structopt(long, parse(try_from_str = parse_bytes))]
+    min_savings: Option<u64>,
+
+    /// Only report near duplicates whose fuzzy digests score at least this,
+    /// from 0 to 100
+    #[structopt(long, default_value = "50")]
+    min_fuzzy_score: u32,
+
+    /// Never delete or move files under this path or matching this glob,
+    /// e.g. "/backups/**"
+    #[structopt(long, number_of_values = 1)]
+    protect: Vec<String>,
+
+    /// What to do with the ._name AppleDouble and .DS_Store files macOS
+    /// leaves on shares: keep, ignore to leave them out of the plan or pair
+    /// to also delete, move or copy each file's ._name with it
+    #[structopt(long, default_value = "keep")]
+    apple_double: AppleDoublePolicy,
+}
+
+impl PlanOptions {
+
+    fn planner(&self) -> Result<DedupPlanner> {
+        let mut builder = DedupPlannerBuilder::new()
+            .min_dupes(self.min_dupes)
+            .min_savings(self.min_savings.unwrap_or(0))
+            .min_fuzzy_score(self.min_fuzzy_score)
+            .apple_double(self.apple_double);
+        for p in &self.protect {
+            builder = builder.protect(p);
+        }
+        builder.build()
+    }
+
+    // keep only the groups that meet the thresholds
+    fn apply(&self, ti: &TreeIndex) -> Result<TreeIndex> {
+        Ok(self.planner()?.plan(ti))
+    }
+}
+
+#[derive(Debug, StructOpt)]
+struct ScriptOptions {
+    /// Write a script that carries out the actions to this file instead of
+    /// doing them, the log records them as a dry run
+    #[structopt(long, parse(from_os_str))]
+    emit_script: Option<PathBuf>,
+
+    /// The shell the script is written for: posix or powershell
+    #[structopt(long, default_value = "posix")]
+    shell: ScriptShell,
+}
+
+impl ScriptOptions {
+
+    // a script is written instead of doing anything, like a dry run. It is
+    // opened like the other outputs so it can go to a socket or pipe.
+    fn apply(&self, builder: DedupExecutorBuilder, dry_run: bool) -> Result<DedupExecutorBuilder> {
+        let builder = builder.shell(self.shell);
+        Ok(match &self.emit_script {
+            Some(p) => builder.mode(ExecutionMode::Script(p.clone())).script(writer(&Some(p.clone()))?),
+            None if dry_run => builder.mode(ExecutionMode::DryRun),
+            None => builder.mode(ExecutionMode::Execute)
+        })
+    }
+}
+
+#[cfg(feature = "watch")]
+#[derive(Debug, StructOpt)]
+struct WatchOptions {
+    /// Use faster file hashing, less precise but mutch faster
+    #[structopt(long)]
+    fast: bool,
+
+    /// Milliseconds to wait for changes to settle before hashing
+    #[structopt(long, default_value = "500")]
+    settle: u64,
+
+    /// The secret for keyed indexes, "-" prompts for it
+    #[structopt(long, parse(try_from_str = read_hash_key))]
+    hash_key: Option<HashKey>,
+}
+
+#[cfg(feature = "watch")]
+impl WatchOptions {
+
+    // load the index or create it if it doesn't exist yet and start
+    // watching the root for changes to it
+    fn watch(&self, input: &InputOptions, token: &CancellationToken, metrics: &Arc<MemoryMetrics>,
+             index: &Path, root: &Path) -> Result<TreeWatcher> {
+        let output = Some(index.to_path_buf());
+        let ti = if index.is_file() {
+            let ti = index_builder(input)
+                .with_dupes(true)
+                .from_reader(&mut reader(&output)?)
+                .build()?;
+
+            // the updates are appended with escaped paths, so an older
+            // index is rewritten with them first
+            if !ti.header.is_escaped() {
+                save_index(&output, |w| ti.write(w))?;
+            }
+            ti
+        } else {
+            let mut builder = scanner(token, metrics)
+                .fast(self.fast)
+                .path(root);
+            if let Some(key) = &self.hash_key {
+                builder = builder.key(&key.0);
+            }
+            let ti = TreeIndexBuilder::new()
+                .with_dupes(true)
+                .from_scan(builder)
+                .build()?;
+            save_index(&output, |w| ti.write(w))?;
+            ti
+        };
+        trace!("loaded {} items with {} dupes in the index",
+               ti.idx.len(), ti.count_dupes());
+
+        let mut builder = TreeWatcherBuilder::new()
+            .fast(self.fast)
+            .settle(Duration::from_millis(self.settle))
+            .index(ti)
+            .path(root)
+            .ignore_path(index)
+            .metrics(metrics.clone())
+            .cancel_token(token);
+        if let Some(key) = &self.hash_key {
+            builder = builder.key(&key.0);
+        }
+        builder.build()
+    }
+}
+
+// logs what the scan did and how many special files of each kind it found
+fn log_scan(tl: &TreeList, scan: &ScanOptions) {
+    let s = &tl.stats;
+    info!(dirs = s.dirs, files = s.files, bytes = s.bytes, skipped = s.skipped, errors = s.errors;
+          "scanned {}", s);
+    for (kind, count) in &tl.special_counts {
+        info!(kind:% = kind, count = *count; "found {} special files of kind {}", count, kind);
+    }
+    if scan.stats_json {
+        eprintln!("{}", tl.stats.to_json());
+    }
+}
+
+// a path prefix and what to replace it with
+#[derive(Debug)]
+struct PrefixMap(PathBuf, PathBuf);
+
+// parses old=new
+fn parse_prefix_map(s: &str) -> Result<PrefixMap> {
+    match s.split_once('=') {
+        Some((from, to)) if !from.is_empty() => Ok(PrefixMap(PathBuf::from(from), PathBuf::from(to))),
+        _ => Err(Error::InvalidArgument(format!("invalid prefix map {}, expected OLD=NEW", s)))
+    }
+}
+
+// a builder for reading indexes with the input options applied
+fn index_builder<'a>(input: &InputOptions) -> TreeIndexBuilder<'a> {
+    let builder = input.map_prefix.iter().fold(TreeIndexBuilder::new(), |b, m| b.map_prefix(&m.0, &m.1));
+    #[cfg(feature = "unicode")]
+    let builder = builder.normalize_unicode(input.nfc);
+    builder
+}
+
+// the secret bytes for keyed digests
+#[derive(Debug)]
+struct HashKey(Vec<u8>);
+
+// reads a hashing key with the line ending stripped
+fn read_hash_key(path: &str) -> Result<HashKey> {
+    let mut key = Vec::new();
+    secure_reader(&Some(PathBuf::from(path)))?.read_to_end(&mut key)?;
+    while key.last() == Some(&b'\n') || key.last() == Some(&b'\r') {
+        key.pop();
+    }
+    Ok(HashKey(key))
+}
+
+impl ScanOptions {
+
+    // apply the options to the list builder, the positional root comes first
+    // and the builder scans the current dir if there are no roots at all
+    fn apply<'a>(&'a self, mut builder: TreeListBuilder<'a>, root: &'a Option<PathBuf>) -> Result<TreeListBuilder<'a>> {
+        if let Some(r) = root {
+            builder = builder.path(r);
+        }
+        if self.paths_from.is_some() {
+            builder = builder.paths_from(reader(&self.paths_from)?, self.null);
+        } else if root.is_none() && self.roots.is_empty() {
+            builder = builder.current_dir();
+        }
+        builder = builder
+            .paths(&self.roots)
+            .min_size(self.min_size.unwrap_or(0))
+            .max_size(self.max_size.unwrap_or(u64::MAX))
+            .respect_ignore_files(self.respect_ignore)
+            .skip_hidden(self.skip_hidden)
+            .max_depth(self.max_depth.unwrap_or(usize::MAX))
+            .same_file_system(self.one_file_system)
+            .order(self.order)
+            .threads(self.threads)
+            .mmap(self.mmap)
+            .max_files_per_sec(self.max_files_per_sec)
+            .max_bytes_per_sec(self.max_bytes_per_sec)
+            .io_priority(self.io_priority)
+            .algorithm(self.algo)
+            .with_metadata(self.metadata)
+            .fuzzy(self.fuzzy)
+            .special_files(self.special)
+            .reparse_points(self.reparse_points)
+            .apple_double(self.apple_double);
+        if self.multihash {
+            builder = builder.encoding(DigestEncoding::Multihash);
+        }
+        if let Some(key) = &self.hash_key {
+            builder = builder.key(&key.0);
+        }
+        if let Some(c) = self.chunks {
+            builder = builder.chunks(c);
+        }
+        if let Some(c) = self.cdc {
+            builder = builder.content_chunks(c);
+        }
+        if let Some(t) = self.newer_than {
+            builder = builder.modified_after(t);
+        }
+        if let Some(t) = self.older_than {
+            builder = builder.modified_before(t);
+        }
+        if !self.extensions.is_empty() {
+            builder = builder.extensions(&self.extensions);
+        }
+        if let Some(m) = &self.mime {
+            builder = builder.mime_prefix(m);
+        }
+        #[cfg(feature = "archive")]
+        {
+            builder = builder.archives(self.archives);
+        }
+        #[cfg(feature = "media")]
+        if let Some(k) = self.image_hash {
+            builder = builder.image_hashes(k);
+        }
+        #[cfg(feature = "unicode")]
+        {
+            builder = builder.normalize_unicode(self.nfc);
+        }
+        Ok(builder)
+    }
+}
+
 #[derive(Debug, StructOpt)]
 enum Command {
 
@@ -50,6 +579,13 @@ enum Command {
         #[structopt(long)]
         fast: bool,
 
+        /// The list format: text, binary, csv or ndjson
+        #[structopt(long, default_value = "text")]
+        format: IndexFormat,
+
+        #[structopt(flatten)]
+        scan: ScanOptions,
+
         /// The root directory to index recursively, otherwise current dir
         #[structopt(parse(from_os_str))]
         root: Option<PathBuf>,
@@ -70,6 +606,43 @@ enum Command {
         #[structopt(long)]
         fast: bool,
 
+        /// Fail if two files of different sizes have the same digest instead
+        /// of keeping them apart
+        #[structopt(long)]
+        fail_on_collision: bool,
+
+        /// The index format: text, binary, csv or ndjson
+        #[structopt(long, default_value = "text")]
+        format: IndexFormat,
+
+        #[structopt(flatten)]
+        scan: ScanOptions,
+
+        /// Keep at most this much of the index in memory and spill the rest
+        /// to disk, e.g. "2GiB"
+        #[structopt(long, parse(try_from_str = parse_bytes))]
+        spill: Option<u64>,
+
+        /// Keep the whole index in memory until it takes more than this,
+        /// e.g. "512MiB", and then spill it, the peak is logged. An index
+        /// with chunk digests or metadata fails if it goes over.
+        #[structopt(long, parse(try_from_str = parse_bytes))]
+        memory_limit: Option<u64>,
+
+        /// The directory to spill to, otherwise the system temp dir
+        #[structopt(long, parse(from_os_str))]
+        spill_dir: Option<PathBuf>,
+
+        /// Sign the index with the secret key in this file, "-" prompts for it
+        #[cfg(feature = "sign")]
+        #[structopt(long, parse(from_os_str))]
+        sign_key: Option<PathBuf>,
+
+        /// Save the index to a SQLite database instead of a text file
+        #[cfg(feature = "sqlite")]
+        #[structopt(long, parse(from_os_str))]
+        sqlite: Option<PathBuf>,
+
         /// The root directory to index recursively, otherwise current dir
         #[structopt(parse(from_os_str))]
         root: Option<PathBuf>,
@@ -86,6 +659,9 @@ enum Command {
         #[structopt(long)]
         fast: bool,
 
+        #[structopt(flatten)]
+        scan: ScanOptions,
+
         /// The root directory to search for duplicates
         #[structopt(parse(from_os_str))]
         root: Option<PathBuf>,
@@ -99,265 +675,1610 @@ enum Command {
         output: Option<PathBuf>,
     },
 
-    #[structopt(name = "confirm")]
-    /// Goes through an index file and uses slow digesting to confirm dupes
-    Confirm {
+    #[structopt(name = "verify")]
+    /// Re-hash the files in an index and report what changed, exits with 1
+    /// if anything was modified, missing or new
+    Verify {
+        /// Use fast hashing even if the index doesn't say it was made with it
+        #[structopt(long)]
+        fast: bool,
+
+        /// Report missing files found again under another path as renamed
+        #[structopt(long)]
+        renames: bool,
+
+        /// The secret for keyed indexes, "-" prompts for it
+        #[structopt(long, parse(try_from_str = read_hash_key))]
+        hash_key: Option<HashKey>,
+
+        /// The root directory the index was made from, otherwise current dir
+        #[structopt(parse(from_os_str))]
+        root: Option<PathBuf>,
+
         /// The index data file, otherwise stdin
         #[structopt(parse(from_os_str))]
         input: Option<PathBuf>,
 
-        /// The file to save the index to, otherwise stdout.
+        /// The file to save the report to, otherwise stdout.
         #[structopt(parse(from_os_str))]
         output: Option<PathBuf>,
     },
 
-    #[structopt(name = "zeroes")]
-    /// Goes through an index file and removes all items and dupes with 0 length
-    Zeroes {
+    #[structopt(name = "diff")]
+    /// Compare two indexes of a tree and report what changed, exits with 1
+    /// if anything did
+    Diff {
+        /// Report files that moved as renamed instead of missing and new
+        #[structopt(long)]
+        renames: bool,
+
+        /// The older index data file
+        #[structopt(parse(from_os_str))]
+        old: PathBuf,
+
+        /// The newer index data file
+        #[structopt(parse(from_os_str))]
+        new: PathBuf,
+
+        /// The file to save the report to, otherwise stdout.
+        #[structopt(parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+
+    #[cfg(feature = "sign")]
+    #[structopt(name = "keygen")]
+    /// Generate an ed25519 key pair for signing indexes
+    Keygen {
+        /// The file to save the secret key to
+        #[structopt(parse(from_os_str))]
+        secret: PathBuf,
+
+        /// The file to save the public key to, otherwise stdout.
+        #[structopt(parse(from_os_str))]
+        public: Option<PathBuf>,
+    },
+
+    #[cfg(feature = "sign")]
+    #[structopt(name = "checksig")]
+    /// Check the signature on an index, exits with 1 if it doesn't verify
+    Checksig {
+        /// The public key file
+        #[structopt(long, parse(from_os_str))]
+        key: PathBuf,
+
         /// The index data file, otherwise stdin
         #[structopt(parse(from_os_str))]
         input: Option<PathBuf>,
+    },
 
-        /// The file to save the index to, otherwise stdout.
+    #[structopt(name = "export")]
+    /// Write an index as a sha256sum or BSD style checksum file
+    Export {
+        /// The checksum layout: gnu or bsd
+        #[structopt(long, default_value = "gnu")]
+        format: ChecksumFormat,
+
+        /// The index data file, otherwise stdin
+        #[structopt(parse(from_os_str))]
+        input: Option<PathBuf>,
+
+        /// The file to save the checksums to, otherwise stdout.
         #[structopt(parse(from_os_str))]
         output: Option<PathBuf>,
     },
 
-    #[structopt(name = "dupes")]
-    /// Commands for handling duplicate files
-    Dupes {
-        /// Subcommand
-        #[structopt(subcommand)]
-        cmd: DupesCommand
-    }
-}
+    #[structopt(name = "import")]
+    /// Read a sha256sum or BSD style checksum file into an index
+    Import {
+        /// Include duplicates? Default is no
+        #[structopt(long)]
+        dupes: bool,
 
-#[derive(Debug, StructOpt)]
-enum DupesCommand {
+        /// The digest algorithm of untagged lines: blake2b or sha256
+        #[structopt(long, default_value = "sha256")]
+        algo: Algorithm,
 
-    #[structopt(name = "find")]
-    /// Find duplicates of files from one index in another and producing a third
-    Find {
+        /// The checksum file, otherwise stdin
+        #[structopt(parse(from_os_str))]
+        input: Option<PathBuf>,
 
-        /// The "needle" index data file, otherwise stdin
+        /// The file to save the index to, otherwise stdout.
         #[structopt(parse(from_os_str))]
-        needle: Option<PathBuf>,
+        output: Option<PathBuf>,
+    },
 
-        /// The "haystack" index data file
+    #[structopt(name = "confirm")]
+    /// Goes through an index file and uses slow digesting to confirm dupes
+    Confirm {
+        /// Number of threads to confirm with, 0 means one per CPU
+        #[structopt(short = "j", long, default_value = "0")]
+        threads: usize,
+
+        /// Only read the dupes whose fast digests match their original's in
+        /// full, groups without any are left out
+        #[structopt(long)]
+        prefilter: bool,
+
+        /// How dupes are checked: digest, or bytes to compare them with the
+        /// original byte for byte
+        #[structopt(long, default_value = "digest")]
+        mode: ConfirmMode,
+
+        /// The index data file, otherwise stdin
         #[structopt(parse(from_os_str))]
-        haystack: Option<PathBuf>,
+        input: Option<PathBuf>,
 
-        /// The file to save the dupe dir list, otherwise stdout.
+        /// The file to save the index to, otherwise stdout.
         #[structopt(parse(from_os_str))]
         output: Option<PathBuf>,
     },
 
-    #[structopt(name = "listdirs")]
-    /// Find duplicates of files in the given index file
-    ListDirs {
+    #[structopt(name = "bench", setting = structopt::clap::AppSettings::Hidden)]
+    /// Measure the hashing throughput of every digest mode on a generated
+    /// tree, made on the disk under test. Files still in the page cache read
+    /// faster than the disk, make the tree bigger than memory to avoid it.
+    Bench {
+        /// The directory to generate the tree in
+        #[structopt(long, parse(from_os_str), default_value = ".")]
+        dir: PathBuf,
+
+        /// The total size of the tree, e.g. 1GiB
+        #[structopt(long, parse(try_from_str = parse_bytes), default_value = "256MiB")]
+        size: u64,
 
+        /// The number of files to split it into
+        #[structopt(long, default_value = "32")]
+        files: usize,
+
+        /// Number of threads to hash with, 0 means one per CPU
+        #[structopt(short = "j", long, default_value = "1")]
+        threads: usize,
+
+        /// The file to save the results to, otherwise stdout.
+        #[structopt(parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+
+    #[structopt(name = "zeroes")]
+    /// Goes through an index file and removes all items and dupes with 0 length
+    Zeroes {
         /// The index data file, otherwise stdin
         #[structopt(parse(from_os_str))]
         input: Option<PathBuf>,
 
-        /// The file to save the dupe dir list, otherwise stdout.
+        /// The file to save the index to, otherwise stdout.
         #[structopt(parse(from_os_str))]
         output: Option<PathBuf>,
     },
 
-    #[structopt(name = "size")]
-    /// Sum up the total size of storage space that would be saved by de-duping
-    Size {
+    #[structopt(name = "migrate")]
+    /// Upgrade an index to the current version of the format
+    Migrate {
+        /// The version 1 index was built with fast digests, they are redone
+        /// for files over 1 MiB
+        #[structopt(long)]
+        fast: bool,
+
+        /// The directory the index paths are relative to, otherwise current
+        /// dir
+        #[structopt(long, parse(from_os_str))]
+        root: Option<PathBuf>,
+
+        /// The format to write: text, binary, csv or ndjson
+        #[structopt(long, default_value = "text")]
+        format: IndexFormat,
 
         /// The index data file, otherwise stdin
         #[structopt(parse(from_os_str))]
         input: Option<PathBuf>,
 
-        /// The file to save the stats to, otherwise stdout.
+        /// The file to save the index to, otherwise stdout.
         #[structopt(parse(from_os_str))]
         output: Option<PathBuf>,
     },
 
-    #[structopt(name = "copy")]
-    /// Copy all duplicate files to the specified folder
-    CopyFiles {
+    #[structopt(name = "prune-empty")]
+    /// Remove the empty directories under a dir tree, deepest first
+    PruneEmpty {
 
         /// Dry run flag
         #[structopt(long)]
         dry_run: bool,
 
-        /// The index data file, otherwise stdin
-        #[structopt(parse(from_os_str))]
-        input: Option<PathBuf>,
+        /// Never remove this directory, its parents or the directories
+        /// matching this glob, e.g. "/backups/**"
+        #[structopt(long, number_of_values = 1)]
+        protect: Vec<String>,
 
-        /// The destination directory to move the dupe files to
+        /// The root directory to prune, it is never removed, otherwise
+        /// current dir
         #[structopt(parse(from_os_str))]
-        dest: Option<PathBuf>,
+        root: Option<PathBuf>,
 
         /// The file to save the log of actions to
-        #[structopt(parse(from_os_str))]
+        #[structopt(long, parse(from_os_str))]
         output: Option<PathBuf>,
     },
 
-    #[structopt(name = "delete")]
-    /// Delete all duplicate files in the index
-    DeleteFiles {
+    #[structopt(name = "undo")]
+    /// Reverse the copies, moves and removed directories in an action log,
+    /// last first, exits with 1 if any couldn't be undone
+    Undo {
 
         /// Dry run flag
         #[structopt(long)]
         dry_run: bool,
 
-        /// The index data file, otherwise stdin
+        /// The metadata to keep on files that have to be copied back, a
+        /// list of times, mode, owner and xattrs, or all or none
+        #[structopt(long, default_value = "times,mode,xattrs")]
+        preserve: PreserveOptions,
+
+        /// The secret of the keyed index the actions were done from, "-"
+        /// prompts for it. Copies are checked against their digests before
+        /// they are deleted.
+        #[structopt(long, parse(try_from_str = read_hash_key))]
+        hash_key: Option<HashKey>,
+
+        /// The action log or move manifest, otherwise stdin
         #[structopt(parse(from_os_str))]
         input: Option<PathBuf>,
 
-        /// The file to save the log of actions to
+        /// The file to save the log of the undo actions to
         #[structopt(parse(from_os_str))]
         output: Option<PathBuf>,
-    }
-}
+    },
 
-fn main() -> Result<()> {
+    #[structopt(name = "stats")]
+    /// Report the totals, largest duplicate groups and file sizes of an index
+    Stats {
+        /// Output JSON instead of text
+        #[structopt(long)]
+        json: bool,
 
-    // parse the command line flags
-    let opt = Opt::from_args();
+        /// How many of the largest duplicate groups to list
+        #[structopt(long, default_value = "20")]
+        top: usize,
 
-    // set up the logger
-    match stderrlog::new().quiet(opt.quiet).verbosity(opt.verbosity).init() {
-        Err(e) => {
-            return Err(Error::LogError(e.to_string()));
-        }
-        _ => {}
-    }
+        /// The index data file, otherwise stdin
+        #[structopt(parse(from_os_str))]
+        input: Option<PathBuf>,
 
-    match opt.cmd {
+        /// The file to save the stats to, otherwise stdout.
+        #[structopt(parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
 
-        Command::List { fast, root, output } => {
-            debug!("listing {} to {}",
-                 dir_name(&root)?.to_string_lossy(),
-                 writer_name(&output)?.to_string_lossy());
+    #[structopt(name = "digest")]
+    /// Digest a stream, e.g. piped from tar or curl, and append its line to
+    /// an index
+    Digest {
+        /// Use faster file hashing, less precise but mutch faster
+        #[structopt(long)]
+        fast: bool,
 
-            // create the list from the directory tree
-            let tl = TreeListBuilder::new()
-                .fast(fast)
-                .path(&dir(&root)?)
-                .build()?;
+        /// The digest algorithm: blake2b or sha256
+        #[structopt(long, default_value = "blake2b")]
+        algo: Algorithm,
 
-            // output the list
-            let mut w = writer(&output)?;
-            for item in tl.list {
-                write!(w, "{}", item)?;
-            }
-        },
+        /// The path to give the stream in the index
+        #[structopt(long, default_value = "stdin")]
+        name: String,
 
-        Command::Index { dupes, fast, root, output } => {
-            debug!("indexing {} to {}",
-                 dir_name(&root)?.to_string_lossy(),
-                 writer_name(&output)?.to_string_lossy());
+        /// The file to digest, otherwise stdin
+        #[structopt(parse(from_os_str))]
+        input: Option<PathBuf>,
 
-            // create the index from the directory tree
-            let tl = TreeListBuilder::new()
-                .fast(fast)
-                .path(&dir(&root)?)
-                .build()?;
-            let ti = TreeIndexBuilder::new()
-                .with_dupes(dupes)
-                .from_list(&tl)
-                .build()?;
+        /// The index file to append the line to, otherwise stdout.
+        #[structopt(parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
 
-            // output the index
-            let mut w = writer(&output)?;
-            for item in ti.idx.into_values() {
-                write!(w, "{}", item)?;
-            }
-        },
+    #[structopt(name = "serve")]
+    /// Scan a dir tree and stream the files to a remote client, over TCP
+    /// or stdout, e.g. for treetool remote to read over ssh
+    Serve {
+        /// Use faster file hashing, less precise but mutch faster
+        #[structopt(long)]
+        fast: bool,
 
-        Command::Match { fast, root, input, output } => {
+        /// Listen on this address and serve a scan to every client that
+        /// connects instead of writing one to stdout, e.g. 0.0.0.0:7878
+        #[structopt(long)]
+        listen: Option<String>,
+
+        #[structopt(flatten)]
+        scan: ScanOptions,
+
+        /// The root directory to scan, otherwise current dir
+        #[structopt(parse(from_os_str))]
+        root: Option<PathBuf>,
+    },
+
+    #[structopt(name = "remote")]
+    /// Build an index from the files streamed by treetool serve
+    Remote {
+        /// Include duplicates? Default is no
+        #[structopt(long)]
+        dupes: bool,
+
+        /// The index format: text, binary, csv or ndjson
+        #[structopt(long, default_value = "text")]
+        format: IndexFormat,
+
+        /// The server to read from, tcp://host:port, or a file, otherwise
+        /// stdin
+        #[structopt(parse(from_os_str))]
+        input: Option<PathBuf>,
+
+        /// The file to save the index to, otherwise stdout.
+        #[structopt(parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+
+    #[cfg(feature = "s3")]
+    #[structopt(name = "s3")]
+    /// Digest the objects in an S3 compatible bucket and output an index,
+    /// credentials come from AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY
+    S3 {
+        /// Include duplicates? Default is no
+        #[structopt(long)]
+        dupes: bool,
+
+        /// Use faster file hashing, less precise but mutch faster
+        #[structopt(long)]
+        fast: bool,
+
+        /// The digest algorithm: blake2b or sha256
+        #[structopt(long, default_value = "blake2b")]
+        algo: Algorithm,
+
+        /// The index format: text, binary, csv or ndjson
+        #[structopt(long, default_value = "text")]
+        format: IndexFormat,
+
+        /// The URL of the server, otherwise AWS
+        #[structopt(long)]
+        endpoint: Option<String>,
+
+        /// The region to sign requests for
+        #[structopt(long, default_value = "us-east-1")]
+        region: String,
+
+        /// Only digest the keys that start with this
+        #[structopt(long)]
+        prefix: Option<String>,
+
+        /// The bucket to digest
+        bucket: String,
+
+        /// The file to save the index to, otherwise stdout.
+        #[structopt(parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+
+    #[structopt(name = "lookup")]
+    /// Output the items and dupes whose digest starts with a prefix, so the
+    /// short digests printed by other tools can be looked up
+    Lookup {
+        /// The digest or the start of it
+        prefix: String,
+
+        /// The index data file, otherwise stdin
+        #[structopt(parse(from_os_str))]
+        input: Option<PathBuf>,
+
+        /// The file to save the matching items to, otherwise stdout.
+        #[structopt(parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+
+    #[cfg(feature = "watch")]
+    #[structopt(name = "watch")]
+    /// Watch a dir tree and append changes to an index file
+    Watch {
+        #[structopt(flatten)]
+        watch: WatchOptions,
+
+        /// The index file to keep up to date, it is created if missing
+        #[structopt(parse(from_os_str))]
+        index: PathBuf,
+
+        /// The root directory to watch, otherwise current dir
+        #[structopt(parse(from_os_str))]
+        root: Option<PathBuf>,
+    },
+
+    #[cfg(all(feature = "service", unix))]
+    #[structopt(name = "daemon")]
+    /// Watch a dir tree, append changes to an index file and answer
+    /// lookup, dupes and stats queries about it as JSON-RPC over a unix
+    /// socket
+    Daemon {
+        #[structopt(flatten)]
+        watch: WatchOptions,
+
+        /// The unix socket to listen on
+        #[structopt(long, parse(from_os_str), default_value = "treetool.sock")]
+        socket: PathBuf,
+
+        /// The index file to keep up to date, it is created if missing
+        #[structopt(parse(from_os_str))]
+        index: PathBuf,
+
+        /// The root directory to watch, otherwise current dir
+        #[structopt(parse(from_os_str))]
+        root: Option<PathBuf>,
+    },
+
+    #[structopt(name = "dupes")]
+    /// Commands for handling duplicate files
+    Dupes {
+        /// Subcommand
+        #[structopt(subcommand)]
+        cmd: DupesCommand
+    },
+
+    #[structopt(name = "sync")]
+    /// Commands for making one tree match another
+    Sync {
+        /// Subcommand
+        #[structopt(subcommand)]
+        cmd: SyncCommand
+    }
+}
+
+#[derive(Debug, StructOpt)]
+enum SyncCommand {
+
+    #[structopt(name = "plan")]
+    /// Output the copies, moves and deletes that make the destination tree
+    /// match the source tree, one per line
+    Plan {
+        /// Move and duplicate the files the destination already has instead
+        /// of copying them from the source
+        #[structopt(long)]
+        content_addressed: bool,
+
+        /// The directory the source index was made from
+        #[structopt(long, parse(from_os_str))]
+        source_root: Option<PathBuf>,
+
+        /// The directory the destination index was made from
+        #[structopt(long, parse(from_os_str))]
+        dest_root: Option<PathBuf>,
+
+        /// The source index data file with dupes
+        #[structopt(parse(from_os_str))]
+        source: PathBuf,
+
+        /// The destination index data file with dupes
+        #[structopt(parse(from_os_str))]
+        dest: PathBuf,
+
+        /// The file to save the plan to, otherwise stdout.
+        #[structopt(parse(from_os_str))]
+        output: Option<PathBuf>,
+    }
+}
+
+#[derive(Debug, StructOpt)]
+enum DupesCommand {
+
+    #[structopt(name = "find")]
+    /// Find duplicates of files from one index in another and producing a third
+    Find {
+
+        /// The "needle" index data file, otherwise stdin
+        #[structopt(parse(from_os_str))]
+        needle: Option<PathBuf>,
+
+        /// The "haystack" index data file
+        #[structopt(parse(from_os_str))]
+        haystack: Option<PathBuf>,
+
+        /// The file to save the dupe dir list, otherwise stdout.
+        #[structopt(parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+
+    #[structopt(name = "listdirs")]
+    /// Find duplicates of files in the given index file
+    ListDirs {
+
+        /// The index data file, otherwise stdin
+        #[structopt(parse(from_os_str))]
+        input: Option<PathBuf>,
+
+        /// The file to save the dupe dir list, otherwise stdout.
+        #[structopt(parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+
+    #[structopt(name = "dirs")]
+    /// Find whole directories that are exact duplicates of each other
+    Dirs {
+
+        /// The index data file with dupes, otherwise stdin
+        #[structopt(parse(from_os_str))]
+        input: Option<PathBuf>,
+
+        /// The file to save the dupe dirs to, otherwise stdout.
+        #[structopt(parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+
+    #[structopt(name = "pairs")]
+    /// Sum up the dupes by the directory of the original and of the dupe
+    Pairs {
+
+        /// The index data file with dupes, otherwise stdin
+        #[structopt(parse(from_os_str))]
+        input: Option<PathBuf>,
+
+        /// The file to save the report to, otherwise stdout.
+        #[structopt(parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+
+    #[structopt(name = "similar")]
+    /// Find directories that are mostly contained in other directories
+    Similar {
+
+        /// Only report directories with at least this percent of their bytes
+        /// in the other directory
+        #[structopt(long, default_value = "50")]
+        min: f64,
+
+        /// The index data file with dupes, otherwise stdin
+        #[structopt(parse(from_os_str))]
+        input: Option<PathBuf>,
+
+        /// The file to save the report to, otherwise stdout.
+        #[structopt(parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+
+    #[structopt(name = "partial")]
+    /// Find files that share most of their content defined chunks, the index
+    /// has to be made with --cdc
+    Partial {
+
+        /// Only report pairs of files sharing at least this percent of the
+        /// bigger file's bytes
+        #[structopt(long, default_value = "50")]
+        min_shared: f64,
+
+        /// The index data file, otherwise stdin
+        #[structopt(parse(from_os_str))]
+        input: Option<PathBuf>,
+
+        /// The file to save the report to, otherwise stdout.
+        #[structopt(parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+
+    #[structopt(name = "fuzzy")]
+    /// Group the files that are alike by their fuzzy digests, the index has
+    /// to be made with --fuzzy
+    Fuzzy {
+
+        #[structopt(flatten)]
+        rules: PlanOptions,
+
+        /// The index data file, otherwise stdin
+        #[structopt(parse(from_os_str))]
+        input: Option<PathBuf>,
+
+        /// The file to save the report to, otherwise stdout.
+        #[structopt(parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+
+    #[structopt(name = "similar-images")]
+    /// Group the images that look alike, the index has to be made with
+    /// --image-hash
+    SimilarImages {
+
+        /// Only group images whose hashes differ in at most this many of
+        /// their 64 bits
+        #[structopt(long, default_value = "10")]
+        distance: u32,
+
+        /// The index data file, otherwise stdin
+        #[structopt(parse(from_os_str))]
+        input: Option<PathBuf>,
+
+        /// The file to save the report to, otherwise stdout.
+        #[structopt(parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+
+    #[structopt(name = "fdupes")]
+    /// Output the duplicate groups in the fdupes/jdupes format
+    Fdupes {
+
+        #[structopt(flatten)]
+        rules: PlanOptions,
+
+        /// Start each group with its file size like fdupes -S
+        #[structopt(short = "S", long)]
+        size: bool,
+
+        /// The index data file with dupes, otherwise stdin
+        #[structopt(parse(from_os_str))]
+        input: Option<PathBuf>,
+
+        /// The file to save the groups to, otherwise stdout.
+        #[structopt(parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+
+    #[structopt(name = "size")]
+    /// Sum up the total size of storage space that would be saved by de-duping
+    Size {
+
+        #[structopt(flatten)]
+        rules: PlanOptions,
+
+        /// Output JSON instead of text
+        #[structopt(long)]
+        json: bool,
+
+        /// Also list the savings in each directory, largest first
+        #[structopt(long)]
+        dirs: bool,
+
+        /// Count the logical or the allocated size of the dupes, allocated
+        /// sizes are only known for indexes built with --metadata
+        #[structopt(long, default_value = "logical")]
+        size_mode: SizeMode,
+
+        /// Leave out the bytes dupes already share with the kept copies
+        /// through reflinks, the files are probed with FIEMAP on Linux
+        #[structopt(long)]
+        reflinks: bool,
+
+        /// The index data file, otherwise stdin
+        #[structopt(parse(from_os_str))]
+        input: Option<PathBuf>,
+
+        /// The file to save the stats to, otherwise stdout.
+        #[structopt(parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+
+    #[structopt(name = "report")]
+    /// Render duplicate groups and the space they waste as a table or as a
+    /// single file Markdown, HTML or JSON report
+    Report {
+
+        #[structopt(flatten)]
+        rules: PlanOptions,
+
+        /// The report layout: text, markdown, html or json
+        #[structopt(long, default_value = "text")]
+        format: ReportFormat,
+
+        /// Colorize the output: auto, always, never
+        #[structopt(long, default_value = "auto")]
+        color: ColorChoice,
+
+        /// The index data file, otherwise stdin
+        #[structopt(parse(from_os_str))]
+        input: Option<PathBuf>,
+
+        /// The file to save the report to, otherwise stdout.
+        #[structopt(parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+
+    #[cfg(feature = "tui")]
+    #[structopt(name = "tui")]
+    /// Browse the duplicate groups, mark files and export the marks as a
+    /// plan that the delete command can carry out
+    Tui {
+
+        #[structopt(flatten)]
+        rules: PlanOptions,
+
+        /// The file to write the plan to
+        #[structopt(long, parse(from_os_str), default_value = "dedup-plan.idx")]
+        plan: PathBuf,
+
+        /// The index data file with dupes, stdin is kept for the keyboard
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+    },
+
+    #[structopt(name = "copy")]
+    /// Copy all duplicate files to the specified folder
+    CopyFiles {
+
+        #[structopt(flatten)]
+        rules: PlanOptions,
+
+        /// Dry run flag
+        #[structopt(long)]
+        dry_run: bool,
+
+        #[structopt(flatten)]
+        script: ScriptOptions,
+
+        /// Recreate the directories of the dupes under the destination
+        /// instead of naming them by digest
+        #[structopt(long)]
+        preserve_paths: bool,
+
+        /// Digest each copy and check it against the index, exits with 1 if
+        /// any copy doesn't match
+        #[structopt(long)]
+        verify: bool,
+
+        /// The metadata to keep on the copies, a list of times, mode, owner
+        /// and xattrs, or all or none
+        #[structopt(long, default_value = "times,mode,xattrs")]
+        preserve: PreserveOptions,
+
+        /// How many more times to copy a file that doesn't verify
+        #[structopt(long, default_value = "0")]
+        retries: usize,
+
+        /// The secret for keyed indexes, "-" prompts for it
+        #[structopt(long, parse(try_from_str = read_hash_key))]
+        hash_key: Option<HashKey>,
+
+        /// The index data file, otherwise stdin
+        #[structopt(parse(from_os_str))]
+        input: Option<PathBuf>,
+
+        /// The destination directory to move the dupe files to
+        #[structopt(parse(from_os_str))]
+        dest: Option<PathBuf>,
+
+        /// The file to save the log of actions to
+        #[structopt(parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+
+    #[structopt(name = "move")]
+    /// Move all duplicate files to the specified folder, renaming them when
+    /// they are on the same file system and copying, verifying and deleting
+    /// them when they aren't
+    MoveFiles {
+
+        #[structopt(flatten)]
+        rules: PlanOptions,
+
+        /// Dry run flag
+        #[structopt(long)]
+        dry_run: bool,
+
+        #[structopt(flatten)]
+        script: ScriptOptions,
+
+        /// Recreate the directories of the dupes under the destination
+        /// instead of naming them by digest
+        #[structopt(long)]
+        preserve_paths: bool,
+
+        /// The metadata to keep on files that have to be copied, a list of
+        /// times, mode, owner and xattrs, or all or none
+        #[structopt(long, default_value = "times,mode,xattrs")]
+        preserve: PreserveOptions,
+
+        /// How many more times to copy a file that doesn't verify
+        #[structopt(long, default_value = "0")]
+        retries: usize,
+
+        /// The secret for keyed indexes, "-" prompts for it
+        #[structopt(long, parse(try_from_str = read_hash_key))]
+        hash_key: Option<HashKey>,
+
+        /// The index data file, otherwise stdin
+        #[structopt(parse(from_os_str))]
+        input: Option<PathBuf>,
+
+        /// The destination directory to move the dupe files to
+        #[structopt(parse(from_os_str))]
+        dest: Option<PathBuf>,
+
+        /// The file to save the log of actions to, treetool undo moves the
+        /// files back with it
+        #[structopt(parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+
+    #[structopt(name = "fsdedupe")]
+    /// Make the dupes share the extents of the kept copies with the kernel's
+    /// FIDEDUPERANGE, which checks the bytes are the same and works on files
+    /// in use. Needs btrfs or XFS on Linux.
+    FsDedupe {
+
+        #[structopt(flatten)]
+        rules: PlanOptions,
+
+        /// Dry run flag
+        #[structopt(long)]
+        dry_run: bool,
+
+        /// The index data file, otherwise stdin
+        #[structopt(parse(from_os_str))]
+        input: Option<PathBuf>,
+
+        /// The file to save the log of actions to
+        #[structopt(parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+
+    #[structopt(name = "hardlink")]
+    /// Replace the dupes with hard links to the kept copies, they have to be
+    /// on the same file system
+    Hardlink {
+
+        #[structopt(flatten)]
+        rules: PlanOptions,
+
+        /// Dry run flag
+        #[structopt(long)]
+        dry_run: bool,
+
+        #[structopt(flatten)]
+        script: ScriptOptions,
+
+        /// Digest each dupe and check it against the index before it is
+        /// replaced, otherwise only its size is checked
+        #[structopt(long)]
+        verify: bool,
+
+        /// The secret for keyed indexes, "-" prompts for it
+        #[structopt(long, parse(try_from_str = read_hash_key))]
+        hash_key: Option<HashKey>,
+
+        /// The index data file, otherwise stdin
+        #[structopt(parse(from_os_str))]
+        input: Option<PathBuf>,
+
+        /// The file to save the log of actions to
+        #[structopt(parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+
+    #[structopt(name = "delete")]
+    /// Delete all duplicate files in the index
+    DeleteFiles {
+
+        #[structopt(flatten)]
+        rules: PlanOptions,
+
+        /// Dry run flag
+        #[structopt(long)]
+        dry_run: bool,
+
+        #[structopt(flatten)]
+        script: ScriptOptions,
+
+        /// Remove the directories the deletions leave empty, protected
+        /// paths, their parents and the directory holding everything in the
+        /// index are kept
+        #[structopt(long)]
+        prune_empty: bool,
+
+        /// The index data file, otherwise stdin
+        #[structopt(parse(from_os_str))]
+        input: Option<PathBuf>,
+
+        /// The file to save the log of actions to
+        #[structopt(parse(from_os_str))]
+        output: Option<PathBuf>,
+    }
+}
+
+fn main() -> Result<()> {
+
+    // parse the command line flags
+    let opt = Opt::from_args();
+
+    // set up the logger, with tracing the scans and indexes are written out
+    // as spans with their counts and times
+    #[cfg(feature = "tracing")]
+    logging::init_tracing(opt.quiet, opt.verbosity, opt.log_format)?;
+    #[cfg(not(feature = "tracing"))]
+    logging::init_with_format(opt.quiet, opt.verbosity, opt.log_format)?;
+
+    // a ^C stops the scans cleanly so the outputs are flushed and the temp
+    // files removed before exiting
+    let token = CancellationToken::new();
+    signals::install(&token)?;
+    let metrics = Arc::new(MemoryMetrics::new());
+    let metrics_file = opt.metrics_file.clone();
+    let result = run(opt, &token, &metrics);
+
+    // a failed run is written too so it shows up
+    if let Some(p) = &metrics_file {
+        if let Err(e) = metrics.write_prometheus(p, METRICS_PREFIX) {
+            warn!("failed to write the metrics to {}: {}", p.to_string_lossy(), e);
+        }
+    }
+    match result {
+        Err(Error::Interrupted) => {
+            warn!("interrupted");
+            std::process::exit(signals::INTERRUPTED_STATUS);
+        },
+        result => result
+    }
+}
+
+// the prefix on the names of the metrics written to the metrics file
+const METRICS_PREFIX: &str = "treetool_";
+
+// a scan that stops cleanly on ^C and counts what it does in the metrics
+fn scanner<'a>(token: &CancellationToken, metrics: &Arc<MemoryMetrics>) -> TreeListBuilder<'a> {
+    TreeListBuilder::new().cancel_token(token).metrics(metrics.clone())
+}
+
+// writes an index to a temp file next to the output file and renames it over
+// the file once it is all there, so a write that fails or is cancelled
+// leaves the index that was there. stdout, sockets and anything that isn't
+// a file are written to as they are.
+fn save_index<F>(output: &Option<PathBuf>, write: F) -> Result<()>
+    where F: FnOnce(&mut dyn Write) -> Result<()>
+{
+    match output {
+        Some(p) if socket_path(p).is_none() && (p.is_file() || !p.exists()) => {
+            let mut tmp = TempFile::next_to(p)?;
+            write(&mut tmp)?;
+            tmp.persist(p)
+        },
+        _ => write(&mut writer(output)?)
+    }
+}
+
+fn run(opt: Opt, token: &CancellationToken, metrics: &Arc<MemoryMetrics>) -> Result<()> {
+    match opt.cmd {
+
+        Command::List { fast, format, scan, root, output } => {
+            debug!("listing {} to {}",
+                 dir_name(&root)?.to_string_lossy(),
+                 writer_name(&output)?.to_string_lossy());
+
+            // stream the items as they are digested so other tools can read
+            // them from a pipe as the scan goes
+            let builder = scan.apply(scanner(token, metrics), &root)?.fast(fast);
+            if format == IndexFormat::Ndjson {
+                let mut w = writer(&output)?;
+                let tl = builder.build_each(&mut |item| {
+                    write_ndjson_item(&mut w, item)?;
+                    Ok(w.flush()?)
+                })?;
+                log_scan(&tl, &scan);
+            } else {
+                // create the list from the directory tree
+                let tl = builder.build()?;
+                log_scan(&tl, &scan);
+
+                // output the list
+                tl.write_format(&mut writer(&output)?, format)?;
+            }
+        },
+
+        #[cfg(feature = "sqlite")]
+        Command::Index { sqlite: Some(db), dupes, fast, fail_on_collision, scan, root, .. } => {
+            debug!("indexing {} to {}",
+                 dir_name(&root)?.to_string_lossy(),
+                 db.to_string_lossy());
+
+            // create the index from the directory tree
+            let summary = ScanSummary::new();
+            let ti = TreeIndexBuilder::new()
+                .with_dupes(dupes)
+                .fail_on_collision(fail_on_collision)
+                .from_scan(scan.apply(scanner(token, metrics), &root)?.fast(fast))
+                .scan_summary(&summary)
+                .build()?;
+            log_scan(&summary.list(), &scan);
+
+            // replace what the database held with it
+            SqliteStore::open(&db)?.save(&ti)?;
+        },
+
+        Command::Index { dupes, fast, fail_on_collision, format, scan, spill, memory_limit, spill_dir, root, output,
+                         #[cfg(feature = "sign")] sign_key, .. } => {
+            debug!("indexing {} to {}",
+                 dir_name(&root)?.to_string_lossy(),
+                 writer_name(&output)?.to_string_lossy());
+
+            // create the index from the directory tree
+            let summary = ScanSummary::new();
+            let mut builder = TreeIndexBuilder::new()
+                .with_dupes(dupes)
+                .fail_on_collision(fail_on_collision)
+                .format(format)
+                .from_scan(scan.apply(scanner(token, metrics), &root)?.fast(fast))
+                .scan_summary(&summary);
+            if let Some(budget) = spill {
+                builder = builder.spill(budget);
+            }
+            if let Some(dir) = &spill_dir {
+                builder = builder.spill_dir(dir);
+            }
+            let usage = MemoryUsage::new();
+            if let Some(limit) = memory_limit {
+                builder = builder.memory_limit(limit).memory_usage(&usage);
+            }
+            #[cfg(feature = "sign")]
+            let key = match &sign_key {
+                Some(path) => Some(read_signing_key(&Some(path.clone()))?),
+                None => None
+            };
+            #[cfg(feature = "sign")]
+            if let Some(key) = &key {
+                builder = builder.sign(key);
+            }
+
+            // output the index
+            save_index(&output, |w| builder.build_to(w))?;
+            log_scan(&summary.list(), &scan);
+            if let Some(limit) = memory_limit {
+                info!("the index took about {} of memory at its peak, the limit is {}",
+                      format_bytes(usage.peak(), Units::Binary), format_bytes(limit, Units::Binary));
+            }
+        },
+
+        Command::Match { fast, scan, root, input, output } => {
             debug!("matching {} to {} output to {}",
                  dir_name(&root)?.to_string_lossy(),
                  reader_name(&input)?.to_string_lossy(),
                  writer_name(&output)?.to_string_lossy());
 
-            // read the index from the input source without dupes
-            let mut ti = TreeIndexBuilder::new()
-                .with_dupes(false)
+            // read the index from the input source without dupes
+            let mut ti = index_builder(&opt.input)
+                .with_dupes(false)
+                .from_reader(&mut reader(&input)?)
+                .build()?;
+
+            // fast digests of large files changed in version 2
+            if fast && ti.header.version == 1 {
+                warn!("{} is a version {} index, fast digests of files over 1 MiB won't match",
+                      reader_name(&input)?.to_string_lossy(), ti.header.version);
+            }
+
+            // keyed digests only match with the same key
+            if ti.header.is_keyed() != scan.hash_key.is_some() {
+                return Err(Error::InvalidArgument(
+                    "--hash-key is needed for keyed indexes and only for keyed indexes".to_string()));
+            }
+
+            // hash the files the same way as the index
+            let mut builder = scan.apply(scanner(token, metrics), &root)?
+                .fast(fast)
+                .algorithm(ti.header.algorithm()?)
+                .encoding(ti.header.encoding()?);
+
+            // get the maximum file size and the sizes in the index so we
+            // don't digest files that can't match, and the filter of sizes
+            // and digests so the ones that don't match aren't kept
+            if ti.header.has_sizes() {
+                let max = ti.max();
+                let sizes = ti.sizes();
+                trace!("matching {} distinct sizes", sizes.len());
+                builder = builder
+                    .max_size(scan.max_size.map_or(max, |m| m.min(max)))
+                    .sizes(sizes)
+                    .size_filter(ti.size_filter());
+            }
+
+            // build a list of files in the target tree
+            let tl = builder.build()?;
+            log_scan(&tl, &scan);
+
+            // go through the list and add any dupes to the source_index
+            for i in tl.list {
+                let key = ti.key(&i);
+                if let Some(item) = ti.idx.get_mut(&key) {
+                    item.push_item(&i);
+                }
+            }
+
+            // output the index with dupes
+            save_index(&output, |w| ti.write(w))?;
+        },
+
+        Command::Verify { fast, renames, hash_key, root, input, output } => {
+            debug!("verifying {} against {} output to {}",
+                 dir_name(&root)?.to_string_lossy(),
+                 reader_name(&input)?.to_string_lossy(),
+                 writer_name(&output)?.to_string_lossy());
+
+            // read the index from the input source with dupes
+            let mut ti = index_builder(&opt.input)
+                .with_dupes(true)
+                .from_reader(&mut reader(&input)?)
+                .build()?;
+            if fast {
+                ti.header.set_fast(true);
+            }
+
+            // re-hash the tree and compare
+            let mut diff = match &hash_key {
+                Some(key) => ti.verify_keyed(&dir(&root)?, &key.0)?,
+                None => ti.verify(&dir(&root)?)?
+            };
+            if renames {
+                diff.detect_renames();
+            }
+            info!("{} matched, {} modified, {} renamed, {} missing, {} new", diff.matched.len(),
+                  diff.modified.len(), diff.renamed.len(), diff.missing.len(), diff.added.len());
+
+            // output the changes
+            let mut w = writer(&output)?;
+            write!(w, "{}", diff)?;
+            w.flush()?;
+            if !diff.is_clean() {
+                std::process::exit(1);
+            }
+        },
+
+        Command::Diff { renames, old, new, output } => {
+            debug!("comparing {} to {} output to {}",
+                 old.to_string_lossy(), new.to_string_lossy(),
+                 writer_name(&output)?.to_string_lossy());
+
+            let old_ti = index_builder(&opt.input)
+                .with_dupes(true)
+                .from_reader(&mut reader(&Some(old))?)
+                .build()?;
+            let new_ti = index_builder(&opt.input)
+                .with_dupes(true)
+                .from_reader(&mut reader(&Some(new))?)
+                .build()?;
+            let mut diff = TreeIndexDiff::between(&old_ti, &new_ti);
+            if renames {
+                diff.detect_renames();
+            }
+            info!("{} matched, {} modified, {} renamed, {} missing, {} new", diff.matched.len(),
+                  diff.modified.len(), diff.renamed.len(), diff.missing.len(), diff.added.len());
+
+            let mut w = writer(&output)?;
+            write!(w, "{}", diff)?;
+            w.flush()?;
+            if !diff.is_clean() {
+                std::process::exit(1);
+            }
+        },
+
+        #[cfg(feature = "sign")]
+        Command::Keygen { secret, public } => {
+            debug!("generating a key pair to {} and {}",
+                 secret.to_string_lossy(),
+                 writer_name(&public)?.to_string_lossy());
+
+            // the secret key is only readable by the owner
+            let key = generate_key();
+            let mut options = std::fs::OpenOptions::new();
+            options.write(true).create_new(true);
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::OpenOptionsExt;
+                options.mode(0o600);
+            }
+            writeln!(options.open(&secret)?, "{}", key_hex(&key.to_bytes()))?;
+
+            // output the public key
+            writeln!(writer(&public)?, "{}", key_hex(key.verifying_key().as_bytes()))?;
+        },
+
+        #[cfg(feature = "sign")]
+        Command::Checksig { key, input } => {
+            debug!("checking the signature on {}",
+                 reader_name(&input)?.to_string_lossy());
+
+            // read the index, the build fails if the signature doesn't verify
+            let result = index_builder(&opt.input)
+                .with_dupes(true)
+                .from_reader(&mut reader(&input)?)
+                .verify_signature(read_verifying_key(&Some(key))?)
+                .build();
+            if let Err(e) = result {
+                error!("{}: {}", reader_name(&input)?.to_string_lossy(), e);
+                std::process::exit(1);
+            }
+            info!("{} is signed by the key", reader_name(&input)?.to_string_lossy());
+        },
+
+        Command::Export { format, input, output } => {
+            debug!("exporting {} as {} checksums to {}",
+                 reader_name(&input)?.to_string_lossy(),
+                 format,
+                 writer_name(&output)?.to_string_lossy());
+
+            // read the index from the input source with dupes
+            let ti = index_builder(&opt.input)
+                .with_dupes(true)
+                .from_reader(&mut reader(&input)?)
+                .build()?;
+
+            // output the checksums
+            ti.write_checksums(&mut writer(&output)?, format)?;
+        },
+
+        Command::Import { dupes, algo, input, output } => {
+            debug!("importing checksums from {} to {}",
+                 reader_name(&input)?.to_string_lossy(),
+                 writer_name(&output)?.to_string_lossy());
+
+            // read the checksums from the input source
+            let ti = index_builder(&opt.input)
+                .with_dupes(dupes)
+                .from_checksums(&mut reader(&input)?, algo)
+                .build()?;
+            if !ti.header.has_sizes() {
+                warn!("some files in {} don't exist, their sizes are unknown",
+                      reader_name(&input)?.to_string_lossy());
+            }
+
+            // output the index
+            save_index(&output, |w| ti.write(w))?;
+        },
+
+        Command::Confirm { threads, prefilter, mode, input, output } => {
+            debug!("confirming {}, output to {}",
+                 reader_name(&input)?.to_string_lossy(),
+                 writer_name(&output)?.to_string_lossy());
+
+            // read the index from the input source with dupes
+            let ti = index_builder(&opt.input)
+                .with_dupes(true)
+                .from_reader(&mut reader(&input)?)
+                .build()?;
+
+            // create new index by confirming old index
+            let cti = TreeIndexBuilder::new()
+                .confirm(&ti)
+                .threads(threads)
+                .prefilter(prefilter)
+                .confirm_mode(mode)
+                .build()?;
+            info!("confirmed {}", cti.stats);
+
+            // output the index with dupes
+            save_index(&output, |w| cti.write(w))?;
+        },
+
+        Command::Bench { dir, size, files, threads, output } => {
+            debug!("benchmarking hashing on {} in {}", format_bytes(size, Units::Binary), dir.to_string_lossy());
+            if files == 0 {
+                return Err(Error::InvalidArgument("the tree needs at least one file".to_string()));
+            }
+            let tree = SyntheticTreeBuilder::new()
+                .in_dir(&dir)
+                .files(files)
+                .file_size(size / files as u64)
+                .build()?;
+            info!("generated {} files, {} in {}", tree.files(), format_bytes(tree.bytes(), Units::Binary),
+                  tree.path().to_string_lossy());
+            let mut w = writer(&output)?;
+            for mode in HashMode::all() {
+                token.check()?;
+                writeln!(w, "{}", bench_hashing(tree.path(), mode, threads)?)?;
+            }
+        },
+
+        Command::Zeroes { input, output } => {
+            debug!("removing zero length items from {}, output to {}",
+                 reader_name(&input)?.to_string_lossy(),
+                 writer_name(&output)?.to_string_lossy());
+
+            // read the index from the input source with dupes
+            let ti = index_builder(&opt.input)
+                .with_dupes(true)
+                .from_reader(&mut reader(&input)?)
+                .build()?;
+
+            // keep anything with a size > 0
+            let mut index = TreeIndex { header: ti.header.clone(), ..Default::default() };
+            for (key, item) in ti.idx.iter() {
+                if item.item.size > 0 {
+                    trace!("{}", item.item.path().to_string_lossy());
+                    index.idx.insert(key.clone(), item.clone());
+                }
+            }
+
+            // output the index with dupes
+            save_index(&output, |w| index.write(w))?;
+        },
+
+        Command::Migrate { fast, root, format, input, output } => {
+            debug!("migrating {} to {}",
+                 reader_name(&input)?.to_string_lossy(),
+                 writer_name(&output)?.to_string_lossy());
+
+            // read the index from the input source with dupes
+            let mut ti = index_builder(&opt.input)
+                .with_dupes(true)
+                .from_reader(&mut reader(&input)?)
+                .build()?;
+
+            if ti.header.is_current() {
+                info!("{} is already version {}", reader_name(&input)?.to_string_lossy(), INDEX_VERSION);
+            } else if ti.header.version == 1 {
+                let digested = ti.upgrade_from_v1(&dir(&root)?, fast)?;
+                info!("upgraded to version {}, digested {} files again", INDEX_VERSION, digested);
+            } else {
+                // only the way the paths are written changed since
+                ti.header.set_escaped(true);
+                info!("upgraded to version {}", INDEX_VERSION);
+            }
+
+            // output the upgraded index
+            save_index(&output, |w| ti.write_format(w, format))?;
+        },
+
+        Command::PruneEmpty { dry_run, protect, root, output } => {
+            debug!("pruning empty dirs under {}, logging to {}",
+                 dir_name(&root)?.to_string_lossy(),
+                 writer_name(&output)?.to_string_lossy());
+
+            let root = dir(&root)?;
+            let mut planner = DedupPlannerBuilder::new();
+            for p in &protect {
+                planner = planner.protect(p);
+            }
+            let planner = planner.build()?;
+            let mut log = ActionLog::new(writer(&output)?);
+            let result = if dry_run { ActionResult::DryRun } else { ActionResult::Done };
+            let is_protected = |d: &Path| root.starts_with(d) || planner.is_protected_dir(d);
+            for d in prune_empty_dirs(subdirs(&root)?, &is_protected, dry_run)? {
+                log.record(&ActionRecord::new(Action::Rmdir, &d, result))?;
+            }
+        },
+
+        Command::Undo { dry_run, preserve, hash_key, input, output } => {
+            debug!("undoing the actions in {}, logging to {}",
+                 reader_name(&input)?.to_string_lossy(),
+                 writer_name(&output)?.to_string_lossy());
+
+            // the last action is undone first so directories are put back
+            // before the files that go in them
+            let (header, records) = read_action_log_with_header(reader(&input)?)?;
+            let mut log = ActionLog::new(WriterBuilder::new(&output).sync(opt.sync).build()?);
+            if let Some(h) = &header {
+                log.header(h)?;
+            }
+            let key = hash_key.as_ref().map(|k| k.0.as_slice());
+            let mut failed = 0;
+            for r in records.iter().rev() {
+                let undo = match r.undo() {
+                    Some(undo) => undo,
+                    None => {
+                        if r.action == Action::Delete && r.result == ActionResult::Done {
+                            warn!("{} was deleted, it can't be put back", r.src.to_string_lossy());
+                        }
+                        continue;
+                    }
+                };
+                if dry_run {
+                    log.record(&ActionRecord { result: ActionResult::DryRun, ..undo })?;
+                    continue;
+                }
+                match apply_action(&undo, header.as_ref(), key, &preserve) {
+                    Ok(()) => log.record(&undo)?,
+                    Err(e) => {
+                        error!("failed to undo the {} of {}: {}", r.action, r.src.to_string_lossy(), e);
+                        log.record(&ActionRecord { result: ActionResult::Failed, ..undo })?;
+                        failed += 1;
+                    }
+                }
+            }
+            if failed > 0 {
+                std::process::exit(1);
+            }
+        },
+
+        Command::Stats { json, top, input, output } => {
+            debug!("reporting stats of {} to {}",
+                 reader_name(&input)?.to_string_lossy(),
+                 writer_name(&output)?.to_string_lossy());
+
+            // read the index from the input source with dupes
+            let ti = index_builder(&opt.input)
+                .with_dupes(true)
                 .from_reader(&mut reader(&input)?)
                 .build()?;
+            trace!("loaded {} items with {} dupes in the index",
+                   ti.idx.len(), ti.count_dupes());
+
+            // output the stats
+            let stats = ti.report(top);
+            let mut w = writer(&output)?;
+            if json {
+                writeln!(w, "{}", stats.to_json())?;
+            } else {
+                write!(w, "{}", stats)?;
+            }
+        },
 
-            // get the maximum file size so we don't digest files that can't match
-            let max = ti.max();
+        Command::Digest { fast, algo, name, input, output } => {
+            debug!("digesting {} as {}, appending to {}",
+                 reader_name(&input)?.to_string_lossy(), name,
+                 writer_name(&output)?.to_string_lossy());
 
-            // build a list of files in the target tree
-            let tl = TreeListBuilder::new()
+            let mut r = reader(&input)?;
+            let item = TreeItemBuilder::new()
                 .fast(fast)
-                .max_size(max)
-                .path(&dir(&root)?)
+                .algorithm(algo)
+                .from_reader(&name, &mut r)
                 .build()?;
+            let mut w = appender(&output)?;
+            write!(w, "{}", item)?;
+        },
 
-            // go through the list and add any dupes to the source_index
-            for i in tl.list {
-                match ti.idx.get_mut(&i.digest) {
-                    Some(item) => {
-                        item.push(i.path.clone());
-                    },
-                    _ => {}
+        Command::Serve { fast, listen, scan, root } => {
+            debug!("serving {} to {}",
+                 dir_name(&root)?.to_string_lossy(),
+                 listen.as_deref().unwrap_or("stdout"));
+
+            let builder = || Ok(scan.apply(scanner(token, metrics), &root)?.fast(fast));
+            match listen {
+                Some(addr) => serve_tcp(&TcpListener::bind(addr)?, builder)?,
+                None => {
+                    let stats = serve(builder()?, &mut std::io::stdout().lock())?;
+                    debug!("sent {} files", stats.files);
                 }
             }
-
-            // output the index with dupes
-            let mut w = writer(&output)?;
-            for item in ti.idx.into_values() {
-                write!(w, "{}", item)?;
-            }
         },
 
-        Command::Confirm { input, output } => {
-            debug!("confirming {}, output to {}",
+        Command::Remote { dupes, format, input, output } => {
+            debug!("reading remote scan from {}, output to {}",
                  reader_name(&input)?.to_string_lossy(),
                  writer_name(&output)?.to_string_lossy());
 
-            // read the index from the input source with dupes
+            let mut r = match input.as_ref().and_then(|p| p.to_str()).and_then(|p| p.strip_prefix("tcp://")) {
+                Some(addr) => Box::new(TcpStream::connect(addr)?) as Box<dyn Read>,
+                None => reader(&input)?
+            };
             let ti = TreeIndexBuilder::new()
-                .with_dupes(true)
-                .from_reader(&mut reader(&input)?)
+                .with_dupes(dupes)
+                .from_remote(&mut r)
                 .build()?;
+            info!("{} files in {} dirs, {} scanned remotely", ti.stats.files, ti.stats.dirs,
+                  format_bytes(ti.stats.bytes, Units::Binary));
+            save_index(&output, |w| ti.write_format(w, format))?;
+        },
 
-            // create new index by confirming old index
-            let cti = TreeIndexBuilder::new()
-                .confirm(&ti)
-                .build()?;
+        #[cfg(feature = "s3")]
+        Command::S3 { dupes, fast, algo, format, endpoint, region, prefix, bucket, output } => {
+            debug!("indexing bucket {} to {}", bucket, writer_name(&output)?.to_string_lossy());
 
-            // output the index with dupes
-            let mut w = writer(&output)?;
-            for item in cti.idx.into_values() {
-                write!(w, "{}", item)?;
+            let mut source = S3SourceBuilder::new(&bucket)
+                .region(&region)
+                .fast(fast)
+                .algorithm(algo);
+            if let Some(e) = &endpoint {
+                source = source.endpoint(e);
+            }
+            if let Some(p) = &prefix {
+                source = source.prefix(p);
+            }
+            if let (Ok(access), Ok(secret)) = (std::env::var("AWS_ACCESS_KEY_ID"), std::env::var("AWS_SECRET_ACCESS_KEY")) {
+                source = source.credentials(&access, &secret);
+                if let Ok(token) = std::env::var("AWS_SESSION_TOKEN") {
+                    source = source.session_token(&token);
+                }
             }
+            let tl = source.build()?;
+            info!("{} objects, {}", tl.stats.files, format_bytes(tl.stats.bytes, Units::Binary));
+            let ti = TreeIndexBuilder::new()
+                .with_dupes(dupes)
+                .from_list(&tl)
+                .build()?;
+            save_index(&output, |w| ti.write_format(w, format))?;
         },
 
-        Command::Zeroes { input, output } => {
-            debug!("removing zero length items from {}, output to {}",
+        Command::Lookup { prefix, input, output } => {
+            debug!("looking up {} in {}, output to {}", prefix,
                  reader_name(&input)?.to_string_lossy(),
                  writer_name(&output)?.to_string_lossy());
 
             // read the index from the input source with dupes
-            let ti = TreeIndexBuilder::new()
+            let ti = index_builder(&opt.input)
                 .with_dupes(true)
                 .from_reader(&mut reader(&input)?)
                 .build()?;
 
-            // keep anything with a size > 0
-            let mut index = TreeIndexBuilder::new().build()?;
-            for (digest, item) in ti.idx.iter() {
-                if item.item.size > 0 {
-                    trace!("{}", item.item.path.to_string_lossy());
-                    index.idx.insert(digest.clone(), item.clone());
+            // output the matching items in the index format
+            let found = ti.find_prefix(&prefix);
+            debug!("{} items match {}", found.len(), prefix);
+            let mut w = writer(&output)?;
+            for entry in found {
+                write!(w, "{}", entry)?;
+            }
+        },
+
+        #[cfg(feature = "watch")]
+        Command::Watch { watch, index, root } => {
+            debug!("watching {}, appending to {}",
+                 dir_name(&root)?.to_string_lossy(),
+                 index.to_string_lossy());
+
+            let mut watcher = watch.watch(&opt.input, token, metrics, &index, &dir(&root)?)?;
+
+            // append the updates as they happen
+            let mut w = appender(&Some(index))?;
+            loop {
+                for update in watcher.wait()? {
+                    info!("{}", update.to_string().trim_end());
+                    write!(w, "{}", update)?;
+                }
+                w.flush()?;
+                if let Some(p) = &opt.metrics_file {
+                    metrics.write_prometheus(p, METRICS_PREFIX)?;
                 }
             }
+        },
 
-            // output the index with dupes
-            let mut w = writer(&output)?;
-            for item in index.idx.into_values() {
-                write!(w, "{}", item)?;
+        #[cfg(all(feature = "service", unix))]
+        Command::Daemon { watch, socket, index, root } => {
+            debug!("watching {}, appending to {}, listening on {}",
+                 dir_name(&root)?.to_string_lossy(),
+                 index.to_string_lossy(),
+                 socket.to_string_lossy());
+
+            let service = IndexService::new(watch.watch(&opt.input, token, metrics, &index, &dir(&root)?)?);
+            // the socket file goes when this returns, whatever stops it
+            let socket = bind_socket(&socket)?;
+            info!("listening on {}", socket.path().to_string_lossy());
+            let listener = socket.listener().try_clone()?;
+            let clients = service.clone();
+            std::thread::spawn(move || {
+                if let Err(e) = clients.serve(&listener) {
+                    error!("stopped listening: {:?}", e);
+                }
+            });
+
+            // the index file is kept up to date too so a restart is quick
+            let mut w = appender(&Some(index))?;
+            loop {
+                for update in service.wait()? {
+                    info!("{}", update.to_string().trim_end());
+                    write!(w, "{}", update)?;
+                }
+                w.flush()?;
+                if let Some(p) = &opt.metrics_file {
+                    metrics.write_prometheus(p, METRICS_PREFIX)?;
+                }
+            }
+        },
+
+        Command::Sync { cmd } => {
+            match cmd {
+                SyncCommand::Plan { content_addressed, source_root, dest_root, source, dest, output } => {
+                    debug!("planning sync from {} to {}, output to {}",
+                         source.to_string_lossy(), dest.to_string_lossy(),
+                         writer_name(&output)?.to_string_lossy());
+
+                    let source_ti = index_builder(&opt.input)
+                        .with_dupes(true)
+                        .from_reader(&mut reader(&Some(source))?)
+                        .build()?;
+                    let dest_ti = index_builder(&opt.input)
+                        .with_dupes(true)
+                        .from_reader(&mut reader(&Some(dest))?)
+                        .build()?;
+
+                    let mut planner = SyncPlannerBuilder::new().content_addressed(content_addressed);
+                    if let Some(r) = &source_root {
+                        planner = planner.source_root(r);
+                    }
+                    if let Some(r) = &dest_root {
+                        planner = planner.dest_root(r);
+                    }
+                    let plan = planner.build()?.plan(&source_ti, &dest_ti)?;
+                    info!("{} steps, {} to copy", plan.ops.len(), format_bytes(plan.copy_bytes(), Units::Binary));
+                    let mut w = WriterBuilder::new(&output).sync(opt.sync).build()?;
+                    write!(w, "{}", plan)?;
+                    w.flush()?;
+                }
             }
         },
 
@@ -371,7 +2292,7 @@ fn main() -> Result<()> {
                          writer_name(&output)?.to_string_lossy());
 
                     // read the needles from the input source without dupes
-                    let needle_ti = TreeIndexBuilder::new()
+                    let needle_ti = index_builder(&opt.input)
                         .with_dupes(false)
                         .from_reader(&mut reader(&needle)?)
                         .build()?;
@@ -379,43 +2300,37 @@ fn main() -> Result<()> {
                            needle_ti.idx.len(), needle_ti.count_dupes());
 
                     // read the haystack from the input source with dupes
-                    let haystack_ti = TreeIndexBuilder::new()
+                    let haystack_ti = index_builder(&opt.input)
                         .with_dupes(true)
                         .from_reader(&mut reader(&haystack)?)
                         .build()?;
                     trace!("loaded {} items with {} dupes in the needle",
                            haystack_ti.idx.len(), haystack_ti.count_dupes());
 
-                    let mut index = TreeIndexBuilder::new().build()?;
-                    for (digest, needle_item) in needle_ti.idx.iter() {
-                        match haystack_ti.idx.get(digest) {
-                            Some(haystack_item) => {
-                                if needle_item.item.path != haystack_item.item.path {
-                                    trace!("adding {} to {}",
-                                           haystack_item.item.path.to_string_lossy(),
-                                           needle_item.item.path.to_string_lossy());
-                                    let mut item = needle_item.clone();
-                                    item.dupes.push(haystack_item.item.path.clone());
-                                    for i in haystack_item.dupes.iter() {
-                                        if item.item.path != *i {
-                                            trace!("adding {} to {}",
-                                                   i.to_string_lossy(),
-                                                   needle_item.item.path.to_string_lossy());
-                                            item.dupes.push(i.clone());
-                                        }
+                    let mut index = TreeIndex { header: needle_ti.header.clone(), ..Default::default() };
+                    for (key, needle_item) in needle_ti.idx.iter() {
+                        if let Some(haystack_item) = haystack_ti.idx.get(key) {
+                            if needle_item.item.item_path() != haystack_item.item.item_path() {
+                                trace!("adding {} to {}",
+                                       haystack_item.item.path().to_string_lossy(),
+                                       needle_item.item.path().to_string_lossy());
+                                let mut item = needle_item.clone();
+                                item.dupes.push(haystack_item.item.item_path().clone());
+                                for i in haystack_item.dupes.iter() {
+                                    if item.item.item_path() != i {
+                                        trace!("adding {} to {}",
+                                               i.path().to_string_lossy(),
+                                               needle_item.item.path().to_string_lossy());
+                                        item.dupes.push(i.clone());
                                     }
-                                    index.idx.insert(digest.clone(), item);
                                 }
-                            },
-                            None => {}
+                                index.idx.insert(key.clone(), item);
+                            }
                         }
                     }
 
                     // output the index
-                    let mut w = writer(&output)?;
-                    for item in index.idx.into_values() {
-                        write!(w, "{}", item)?;
-                    }
+                    save_index(&output, |w| index.write(w))?;
                 },
 
                 DupesCommand::ListDirs { input, output } => {
@@ -424,7 +2339,7 @@ fn main() -> Result<()> {
                            writer_name(&output)?.to_string_lossy());
 
                     // read the index from the input source with dupes
-                    let ti = TreeIndexBuilder::new()
+                    let ti = index_builder(&opt.input)
                         .with_dupes(true)
                         .from_reader(&mut reader(&input)?)
                         .build()?;
@@ -435,7 +2350,7 @@ fn main() -> Result<()> {
                     let mut set = HashSet::new();
                     for (_, i) in ti.idx {
                         for d in i.dupes {
-                            if let Some(p) = d.parent() {
+                            if let Some(p) = d.path().parent() {
                                 let pb = PathBuf::from(p);
                                 set.insert(pb.clone());
                             }
@@ -450,53 +2365,225 @@ fn main() -> Result<()> {
                     }
                 },
 
-                DupesCommand::Size { input, output } => {
+                DupesCommand::Dirs { input, output } => {
+                    debug!("finding dupe dirs in {} to {}",
+                           reader_name(&input)?.to_string_lossy(),
+                           writer_name(&output)?.to_string_lossy());
+
+                    // read the index from the input source with dupes
+                    let ti = index_builder(&opt.input)
+                        .with_dupes(true)
+                        .from_reader(&mut reader(&input)?)
+                        .build()?;
+                    trace!("loaded {} items with {} dupes in the index",
+                           ti.idx.len(), ti.count_dupes());
+
+                    // digest every directory and group the matching ones
+                    let di = DirIndex::from(&ti);
+                    let dupes = di.dupes();
+                    trace!("found {} groups of dupe dirs in {} dirs", dupes.len(), di.dirs.len());
+
+                    // output the groups in the index format, largest first
+                    let mut w = writer(&output)?;
+                    write!(w, "{}", ti.header.for_text())?;
+                    for d in &dupes {
+                        write!(w, "{}", d)?;
+                    }
+                },
+
+                DupesCommand::Pairs { input, output } => {
+                    debug!("pairing dupe dirs in {} to {}",
+                           reader_name(&input)?.to_string_lossy(),
+                           writer_name(&output)?.to_string_lossy());
+
+                    // read the index from the input source with dupes
+                    let ti = index_builder(&opt.input)
+                        .with_dupes(true)
+                        .from_reader(&mut reader(&input)?)
+                        .build()?;
+                    trace!("loaded {} items with {} dupes in the index",
+                           ti.idx.len(), ti.count_dupes());
+
+                    // output the pairs, most bytes first
+                    let mut w = writer(&output)?;
+                    for p in dir_pairs(&ti) {
+                        write!(w, "{}", p)?;
+                    }
+                },
+
+                DupesCommand::Similar { min, input, output } => {
+                    debug!("scoring similar dirs in {} to {}",
+                           reader_name(&input)?.to_string_lossy(),
+                           writer_name(&output)?.to_string_lossy());
+
+                    // read the index from the input source with dupes
+                    let ti = index_builder(&opt.input)
+                        .with_dupes(true)
+                        .from_reader(&mut reader(&input)?)
+                        .build()?;
+                    trace!("loaded {} items with {} dupes in the index",
+                           ti.idx.len(), ti.count_dupes());
+
+                    // output the pairs, best first
+                    let mut w = writer(&output)?;
+                    for s in dir_similarity(&ti, min / 100.0) {
+                        writeln!(w, "{} ({} of {})", s.to_string().trim_end(),
+                                 format_bytes(s.shared, Units::Binary),
+                                 format_bytes(s.size, Units::Binary))?;
+                    }
+                },
+
+                DupesCommand::Partial { min_shared, input, output } => {
+                    debug!("finding files sharing chunks in {} to {}",
+                           reader_name(&input)?.to_string_lossy(),
+                           writer_name(&output)?.to_string_lossy());
+
+                    let ti = index_builder(&opt.input)
+                        .from_reader(&mut reader(&input)?)
+                        .build()?;
+                    if ti.header.chunking()?.is_none() {
+                        warn!("the index has no chunk digests, scan with --cdc");
+                    }
+
+                    // output the pairs, most similar first
+                    let mut w = writer(&output)?;
+                    for s in similar_files(&ti, min_shared / 100.0) {
+                        writeln!(w, "{} ({})", s.to_string().trim_end(),
+                                 format_bytes(s.shared, Units::Binary))?;
+                    }
+                },
+
+                DupesCommand::Fuzzy { rules, input, output } => {
+                    debug!("grouping near duplicates in {} to {}",
+                           reader_name(&input)?.to_string_lossy(),
+                           writer_name(&output)?.to_string_lossy());
+
+                    let ti = index_builder(&opt.input)
+                        .from_reader(&mut reader(&input)?)
+                        .build()?;
+                    if !ti.header.has_fuzzy() {
+                        warn!("the index has no fuzzy digests, scan with --fuzzy");
+                    }
+
+                    // output the groups, biggest first
+                    let mut w = writer(&output)?;
+                    for g in rules.planner()?.near_dupes(&ti) {
+                        write!(w, "{}", g)?;
+                    }
+                },
+
+                DupesCommand::SimilarImages { distance, input, output } => {
+                    debug!("grouping similar images in {} to {}",
+                           reader_name(&input)?.to_string_lossy(),
+                           writer_name(&output)?.to_string_lossy());
+
+                    let ti = index_builder(&opt.input)
+                        .from_reader(&mut reader(&input)?)
+                        .build()?;
+                    if ti.header.image_hashes()?.is_none() {
+                        warn!("the index has no image hashes, scan with --image-hash");
+                    }
+
+                    // output the groups, biggest first
+                    let mut w = writer(&output)?;
+                    for g in similar_images(&ti, distance) {
+                        write!(w, "{}", g)?;
+                    }
+                },
+
+                DupesCommand::Fdupes { rules, size, input, output } => {
+                    debug!("writing dupe groups in {} to {}",
+                           reader_name(&input)?.to_string_lossy(),
+                           writer_name(&output)?.to_string_lossy());
+
+                    // read the index from the input source with dupes
+                    let ti = index_builder(&opt.input)
+                        .with_dupes(true)
+                        .from_reader(&mut reader(&input)?)
+                        .build()?;
+                    trace!("loaded {} items with {} dupes in the index",
+                           ti.idx.len(), ti.count_dupes());
+                    let ti = rules.apply(&ti)?;
+
+                    // output the groups
+                    ti.write_fdupes(&mut writer(&output)?, size)?;
+                },
+
+                DupesCommand::Size { rules, json, dirs, size_mode, reflinks, input, output } => {
                     debug!("summing size of dups in {} to {}",
                            reader_name(&input)?.to_string_lossy(),
                            writer_name(&output)?.to_string_lossy());
 
                     // read the index from the input source with dupes
-                    let ti = TreeIndexBuilder::new()
+                    let ti = index_builder(&opt.input)
                         .with_dupes(true)
                         .from_reader(&mut reader(&input)?)
                         .build()?;
                     trace!("loaded {} items with {} dupes in the index",
                            ti.idx.len(), ti.count_dupes());
+                    let ti = rules.apply(&ti)?;
 
                     // sum up the size of all of the dupes
-                    let mut size = 0u64;
-                    for (_, i) in ti.idx {
-                        let dupe_size = i.item.size * i.dupes.len() as u64;
-                        trace!("{} saved {}", dupe_size, i.item.path.to_string_lossy());
-                        size += dupe_size;
+                    let mut report = SavingsReport::from(&ti).mode(size_mode);
+                    if reflinks {
+                        report = report.probe_shared(&ti);
                     }
 
-                    // output the list
+                    // output the totals
                     let mut w = writer(&output)?;
-                    if size > (1024 * 1024 * 1024) {
-                        writeln!(w, "Total saved {} GB", size >> 30)?;
-                    } else if size > (1024 * 1024) {
-                        writeln!(w, "Total saved {} MB", size >> 20)?;
-                    } else if size > (1024) {
-                        writeln!(w, "Total saved {} KB", size >> 10)?;
+                    if json {
+                        writeln!(w, "{}", report.to_json())?;
                     } else {
-                        writeln!(w, "Total saved {} Bytes", size)?;
+                        write!(w, "{}", report)?;
+                        if dirs {
+                            for (d, s) in report.largest_dirs() {
+                                let bytes = s.saved(size_mode);
+                                writeln!(w, "{} bytes ({}) in {} files {}", bytes,
+                                         format_bytes(bytes, Units::Binary), s.files, d.to_string_lossy())?;
+                            }
+                        }
                     }
                 },
 
-                DupesCommand::CopyFiles { dry_run, input, dest, output } => {
+                DupesCommand::Report { rules, format, color, input, output } => {
+                    debug!("reporting dupes in {} to {}",
+                           reader_name(&input)?.to_string_lossy(),
+                           writer_name(&output)?.to_string_lossy());
+
+                    // read the index from the input source with dupes
+                    let ti = index_builder(&opt.input)
+                        .with_dupes(true)
+                        .from_reader(&mut reader(&input)?)
+                        .build()?;
+                    trace!("loaded {} items with {} dupes in the index",
+                           ti.idx.len(), ti.count_dupes());
+                    let ti = rules.apply(&ti)?;
+
+                    // only truncate when writing text to the terminal
+                    let width = match output {
+                        Some(_) => None,
+                        None => terminal_width()
+                    };
+
+                    // output the report, the biggest savings first
+                    let mut w = color_writer(&output, color)?;
+                    DupeReport::from(&ti).render(&mut w, format, width)?;
+                },
+
+                DupesCommand::CopyFiles { rules, dry_run, script, preserve_paths, verify, preserve, retries, hash_key, input, dest, output } => {
                     debug!("copy dupe files in {} to {}, logging to {}",
                          reader_name(&input)?.to_string_lossy(),
                          dir(&dest)?.to_string_lossy(),
                          writer_name(&output)?.to_string_lossy());
 
                     // read the index from the input source with dupes
-                    let ti = TreeIndexBuilder::new()
+                    let ti = index_builder(&opt.input)
                         .with_dupes(true)
                         .from_reader(&mut reader(&input)?)
                         .build()?;
                     trace!("loaded {} items with {} dupes in the index",
                            ti.idx.len(), ti.count_dupes());
+                    let ti = rules.apply(&ti)?;
 
                     let destd = dir(&dest)?;
                     trace!("is_dir == {}", destd.is_dir());
@@ -504,49 +2591,232 @@ fn main() -> Result<()> {
                         Some(f) => trace!("filename == {}", f.to_string_lossy()),
                         None => trace!("no file name")
                     }
-                    let mut w = writer(&output)?;
-                    for (digest, i) in ti.idx {
-                        for d in i.dupes {
+                    let layout = if preserve_paths { CopyLayout::PreservePaths } else { CopyLayout::Flat };
+                    let mut targets = CopyTargets::new(&destd, layout, &ti);
+                    let mut builder = script.apply(DedupExecutorBuilder::new().metrics(metrics.clone()), dry_run)?
+                        .log(WriterBuilder::new(&output).sync(opt.sync).build()?)
+                        .header(&ti.header)
+                        .verify(verify)
+                        .retries(retries)
+                        .preserve(preserve)
+                        .apple_double(rules.apple_double);
+                    if let Some(key) = &hash_key {
+                        builder = builder.key(&key.0);
+                    }
+                    let mut x = builder.build()?;
+                    for i in ti.idx.values() {
+                        let digest = i.item.digest.to_string();
+                        for d in &i.dupes {
+                            let d = &d.path();
                             if d.is_file() {
-                                let mut destf = destd.clone();
-                                destf.push(&digest);
-                                let destf = match d.extension() {
-                                    Some(ext) => destf.with_extension(ext),
-                                    None => destf
-                                };
-                                writeln!(w, "cp {} {}", d.to_string_lossy(), destf.to_string_lossy())?;
-                                if !dry_run {
-                                    std::fs::copy(d.as_path(), &destf)?;
-                                }
+                                let destf = targets.target(&digest, d);
+                                x.copy(&digest, i.item.size, d, &destf)?;
+                            }
+                        }
+                    }
+                    if x.finish()? > 0 {
+                        std::process::exit(1);
+                    }
+                },
+
+                DupesCommand::MoveFiles { rules, dry_run, script, preserve_paths, preserve, retries, hash_key, input, dest, output } => {
+                    debug!("move dupe files in {} to {}, logging to {}",
+                         reader_name(&input)?.to_string_lossy(),
+                         dir(&dest)?.to_string_lossy(),
+                         writer_name(&output)?.to_string_lossy());
+
+                    // read the index from the input source with dupes
+                    let ti = index_builder(&opt.input)
+                        .with_dupes(true)
+                        .from_reader(&mut reader(&input)?)
+                        .build()?;
+                    trace!("loaded {} items with {} dupes in the index",
+                           ti.idx.len(), ti.count_dupes());
+                    let planner = rules.planner()?;
+                    let ti = planner.plan(&ti);
+
+                    let layout = if preserve_paths { CopyLayout::PreservePaths } else { CopyLayout::Flat };
+                    let mut targets = CopyTargets::new(&dir(&dest)?, layout, &ti);
+                    let mut builder = script.apply(DedupExecutorBuilder::new().metrics(metrics.clone()), dry_run)?
+                        .log(WriterBuilder::new(&output).sync(opt.sync).build()?)
+                        .header(&ti.header)
+                        .retries(retries)
+                        .preserve(preserve)
+                        .apple_double(rules.apple_double);
+                    if let Some(key) = &hash_key {
+                        builder = builder.key(&key.0);
+                    }
+                    let mut x = builder.build()?;
+                    for i in ti.idx.values() {
+                        let digest = i.item.digest.to_string();
+                        for d in &i.dupes {
+                            let d = &d.path();
+                            if planner.is_protected(d) {
+                                info!("skipping protected {}", d.to_string_lossy());
+                                continue;
+                            }
+                            if d.is_file() {
+                                let destf = targets.target(&digest, d);
+                                x.move_to(&digest, i.item.size, d, &destf)?;
+                            }
+                        }
+                    }
+                    if x.finish()? > 0 {
+                        std::process::exit(1);
+                    }
+                },
+
+                #[cfg(feature = "tui")]
+                DupesCommand::Tui { rules, plan, input } => {
+                    debug!("browsing dupes in {}, plan to {}",
+                           input.to_string_lossy(),
+                           plan.to_string_lossy());
+
+                    // read the index with dupes
+                    let ti = index_builder(&opt.input)
+                        .with_dupes(true)
+                        .from_reader(&mut reader(&Some(input))?)
+                        .build()?;
+                    trace!("loaded {} items with {} dupes in the index",
+                           ti.idx.len(), ti.count_dupes());
+                    let ti = rules.apply(&ti)?;
+
+                    tui::browse(&ti, &plan)?;
+                },
+
+                DupesCommand::FsDedupe { rules, dry_run, input, output } => {
+                    debug!("de-duping files in {} with the kernel, logging to {}",
+                           reader_name(&input)?.to_string_lossy(),
+                           writer_name(&output)?.to_string_lossy());
+
+                    // read the index from the input source with dupes
+                    let ti = index_builder(&opt.input)
+                        .with_dupes(true)
+                        .from_reader(&mut reader(&input)?)
+                        .build()?;
+                    trace!("loaded {} items with {} dupes in the index",
+                           ti.idx.len(), ti.count_dupes());
+                    let planner = rules.planner()?;
+                    let ti = planner.plan(&ti);
+
+                    // the kernel compares the files so a dupe that changed
+                    // since the index was made is only logged
+                    let mode = if dry_run { ExecutionMode::DryRun } else { ExecutionMode::Execute };
+                    let mut x = DedupExecutorBuilder::new().metrics(metrics.clone())
+                        .mode(mode)
+                        .log(WriterBuilder::new(&output).sync(opt.sync).build()?)
+                        .header(&ti.header)
+                        .build()?;
+                    for i in ti.idx.values() {
+                        let digest = i.item.digest.to_string();
+                        for d in &i.dupes {
+                            let d = &d.path();
+                            if planner.is_protected(d) {
+                                info!("skipping protected {}", d.to_string_lossy());
+                                continue;
+                            }
+                            if d.is_file() && i.item.path().is_file() {
+                                x.dedupe(&digest, i.item.size, &i.item.path(), d)?;
+                            }
+                        }
+                    }
+                    let total = x.deduped();
+                    info!("de-duped {} ({} bytes)", format_bytes(total, Units::Binary), total);
+                    if x.finish()? > 0 {
+                        std::process::exit(1);
+                    }
+                },
+
+                DupesCommand::Hardlink { rules, dry_run, script, verify, hash_key, input, output } => {
+                    debug!("linking dupe files in {}, logging to {}",
+                           reader_name(&input)?.to_string_lossy(),
+                           writer_name(&output)?.to_string_lossy());
+
+                    // read the index from the input source with dupes
+                    let ti = index_builder(&opt.input)
+                        .with_dupes(true)
+                        .from_reader(&mut reader(&input)?)
+                        .build()?;
+                    trace!("loaded {} items with {} dupes in the index",
+                           ti.idx.len(), ti.count_dupes());
+                    let planner = rules.planner()?;
+                    let ti = planner.plan(&ti);
+
+                    let mut builder = script.apply(DedupExecutorBuilder::new().metrics(metrics.clone()), dry_run)?
+                        .log(WriterBuilder::new(&output).sync(opt.sync).build()?)
+                        .header(&ti.header)
+                        .verify(verify);
+                    if let Some(key) = &hash_key {
+                        builder = builder.key(&key.0);
+                    }
+                    let mut x = builder.build()?;
+                    for i in ti.idx.values() {
+                        let digest = i.item.digest.to_string();
+                        for d in &i.dupes {
+                            let d = &d.path();
+                            if planner.is_protected(d) {
+                                info!("skipping protected {}", d.to_string_lossy());
+                                continue;
+                            }
+                            if d.is_file() && i.item.path().is_file() {
+                                x.hardlink(&digest, i.item.size, &i.item.path(), d)?;
                             }
                         }
                     }
+                    if x.finish()? > 0 {
+                        std::process::exit(1);
+                    }
                 },
 
-                DupesCommand::DeleteFiles { dry_run, input, output } => {
+                DupesCommand::DeleteFiles { rules, dry_run, script, prune_empty, input, output } => {
                     trace!("deleting dupe files in {}, logging to {}",
                          reader_name(&input)?.to_string_lossy(),
                          writer_name(&output)?.to_string_lossy());
 
                     // read the index from the input source with dupes
-                    let ti = TreeIndexBuilder::new()
+                    let ti = index_builder(&opt.input)
                         .with_dupes(true)
                         .from_reader(&mut reader(&input)?)
                         .build()?;
                     trace!("loaded {} items with {} dupes in the index",
                            ti.idx.len(), ti.count_dupes());
+                    let planner = rules.planner()?;
+                    let ti = planner.plan(&ti);
 
-                    let mut w = writer(&output)?;
-                    for (_, i) in ti.idx {
-                        for d in i.dupes {
+                    let mut x = script.apply(DedupExecutorBuilder::new().metrics(metrics.clone()), dry_run)?
+                        .log(WriterBuilder::new(&output).sync(opt.sync).build()?)
+                        .header(&ti.header)
+                        .apple_double(rules.apple_double)
+                        .build()?;
+                    let mut emptied = Vec::new();
+                    for i in ti.idx.values() {
+                        let digest = i.item.digest.to_string();
+                        for d in &i.dupes {
+                            let d = &d.path();
+                            if planner.is_protected(d) {
+                                info!("skipping protected {}", d.to_string_lossy());
+                                continue;
+                            }
                             if d.is_file() {
-                                writeln!(w, "rm {}", d.to_string_lossy())?;
-                                if !dry_run {
-                                    std::fs::remove_file(d.as_path())?;
+                                x.delete(&digest, i.item.size, d)?;
+                                if let Some(parent) = d.parent() {
+                                    emptied.push(parent.to_path_buf());
                                 }
                             }
                         }
                     }
+
+                    // remove the directories left empty but nothing above
+                    // the tree the index covers
+                    if prune_empty {
+                        let root = common_root(ti.idx.values()
+                            .flat_map(|v| std::iter::once(v.item.item_path()).chain(v.dupes.iter()))
+                            .map(|p| p.path()));
+                        x.prune(emptied, &|d| root.starts_with(d) || planner.is_protected_dir(d))?;
+                    }
+                    if x.finish()? > 0 {
+                        std::process::exit(1);
+                    }
                 }
             }
         }
@@ -0,0 +1,281 @@
+use best_practices::{
+    fs::{FileMetadata, ItemPath, TreeIndex, TreeIndexHeader, TreeItemDupes},
+    cli::report::DupeReport,
+    units::{format_bytes, Units},
+    Result,
+};
+use ratatui::{
+    backend::CrosstermBackend,
+    crossterm::{
+        event::{self, Event, KeyCode, KeyEventKind},
+        execute,
+        terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+    },
+    layout::{Constraint, Direction, Layout},
+    style::{Modifier, Style},
+    text::Line,
+    widgets::{Block, Borders, List, ListItem, ListState, Paragraph},
+    Frame, Terminal,
+};
+use std::collections::HashSet;
+use std::io::stdout;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+// which list the arrow keys move in
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Focus {
+    Groups,
+    Files
+}
+
+// the state of the browser, the marks are the files to remove
+struct Browser {
+    header: TreeIndexHeader,
+    groups: Vec<TreeItemDupes>,
+    group: ListState,
+    file: ListState,
+    focus: Focus,
+    marks: HashSet<ItemPath>,
+    plan: PathBuf,
+    status: String
+}
+
+/// Browses the duplicate groups of the index. Marked files are written to
+/// the plan as an index where each group keeps its first unmarked file and
+/// lists the marked ones as dupes, so `dupes delete` can carry it out.
+pub fn browse(ti: &TreeIndex, plan: &Path) -> Result<()> {
+    // the plan doesn't have the chunk digests or metadata of the index
+    let mut header = ti.header.clone();
+    header.set_chunk_size(None);
+    header.set_metadata(false);
+
+    let mut b = Browser {
+        header,
+        groups: DupeReport::from(ti).groups,
+        group: ListState::default(),
+        file: ListState::default(),
+        focus: Focus::Groups,
+        marks: HashSet::new(),
+        plan: plan.to_path_buf(),
+        status: "space: mark  a: mark dupes  u: unmark  w: write plan  q: quit".to_string()
+    };
+    if !b.groups.is_empty() {
+        b.group.select(Some(0));
+        b.file.select(Some(0));
+    }
+
+    enable_raw_mode()?;
+    execute!(stdout(), EnterAlternateScreen)?;
+    let result = run(&mut b);
+
+    // put the terminal back even if the browser failed
+    disable_raw_mode()?;
+    execute!(stdout(), LeaveAlternateScreen)?;
+    result
+}
+
+fn run(b: &mut Browser) -> Result<()> {
+    let mut terminal = Terminal::new(CrosstermBackend::new(stdout()))?;
+    loop {
+        terminal.draw(|f| draw(f, b))?;
+        let key = match event::read()? {
+            Event::Key(k) if k.kind == KeyEventKind::Press => k,
+            _ => continue
+        };
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+            KeyCode::Up | KeyCode::Char('k') => b.step(-1),
+            KeyCode::Down | KeyCode::Char('j') => b.step(1),
+            KeyCode::Tab | KeyCode::Left | KeyCode::Right => {
+                b.focus = match b.focus {
+                    Focus::Groups => Focus::Files,
+                    Focus::Files => Focus::Groups
+                };
+            },
+            KeyCode::Char(' ') => b.toggle(),
+            KeyCode::Char('a') => b.mark_dupes(),
+            KeyCode::Char('u') => b.unmark(),
+            KeyCode::Char('w') => {
+                b.status = match b.write_plan() {
+                    Ok(n) => format!("wrote {} marked files to {}", n, b.plan.to_string_lossy()),
+                    Err(e) => format!("failed to write {}: {}", b.plan.to_string_lossy(), e)
+                };
+            },
+            _ => {}
+        }
+    }
+}
+
+impl Browser {
+
+    fn current(&self) -> Option<&TreeItemDupes> {
+        self.group.selected().and_then(|i| self.groups.get(i))
+    }
+
+    // the primary followed by the dupes
+    fn paths(g: &TreeItemDupes) -> Vec<ItemPath> {
+        std::iter::once(g.item.item_path().clone()).chain(g.dupes.iter().cloned()).collect()
+    }
+
+    fn current_path(&self) -> Option<ItemPath> {
+        let g = self.current()?;
+        Browser::paths(g).get(self.file.selected()?).cloned()
+    }
+
+    fn step(&mut self, by: isize) {
+        let (state, len) = match self.focus {
+            Focus::Groups => (&mut self.group, self.groups.len()),
+            Focus::Files => {
+                let len = self.current().map_or(0, |g| g.dupes.len() + 1);
+                (&mut self.file, len)
+            }
+        };
+        if len == 0 {
+            return;
+        }
+        let i = state.selected().unwrap_or(0) as isize + by;
+        state.select(Some(i.clamp(0, len as isize - 1) as usize));
+        if self.focus == Focus::Groups {
+            self.file.select(Some(0));
+        }
+    }
+
+    // every group has to keep one copy
+    fn toggle(&mut self) {
+        let path = match self.current_path() {
+            Some(p) => p,
+            None => return
+        };
+        if self.marks.remove(&path) {
+            return;
+        }
+        let unmarked = self.current()
+            .map_or(0, |g| Browser::paths(g).iter().filter(|p| !self.marks.contains(*p)).count());
+        if unmarked > 1 {
+            self.marks.insert(path);
+        } else {
+            self.status = "the last copy in a group can't be marked".to_string();
+        }
+    }
+
+    fn mark_dupes(&mut self) {
+        if let Some(g) = self.current() {
+            let dupes = g.dupes.clone();
+            let path = g.item.item_path().clone();
+            self.marks.remove(&path);
+            self.marks.extend(dupes);
+        }
+    }
+
+    fn unmark(&mut self) {
+        if let Some(g) = self.current() {
+            for p in Browser::paths(g) {
+                self.marks.remove(&p);
+            }
+        }
+    }
+
+    // writes the marks as an index and returns how many files are in it
+    fn write_plan(&self) -> Result<usize> {
+        let mut plan = TreeIndex {
+            header: self.header.clone(),
+            ..Default::default()
+        };
+        let mut count = 0;
+        for g in &self.groups {
+            let paths = Browser::paths(g);
+            let keep = match paths.iter().find(|p| !self.marks.contains(*p)) {
+                Some(k) => k,
+                None => continue
+            };
+            let marked: Vec<_> = paths.iter().filter(|p| self.marks.contains(*p)).collect();
+            if marked.is_empty() {
+                continue;
+            }
+            let mut entry = TreeItemDupes::new(&g.item.digest, keep, g.item.size);
+            for p in marked {
+                entry.push(p.clone());
+                count += 1;
+            }
+            plan.idx.insert(plan.key(&g.item), entry);
+        }
+        plan.write(&mut std::fs::File::create(&self.plan)?)?;
+        Ok(count)
+    }
+}
+
+fn draw(f: &mut Frame, b: &mut Browser) {
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Min(3), Constraint::Length(1)])
+        .split(f.area());
+    let cols = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+        .split(rows[0]);
+    let right = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Min(3), Constraint::Length(7)])
+        .split(cols[1]);
+
+    let highlight = Style::default().add_modifier(Modifier::REVERSED);
+    let border = |title: &str, focused: bool| {
+        let style = if focused { Style::default().add_modifier(Modifier::BOLD) } else { Style::default() };
+        Block::default().borders(Borders::ALL).title(title.to_string()).border_style(style)
+    };
+
+    // the groups, biggest savings first
+    let groups: Vec<ListItem> = b.groups.iter()
+        .map(|g| ListItem::new(format!("{:>10} x{} {}",
+                                       format_bytes(g.item.size * g.dupes.len() as u64, Units::Binary),
+                                       g.dupes.len() + 1,
+                                       g.item.path().to_string_lossy())))
+        .collect();
+    let title = format!("groups ({})", b.groups.len());
+    f.render_stateful_widget(List::new(groups).block(border(&title, b.focus == Focus::Groups))
+                             .highlight_style(highlight), cols[0], &mut b.group);
+
+    // the files in the selected group
+    let files: Vec<ListItem> = b.current()
+        .map(|g| Browser::paths(g).iter()
+             .map(|p| ListItem::new(format!("[{}] {}", if b.marks.contains(p) { "x" } else { " " },
+                                            p.path().to_string_lossy())))
+             .collect())
+        .unwrap_or_default();
+    f.render_stateful_widget(List::new(files).block(border("files", b.focus == Focus::Files))
+                             .highlight_style(highlight), right[0], &mut b.file);
+
+    // the metadata of the selected file
+    let info = match b.current_path() {
+        Some(p) => describe(&p.path(), b.current().map_or(0, |g| g.item.size)),
+        None => Vec::new()
+    };
+    f.render_widget(Paragraph::new(info).block(border("metadata", false)), right[1]);
+
+    let marked: u64 = b.groups.iter()
+        .map(|g| Browser::paths(g).iter().filter(|p| b.marks.contains(*p)).count() as u64 * g.item.size)
+        .sum();
+    f.render_widget(Paragraph::new(format!("{} marked, {}  |  {}", b.marks.len(),
+                                           format_bytes(marked, Units::Binary), b.status)), rows[1]);
+}
+
+fn describe(path: &Path, size: u64) -> Vec<Line<'static>> {
+    let mut lines = vec![Line::from(format!("size: {} ({} bytes)", format_bytes(size, Units::Binary), size))];
+    match FileMetadata::read(path) {
+        Ok(m) => {
+            lines.push(Line::from(format!("kind: {}", m.kind)));
+            if let Some(d) = m.mtime.and_then(|t| t.duration_since(UNIX_EPOCH).ok()) {
+                lines.push(Line::from(format!("mtime: {}", d.as_secs())));
+            }
+            if let Some(mode) = m.mode {
+                lines.push(Line::from(format!("mode: {:o}", mode)));
+            }
+            if let (Some(uid), Some(gid)) = (m.uid, m.gid) {
+                lines.push(Line::from(format!("owner: {}:{}", uid, gid)));
+            }
+        },
+        Err(e) => lines.push(Line::from(format!("error: {}", e)))
+    }
+    lines
+}
@@ -0,0 +1,56 @@
+/*
+ * The C API of the best-practices crate, built with the ffi feature.
+ *
+ * An index is made by scanning a directory tree with bp_index_new and is
+ * freed with bp_index_free. It holds the groups of duplicate files in the
+ * tree, ordered by the bytes they waste with the largest first. The paths
+ * in a group have the primary first. Strings handed out by an index belong
+ * to it and are good until it is freed.
+ */
+
+#ifndef BEST_PRACTICES_H
+#define BEST_PRACTICES_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct BpIndex BpIndex;
+
+/* Scans the tree at root, NULL on failure, see bp_last_error. A non-zero
+ * fast uses fast digests. */
+BpIndex *bp_index_new(const char *root, int fast);
+
+/* Frees the index, NULL is ignored. */
+void bp_index_free(BpIndex *index);
+
+/* The number of distinct files in the index. */
+size_t bp_index_entries(const BpIndex *index);
+
+/* The number of groups of duplicate files. */
+size_t bp_index_group_count(const BpIndex *index);
+
+/* The size of each file in the group, 0 if there is no such group. */
+uint64_t bp_index_group_size(const BpIndex *index, size_t group);
+
+/* The number of paths in the group, 0 if there is no such group. */
+size_t bp_index_group_len(const BpIndex *index, size_t group);
+
+/* A path in the group, NULL if there is no such path. */
+const char *bp_index_group_path(const BpIndex *index, size_t group, size_t path);
+
+/* The group with the path in it, -1 if it has no duplicates or isn't in
+ * the index. Paths are matched as the root joined with the path under it. */
+intptr_t bp_index_dupes_of(const BpIndex *index, const char *path);
+
+/* Why the last call that failed on this thread failed, or NULL. */
+const char *bp_last_error(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,48 @@
+use crate::{
+    error::Error,
+    Result
+};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// A CancellationToken is a flag shared between the code doing the work and
+/// whatever wants to stop it. Clones share the same flag. Long running work
+/// such as a scan checks it between files and returns Error::Interrupted
+/// once it is set so the error unwinds normally, dropping and flushing the
+/// writers and removing the temp files on the way out.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    flag: Arc<AtomicBool>
+}
+
+impl CancellationToken {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Asks the work to stop
+    pub fn cancel(&self) {
+        self.flag.store(true, Ordering::SeqCst);
+    }
+
+    /// Returns true once the work has been asked to stop
+    pub fn is_cancelled(&self) -> bool {
+        self.flag.load(Ordering::SeqCst)
+    }
+
+    /// Returns Error::Interrupted if the work has been asked to stop
+    pub fn check(&self) -> Result<()> {
+        if self.is_cancelled() {
+            Err(Error::Interrupted)
+        } else {
+            Ok(())
+        }
+    }
+
+    // the flag itself, for signal handlers that set it
+    #[cfg(all(feature = "signals", not(target_os = "wasi")))]
+    pub(crate) fn flag(&self) -> &Arc<AtomicBool> {
+        &self.flag
+    }
+}
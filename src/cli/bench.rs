@@ -0,0 +1,282 @@
+use crate::{
+    error::Error,
+    Result,
+    fs::{Algorithm, TempDir, TreeListBuilder},
+    units::{format_bytes, IntoBytes, Units}
+};
+use std::fmt::{Display, Formatter};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// A SyntheticTree is a directory tree of generated files for measuring the
+/// scanner. The contents come from a seeded generator so the same settings
+/// make the same tree, and some of the files are made copies of others so
+/// there are dupes to find. The tree is deleted when it is dropped.
+#[derive(Debug)]
+pub struct SyntheticTree {
+    dir: TempDir,
+    files: usize,
+    dupes: usize,
+    bytes: u64
+}
+
+impl SyntheticTree {
+
+    /// The root of the tree
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    /// The number of files in the tree
+    pub fn files(&self) -> usize {
+        self.files
+    }
+
+    /// The number of files that copy another one
+    pub fn dupes(&self) -> usize {
+        self.dupes
+    }
+
+    /// The bytes in all of the files
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+}
+
+pub struct SyntheticTreeBuilder {
+    files: usize,
+    size: u64,
+    fanout: usize,
+    depth: usize,
+    dupes: f64,
+    seed: u64,
+    dir: Option<PathBuf>,
+    error: Option<Error>
+}
+
+impl Default for SyntheticTreeBuilder {
+    fn default() -> Self {
+        Self {
+            files: 1000,
+            size: 64 * 1024,
+            fanout: 8,
+            depth: 2,
+            dupes: 0.1,
+            seed: 1,
+            dir: None,
+            error: None
+        }
+    }
+}
+
+impl SyntheticTreeBuilder {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// How many files to make, the default is 1000
+    pub fn files(mut self, files: usize) -> Self {
+        self.files = files;
+        self
+    }
+
+    /// The size of every file, the default is 64KiB. This takes either a
+    /// u64 or a human readable string such as "1MiB".
+    pub fn file_size<B: IntoBytes>(mut self, size: B) -> Self {
+        match size.into_bytes() {
+            Ok(size) => self.size = size,
+            Err(e) => self.error = Some(e)
+        }
+        self
+    }
+
+    /// How many directories each directory has, the default is 8
+    pub fn fanout(mut self, fanout: usize) -> Self {
+        self.fanout = fanout;
+        self
+    }
+
+    /// How many levels of directories there are under the root, the files
+    /// are spread over the deepest ones. The default is 2, 0 puts them all
+    /// in the root.
+    pub fn depth(mut self, depth: usize) -> Self {
+        self.depth = depth;
+        self
+    }
+
+    /// The fraction of the files, from 0 to 1, that are copies of another
+    /// one, the default is 0.1
+    pub fn dupes(mut self, fraction: f64) -> Self {
+        self.dupes = fraction;
+        self
+    }
+
+    /// Seeds the generator of the contents
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Makes the tree in this directory instead of the system temp dir, to
+    /// measure a particular disk
+    pub fn in_dir(mut self, dir: &Path) -> Self {
+        self.dir = Some(dir.to_path_buf());
+        self
+    }
+
+    pub fn build(self) -> Result<SyntheticTree> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+        if !(0.0..=1.0).contains(&self.dupes) {
+            return Err(Error::InvalidArgument(format!("the fraction of dupes {} isn't between 0 and 1", self.dupes)));
+        }
+        if self.depth > 0 && self.fanout == 0 {
+            return Err(Error::InvalidArgument("a tree with depth needs a fanout".to_string()));
+        }
+        let dir = match &self.dir {
+            Some(d) => TempDir::in_dir(d)?,
+            None => TempDir::in_dir(&std::env::temp_dir())?
+        };
+
+        // the leaf directories, numbered in base fanout
+        let leaves = self.fanout.saturating_pow(self.depth as u32).max(1);
+        let unique = self.files - (self.files as f64 * self.dupes) as usize;
+        let mut bytes = 0;
+        for n in 0..self.files {
+            let leaf = n % leaves;
+            let mut parent = dir.path().to_path_buf();
+            let mut rest = leaf;
+            for _ in 0..self.depth {
+                parent.push(format!("d{}", rest % self.fanout));
+                rest /= self.fanout;
+            }
+            fs::create_dir_all(&parent)?;
+
+            // the dupes copy the unique files in turn
+            let contents = if n < unique.max(1) { n } else { n % unique.max(1) };
+            write_generated(&parent.join(format!("f{}.bin", n)), self.size, self.seed ^ contents as u64)?;
+            bytes += self.size;
+        }
+        Ok(SyntheticTree {
+            dir,
+            files: self.files,
+            dupes: self.files.saturating_sub(unique.max(1)),
+            bytes
+        })
+    }
+}
+
+/// A way of hashing files that a scan can be set up with
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HashMode {
+    pub algorithm: Algorithm,
+    pub mmap: bool,
+    pub fast: bool
+}
+
+impl HashMode {
+
+    /// Every algorithm read and memory mapped, and fast digests
+    pub fn all() -> Vec<HashMode> {
+        let mut modes = Vec::new();
+        for algorithm in [Algorithm::Blake2b, Algorithm::Sha256] {
+            for mmap in [false, true] {
+                modes.push(HashMode { algorithm, mmap, fast: false });
+            }
+            modes.push(HashMode { algorithm, mmap: false, fast: true });
+        }
+        modes
+    }
+}
+
+impl Display for HashMode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error>
+    {
+        write!(f, "{}", self.algorithm)?;
+        if self.mmap {
+            write!(f, "+mmap")?;
+        }
+        if self.fast {
+            write!(f, "+fast")?;
+        }
+        Ok(())
+    }
+}
+
+/// What one measured scan did
+#[derive(Clone, Debug)]
+pub struct BenchResult {
+    pub mode: HashMode,
+    pub threads: usize,
+    pub files: u64,
+    pub bytes: u64,
+    pub elapsed: Duration
+}
+
+impl BenchResult {
+
+    /// The file bytes indexed a second. Fast digests only read the ends of
+    /// the bigger files so they index more than they read.
+    pub fn bytes_per_sec(&self) -> f64 {
+        self.bytes as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+impl Display for BenchResult {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error>
+    {
+        write!(f, "{:<14} {:>3} threads {:>8} files {:>10} in {:>8.3}s {:>10}/s",
+               self.mode.to_string(), self.threads, self.files, format_bytes(self.bytes, Units::Binary),
+               self.elapsed.as_secs_f64(), format_bytes(self.bytes_per_sec() as u64, Units::Binary))
+    }
+}
+
+/// This function scans and hashes the tree at the path the way the mode
+/// says and times it. Files read again soon after are likely to come from
+/// the page cache, make the tree bigger than memory to measure the disk.
+pub fn bench_hashing(path: &Path, mode: HashMode, threads: usize) -> Result<BenchResult> {
+    let start = Instant::now();
+    let tl = TreeListBuilder::new()
+        .path(path)
+        .algorithm(mode.algorithm)
+        .mmap(mode.mmap)
+        .fast(mode.fast)
+        .threads(threads)
+        .build()?;
+    Ok(BenchResult {
+        mode,
+        threads,
+        files: tl.stats.files,
+        bytes: tl.stats.bytes,
+        elapsed: start.elapsed()
+    })
+}
+
+// generated files are written this much at a time
+const WRITE_CHUNK: usize = 64 * 1024;
+
+// fills a file with bytes from a xorshift generator, the same seed and size
+// always make the same file
+pub(crate) fn write_generated(path: &Path, size: u64, seed: u64) -> Result<()> {
+    let mut w = BufWriter::new(File::create(path)?);
+    let mut state = seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1;
+    let mut buf = vec![0u8; WRITE_CHUNK];
+    let mut left = size;
+    while left > 0 {
+        for word in buf.chunks_exact_mut(8) {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            word.copy_from_slice(&state.to_le_bytes());
+        }
+        let n = left.min(WRITE_CHUNK as u64) as usize;
+        w.write_all(&buf[..n])?;
+        left -= n as u64;
+    }
+    w.flush()?;
+    Ok(())
+}
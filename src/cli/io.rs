@@ -1,36 +1,47 @@
-use crate::Result;
-use std::fs::File;
+use crate::{Result, lock::open_locked};
+use log::warn;
+use std::fs::{File, OpenOptions};
 use std::ffi::OsString;
 use std::io::{self, Read, Write};
 use std::path::{Path, PathBuf};
 
-///! This function takes an optional path and returns a concrete Read'er object.
-///! This is most useful for command line applications that take either a file
-///! or stdin as input. The user can specify "-" or nothing and the result of
-///! this function is a Read'er for the stdin stream. If they specify a file,
-///! then the Read'er is the file stream. If there is an error opening the file
-///! then a crate::error::IoError result.
+/// This function takes an optional path and returns a concrete Read'er object.
+/// This is most useful for command line applications that take either a file
+/// or stdin as input. The user can specify "-" or nothing and the result of
+/// this function is a Read'er for the stdin stream. If they specify a file,
+/// then the Read'er is the file stream. If there is an error opening the file
+/// then a crate::error::IoError result. With the net feature, http:// and
+/// https:// URLs are fetched and the Read'er streams the response body,
+/// without it they are opened like any other path. A path of the form unix:/path/to.sock connects to that unix domain socket.
+/// Windows named pipes like `\\.\pipe\name` are opened like any other file.
 pub fn reader(path: &Option<PathBuf>) -> Result<Box<dyn Read>> {
     match path {
         Some(p) => {
+            #[cfg(feature = "net")]
+            if is_url(p) {
+                return url_reader(&p.to_string_lossy());
+            }
             if p.to_string_lossy() == "-" {
                 Ok(Box::new(io::stdin()) as Box<dyn Read>)
+            } else if let Some(sock) = socket_path(p) {
+                Ok(Box::new(connect(&sock)?) as Box<dyn Read>)
             } else {
                 let path = Path::new(&p);
-                Ok(Box::new(File::open(&path)?) as Box<dyn Read>)
+                Ok(Box::new(File::open(path)?) as Box<dyn Read>)
             }
         }
         None => Ok(Box::new(io::stdin()) as Box<dyn Read>)
     }
 }
 
-///! This function takes an optional path and returns a concrete Read'er object.
-///! This is most useful for command line applications that take either a file
-///! or stdin as input. The user can specify "-" or nothing and the result of
-///! this function is a Read'er for the stdin stream. If they specify a file,
-///! then the Read'er is the file stream. If there is an error opening the file
-///! then a crate::error::IoError result. Secure read implies whatever the
-///! types is not echoed back to the TTY.
+/// This function takes an optional path and returns a concrete Read'er object.
+/// This is most useful for command line applications that take either a file
+/// or stdin as input. The user can specify "-" or nothing and the result of
+/// this function is a Read'er for the stdin stream. If they specify a file,
+/// then the Read'er is the file stream. If there is an error opening the file
+/// then a crate::error::IoError result. Secure read implies whatever the
+/// types is not echoed back to the TTY. It needs the password feature.
+#[cfg(feature = "password")]
 pub fn secure_reader(path: &Option<PathBuf>) -> Result<Box<dyn Read>> {
     match path {
         Some(p) => {
@@ -39,7 +50,7 @@ pub fn secure_reader(path: &Option<PathBuf>) -> Result<Box<dyn Read>> {
                 Ok(Box::new(io::Cursor::new(secret)))
             } else {
                 let path = Path::new(&p);
-                Ok(Box::new(File::open(&path)?) as Box<dyn Read>)
+                Ok(Box::new(File::open(path)?) as Box<dyn Read>)
             }
         }
         None => {
@@ -49,9 +60,9 @@ pub fn secure_reader(path: &Option<PathBuf>) -> Result<Box<dyn Read>> {
     }
 }
 
-///! This function works in tandem with the above reader function except that
-///! it returns a convenient OsString name for the reader. This is used for
-///! verbose output to describe where the input is coming from.
+/// This function works in tandem with the above reader function except that
+/// it returns a convenient OsString name for the reader. This is used for
+/// verbose output to describe where the input is coming from.
 pub fn reader_name(path: &Option<PathBuf>) -> Result<OsString> {
     match path {
         Some(p) => {
@@ -65,20 +76,121 @@ pub fn reader_name(path: &Option<PathBuf>) -> Result<OsString> {
     }
 }
 
-///! This function works the same as the reader function but is for writers.
-///! If the path is provided then the Write'er is for the file stream. If the
-///! path is not provided then the Write'er is for the stdout stream.
+/// This function works the same as the reader function but is for writers.
+/// If the path is provided then the Write'er is for the file stream. If the
+/// path is not provided then the Write'er is for the stdout stream. Unix
+/// domain sockets and named pipes work the same as for the reader. Files
+/// are locked while they are written so two runs writing the same output
+/// take turns.
 pub fn writer(path: &Option<PathBuf>) -> Result<Box<dyn Write>> {
-    match path {
-        Some(p) => {
-            let path = Path::new(&p);
-            Ok(Box::new(File::create(&path)?) as Box<dyn Write>)
+    WriterBuilder::new(path).build()
+}
+
+/// This function works like the writer function but appends to the file
+/// instead of truncating it. The file is created if it doesn't exist.
+pub fn appender(path: &Option<PathBuf>) -> Result<Box<dyn Write>> {
+    WriterBuilder::new(path).append(true).build()
+}
+
+/// Builds the Write'ers that writer and appender return, with the options
+/// they don't take
+pub struct WriterBuilder<'a> {
+    path: &'a Option<PathBuf>,
+    append: bool,
+    sync: bool
+}
+
+impl<'a> WriterBuilder<'a> {
+
+    pub fn new(path: &'a Option<PathBuf>) -> Self {
+        Self { path, append: false, sync: false }
+    }
+
+    /// Append to the file instead of truncating it
+    pub fn append(mut self, append: bool) -> Self {
+        self.append = append;
+        self
+    }
+
+    /// Make the file durable: the directory it is in is synced once it is
+    /// created and every flush, and closing it, syncs what was written to
+    /// the disk. This is for outputs that have to survive a power cut, like
+    /// the logs of files that were deleted or moved, and it is slow. It has
+    /// no effect on stdout and sockets.
+    pub fn sync(mut self, sync: bool) -> Self {
+        self.sync = sync;
+        self
+    }
+
+    pub fn build(self) -> Result<Box<dyn Write>> {
+        match self.path {
+            Some(p) => {
+                if let Some(sock) = socket_path(p) {
+                    return Ok(Box::new(connect(&sock)?) as Box<dyn Write>);
+                }
+                if is_pipe(p) {
+                    return Ok(Box::new(OpenOptions::new().write(true).open(p)?) as Box<dyn Write>);
+                }
+                let file = open_locked(p, self.append)?;
+                if !self.sync {
+                    return Ok(Box::new(file) as Box<dyn Write>);
+                }
+                sync_parent(p)?;
+                Ok(Box::new(SyncedFile { file, path: p.clone(), dirty: false }) as Box<dyn Write>)
+            }
+            None => Ok(Box::new(io::stdout()) as Box<dyn Write>)
+        }
+    }
+}
+
+// a file that is synced to the disk when it is flushed and closed
+struct SyncedFile {
+    file: File,
+    path: PathBuf,
+    dirty: bool
+}
+
+impl Write for SyncedFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.dirty = true;
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.dirty {
+            self.file.sync_all()?;
+            self.dirty = false;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for SyncedFile {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            warn!("failed to sync {}: {}", self.path.to_string_lossy(), e);
         }
-        None => Ok(Box::new(io::stdout()) as Box<dyn Write>)
     }
 }
 
-///! This function gives the name for the writer for verbose output purposes.
+// syncs the directory the file is in so the file's entry in it is on the
+// disk, windows can't open directories to do that
+#[cfg(unix)]
+fn sync_parent(path: &Path) -> Result<()> {
+    let dir = match path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new(".")
+    };
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn sync_parent(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+/// This function gives the name for the writer for verbose output purposes.
 pub fn writer_name(path: &Option<PathBuf>) -> Result<OsString> {
     match path {
         Some(p) => {
@@ -88,8 +200,8 @@ pub fn writer_name(path: &Option<PathBuf>) -> Result<OsString> {
     }
 }
 
-///! This function takes an optional path and returns the path if supplied,
-///! otherwise it defaults to the current working directory.
+/// This function takes an optional path and returns the path if supplied,
+/// otherwise it defaults to the current working directory.
 pub fn dir(path: &Option<PathBuf>) -> Result<PathBuf> {
     match path {
         Some(p) => Ok(p.to_path_buf()),
@@ -97,8 +209,8 @@ pub fn dir(path: &Option<PathBuf>) -> Result<PathBuf> {
     }
 }
 
-///! This function works with the above dir function but gives the name of the
-///! directory for verbose output purposes.
+/// This function works with the above dir function but gives the name of the
+/// directory for verbose output purposes.
 pub fn dir_name(path: &Option<PathBuf>) -> Result<OsString> {
     match path {
         Some(p) => {
@@ -108,3 +220,67 @@ pub fn dir_name(path: &Option<PathBuf>) -> Result<OsString> {
     }
 }
 
+
+/// Returns true if the path is an http:// or https:// URL
+pub fn is_url(path: &Path) -> bool {
+    let p = path.to_string_lossy();
+    p.starts_with("http://") || p.starts_with("https://")
+}
+
+/// Returns the socket path of a unix:/path/to.sock path
+pub fn socket_path(path: &Path) -> Option<PathBuf> {
+    path.to_str()
+        .and_then(|p| p.strip_prefix("unix:"))
+        .filter(|p| !p.is_empty())
+        .map(PathBuf::from)
+}
+
+// named pipes exist already and can't be created or truncated like files
+fn is_pipe(path: &Path) -> bool {
+    cfg!(windows) && path.to_string_lossy().starts_with(r"\\.\pipe\")
+}
+
+// connects to a unix domain socket, the stream closes when it is dropped so
+// the other end sees the end of the data
+#[cfg(unix)]
+fn connect(path: &Path) -> Result<std::os::unix::net::UnixStream> {
+    Ok(std::os::unix::net::UnixStream::connect(path)?)
+}
+
+#[cfg(not(unix))]
+fn connect(path: &Path) -> Result<File> {
+    Err(crate::error::Error::InvalidArgument(format!("unix domain socket {} isn't supported here", path.to_string_lossy())))
+}
+
+// fetches the URL, errors are the connection failing or a status >= 400
+#[cfg(feature = "net")]
+fn url_reader(url: &str) -> Result<Box<dyn Read>> {
+    let response = ureq::get(url).call().map_err(|e| crate::error::Error::HttpError(e.to_string()))?;
+    Ok(Box::new(response.into_reader()) as Box<dyn Read>)
+}
+
+/// This function is the async version of reader. It returns an AsyncRead'er
+/// for stdin if the path is "-" or not specified, otherwise for the file.
+#[cfg(feature = "async")]
+pub async fn async_reader(path: &Option<PathBuf>) -> Result<Box<dyn tokio::io::AsyncRead + Unpin + Send>> {
+    match path {
+        Some(p) => {
+            if p.to_string_lossy() == "-" {
+                Ok(Box::new(tokio::io::stdin()))
+            } else {
+                Ok(Box::new(tokio::fs::File::open(p).await?))
+            }
+        }
+        None => Ok(Box::new(tokio::io::stdin()))
+    }
+}
+
+/// This function is the async version of writer. It returns an
+/// AsyncWrite'er for the file if a path is given, otherwise for stdout.
+#[cfg(feature = "async")]
+pub async fn async_writer(path: &Option<PathBuf>) -> Result<Box<dyn tokio::io::AsyncWrite + Unpin + Send>> {
+    match path {
+        Some(p) => Ok(Box::new(tokio::fs::File::create(p).await?)),
+        None => Ok(Box::new(tokio::io::stdout()))
+    }
+}
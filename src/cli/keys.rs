@@ -0,0 +1,24 @@
+use crate::{
+    Result,
+    cli::io::{reader, secure_reader},
+    fs::sign::{parse_signing_key, parse_verifying_key}
+};
+use ed25519_dalek::{SigningKey, VerifyingKey};
+use std::io::Read;
+use std::path::PathBuf;
+
+/// Reads a signing key as 64 hex characters. The key is read with the
+/// secure_reader so it is prompted for without echo when the path is None
+/// or "-".
+pub fn read_signing_key(path: &Option<PathBuf>) -> Result<SigningKey> {
+    let mut s = String::new();
+    secure_reader(path)?.read_to_string(&mut s)?;
+    parse_signing_key(&s)
+}
+
+/// Reads a public key as 64 hex characters
+pub fn read_verifying_key(path: &Option<PathBuf>) -> Result<VerifyingKey> {
+    let mut s = String::new();
+    reader(path)?.read_to_string(&mut s)?;
+    parse_verifying_key(&s)
+}
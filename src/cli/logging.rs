@@ -0,0 +1,183 @@
+use crate::{
+    error::Error,
+    Result
+};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// How log records are written to stderr
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Lines of text for people to read
+    #[default]
+    Text,
+
+    /// A JSON object per line with the timestamp, level, target, message
+    /// and fields of the record, for log pipelines to ingest
+    Json
+}
+
+impl FromStr for LogFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(Error::InvalidArgument(format!("unknown log format {}", s)))
+        }
+    }
+}
+
+impl Display for LogFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            LogFormat::Text => write!(f, "text"),
+            LogFormat::Json => write!(f, "json")
+        }
+    }
+}
+
+// the most detailed level shown, quiet silences everything and each step of
+// verbosity shows one more level starting from errors only
+fn max_level(quiet: bool, verbosity: usize) -> log::LevelFilter {
+    match (quiet, verbosity) {
+        (true, _) => log::LevelFilter::Off,
+        (false, 0) => log::LevelFilter::Error,
+        (false, 1) => log::LevelFilter::Warn,
+        (false, 2) => log::LevelFilter::Info,
+        (false, 3) => log::LevelFilter::Debug,
+        (false, _) => log::LevelFilter::Trace
+    }
+}
+
+/// This function sets up the logger for a command line app so log records
+/// go to stderr as text. Quiet silences everything and each step of
+/// verbosity shows one more level, starting from errors only, the way the
+/// -q and -v flags of the example apps work.
+#[cfg(feature = "logging")]
+pub fn init(quiet: bool, verbosity: usize) -> Result<()> {
+    init_with_format(quiet, verbosity, LogFormat::Text)
+}
+
+/// Sets up the logger like init but with log records written to stderr in
+/// the format
+#[cfg(feature = "logging")]
+pub fn init_with_format(quiet: bool, verbosity: usize, format: LogFormat) -> Result<()> {
+    match format {
+        LogFormat::Text => stderrlog::new()
+            .quiet(quiet)
+            .verbosity(verbosity)
+            .init()
+            .map_err(|e| Error::LogError(e.to_string())),
+        LogFormat::Json => {
+            let level = max_level(quiet, verbosity);
+            log::set_boxed_logger(Box::new(json::JsonLogger { level }))
+                .map_err(|e| Error::LogError(e.to_string()))?;
+            log::set_max_level(level);
+            Ok(())
+        }
+    }
+}
+
+/// This function sets up a tracing subscriber that writes to stderr in the
+/// format with the same levels as init_with_format. Log records from this crate and
+/// others are passed on to it, and every span is written out when it closes
+/// with its fields and how long it took, so the totals of a scan or an index
+/// show up with the time they took. JSON records are in the layout of
+/// tracing-subscriber's JSON format, which also carries the spans.
+#[cfg(feature = "tracing")]
+pub fn init_tracing(quiet: bool, verbosity: usize, format: LogFormat) -> Result<()> {
+    use std::io::IsTerminal;
+    use tracing_subscriber::{filter::LevelFilter, fmt::format::FmtSpan};
+    let level = match max_level(quiet, verbosity) {
+        log::LevelFilter::Off => LevelFilter::OFF,
+        log::LevelFilter::Error => LevelFilter::ERROR,
+        log::LevelFilter::Warn => LevelFilter::WARN,
+        log::LevelFilter::Info => LevelFilter::INFO,
+        log::LevelFilter::Debug => LevelFilter::DEBUG,
+        log::LevelFilter::Trace => LevelFilter::TRACE
+    };
+    let builder = tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .with_max_level(level)
+        .with_span_events(FmtSpan::CLOSE);
+    match format {
+        LogFormat::Text => builder.with_ansi(std::io::stderr().is_terminal()).try_init(),
+        LogFormat::Json => builder.json().try_init()
+    }
+    .map_err(|e| Error::LogError(e.to_string()))
+}
+
+#[cfg(feature = "logging")]
+mod json {
+    use crate::{
+        json::json_string,
+        units::format_timestamp
+    };
+    use log::kv::{Error, Key, Value, VisitSource};
+    use log::{LevelFilter, Log, Metadata, Record};
+    use std::fmt::Write as _;
+    use std::io::{self, Write};
+    use std::time::SystemTime;
+
+    // writes every record to stderr as a line of JSON
+    pub(super) struct JsonLogger {
+        pub(super) level: LevelFilter
+    }
+
+    impl Log for JsonLogger {
+        fn enabled(&self, metadata: &Metadata) -> bool {
+            metadata.level() <= self.level
+        }
+
+        fn log(&self, record: &Record) {
+            if !self.enabled(record.metadata()) {
+                return;
+            }
+            let mut fields = Fields(String::new());
+            let _ = record.key_values().visit(&mut fields);
+            let line = format!("{{\"timestamp\":{},\"level\":{},\"target\":{},\"message\":{},\"fields\":{{{}}}}}\n",
+                               json_string(&format_timestamp(SystemTime::now())),
+                               json_string(record.level().as_str()),
+                               json_string(record.target()),
+                               json_string(&record.args().to_string()),
+                               fields.0);
+            // the whole line goes out in one write so records from threads
+            // don't interleave
+            let _ = io::stderr().lock().write_all(line.as_bytes());
+        }
+
+        fn flush(&self) {
+            let _ = io::stderr().flush();
+        }
+    }
+
+    // the key values of a record as the members of a JSON object
+    struct Fields(String);
+
+    impl<'kvs> VisitSource<'kvs> for Fields {
+        fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), Error> {
+            if !self.0.is_empty() {
+                self.0.push(',');
+            }
+            let _ = write!(self.0, "{}:{}", json_string(key.as_str()), json_value(&value));
+            Ok(())
+        }
+    }
+
+    // numbers and bools are written as they are, everything else as a string
+    fn json_value(value: &Value) -> String {
+        if let Some(n) = value.to_u64() {
+            n.to_string()
+        } else if let Some(n) = value.to_i64() {
+            n.to_string()
+        } else if let Some(b) = value.to_bool() {
+            b.to_string()
+        } else if let Some(f) = value.to_f64().filter(|f| f.is_finite()) {
+            f.to_string()
+        } else {
+            json_string(&value.to_string())
+        }
+    }
+}
@@ -1,2 +1,27 @@
+#[cfg(feature = "dedup")]
+pub mod bench;
 pub mod io;
-pub mod fs;
+#[cfg(all(feature = "sign", feature = "password"))]
+pub mod keys;
+#[cfg(any(feature = "logging", feature = "tracing"))]
+pub mod logging;
+#[cfg(feature = "dedup")]
+pub mod report;
+#[cfg(feature = "signals")]
+pub mod signals;
+pub mod term;
+
+/// The fs module was moved out of cli to the root of the crate, this keeps
+/// the old paths working for now
+#[deprecated(note = "use best_practices::fs")]
+#[cfg(feature = "dedup")]
+pub mod fs {
+    pub use crate::fs::*;
+}
+
+/// The units module was moved out of cli to the root of the crate, this
+/// keeps the old paths working for now
+#[deprecated(note = "use best_practices::units")]
+pub mod units {
+    pub use crate::units::*;
+}
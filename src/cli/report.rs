@@ -0,0 +1,393 @@
+use crate::{
+    error::Error,
+    Result,
+    fs::{stats::{json_string, savings}, SavingsReport, TreeIndex, TreeItemDupes},
+    cli::term::{Color, ColorWriter},
+    units::{format_bytes, Units}
+};
+use std::fmt::{Display, Formatter};
+use std::io::Write;
+use std::str::FromStr;
+
+/// How the contents of a column are aligned
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Align {
+    Left,
+    Right
+}
+
+// A column in a Table
+#[derive(Clone, Debug)]
+struct Column {
+    title: String,
+    align: Align,
+    truncate: bool
+}
+
+// A row in a Table along with an optional color for the whole row
+#[derive(Clone, Debug)]
+struct Row {
+    cells: Vec<String>,
+    color: Option<Color>
+}
+
+/// A Table is a simple column formatter. Column widths are computed from
+/// the widest cell in each column. If a maximum width is set, then the
+/// columns marked as truncatable are shortened until the table fits. Long
+/// cells are truncated from the front with a leading "..." because the tail
+/// of a path is usually the interesting part.
+#[derive(Clone, Debug, Default)]
+pub struct Table {
+    columns: Vec<Column>,
+    rows: Vec<Row>,
+    max_width: Option<usize>,
+    header: bool
+}
+
+const SEPARATOR: &str = "  ";
+const ELLIPSIS: &str = "...";
+
+impl Table {
+
+    pub fn new() -> Self {
+        Self {
+            header: true,
+            ..Default::default()
+        }
+    }
+
+    /// Adds a column that is never truncated
+    pub fn column(mut self, title: &str, align: Align) -> Self {
+        self.columns.push(Column {
+            title: title.to_string(),
+            align,
+            truncate: false
+        });
+        self
+    }
+
+    /// Adds a column that may be truncated to fit the maximum width
+    pub fn truncated_column(mut self, title: &str, align: Align) -> Self {
+        self.columns.push(Column {
+            title: title.to_string(),
+            align,
+            truncate: true
+        });
+        self
+    }
+
+    /// Sets the maximum width of the rendered table, usually the terminal width
+    pub fn max_width(mut self, width: Option<usize>) -> Self {
+        self.max_width = width;
+        self
+    }
+
+    /// Turns the header line on or off
+    pub fn header(mut self, header: bool) -> Self {
+        self.header = header;
+        self
+    }
+
+    pub fn push_row(&mut self, cells: Vec<String>) {
+        self.rows.push(Row { cells, color: None });
+    }
+
+    pub fn push_colored_row(&mut self, cells: Vec<String>, color: Color) {
+        self.rows.push(Row { cells, color: Some(color) });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    // calculate the width of each column, shrinking the truncatable columns
+    // if the table is wider than the maximum width
+    fn widths(&self) -> Vec<usize> {
+        let mut widths: Vec<usize> = self.columns.iter().map(|c| {
+            if self.header { c.title.chars().count() } else { 0 }
+        }).collect();
+        for row in &self.rows {
+            for (i, cell) in row.cells.iter().enumerate().take(widths.len()) {
+                widths[i] = widths[i].max(cell.chars().count());
+            }
+        }
+
+        if let Some(max) = self.max_width {
+            let seps = SEPARATOR.len() * widths.len().saturating_sub(1);
+            let mut total: usize = widths.iter().sum::<usize>() + seps;
+            while total > max {
+                // shrink the widest truncatable column by one
+                let widest = self.columns.iter().enumerate()
+                    .filter(|(i, c)| c.truncate && widths[*i] > ELLIPSIS.len() + 1)
+                    .max_by_key(|(i, _)| widths[*i])
+                    .map(|(i, _)| i);
+                match widest {
+                    Some(i) => {
+                        widths[i] -= 1;
+                        total -= 1;
+                    },
+                    None => break
+                }
+            }
+        }
+        widths
+    }
+
+    /// Renders the table to the writer. The header is bold and rows with a
+    /// color are painted if the writer is colored.
+    pub fn render(&self, w: &mut ColorWriter) -> Result<()> {
+        let widths = self.widths();
+        if self.header {
+            let titles: Vec<String> = self.columns.iter().map(|c| c.title.clone()).collect();
+            w.set_color(Color::Bold)?;
+            self.render_cells(w, &titles, &widths)?;
+            w.reset()?;
+            writeln!(w)?;
+        }
+        for row in &self.rows {
+            if let Some(color) = row.color {
+                w.set_color(color)?;
+            }
+            self.render_cells(w, &row.cells, &widths)?;
+            if row.color.is_some() {
+                w.reset()?;
+            }
+            writeln!(w)?;
+        }
+        Ok(())
+    }
+
+    fn render_cells(&self, w: &mut ColorWriter, cells: &[String], widths: &[usize]) -> Result<()> {
+        let mut line = String::new();
+        for (i, col) in self.columns.iter().enumerate() {
+            let width = widths[i];
+            let cell = cells.get(i).map(|c| c.as_str()).unwrap_or("");
+            let cell = truncate(cell, width);
+            if i > 0 {
+                line.push_str(SEPARATOR);
+            }
+            let last = i == self.columns.len() - 1;
+            match col.align {
+                Align::Left if last => line.push_str(&cell),
+                Align::Left => line.push_str(&format!("{:<width$}", cell, width = width)),
+                Align::Right => line.push_str(&format!("{:>width$}", cell, width = width))
+            }
+        }
+        write!(w, "{}", line)?;
+        Ok(())
+    }
+}
+
+/// This function truncates a string to the given number of chars by
+/// removing characters from the front and prepending "..."
+pub fn truncate(s: &str, width: usize) -> String {
+    let len = s.chars().count();
+    if len <= width {
+        s.to_string()
+    } else if width <= ELLIPSIS.len() {
+        s.chars().skip(len - width).collect()
+    } else {
+        let keep = width - ELLIPSIS.len();
+        let tail: String = s.chars().skip(len - keep).collect();
+        format!("{}{}", ELLIPSIS, tail)
+    }
+}
+
+/// The layouts a duplicate report can be rendered in
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ReportFormat {
+    #[default]
+    Text,
+    Markdown,
+    Html,
+    Json
+}
+
+impl FromStr for ReportFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "text" => Ok(ReportFormat::Text),
+            "markdown" | "md" => Ok(ReportFormat::Markdown),
+            "html" => Ok(ReportFormat::Html),
+            "json" => Ok(ReportFormat::Json),
+            _ => Err(Error::InvalidArgument(format!("unknown report format {}", s)))
+        }
+    }
+}
+
+impl Display for ReportFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            ReportFormat::Text => write!(f, "text"),
+            ReportFormat::Markdown => write!(f, "markdown"),
+            ReportFormat::Html => write!(f, "html"),
+            ReportFormat::Json => write!(f, "json")
+        }
+    }
+}
+
+/// A DupeReport holds the duplicate groups of an index, the biggest savings
+/// first, along with the savings of each directory. The HTML and Markdown
+/// renderings are single files that can be shared as they are.
+#[derive(Clone, Default)]
+pub struct DupeReport {
+    pub groups: Vec<TreeItemDupes>,
+    pub savings: SavingsReport
+}
+
+impl From<&TreeIndex> for DupeReport {
+    fn from(ti: &TreeIndex) -> Self {
+        let mut groups: Vec<TreeItemDupes> = ti.idx.values()
+            .filter(|i| !i.dupes.is_empty())
+            .cloned()
+            .collect();
+        groups.sort_by_cached_key(|g| (std::cmp::Reverse(savings(g)), g.item.path()));
+        Self {
+            groups,
+            savings: SavingsReport::from(ti)
+        }
+    }
+}
+
+impl DupeReport {
+
+    /// Renders the report in the format. Only the text format uses color
+    /// and the width, paths are truncated to fit it.
+    pub fn render(&self, w: &mut ColorWriter, format: ReportFormat, width: Option<usize>) -> Result<()> {
+        match format {
+            ReportFormat::Text => self.text(w, width),
+            ReportFormat::Markdown => self.markdown(w),
+            ReportFormat::Html => self.html(w),
+            ReportFormat::Json => self.json(w)
+        }
+    }
+
+    fn summary(&self) -> String {
+        format!("{} groups, total saved {} ({} bytes)", self.groups.len(),
+                format_bytes(self.savings.bytes, Units::Binary), self.savings.bytes)
+    }
+
+    fn text(&self, w: &mut ColorWriter, width: Option<usize>) -> Result<()> {
+        let mut table = Table::new()
+            .column("DIGEST", Align::Left)
+            .column("SIZE", Align::Right)
+            .column("COPIES", Align::Right)
+            .column("SAVINGS", Align::Right)
+            .truncated_column("PATH", Align::Left)
+            .max_width(width);
+        for g in &self.groups {
+            table.push_row(vec![
+                g.item.digest.to_string().chars().take(12).collect(),
+                format_bytes(g.item.size, Units::Binary),
+                g.dupes.len().to_string(),
+                format_bytes(savings(g), Units::Binary),
+                g.item.path().to_string_lossy().to_string()
+            ]);
+            for d in &g.dupes {
+                table.push_colored_row(vec![
+                    "-".to_string(),
+                    String::new(),
+                    String::new(),
+                    String::new(),
+                    d.path().to_string_lossy().to_string()
+                ], Color::Dim);
+            }
+        }
+        table.render(w)?;
+        writeln!(w)?;
+        w.paint(Color::Bold, &self.summary())?;
+        writeln!(w)?;
+        Ok(())
+    }
+
+    fn markdown(&self, w: &mut ColorWriter) -> Result<()> {
+        writeln!(w, "# Duplicate files\n")?;
+        writeln!(w, "{}\n", self.summary())?;
+        writeln!(w, "| Digest | Size | Copies | Savings | Path |")?;
+        writeln!(w, "|---|---:|---:|---:|---|")?;
+        for g in &self.groups {
+            writeln!(w, "| `{}` | {} | {} | {} | {} |",
+                     g.item.digest.to_string().chars().take(12).collect::<String>(),
+                     format_bytes(g.item.size, Units::Binary),
+                     g.dupes.len(),
+                     format_bytes(savings(g), Units::Binary),
+                     md_escape(&g.item.path().to_string_lossy()))?;
+            for d in &g.dupes {
+                writeln!(w, "| | | | | {} |", md_escape(&d.path().to_string_lossy()))?;
+            }
+        }
+        if !self.savings.dirs.is_empty() {
+            writeln!(w, "\n## Savings by directory\n")?;
+            writeln!(w, "| Savings | Files | Directory |")?;
+            writeln!(w, "|---:|---:|---|")?;
+            for (d, s) in self.savings.largest_dirs() {
+                writeln!(w, "| {} | {} | {} |", format_bytes(s.bytes, Units::Binary), s.files,
+                         md_escape(&d.to_string_lossy()))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn html(&self, w: &mut ColorWriter) -> Result<()> {
+        writeln!(w, "<!DOCTYPE html>")?;
+        writeln!(w, "<html><head><meta charset=\"utf-8\"><title>Duplicate files</title>")?;
+        writeln!(w, "<style>body{{font-family:sans-serif;margin:2em}}summary{{cursor:pointer}}\
+                     td,th{{padding:0 1em 0 0;text-align:left}}.n{{text-align:right}}\
+                     code,li{{font-family:monospace}}</style>")?;
+        writeln!(w, "</head><body>")?;
+        writeln!(w, "<h1>Duplicate files</h1>")?;
+        writeln!(w, "<p>{}</p>", html_escape(&self.summary()))?;
+        for g in &self.groups {
+            writeln!(w, "<details><summary>{} in {} copies of {}</summary>",
+                     format_bytes(savings(g), Units::Binary), g.dupes.len() + 1,
+                     html_escape(&g.item.path().to_string_lossy()))?;
+            writeln!(w, "<p><code>{}</code> {} each</p><ul>", html_escape(&g.item.digest.to_string()),
+                     format_bytes(g.item.size, Units::Binary))?;
+            writeln!(w, "<li>{}</li>", html_escape(&g.item.path().to_string_lossy()))?;
+            for d in &g.dupes {
+                writeln!(w, "<li>{}</li>", html_escape(&d.path().to_string_lossy()))?;
+            }
+            writeln!(w, "</ul></details>")?;
+        }
+        if !self.savings.dirs.is_empty() {
+            writeln!(w, "<h2>Savings by directory</h2>")?;
+            writeln!(w, "<table><tr><th class=\"n\">Savings</th><th class=\"n\">Files</th><th>Directory</th></tr>")?;
+            for (d, s) in self.savings.largest_dirs() {
+                writeln!(w, "<tr><td class=\"n\">{}</td><td class=\"n\">{}</td><td>{}</td></tr>",
+                         format_bytes(s.bytes, Units::Binary), s.files, html_escape(&d.to_string_lossy()))?;
+            }
+            writeln!(w, "</table>")?;
+        }
+        writeln!(w, "</body></html>")?;
+        Ok(())
+    }
+
+    fn json(&self, w: &mut ColorWriter) -> Result<()> {
+        write!(w, "{{\"groups\":[")?;
+        for (i, g) in self.groups.iter().enumerate() {
+            let paths: Vec<String> = std::iter::once(g.item.item_path())
+                .chain(g.dupes.iter())
+                .map(|p| json_string(&p.path().to_string_lossy()))
+                .collect();
+            write!(w, "{}{{\"digest\":{},\"size\":{},\"copies\":{},\"reclaimable\":{},\"paths\":[{}]}}",
+                   if i > 0 { "," } else { "" }, json_string(&g.item.digest.to_string()), g.item.size,
+                   g.dupes.len() + 1, savings(g), paths.join(","))?;
+        }
+        writeln!(w, "],\"savings\":{}}}", self.savings.to_json())?;
+        Ok(())
+    }
+}
+
+fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// pipes would end a table cell
+fn md_escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('|', "\\|")
+}
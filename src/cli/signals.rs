@@ -0,0 +1,31 @@
+use crate::{
+    Result,
+    cancel::CancellationToken
+};
+#[cfg(not(target_os = "wasi"))]
+use signal_hook::consts::{SIGINT, SIGTERM};
+#[cfg(not(target_os = "wasi"))]
+use std::sync::Arc;
+
+/// The exit status for a command that was interrupted, the same as a shell
+/// gives a process killed by SIGINT
+pub const INTERRUPTED_STATUS: i32 = 130;
+
+/// This function installs SIGINT and SIGTERM handlers that cancel the
+/// token. The first signal lets the work stop cleanly, a second one exits
+/// straight away with INTERRUPTED_STATUS in case the work is stuck.
+#[cfg(not(target_os = "wasi"))]
+pub fn install(token: &CancellationToken) -> Result<()> {
+    for sig in [SIGINT, SIGTERM] {
+        // registered first so it only fires once the flag is already set
+        signal_hook::flag::register_conditional_shutdown(sig, INTERRUPTED_STATUS, Arc::clone(token.flag()))?;
+        signal_hook::flag::register(sig, Arc::clone(token.flag()))?;
+    }
+    Ok(())
+}
+
+/// WASI has no signals so there is nothing to install
+#[cfg(target_os = "wasi")]
+pub fn install(_token: &CancellationToken) -> Result<()> {
+    Ok(())
+}
@@ -0,0 +1,228 @@
+use crate::{
+    error::Error,
+    Result,
+    cli::io::writer
+};
+use std::env;
+use std::fmt::{Display, Formatter};
+use std::io::{self, IsTerminal, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// The standard streams that can be checked for a TTY
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stream {
+    Stdin,
+    Stdout,
+    Stderr
+}
+
+/// This function returns true if the given standard stream is connected to a
+/// terminal. This is used to decide whether to emit color codes and whether
+/// the output can be formatted for a human instead of a pipe.
+pub fn is_tty(stream: Stream) -> bool {
+    match stream {
+        Stream::Stdin => io::stdin().is_terminal(),
+        Stream::Stdout => io::stdout().is_terminal(),
+        Stream::Stderr => io::stderr().is_terminal()
+    }
+}
+
+/// This function returns the width of the terminal attached to stdout in
+/// columns. If stdout isn't a terminal, the COLUMNS environment variable is
+/// used if it is set, otherwise None is returned and callers should not
+/// truncate their output.
+pub fn terminal_width() -> Option<usize> {
+    if let Some(w) = tty_width() {
+        return Some(w);
+    }
+    match env::var("COLUMNS") {
+        Ok(c) => c.parse::<usize>().ok().filter(|w| *w > 0),
+        Err(_) => None
+    }
+}
+
+#[cfg(unix)]
+fn tty_width() -> Option<usize> {
+    if !is_tty(Stream::Stdout) {
+        return None;
+    }
+    let mut ws: libc::winsize = unsafe { std::mem::zeroed() };
+    let ret = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut ws) };
+    if ret == 0 && ws.ws_col > 0 {
+        Some(ws.ws_col as usize)
+    } else {
+        None
+    }
+}
+
+#[cfg(not(unix))]
+fn tty_width() -> Option<usize> {
+    None
+}
+
+/// The user's choice of color output, typically from a `--color` flag
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ColorChoice {
+    #[default]
+    Auto,
+    Always,
+    Never
+}
+
+impl ColorChoice {
+
+    /// This function resolves the choice into a yes/no answer for the given
+    /// stream. Always and Never are absolute. Auto honors the NO_COLOR,
+    /// CLICOLOR and CLICOLOR_FORCE conventions and otherwise only enables
+    /// color if the stream is a TTY.
+    pub fn resolve(&self, stream: Stream) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                if env_set("NO_COLOR") {
+                    false
+                } else if env::var("CLICOLOR_FORCE").map(|v| !v.is_empty() && v != "0").unwrap_or(false) {
+                    true
+                } else if env::var("CLICOLOR").map(|v| v == "0").unwrap_or(false) {
+                    false
+                } else {
+                    is_tty(stream)
+                }
+            }
+        }
+    }
+}
+
+impl FromStr for ColorChoice {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => Err(Error::InvalidArgument(format!("unknown color choice {}", s)))
+        }
+    }
+}
+
+impl Display for ColorChoice {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            ColorChoice::Auto => write!(f, "auto"),
+            ColorChoice::Always => write!(f, "always"),
+            ColorChoice::Never => write!(f, "never")
+        }
+    }
+}
+
+// returns true if the environment variable is set and not empty
+fn env_set(name: &str) -> bool {
+    match env::var_os(name) {
+        Some(v) => !v.is_empty(),
+        None => false
+    }
+}
+
+/// The colors supported by the ColorWriter
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Color {
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Magenta,
+    Cyan,
+    White,
+    Bold,
+    Dim
+}
+
+impl Color {
+    fn code(&self) -> &'static str {
+        match self {
+            Color::Red => "\x1b[31m",
+            Color::Green => "\x1b[32m",
+            Color::Yellow => "\x1b[33m",
+            Color::Blue => "\x1b[34m",
+            Color::Magenta => "\x1b[35m",
+            Color::Cyan => "\x1b[36m",
+            Color::White => "\x1b[37m",
+            Color::Bold => "\x1b[1m",
+            Color::Dim => "\x1b[2m"
+        }
+    }
+}
+
+const RESET: &str = "\x1b[0m";
+
+/// A ColorWriter wraps a Write'er and either emits ANSI color codes or
+/// silently drops them, depending on how it was constructed. This lets the
+/// code producing a report always call set_color/reset without caring if
+/// the output is a terminal, a pipe or a file.
+pub struct ColorWriter {
+    inner: Box<dyn Write>,
+    colored: bool
+}
+
+impl ColorWriter {
+
+    pub fn new(inner: Box<dyn Write>, colored: bool) -> Self {
+        Self { inner, colored }
+    }
+
+    pub fn is_colored(&self) -> bool {
+        self.colored
+    }
+
+    pub fn set_color(&mut self, color: Color) -> Result<()> {
+        if self.colored {
+            self.inner.write_all(color.code().as_bytes())?;
+        }
+        Ok(())
+    }
+
+    pub fn reset(&mut self) -> Result<()> {
+        if self.colored {
+            self.inner.write_all(RESET.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Writes the string in the given color and then resets the color
+    pub fn paint(&mut self, color: Color, s: &str) -> Result<()> {
+        self.set_color(color)?;
+        self.inner.write_all(s.as_bytes())?;
+        self.reset()
+    }
+}
+
+impl Write for ColorWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// This function works like the io::writer function but returns a
+/// ColorWriter. Output to stdout is colored according to the choice. Output
+/// to a file is only colored if the choice is Always.
+pub fn color_writer(path: &Option<PathBuf>, choice: ColorChoice) -> Result<ColorWriter> {
+    let colored = match path {
+        Some(_) => choice == ColorChoice::Always,
+        None => choice.resolve(Stream::Stdout)
+    };
+    Ok(ColorWriter::new(writer(path)?, colored))
+}
+
+/// This function returns a ColorWriter for stderr, colored according to the
+/// choice. This is handy for warnings and progress messages.
+pub fn color_stderr(choice: ColorChoice) -> Result<ColorWriter> {
+    let colored = choice.resolve(Stream::Stderr);
+    Ok(ColorWriter::new(Box::new(io::stderr()), colored))
+}
@@ -11,6 +11,44 @@ pub enum Error {
     #[error("fmt error")]
     FmtError(#[from] std::fmt::Error),
 
+    // auto-convert file watcher Errors
+    #[cfg(feature = "watch")]
+    #[error("watch error")]
+    WatchError(#[from] notify::Error),
+
+    // auto-convert json Errors
+    #[cfg(feature = "json")]
+    #[error("json error")]
+    JsonError(#[from] serde_json::Error),
+
+    // auto-convert zip archive Errors
+    #[cfg(feature = "archive")]
+    #[error("archive error")]
+    ArchiveError(#[from] zip::result::ZipError),
+
+    // auto-convert image Errors
+    #[cfg(feature = "media")]
+    #[error("image error")]
+    ImageError(#[from] image::ImageError),
+
+    // auto-convert sqlite Errors
+    #[cfg(feature = "sqlite")]
+    #[error("sqlite error")]
+    SqliteError(#[from] rusqlite::Error),
+
+    // an http request failed
+    #[cfg(feature = "net")]
+    #[error("http error {0}")]
+    HttpError(String),
+
+    // the work was cancelled, e.g. by SIGINT
+    #[error("interrupted")]
+    Interrupted,
+
+    // the other end of a remote scan failed
+    #[error("remote error {0}")]
+    RemoteError(String),
+
     // log Error
     #[error("log error {0}")]
     LogError(String),
@@ -26,6 +64,31 @@ pub enum Error {
     // invalid file format
     #[error("invalid file format {0}")]
     InvalidFormat(String),
+
+    // two files of different sizes have the same digest, e.g. version 1
+    // fast digests of files with the same head and tail
+    #[error("digest collision {0} between {1} and {2}")]
+    DigestCollision(String, std::path::PathBuf, std::path::PathBuf),
+
+    // a background task failed or panicked
+    #[error("task error {0}")]
+    TaskError(String),
+
+    // a signed index failed to verify
+    #[error("signature error {0}")]
+    SignatureError(String),
+
+    // invalid command line or builder argument
+    #[error("invalid argument {0}")]
+    InvalidArgument(String),
+
+    // a builder wasn't told what to build from
+    #[error("missing source {0}")]
+    MissingSource(String),
+
+    // builder options that can't be used together
+    #[error("conflicting options {0}")]
+    ConflictingOptions(String),
 }
 
 // create a convenient alias
@@ -0,0 +1,215 @@
+use crate::{
+    error::Error,
+    Result,
+    fs::{IndexStats, TreeIndexBuilder, TreeListBuilder}
+};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::ptr;
+
+thread_local! {
+    // what went wrong last on this thread, for bp_last_error
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// A BpIndex is an index of a directory tree for C callers, as the groups
+/// of duplicate files in it. The groups are ordered by the bytes they
+/// waste, largest first, and the paths in a group have the primary first.
+/// It is made by bp_index_new and freed by bp_index_free, the strings it
+/// hands out live as long as it does.
+pub struct BpIndex {
+    entries: usize,
+    groups: Vec<BpGroup>,
+    by_path: HashMap<PathBuf, usize>
+}
+
+struct BpGroup {
+    size: u64,
+    paths: Vec<CString>
+}
+
+impl BpIndex {
+
+    fn new(root: &Path, fast: bool) -> Result<Self> {
+        let ti = TreeIndexBuilder::new()
+            .with_dupes(true)
+            .from_scan(TreeListBuilder::new().fast(fast).path(root))
+            .build()?;
+
+        let mut by_path = HashMap::new();
+        let mut groups = Vec::new();
+        for (i, g) in IndexStats::new(&ti, usize::MAX).top.into_iter().enumerate() {
+            let mut paths = Vec::new();
+            for p in std::iter::once(g.item.item_path()).chain(g.dupes.iter()) {
+                let p = p.path();
+                paths.push(c_path(&p)?);
+                by_path.insert(p, i);
+            }
+            groups.push(BpGroup { size: g.item.size, paths });
+        }
+        Ok(Self {
+            entries: ti.idx.len(),
+            groups,
+            by_path
+        })
+    }
+
+    fn group(&self, group: usize) -> Option<&BpGroup> {
+        self.groups.get(group)
+    }
+}
+
+/// Scans the directory tree at root and returns its index, or NULL if it
+/// couldn't be indexed, bp_last_error says why. A non-zero fast uses fast
+/// digests, see TreeItemBuilder::fast.
+///
+/// # Safety
+///
+/// root must be NULL or a NUL terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn bp_index_new(root: *const c_char, fast: c_int) -> *mut BpIndex {
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        BpIndex::new(&native_path(root)?, fast != 0)
+    }));
+    match result {
+        Ok(Ok(index)) => Box::into_raw(Box::new(index)),
+        Ok(Err(e)) => {
+            set_last_error(&format!("{:?}", e));
+            ptr::null_mut()
+        },
+        Err(_) => {
+            set_last_error("the scan panicked");
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Frees an index and the strings it handed out, NULL is ignored
+///
+/// # Safety
+///
+/// index must be NULL or from bp_index_new and not freed already.
+#[no_mangle]
+pub unsafe extern "C" fn bp_index_free(index: *mut BpIndex) {
+    if !index.is_null() {
+        drop(Box::from_raw(index));
+    }
+}
+
+/// The number of distinct files in the index
+///
+/// # Safety
+///
+/// index must be from bp_index_new and not freed.
+#[no_mangle]
+pub unsafe extern "C" fn bp_index_entries(index: *const BpIndex) -> usize {
+    (*index).entries
+}
+
+/// The number of groups of duplicate files in the index
+///
+/// # Safety
+///
+/// index must be from bp_index_new and not freed.
+#[no_mangle]
+pub unsafe extern "C" fn bp_index_group_count(index: *const BpIndex) -> usize {
+    (*index).groups.len()
+}
+
+/// The size of each of the files in the group, 0 for a group that doesn't
+/// exist
+///
+/// # Safety
+///
+/// index must be from bp_index_new and not freed.
+#[no_mangle]
+pub unsafe extern "C" fn bp_index_group_size(index: *const BpIndex, group: usize) -> u64 {
+    (*index).group(group).map(|g| g.size).unwrap_or(0)
+}
+
+/// The number of paths in the group, 0 for a group that doesn't exist
+///
+/// # Safety
+///
+/// index must be from bp_index_new and not freed.
+#[no_mangle]
+pub unsafe extern "C" fn bp_index_group_len(index: *const BpIndex, group: usize) -> usize {
+    (*index).group(group).map(|g| g.paths.len()).unwrap_or(0)
+}
+
+/// A path in the group, the first is the primary, or NULL if there is no
+/// such path. The string belongs to the index.
+///
+/// # Safety
+///
+/// index must be from bp_index_new and not freed.
+#[no_mangle]
+pub unsafe extern "C" fn bp_index_group_path(index: *const BpIndex, group: usize, path: usize) -> *const c_char {
+    (*index).group(group)
+        .and_then(|g| g.paths.get(path))
+        .map(|p| p.as_ptr())
+        .unwrap_or(ptr::null())
+}
+
+/// The group with the path in it or -1 if the path has no duplicates or
+/// isn't in the index. Paths are matched as they are in the index, the
+/// root joined with the path under it.
+///
+/// # Safety
+///
+/// index must be from bp_index_new and not freed, path must be NULL or a
+/// NUL terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn bp_index_dupes_of(index: *const BpIndex, path: *const c_char) -> isize {
+    match native_path(path) {
+        Ok(p) => (*index).by_path.get(&p).map(|g| *g as isize).unwrap_or(-1),
+        Err(_) => -1
+    }
+}
+
+/// What went wrong with the last call on this thread that failed, or NULL.
+/// The string is good until the next call that fails on the thread.
+#[no_mangle]
+pub extern "C" fn bp_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| e.borrow().as_ref().map(|s| s.as_ptr()).unwrap_or(ptr::null()))
+}
+
+fn set_last_error(message: &str) {
+    let message = CString::new(message.replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|e| *e.borrow_mut() = Some(message));
+}
+
+// a C string from the caller as a path, paths are bytes on unix
+unsafe fn native_path(s: *const c_char) -> Result<PathBuf> {
+    if s.is_null() {
+        return Err(Error::InvalidArgument("the path is NULL".to_string()));
+    }
+    let s = CStr::from_ptr(s);
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        Ok(PathBuf::from(std::ffi::OsStr::from_bytes(s.to_bytes())))
+    }
+    #[cfg(not(unix))]
+    {
+        s.to_str()
+            .map(PathBuf::from)
+            .map_err(|_| Error::InvalidArgument("the path isn't UTF-8".to_string()))
+    }
+}
+
+// a path as a C string for the caller, no path has a NUL in it
+fn c_path(p: &Path) -> Result<CString> {
+    #[cfg(unix)]
+    let bytes = {
+        use std::os::unix::ffi::OsStrExt;
+        p.as_os_str().as_bytes().to_vec()
+    };
+    #[cfg(not(unix))]
+    let bytes = p.to_string_lossy().into_owned().into_bytes();
+    CString::new(bytes).map_err(|_| Error::InvalidArgument(format!("{} has a NUL in it", p.to_string_lossy())))
+}
@@ -0,0 +1,490 @@
+use crate::{
+    error::Error,
+    Result,
+    fs::{
+        copy_file,
+        copy_verified,
+        digest_matches,
+        hardlink_file,
+        os_path,
+        read_manifest,
+        same_contents,
+        stats::json_string,
+        treeitem::{escape_path, unescape_path},
+        MoveRecord,
+        PreserveOptions,
+        TempFile,
+        TreeIndexHeader
+    }
+};
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::{Chars, FromStr};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// What was done to a file
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    /// src was copied to dst
+    Copy,
+
+    /// src was moved to dst
+    Move,
+
+    /// src was deleted
+    Delete,
+
+    /// dst was made to share its data with src by the file system
+    Dedupe,
+
+    /// dst was replaced with a hard link to src
+    Hardlink,
+
+    /// the empty directory src was removed
+    Rmdir,
+
+    /// the directory src was made
+    Mkdir
+}
+
+impl FromStr for Action {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "copy" => Ok(Action::Copy),
+            "move" => Ok(Action::Move),
+            "delete" => Ok(Action::Delete),
+            "dedupe" => Ok(Action::Dedupe),
+            "hardlink" => Ok(Action::Hardlink),
+            "rmdir" => Ok(Action::Rmdir),
+            "mkdir" => Ok(Action::Mkdir),
+            _ => Err(Error::InvalidFormat(format!("unknown action {}", s)))
+        }
+    }
+}
+
+impl Display for Action {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error>
+    {
+        match self {
+            Action::Copy => write!(f, "copy"),
+            Action::Move => write!(f, "move"),
+            Action::Delete => write!(f, "delete"),
+            Action::Dedupe => write!(f, "dedupe"),
+            Action::Hardlink => write!(f, "hardlink"),
+            Action::Rmdir => write!(f, "rmdir"),
+            Action::Mkdir => write!(f, "mkdir")
+        }
+    }
+}
+
+/// How an action turned out
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ActionResult {
+    /// The action was carried out
+    Done,
+
+    /// The action was only planned, nothing was touched
+    DryRun,
+
+    /// The action was tried and failed, the file was left as it was
+    Failed
+}
+
+impl FromStr for ActionResult {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "done" => Ok(ActionResult::Done),
+            "dry-run" => Ok(ActionResult::DryRun),
+            "failed" => Ok(ActionResult::Failed),
+            _ => Err(Error::InvalidFormat(format!("unknown action result {}", s)))
+        }
+    }
+}
+
+impl Display for ActionResult {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error>
+    {
+        match self {
+            ActionResult::Done => write!(f, "done"),
+            ActionResult::DryRun => write!(f, "dry-run"),
+            ActionResult::Failed => write!(f, "failed")
+        }
+    }
+}
+
+/// One entry in an action log. dst is only set for the actions that have a
+/// second path and the digest and size are those of the file acted on, or
+/// empty and zero for directories.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ActionRecord {
+    pub action: Action,
+    pub src: PathBuf,
+    pub dst: Option<PathBuf>,
+    pub digest: String,
+    pub size: u64,
+    pub timestamp: SystemTime,
+    pub result: ActionResult
+}
+
+impl ActionRecord {
+
+    /// Makes a record of the action on src done now, with no digest
+    pub fn new(action: Action, src: &Path, result: ActionResult) -> Self {
+        Self {
+            action,
+            src: src.to_path_buf(),
+            dst: None,
+            digest: String::new(),
+            size: 0,
+            timestamp: SystemTime::now(),
+            result
+        }
+    }
+
+    /// Sets the second path of the action
+    pub fn dst(mut self, dst: &Path) -> Self {
+        self.dst = Some(dst.to_path_buf());
+        self
+    }
+
+    /// Sets the digest and size of the file acted on
+    pub fn file(mut self, digest: &str, size: u64) -> Self {
+        self.digest = digest.to_string();
+        self.size = size;
+        self
+    }
+
+    /// Returns the action that reverses this one if it was done and can be
+    /// reversed. Deletes can't be and neither can de-duping or linking,
+    /// which leave both paths with the same contents anyway.
+    pub fn undo(&self) -> Option<ActionRecord> {
+        if self.result != ActionResult::Done {
+            return None;
+        }
+        let mut undo = ActionRecord::new(self.action, &self.src, ActionResult::Done)
+            .file(&self.digest, self.size);
+        match (self.action, &self.dst) {
+            (Action::Copy, Some(dst)) => {
+                undo.action = Action::Delete;
+                undo.src = dst.clone();
+            },
+            (Action::Move, Some(dst)) => {
+                undo.src = dst.clone();
+                undo.dst = Some(self.src.clone());
+            },
+            (Action::Rmdir, _) => undo.action = Action::Mkdir,
+            (Action::Mkdir, _) => undo.action = Action::Rmdir,
+            _ => return None
+        }
+        Some(undo)
+    }
+}
+
+// one JSON object per line. The paths are escaped the way text indexes
+// escape them so names that aren't UTF-8 survive, paths=escaped says so.
+impl Display for ActionRecord {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error>
+    {
+        let dst = match &self.dst {
+            Some(d) => json_string(&escape_path(d)?),
+            None => "null".to_string()
+        };
+        let timestamp = self.timestamp.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        writeln!(f, "{{\"action\":\"{}\",\"src\":{},\"dst\":{},\"digest\":{},\"size\":{},\"timestamp\":{},\"result\":\"{}\",\"paths\":\"escaped\"}}",
+                 self.action, json_string(&escape_path(&self.src)?), dst, json_string(&self.digest),
+                 self.size, timestamp, self.result)
+    }
+}
+
+/// An ActionLog writes a record of every action as it happens, one JSON
+/// object per line, so there is a record of what was done to undo it with
+/// even if the run doesn't finish
+pub struct ActionLog {
+    w: Box<dyn Write>
+}
+
+impl ActionLog {
+
+    pub fn new(w: Box<dyn Write>) -> Self {
+        Self { w }
+    }
+
+    /// Writes the header of the index the digests in the records are from,
+    /// before the first record, so undoing the actions can check files
+    /// against them
+    pub fn header(&mut self, header: &TreeIndexHeader) -> Result<()> {
+        writeln!(self.w, "{{\"header\":{}}}", json_string(header.to_string().trim_end()))?;
+        self.w.flush()?;
+        Ok(())
+    }
+
+    /// Writes the record and flushes it
+    pub fn record(&mut self, record: &ActionRecord) -> Result<()> {
+        write!(self.w, "{}", record)?;
+        self.w.flush()?;
+        Ok(())
+    }
+}
+
+/// Reads an action log, or a move manifest which is read as a log of moves
+pub fn read_action_log<R: Read>(r: R) -> Result<Vec<ActionRecord>> {
+    Ok(read_action_log_with_header(r)?.1)
+}
+
+/// Reads an action log and the header of the index its digests are from,
+/// None for logs and move manifests that don't have one
+pub fn read_action_log_with_header<R: Read>(r: R) -> Result<(Option<TreeIndexHeader>, Vec<ActionRecord>)> {
+    let mut r = BufReader::new(r);
+    // move manifests were written before there were action logs
+    if r.fill_buf()?.starts_with(MoveRecord::header().trim_end().as_bytes()) {
+        let records = read_manifest(r)?
+            .into_iter()
+            .map(|m| ActionRecord::new(Action::Move, &m.from, ActionResult::Done)
+                .dst(&m.to)
+                .file(&m.digest, m.size))
+            .collect();
+        return Ok((None, records));
+    }
+
+    let mut header = None;
+    let mut records = Vec::new();
+    for (n, line) in r.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let invalid = || Error::InvalidFormat(format!("invalid action log line {}", n + 1));
+        let mut fields = parse_object(&line).ok_or_else(invalid)?;
+        let mut string = |k: &str| match fields.remove(k) {
+            Some(Value::String(s)) => Ok(Some(s)),
+            Some(Value::Null) | None => Ok(None),
+            _ => Err(invalid())
+        };
+        if let Some(h) = string("header")? {
+            header = Some(h.parse::<TreeIndexHeader>()?);
+            continue;
+        }
+        // logs written before paths were escaped have them as they are
+        let escaped = string("paths")?.as_deref() == Some("escaped");
+        let path = |p: String| if escaped { unescape_path(p.as_bytes()) } else { Ok(PathBuf::from(p)) };
+        let action = string("action")?.ok_or_else(invalid)?.parse()?;
+        let src = path(string("src")?.ok_or_else(invalid)?)?;
+        let dst = string("dst")?.map(path).transpose()?;
+        let digest = string("digest")?.unwrap_or_default();
+        let result = string("result")?.ok_or_else(invalid)?.parse()?;
+        let mut number = |k: &str| match fields.remove(k) {
+            Some(Value::Number(n)) => Ok(n),
+            None => Ok(0),
+            _ => Err(invalid())
+        };
+        records.push(ActionRecord {
+            action,
+            src,
+            dst,
+            digest,
+            size: number("size")?,
+            timestamp: UNIX_EPOCH + Duration::from_secs(number("timestamp")?),
+            result
+        });
+    }
+    Ok((header, records))
+}
+
+/// Carries out the action. It refuses to replace a file that is in the way
+/// of a copy or move, and to delete a file that isn't the one the record is
+/// about. A file with a digest in the record has to match it, which needs
+/// the header of the index it is from, and a file without one has to be the
+/// size in the record. A move between file systems is only finished once
+/// the copy is checked the same way, or against the original when there is
+/// no digest. Keyed indexes need their key.
+pub fn apply_action(record: &ActionRecord, header: Option<&TreeIndexHeader>, key: Option<&[u8]>,
+                    preserve: &PreserveOptions) -> Result<()> {
+    let dst = || record.dst.as_deref()
+        .ok_or_else(|| Error::InvalidArgument(format!("{} of {} has no destination", record.action, record.src.to_string_lossy())));
+    let clear = |p: &Path| match fs::symlink_metadata(os_path(p)) {
+        Ok(_) => Err(Error::InvalidArgument(format!("{} is in the way", p.to_string_lossy()))),
+        Err(_) => Ok(())
+    };
+    match record.action {
+        Action::Copy => {
+            let dst = dst()?;
+            clear(dst)?;
+            copy_file(&record.src, dst, preserve)?;
+        },
+        Action::Move => {
+            let dst = dst()?;
+            clear(dst)?;
+            if let Some(parent) = dst.parent() {
+                fs::create_dir_all(os_path(parent))?;
+            }
+            match fs::rename(os_path(&record.src), os_path(dst)) {
+                Ok(()) => {},
+                Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+                    if !copy_checked(record, dst, header, key, preserve)? {
+                        return Err(Error::InvalidArgument(format!("the copy of {} didn't verify, it wasn't moved",
+                                                                  record.src.to_string_lossy())));
+                    }
+                    fs::remove_file(os_path(&record.src))?;
+                },
+                Err(e) => return Err(e.into())
+            }
+        },
+        Action::Delete => {
+            let size = fs::metadata(os_path(&record.src))?.len();
+            if size != record.size {
+                return Err(Error::InvalidArgument(format!("{} is {} bytes, not {}, it has changed",
+                                                          record.src.to_string_lossy(), size, record.size)));
+            }
+            if !record.digest.is_empty() {
+                let header = header.ok_or_else(|| Error::InvalidArgument(format!(
+                    "there is no index header to check {} against, it wasn't deleted", record.src.to_string_lossy())))?;
+                if !digest_matches(header, &record.digest, &record.src, key)? {
+                    return Err(Error::InvalidArgument(format!("{} doesn't match its digest, it has changed",
+                                                              record.src.to_string_lossy())));
+                }
+            }
+            fs::remove_file(os_path(&record.src))?;
+        },
+        Action::Rmdir => fs::remove_dir(os_path(&record.src))?,
+        Action::Mkdir => fs::create_dir_all(os_path(&record.src))?,
+        Action::Hardlink => hardlink_file(&record.src, dst()?)?,
+        Action::Dedupe => return Err(Error::InvalidArgument("de-duping is done by the file system".to_string()))
+    }
+    Ok(())
+}
+
+// copies the file a move is about to dst through a temp file next to it,
+// checking the copy against the digest when there is one and the original
+// otherwise. Returns false if it didn't match.
+fn copy_checked(record: &ActionRecord, dst: &Path, header: Option<&TreeIndexHeader>, key: Option<&[u8]>,
+                preserve: &PreserveOptions) -> Result<bool> {
+    if let Some(header) = header.filter(|_| !record.digest.is_empty()) {
+        return copy_verified(header, &record.digest, &record.src, dst, key, 0, preserve);
+    }
+    let tmp = TempFile::next_to(dst)?;
+    copy_file(&record.src, tmp.path(), preserve)?;
+    if !same_contents(&record.src, tmp.path())? {
+        return Ok(false);
+    }
+    tmp.persist(dst)?;
+    Ok(true)
+}
+
+// the values a flat JSON object in a log can have
+enum Value {
+    String(String),
+    Number(u64),
+    Null
+}
+
+// parses one flat JSON object of strings, whole numbers and nulls, which is
+// all an action log line has
+fn parse_object(line: &str) -> Option<HashMap<String, Value>> {
+    let mut fields = HashMap::new();
+    let mut c = line.trim().chars();
+    if c.next()? != '{' {
+        return None;
+    }
+    loop {
+        match skip_ws(&mut c)? {
+            '}' if fields.is_empty() => break,
+            '"' => {},
+            _ => return None
+        }
+        let key = parse_string(&mut c)?;
+        if skip_ws(&mut c)? != ':' {
+            return None;
+        }
+        let (value, next) = match skip_ws(&mut c)? {
+            '"' => (Value::String(parse_string(&mut c)?), skip_ws(&mut c)?),
+            'n' => {
+                if c.by_ref().take(3).collect::<String>() != "ull" {
+                    return None;
+                }
+                (Value::Null, skip_ws(&mut c)?)
+            },
+            d if d.is_ascii_digit() => {
+                let mut n = d.to_digit(10)? as u64;
+                loop {
+                    match c.next()? {
+                        d if d.is_ascii_digit() => n = n.checked_mul(10)?.checked_add(d.to_digit(10)? as u64)?,
+                        d if d.is_whitespace() => break (Value::Number(n), skip_ws(&mut c)?),
+                        d => break (Value::Number(n), d)
+                    }
+                }
+            },
+            _ => return None
+        };
+        fields.insert(key, value);
+        match next {
+            ',' => continue,
+            '}' => break,
+            _ => return None
+        }
+    }
+    if skip_ws(&mut c).is_some() {
+        return None;
+    }
+    Some(fields)
+}
+
+// returns the next character that isn't whitespace
+fn skip_ws(c: &mut Chars) -> Option<char> {
+    c.find(|ch| !ch.is_whitespace())
+}
+
+// parses the rest of a string after the opening quote
+fn parse_string(c: &mut Chars) -> Option<String> {
+    let mut s = String::new();
+    loop {
+        match c.next()? {
+            '"' => return Some(s),
+            '\\' => match c.next()? {
+                '"' => s.push('"'),
+                '\\' => s.push('\\'),
+                '/' => s.push('/'),
+                'n' => s.push('\n'),
+                'r' => s.push('\r'),
+                't' => s.push('\t'),
+                'b' => s.push('\u{8}'),
+                'f' => s.push('\u{c}'),
+                'u' => {
+                    let hi = hex4(c)?;
+                    // characters outside the BMP are a surrogate pair
+                    let ch = if (0xd800..0xdc00).contains(&hi) {
+                        if c.next()? != '\\' || c.next()? != 'u' {
+                            return None;
+                        }
+                        let lo = hex4(c)?;
+                        if !(0xdc00..0xe000).contains(&lo) {
+                            return None;
+                        }
+                        char::from_u32(0x10000 + ((hi - 0xd800) << 10) + (lo - 0xdc00))?
+                    } else {
+                        char::from_u32(hi)?
+                    };
+                    s.push(ch);
+                },
+                _ => return None
+            },
+            ch => s.push(ch)
+        }
+    }
+}
+
+// parses the four hex digits of a \u escape, from_str_radix alone would
+// take a sign too
+fn hex4(c: &mut Chars) -> Option<u32> {
+    let h: String = c.by_ref().take(4).collect();
+    if h.len() != 4 || !h.chars().all(|ch| ch.is_ascii_hexdigit()) {
+        return None;
+    }
+    u32::from_str_radix(&h, 16).ok()
+}
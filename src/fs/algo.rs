@@ -0,0 +1,141 @@
+use crate::{
+    error::Error,
+    Result,
+    fs::multihash::hex
+};
+use blake2b_simd::{Params, State};
+use sha2::{Digest, Sha256};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// The digest algorithm used to hash files. Blake2b is the default and is
+/// what indexes without an algo header field use. Sha256 makes digests that
+/// sha256sum and other standard tools understand.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Algorithm {
+    #[default]
+    Blake2b,
+    Sha256
+}
+
+impl Algorithm {
+
+    /// The name used for the algorithm in BSD style checksum files
+    pub fn bsd_tag(&self) -> &'static str {
+        match self {
+            Algorithm::Blake2b => "BLAKE2b-256",
+            Algorithm::Sha256 => "SHA256"
+        }
+    }
+
+    /// Returns the algorithm for a BSD style checksum tag
+    pub fn from_bsd_tag(tag: &str) -> Option<Self> {
+        match tag {
+            "BLAKE2b-256" => Some(Algorithm::Blake2b),
+            "SHA256" => Some(Algorithm::Sha256),
+            _ => None
+        }
+    }
+
+    pub(crate) fn hasher(&self) -> Hasher {
+        match self {
+            Algorithm::Blake2b => Hasher::Blake2b(Box::new(Params::new().hash_length(32).to_state())),
+            Algorithm::Sha256 => Hasher::Sha256(Box::new(Sha256::new()))
+        }
+    }
+
+    // a hasher keyed with a secret. Blake2b has keyed hashing built in and
+    // takes keys up to 64 bytes, Sha256 is keyed as HMAC-SHA256.
+    pub(crate) fn keyed_hasher(&self, key: &[u8]) -> Result<Hasher> {
+        if key.is_empty() {
+            return Err(Error::InvalidArgument("the hashing key is empty".to_string()));
+        }
+        match self {
+            Algorithm::Blake2b => {
+                if key.len() > blake2b_simd::KEYBYTES {
+                    return Err(Error::InvalidArgument(
+                        format!("blake2b keys can be at most {} bytes", blake2b_simd::KEYBYTES)));
+                }
+                Ok(Hasher::Blake2b(Box::new(Params::new().hash_length(32).key(key).to_state())))
+            },
+            Algorithm::Sha256 => {
+                // keys longer than the block size are hashed first
+                let mut block = [0u8; HMAC_BLOCK];
+                if key.len() > HMAC_BLOCK {
+                    block[..32].copy_from_slice(&Sha256::digest(key));
+                } else {
+                    block[..key.len()].copy_from_slice(key);
+                }
+                let mut inner = Sha256::new();
+                inner.update(block.map(|b| b ^ 0x36));
+                Ok(Hasher::HmacSha256(Box::new(inner), Box::new(block.map(|b| b ^ 0x5c))))
+            }
+        }
+    }
+
+    /// Names a hashing key without giving it away, the start of the keyed
+    /// digest of a fixed string. Indexes made with the same key and
+    /// algorithm have the same key id.
+    pub fn key_id(&self, key: &[u8]) -> Result<String> {
+        let mut h = self.keyed_hasher(key)?;
+        h.update(b"treeindex key id");
+        Ok(hex(&h.finalize()[..8]))
+    }
+}
+
+// the SHA-256 block size used to pad HMAC keys
+const HMAC_BLOCK: usize = 64;
+
+impl FromStr for Algorithm {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "blake2b" | "blake2b-256" => Ok(Algorithm::Blake2b),
+            "sha256" | "sha-256" => Ok(Algorithm::Sha256),
+            _ => Err(Error::InvalidArgument(format!("unknown digest algorithm {}", s)))
+        }
+    }
+}
+
+impl Display for Algorithm {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error>
+    {
+        match self {
+            Algorithm::Blake2b => write!(f, "blake2b"),
+            Algorithm::Sha256 => write!(f, "sha256")
+        }
+    }
+}
+
+// the running state of a file digest
+pub(crate) enum Hasher {
+    Blake2b(Box<State>),
+    Sha256(Box<Sha256>),
+
+    // the inner hash and the outer padded key
+    HmacSha256(Box<Sha256>, Box<[u8; HMAC_BLOCK]>)
+}
+
+impl Hasher {
+
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Blake2b(s) => { s.update(data); },
+            Hasher::Sha256(s) | Hasher::HmacSha256(s, _) => s.update(data)
+        }
+    }
+
+    pub(crate) fn finalize(self) -> Vec<u8> {
+        match self {
+            Hasher::Blake2b(s) => s.finalize().as_bytes().to_vec(),
+            Hasher::Sha256(s) => s.finalize().to_vec(),
+            Hasher::HmacSha256(s, opad) => {
+                let mut outer = Sha256::new();
+                outer.update(&opad[..]);
+                outer.update(s.finalize());
+                outer.finalize().to_vec()
+            }
+        }
+    }
+}
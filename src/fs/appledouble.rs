@@ -0,0 +1,63 @@
+use crate::{
+    error::Error,
+    Result
+};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+// the name of the Finder's per directory settings file
+const DS_STORE: &str = ".DS_Store";
+
+// AppleDouble files are the name of the file they belong to after this
+const APPLE_DOUBLE_PREFIX: &str = "._";
+
+/// What scans and dedup do with the metadata files macOS leaves on file
+/// systems that can't hold its extended attributes, like SMB shares and FAT
+/// drives: the ._name AppleDouble file holding the resource fork and Finder
+/// info of name, and .DS_Store. Many of them are identical so they show up
+/// as dupes. Keep treats them like any other file. Ignore leaves them out
+/// of scans and plans. Pair leaves them out too but deleting, moving or
+/// copying a file does the same to its AppleDouble file.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AppleDoublePolicy {
+    #[default]
+    Keep,
+    Ignore,
+    Pair
+}
+
+impl FromStr for AppleDoublePolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "keep" => Ok(AppleDoublePolicy::Keep),
+            "ignore" => Ok(AppleDoublePolicy::Ignore),
+            "pair" => Ok(AppleDoublePolicy::Pair),
+            _ => Err(Error::InvalidArgument(format!("unknown AppleDouble policy {}", s)))
+        }
+    }
+}
+
+/// Returns true if the path is an AppleDouble file or a .DS_Store
+pub fn is_mac_metadata(path: &Path) -> bool {
+    match path.file_name() {
+        Some(name) => {
+            let name = name.to_string_lossy();
+            name == DS_STORE || (name.len() > APPLE_DOUBLE_PREFIX.len() && name.starts_with(APPLE_DOUBLE_PREFIX))
+        },
+        None => false
+    }
+}
+
+/// Returns the path of the AppleDouble file that goes with the file, ._name
+/// next to it, or None for a path without a name or one that is macOS
+/// metadata itself
+pub fn apple_double_path(path: &Path) -> Option<PathBuf> {
+    if is_mac_metadata(path) {
+        return None;
+    }
+    let mut name = std::ffi::OsString::from(APPLE_DOUBLE_PREFIX);
+    name.push(path.file_name()?);
+    Some(path.with_file_name(name))
+}
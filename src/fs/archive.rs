@@ -0,0 +1,105 @@
+use crate::{
+    error::Error,
+    Result
+};
+use flate2::read::GzDecoder;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+
+/// The character between the path of an archive and the path of a member
+/// inside it, e.g. backup.zip!photos/cat.jpg
+pub const MEMBER_SEPARATOR: char = '!';
+
+/// The kinds of archives a scan can look inside
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArchiveKind {
+    Zip,
+    Tar,
+    TarGz
+}
+
+impl ArchiveKind {
+
+    /// Returns the kind of archive from the file name or None if it isn't
+    /// one, the contents aren't looked at
+    pub fn of(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?.to_lowercase();
+        if name.ends_with(".zip") {
+            Some(ArchiveKind::Zip)
+        } else if name.ends_with(".tar") {
+            Some(ArchiveKind::Tar)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(ArchiveKind::TarGz)
+        } else {
+            None
+        }
+    }
+}
+
+/// Returns the path of a member of an archive, the member path always uses
+/// forward slashes
+pub fn member_path(archive: &Path, member: &Path) -> PathBuf {
+    let member: Vec<_> = member.components().map(|c| c.as_os_str().to_string_lossy()).collect();
+    let mut path = archive.as_os_str().to_os_string();
+    path.push(MEMBER_SEPARATOR.to_string());
+    path.push(member.join("/"));
+    PathBuf::from(path)
+}
+
+/// Splits the path of an archive member into the path of the archive and
+/// the path inside it. Returns None for paths that aren't in an archive.
+pub fn split_member_path(path: &Path) -> Option<(PathBuf, PathBuf)> {
+    let p = path.to_str()?;
+    for (i, _) in p.match_indices(MEMBER_SEPARATOR) {
+        let archive = Path::new(&p[..i]);
+        if ArchiveKind::of(archive).is_some() {
+            return Some((archive.to_path_buf(), PathBuf::from(&p[i + 1..])));
+        }
+    }
+    None
+}
+
+// calls f with the path, size and contents of every regular file in the
+// archive. archives inside the archive are treated as plain files.
+pub(crate) fn read_members(path: &Path, f: &mut dyn FnMut(&Path, u64, &mut dyn Read) -> Result<()>) -> Result<()> {
+    match ArchiveKind::of(path) {
+        Some(ArchiveKind::Zip) => read_zip(path, f),
+        Some(ArchiveKind::Tar) => read_tar(BufReader::new(File::open(path)?), f),
+        Some(ArchiveKind::TarGz) => read_tar(GzDecoder::new(BufReader::new(File::open(path)?)), f),
+        None => Err(Error::InvalidArgument(format!("{} isn't an archive", path.to_string_lossy())))
+    }
+}
+
+fn read_zip(path: &Path, f: &mut dyn FnMut(&Path, u64, &mut dyn Read) -> Result<()>) -> Result<()> {
+    let mut zip = zip::ZipArchive::new(BufReader::new(File::open(path)?))?;
+    for i in 0..zip.len() {
+        let mut entry = zip.by_index(i)?;
+        if !entry.is_file() {
+            continue;
+        }
+
+        // names that would escape the archive, like ../x, are left out
+        let name = match entry.enclosed_name() {
+            Some(name) => name,
+            None => continue
+        };
+        let size = entry.size();
+        f(&name, size, &mut entry)?;
+    }
+    Ok(())
+}
+
+fn read_tar<R: Read>(r: R, f: &mut dyn FnMut(&Path, u64, &mut dyn Read) -> Result<()>) -> Result<()> {
+    let mut tar = tar::Archive::new(r);
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let name = entry.path()?.into_owned();
+        let size = entry.header().size()?;
+        f(&name, size, &mut entry)?;
+    }
+    Ok(())
+}
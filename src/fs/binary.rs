@@ -0,0 +1,248 @@
+use crate::{
+    error::Error,
+    Result,
+    fs::{
+        chunks::CHUNKS_TAG,
+        fuzzy::FUZZY_TAG,
+        media::IMAGE_HASH_TAG,
+        metadata::METADATA_TAG,
+        multihash::{hex, varint},
+        treeindex::IndexLine,
+        treeitem::{index_path, native_path},
+        Digest,
+        ItemDigest,
+        TreeIndexHeader,
+        TreeItem,
+        TreeItemDupes
+    }
+};
+use std::io::{BufRead, Read, Write};
+use std::sync::Arc;
+
+/// Binary indexes start with these bytes followed by the format version.
+/// Text indexes start with a header line or a digest so the two can't be
+/// mistaken for each other.
+pub const BINARY_MAGIC: &[u8; 4] = b"TIDX";
+
+/// The version of the binary format that is written
+pub const BINARY_VERSION: u8 = 1;
+
+// the digest of an entry is stored as its text or as the bytes of its hex
+const DIGEST_TEXT: u8 = 0;
+const DIGEST_HEX: u8 = 1;
+
+// marks the end of the entries so a truncated file is an error
+const END: u8 = 0xff;
+
+/// Returns true if the bytes are the start of a binary index
+pub fn is_binary(start: &[u8]) -> bool {
+    start.starts_with(BINARY_MAGIC)
+}
+
+// A BinaryWriter writes an index in the binary format one entry at a time.
+// The format is the magic bytes and version, the text header line and then
+// one record per entry:
+//
+//   digest kind, digest length, digest bytes
+//   size
+//   path count
+//   for each path: bytes shared with the path before, length of the rest,
+//   the rest, length of the side lines, the side lines
+//
+// numbers are LEB128 varints. The side lines are the chunk digests, fuzzy
+// digests, image hashes and metadata in their text form, they are rare
+// enough that a compact encoding isn't worth it. Paths share their prefix
+// with the path written before them so sorted paths take little space.
+pub(crate) struct BinaryWriter<'a> {
+    w: &'a mut dyn Write,
+    last_path: Vec<u8>,
+    buf: Vec<u8>
+}
+
+impl<'a> BinaryWriter<'a> {
+
+    pub(crate) fn new(w: &'a mut dyn Write, header: &TreeIndexHeader) -> Result<Self> {
+        w.write_all(BINARY_MAGIC)?;
+        w.write_all(&[BINARY_VERSION])?;
+        let mut bw = Self { w, last_path: Vec::new(), buf: Vec::new() };
+        bw.bytes(header.to_string().trim_end().as_bytes());
+        bw.flush_buf()?;
+        Ok(bw)
+    }
+
+    pub(crate) fn entry(&mut self, entry: &TreeItemDupes) -> Result<()> {
+        match &entry.item.digest {
+            ItemDigest::Hex(d) => {
+                self.buf.push(DIGEST_HEX);
+                self.bytes(d.as_bytes());
+            },
+            digest => {
+                self.buf.push(DIGEST_TEXT);
+                self.bytes(digest.to_string().as_bytes());
+            }
+        }
+        varint(entry.item.size, &mut self.buf);
+        varint(1 + entry.dupes.len() as u64, &mut self.buf);
+
+        let mut side = String::new();
+        side_lines(&entry.item, &mut side);
+        self.path(&entry.item.path(), &side)?;
+        for d in &entry.dupes {
+            side.clear();
+            if let Some(m) = entry.dupe_metadata.get(d) {
+                side.push_str(&m.to_string());
+            }
+            self.path(&d.path(), &side)?;
+        }
+        self.flush_buf()
+    }
+
+    pub(crate) fn finish(mut self) -> Result<()> {
+        self.buf.push(END);
+        self.flush_buf()?;
+        self.w.flush()?;
+        Ok(())
+    }
+
+    fn path(&mut self, path: &std::path::Path, side: &str) -> Result<()> {
+        let p = index_path(path)?.into_bytes();
+        let shared = p.iter().zip(&self.last_path).take_while(|(a, b)| a == b).count();
+        varint(shared as u64, &mut self.buf);
+        self.bytes(&p[shared..]);
+        self.bytes(side.as_bytes());
+        self.last_path = p;
+        Ok(())
+    }
+
+    fn bytes(&mut self, b: &[u8]) {
+        varint(b.len() as u64, &mut self.buf);
+        self.buf.extend_from_slice(b);
+    }
+
+    fn flush_buf(&mut self) -> Result<()> {
+        self.w.write_all(&self.buf)?;
+        self.buf.clear();
+        Ok(())
+    }
+}
+
+// the side lines written after an item in the text format
+pub(crate) fn side_lines(item: &TreeItem, out: &mut String) {
+    if let Some(chunks) = &item.chunks {
+        out.push_str(&chunks.to_string());
+    }
+    if let Some(fuzzy) = &item.fuzzy {
+        out.push_str(&fuzzy.to_string());
+    }
+    if let Some(hash) = &item.image_hash {
+        out.push_str(&hash.to_string());
+    }
+    if let Some(metadata) = &item.metadata {
+        out.push_str(&metadata.to_string());
+    }
+}
+
+// reads a binary index and hands every path to the function as an item,
+// the same as reading the text format does. returns the header.
+pub(crate) fn read_binary<R: BufRead>(mut r: R, f: &mut dyn FnMut(IndexLine) -> Result<()>) -> Result<TreeIndexHeader> {
+    let mut magic = [0u8; 5];
+    r.read_exact(&mut magic)?;
+    if !is_binary(&magic) {
+        return Err(Error::InvalidFormat("not a binary index".to_string()));
+    }
+    if magic[4] != BINARY_VERSION {
+        return Err(Error::InvalidFormat(format!("unsupported binary index version {}", magic[4])));
+    }
+    let header: TreeIndexHeader = string(&mut r)?.parse()?;
+
+    let mut last_path: Vec<u8> = Vec::new();
+    let mut count = 0u64;
+    loop {
+        let digest = match byte(&mut r)? {
+            DIGEST_HEX => {
+                // indexes written before digests were kept as bytes may
+                // have hex digests of other lengths
+                let b = bytes(&mut r)?;
+                match Digest::from_bytes(&b) {
+                    Ok(d) => ItemDigest::Hex(d),
+                    Err(_) => ItemDigest::from(hex(&b).as_str())
+                }
+            },
+            DIGEST_TEXT => ItemDigest::from(std::str::from_utf8(&bytes(&mut r)?).map_err(|_| corrupt(count))?),
+            END => return Ok(header),
+            _ => return Err(corrupt(count))
+        };
+        let size = read_varint(&mut r)?;
+        let paths = read_varint(&mut r)?;
+        if paths == 0 {
+            return Err(corrupt(count));
+        }
+        for _ in 0..paths {
+            let shared = read_varint(&mut r)? as usize;
+            if shared > last_path.len() {
+                return Err(corrupt(count));
+            }
+            last_path.truncate(shared);
+            last_path.extend(bytes(&mut r)?);
+            let path = String::from_utf8(last_path.clone()).map_err(|_| corrupt(count))?;
+            let mut item = TreeItem::new(&digest, &native_path(path, &header), size);
+            for line in string(&mut r)?.lines() {
+                side_line(&mut item, line)?;
+            }
+            f(IndexLine::Item(item))?;
+        }
+        count += 1;
+    }
+}
+
+// sets the part of the item the side line is for
+pub(crate) fn side_line(item: &mut TreeItem, line: &str) -> Result<()> {
+    if line.starts_with(CHUNKS_TAG) {
+        item.chunks = Some(Arc::new(line.parse()?));
+    } else if line.starts_with(FUZZY_TAG) {
+        item.fuzzy = Some(Arc::new(line.parse()?));
+    } else if line.starts_with(IMAGE_HASH_TAG) {
+        item.image_hash = Some(line.parse()?);
+    } else if line.starts_with(METADATA_TAG) {
+        item.metadata = Some(Arc::new(line.parse()?));
+    } else {
+        return Err(Error::InvalidFormat(format!("unexpected side line {}", line)));
+    }
+    Ok(())
+}
+
+fn corrupt(entry: u64) -> Error {
+    Error::InvalidFormat(format!("corrupt binary index entry {}", entry))
+}
+
+pub(crate) fn byte<R: Read>(r: &mut R) -> Result<u8> {
+    let mut b = [0u8; 1];
+    r.read_exact(&mut b)?;
+    Ok(b[0])
+}
+
+pub(crate) fn read_varint<R: Read>(r: &mut R) -> Result<u64> {
+    let mut n = 0u64;
+    for i in 0..10 {
+        let b = byte(r)?;
+        n |= ((b & 0x7f) as u64) << (7 * i);
+        if b & 0x80 == 0 {
+            return Ok(n);
+        }
+    }
+    Err(Error::InvalidFormat("varint too long in binary index".to_string()))
+}
+
+pub(crate) fn bytes<R: Read>(r: &mut R) -> Result<Vec<u8>> {
+    let len = read_varint(r)?;
+    let mut b = Vec::new();
+    r.take(len).read_to_end(&mut b)?;
+    if b.len() as u64 != len {
+        return Err(Error::InvalidFormat("truncated binary index".to_string()));
+    }
+    Ok(b)
+}
+
+pub(crate) fn string<R: Read>(r: &mut R) -> Result<String> {
+    String::from_utf8(bytes(r)?).map_err(|_| Error::InvalidFormat("invalid text in binary index".to_string()))
+}
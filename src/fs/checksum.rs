@@ -0,0 +1,164 @@
+use crate::{
+    error::Error,
+    Result,
+    fs::{
+        Algorithm,
+        SIZES_FIELD,
+        TreeIndex,
+        TreeItem,
+        os_path
+    }
+};
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// The layout of a checksum file. Gnu is the "digest  path" layout written
+/// by sha256sum and b2sum, Bsd is the "SHA256 (path) = digest" layout written
+/// by the BSD tools and by the GNU tools with --tag.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ChecksumFormat {
+    #[default]
+    Gnu,
+    Bsd
+}
+
+impl FromStr for ChecksumFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "gnu" => Ok(ChecksumFormat::Gnu),
+            "bsd" | "tag" => Ok(ChecksumFormat::Bsd),
+            _ => Err(Error::InvalidArgument(format!("unknown checksum format {}", s)))
+        }
+    }
+}
+
+impl Display for ChecksumFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error>
+    {
+        match self {
+            ChecksumFormat::Gnu => write!(f, "gnu"),
+            ChecksumFormat::Bsd => write!(f, "bsd")
+        }
+    }
+}
+
+// writes one checksum line, paths with backslashes or newlines are escaped
+// and the line starts with a backslash the same as coreutils does it
+pub(crate) fn write_line(w: &mut dyn Write, format: ChecksumFormat, algorithm: Algorithm,
+                         digest: &str, path: &str) -> Result<()> {
+    let escaped = path.contains('\\') || path.contains('\n');
+    let path = if escaped {
+        path.replace('\\', "\\\\").replace('\n', "\\n")
+    } else {
+        path.to_string()
+    };
+    let prefix = if escaped { "\\" } else { "" };
+    match format {
+        ChecksumFormat::Gnu => writeln!(w, "{}{}  {}", prefix, digest, path)?,
+        ChecksumFormat::Bsd => writeln!(w, "{}{} ({}) = {}", prefix, algorithm.bsd_tag(), path, digest)?
+    }
+    Ok(())
+}
+
+// reads GNU and BSD checksum lines into an index. the sizes come from the
+// files on disk and are marked unknown in the header if any are missing.
+pub(crate) fn read_checksums<R: Read>(r: R, algorithm: Algorithm, with_dupes: bool) -> Result<TreeIndex> {
+    let mut ti = TreeIndex::default();
+    ti.header.set_algorithm(algorithm);
+
+    let mut tagged: Option<Algorithm> = None;
+    let mut missing = false;
+    for (n, line) in BufReader::new(r).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (digest, path, tag) = parse_line(&line)
+            .ok_or_else(|| Error::InvalidFormat(format!("invalid checksum line {}", n + 1)))?;
+
+        // tagged lines name their algorithm and they all have to agree
+        if let Some(tag) = tag {
+            let algo = Algorithm::from_bsd_tag(tag)
+                .ok_or_else(|| Error::InvalidFormat(format!("unsupported checksum {} on line {}", tag, n + 1)))?;
+            match tagged {
+                None => {
+                    tagged = Some(algo);
+                    ti.header.set_algorithm(algo);
+                    ti.rekey();
+                },
+                Some(a) if a == algo => {},
+                Some(_) => return Err(Error::InvalidFormat(format!("mixed checksum algorithms on line {}", n + 1)))
+            }
+        }
+
+        let size = match fs::metadata(os_path(&path)) {
+            Ok(meta) => meta.len(),
+            Err(_) => {
+                missing = true;
+                0
+            }
+        };
+
+        let item = TreeItem::new(&digest.to_lowercase(), &path, size);
+        match ti.idx.get_mut(&ti.key(&item)) {
+            Some(entry) => {
+                if with_dupes {
+                    entry.push(item.path);
+                }
+            },
+            None => ti.insert(item)
+        }
+    }
+
+    if missing {
+        ti.header.fields.insert(SIZES_FIELD.to_string(), "unknown".to_string());
+    }
+    Ok(ti)
+}
+
+// returns the digest, the path and the BSD tag if there was one
+fn parse_line(line: &str) -> Option<(&str, PathBuf, Option<&str>)> {
+    let (escaped, line) = match line.strip_prefix('\\') {
+        Some(rest) => (true, rest),
+        None => (false, line)
+    };
+    let unescape = |p: &str| -> PathBuf {
+        if !escaped {
+            return PathBuf::from(p);
+        }
+        let mut out = String::with_capacity(p.len());
+        let mut chars = p.chars();
+        while let Some(c) = chars.next() {
+            match (c, chars.clone().next()) {
+                ('\\', Some('n')) => { out.push('\n'); chars.next(); },
+                ('\\', Some('\\')) => { out.push('\\'); chars.next(); },
+                _ => out.push(c)
+            }
+        }
+        PathBuf::from(out)
+    };
+
+    // BSD: TAG (path) = digest
+    if let Some(open) = line.find(" (") {
+        if let Some(close) = line.rfind(") = ") {
+            let tag = &line[..open];
+            if close > open && !tag.is_empty() && tag.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+                let digest = &line[close + 4..];
+                return Some((digest, unescape(&line[open + 2..close]), Some(tag)));
+            }
+        }
+    }
+
+    // GNU: digest, a space, a space or * for the mode, then the path
+    let (digest, rest) = line.split_once(' ')?;
+    if digest.is_empty() || !digest.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let path = rest.strip_prefix(' ').or_else(|| rest.strip_prefix('*'))?;
+    Some((digest, unescape(path), None))
+}
@@ -0,0 +1,456 @@
+use crate::{
+    error::Error,
+    Result,
+    fs::{
+        Algorithm,
+        TreeIndex,
+        algo::Hasher,
+        multihash::hex,
+        os_path
+    }
+};
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::Read;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::str::FromStr;
+
+// leaves and inner nodes are hashed with different prefixes so a chunk can't
+// be passed off as a pair of child digests
+const LEAF_TAG: u8 = 0;
+const NODE_TAG: u8 = 1;
+
+// index lines holding chunk digests start with this
+pub(crate) const CHUNKS_TAG: &str = "~ ";
+
+// content defined chunks are at least a quarter and at most eight times the
+// average size
+const CDC_MIN_DIVISOR: u64 = 4;
+const CDC_MAX_FACTOR: u64 = 8;
+
+// the smallest average size for content defined chunks
+const CDC_MIN_AVERAGE: u64 = 64;
+
+// chunks in more files than this are too common to say anything
+const MAX_CHUNK_HOLDERS: usize = 256;
+
+/// How a file is split into chunks. Fixed chunks all have the given size
+/// except the last, they show which regions of a file changed in place.
+/// Content chunks are cut where the bytes look a certain way, with FastCDC,
+/// and have the given average size. Their boundaries move with the data so
+/// files that are mostly the same, like appended logs, share most chunks
+/// even when the bytes shifted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Chunking {
+    Fixed(u64),
+    Content(u64)
+}
+
+impl Chunking {
+
+    /// Returns the chunk size or the average chunk size
+    pub fn size(&self) -> u64 {
+        match self {
+            Chunking::Fixed(s) | Chunking::Content(s) => *s
+        }
+    }
+}
+
+/// The digests of the chunks of a file and the Merkle root over them.
+/// Comparing the leaves of two versions of a file tells which regions
+/// changed, comparing them while reading a file can stop at the first chunk
+/// that differs. Content defined chunks have their lengths, the chunk size
+/// is their average then.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChunkDigests {
+    pub chunk_size: u64,
+    pub root: String,
+    pub leaves: Vec<String>,
+    pub lengths: Vec<u64>
+}
+
+impl ChunkDigests {
+
+    /// Returns how the chunks were cut
+    pub fn chunking(&self) -> Chunking {
+        if self.lengths.is_empty() {
+            Chunking::Fixed(self.chunk_size)
+        } else {
+            Chunking::Content(self.chunk_size)
+        }
+    }
+
+    /// Returns the bytes of this file that are in chunks the other file has
+    /// too, each chunk of the other file is only matched once. Fixed chunks
+    /// are counted as full chunks.
+    pub fn shared_bytes(&self, other: &ChunkDigests) -> u64 {
+        if self.chunking() != other.chunking() {
+            return 0;
+        }
+        let mut theirs: HashMap<&str, usize> = HashMap::new();
+        for l in &other.leaves {
+            *theirs.entry(l.as_str()).or_insert(0) += 1;
+        }
+        let mut shared = 0;
+        for (i, l) in self.leaves.iter().enumerate() {
+            if let Some(n) = theirs.get_mut(l.as_str()) {
+                if *n > 0 {
+                    *n -= 1;
+                    shared += self.lengths.get(i).copied().unwrap_or(self.chunk_size);
+                }
+            }
+        }
+        shared
+    }
+
+    /// Returns the byte ranges that differ between the two, adjacent chunks
+    /// are merged into one range. Files chunked with different sizes or by
+    /// their content can't be compared so this returns None for them.
+    pub fn changed_regions(&self, other: &ChunkDigests, size: u64) -> Option<Vec<Range<u64>>> {
+        if self.chunk_size != other.chunk_size || !self.lengths.is_empty() || !other.lengths.is_empty() {
+            return None;
+        }
+        let count = self.leaves.len().max(other.leaves.len());
+        let mut regions: Vec<Range<u64>> = Vec::new();
+        for i in 0..count {
+            if self.leaves.get(i) == other.leaves.get(i) {
+                continue;
+            }
+            let start = i as u64 * self.chunk_size;
+            let end = (start + self.chunk_size).min(size.max(start + 1));
+            match regions.last_mut() {
+                Some(last) if last.end == start => last.end = end,
+                _ => regions.push(start..end)
+            }
+        }
+        Some(regions)
+    }
+
+    /// Reads the file a chunk at a time and returns false as soon as a chunk
+    /// doesn't match
+    pub fn matches(&self, path: &Path, algorithm: Algorithm, key: Option<&[u8]>) -> Result<bool> {
+        let mut f = File::open(os_path(path))?;
+        let mut chunker = Chunker::new(self.chunking(), algorithm, key)?;
+        let mut buf = vec![0; self.chunk_size.min(1_048_576) as usize];
+        let mut checked = 0;
+        loop {
+            let n = f.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            chunker.update(&buf[..n])?;
+
+            // compare the chunks this read finished
+            while checked < chunker.digests.len() {
+                if self.leaves.get(checked) != Some(&hex(&chunker.digests[checked])) {
+                    return Ok(false);
+                }
+                checked += 1;
+            }
+        }
+        Ok(chunker.finish()? == *self)
+    }
+}
+
+// the line written after an item in an index, the lengths of content
+// defined chunks follow the leaves
+impl Display for ChunkDigests {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error>
+    {
+        write!(f, "{}{} {} {}", CHUNKS_TAG, self.chunk_size, self.root, self.leaves.join(","))?;
+        if !self.lengths.is_empty() {
+            let lengths: Vec<String> = self.lengths.iter().map(|l| l.to_string()).collect();
+            write!(f, " {}", lengths.join(","))?;
+        }
+        writeln!(f)
+    }
+}
+
+impl FromStr for ChunkDigests {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || Error::InvalidFormat(format!("invalid chunk digests {}", s));
+        let mut parts = s.strip_prefix(CHUNKS_TAG).unwrap_or(s).split_whitespace();
+        let chunk_size = parts.next().and_then(|c| c.parse::<u64>().ok()).ok_or_else(invalid)?;
+        let root = parts.next().ok_or_else(invalid)?.to_string();
+        let leaves: Vec<String> = parts.next().ok_or_else(invalid)?
+            .split(',')
+            .map(|l| l.to_string())
+            .collect();
+        let lengths = match parts.next() {
+            Some(l) => l.split(',').map(|l| l.parse::<u64>().map_err(|_| invalid())).collect::<Result<Vec<_>>>()?,
+            None => Vec::new()
+        };
+        if chunk_size == 0 || parts.next().is_some() || (!lengths.is_empty() && lengths.len() != leaves.len()) {
+            return Err(invalid());
+        }
+        Ok(Self { chunk_size, root, leaves, lengths })
+    }
+}
+
+/// Two different files that have chunks in common. The ratio is the shared
+/// bytes over the size of the bigger file, so a file that only grew at the
+/// end is close to 1.
+#[derive(Clone, Debug)]
+pub struct SimilarFiles {
+    pub a: Arc<PathBuf>,
+    pub b: Arc<PathBuf>,
+    pub shared: u64,
+    pub ratio: f64
+}
+
+impl Display for SimilarFiles {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error>
+    {
+        writeln!(f, "{:.1}% shared by {} and {}", self.ratio * 100.0,
+                 self.a.to_string_lossy(), self.b.to_string_lossy())
+    }
+}
+
+/// Returns the pairs of files in the index whose chunks share at least
+/// min_ratio of their bytes, most similar first. Only the files hashed with
+/// the same chunking are compared and identical files are left out, they
+/// are dupes already. Chunks that are in more than a few hundred files,
+/// like runs of zeros, don't make files similar and are skipped.
+pub fn similar_files(ti: &TreeIndex, min_ratio: f64) -> Vec<SimilarFiles> {
+    // the files holding each chunk
+    let entries: Vec<_> = ti.idx.values()
+        .filter(|v| v.item.size > 0)
+        .filter_map(|v| v.item.chunks.as_ref().map(|c| (v, c)))
+        .collect();
+    let mut holders: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (i, (_, c)) in entries.iter().enumerate() {
+        for l in &c.leaves {
+            let h = holders.entry(l.as_str()).or_default();
+            if h.last() != Some(&i) {
+                h.push(i);
+            }
+        }
+    }
+
+    // only the pairs that have a chunk in common are compared
+    let mut candidates: HashSet<(usize, usize)> = HashSet::new();
+    for h in holders.values().filter(|h| h.len() <= MAX_CHUNK_HOLDERS) {
+        for (n, a) in h.iter().enumerate() {
+            for b in &h[n + 1..] {
+                candidates.insert((*a, *b));
+            }
+        }
+    }
+
+    let mut pairs: Vec<SimilarFiles> = candidates.into_iter()
+        .filter_map(|(a, b)| {
+            let ((x, xc), (y, yc)) = (entries[a], entries[b]);
+            let shared = xc.shared_bytes(yc).min(x.item.size).min(y.item.size);
+            let ratio = shared as f64 / x.item.size.max(y.item.size) as f64;
+            if shared == 0 || ratio < min_ratio {
+                return None;
+            }
+            let (a, b) = (Arc::new(x.item.path()), Arc::new(y.item.path()));
+            let (a, b) = if a <= b { (a, b) } else { (b, a) };
+            Some(SimilarFiles { a, b, shared, ratio })
+        })
+        .collect();
+    pairs.sort_by(|a, b| {
+        b.ratio.partial_cmp(&a.ratio).unwrap_or(std::cmp::Ordering::Equal)
+            .then_with(|| b.shared.cmp(&a.shared))
+            .then_with(|| a.a.cmp(&b.a))
+            .then_with(|| a.b.cmp(&b.b))
+    });
+    pairs
+}
+
+// splits the bytes of a file into chunks as they are hashed
+pub(crate) struct Chunker<'a> {
+    chunking: Chunking,
+    algorithm: Algorithm,
+    key: Option<&'a [u8]>,
+    hasher: Hasher,
+    filled: u64,
+    digests: Vec<Vec<u8>>,
+    lengths: Vec<u64>,
+    cdc: Option<Cdc>
+}
+
+impl<'a> Chunker<'a> {
+
+    pub(crate) fn new(chunking: Chunking, algorithm: Algorithm, key: Option<&'a [u8]>) -> Result<Self> {
+        let cdc = match chunking {
+            Chunking::Fixed(0) => {
+                return Err(Error::InvalidArgument("the chunk size can't be zero".to_string()));
+            },
+            Chunking::Fixed(_) => None,
+            Chunking::Content(avg) if avg < CDC_MIN_AVERAGE => {
+                return Err(Error::InvalidArgument(
+                    format!("the average chunk size has to be at least {}", CDC_MIN_AVERAGE)));
+            },
+            Chunking::Content(avg) => Some(Cdc::new(avg))
+        };
+        Ok(Self {
+            chunking,
+            algorithm,
+            key,
+            hasher: leaf_hasher(algorithm, key)?,
+            filled: 0,
+            digests: Vec::new(),
+            lengths: Vec::new(),
+            cdc
+        })
+    }
+
+    pub(crate) fn update(&mut self, mut data: &[u8]) -> Result<()> {
+        while !data.is_empty() {
+            let (n, cut) = match self.cdc.as_mut() {
+                Some(cdc) => cdc.next_cut(self.filled, data),
+                None => {
+                    let n = ((self.chunking.size() - self.filled) as usize).min(data.len());
+                    (n, self.filled + n as u64 == self.chunking.size())
+                }
+            };
+            self.hasher.update(&data[..n]);
+            self.filled += n as u64;
+            data = &data[n..];
+            if cut {
+                self.cut()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn cut(&mut self) -> Result<()> {
+        let hasher = std::mem::replace(&mut self.hasher, leaf_hasher(self.algorithm, self.key)?);
+        self.digests.push(hasher.finalize());
+        self.lengths.push(self.filled);
+        self.filled = 0;
+        if let Some(cdc) = self.cdc.as_mut() {
+            cdc.fingerprint = 0;
+        }
+        Ok(())
+    }
+
+    // an empty file is a single empty chunk
+    pub(crate) fn finish(mut self) -> Result<ChunkDigests> {
+        if self.filled > 0 || self.digests.is_empty() {
+            self.digests.push(self.hasher.finalize());
+            self.lengths.push(self.filled);
+        }
+        let leaves = self.digests.iter().map(|d| hex(d)).collect();
+
+        // hash pairs of digests until there is only the root left, an odd
+        // digest at the end of a level moves up as it is
+        let mut level = self.digests;
+        while level.len() > 1 {
+            let mut next = Vec::with_capacity(level.len().div_ceil(2));
+            for pair in level.chunks(2) {
+                if pair.len() == 1 {
+                    next.push(pair[0].clone());
+                    continue;
+                }
+                let mut node = new_hasher(self.algorithm, self.key)?;
+                node.update(&[NODE_TAG]);
+                node.update(&pair[0]);
+                node.update(&pair[1]);
+                next.push(node.finalize());
+            }
+            level = next;
+        }
+
+        Ok(ChunkDigests {
+            chunk_size: self.chunking.size(),
+            root: hex(&level[0]),
+            leaves,
+            lengths: match self.chunking {
+                Chunking::Fixed(_) => Vec::new(),
+                Chunking::Content(_) => self.lengths
+            }
+        })
+    }
+}
+
+// the rolling gear hash of FastCDC. A chunk is cut where the fingerprint of
+// the bytes since the cut has the mask bits clear. Before the average size
+// the mask has more bits so cuts are less likely, after it fewer, which
+// keeps the sizes close to the average.
+struct Cdc {
+    min: u64,
+    avg: u64,
+    max: u64,
+    mask_small: u64,
+    mask_large: u64,
+    fingerprint: u64
+}
+
+impl Cdc {
+
+    fn new(avg: u64) -> Self {
+        let bits = 63 - avg.leading_zeros();
+        Self {
+            min: avg / CDC_MIN_DIVISOR,
+            avg,
+            max: avg * CDC_MAX_FACTOR,
+            mask_small: high_bits(bits + 1),
+            mask_large: high_bits(bits - 1),
+            fingerprint: 0
+        }
+    }
+
+    // returns how many of the bytes belong to the current chunk, which has
+    // filled bytes already, and whether the chunk ends after them
+    fn next_cut(&mut self, filled: u64, data: &[u8]) -> (usize, bool) {
+        for (i, b) in data.iter().enumerate() {
+            let len = filled + i as u64 + 1;
+
+            // the first bytes of a chunk can't end it so they aren't hashed
+            if len <= self.min {
+                continue;
+            }
+            self.fingerprint = (self.fingerprint << 1).wrapping_add(GEAR[*b as usize]);
+            let mask = if len < self.avg { self.mask_small } else { self.mask_large };
+            if self.fingerprint & mask == 0 || len >= self.max {
+                return (i + 1, true);
+            }
+        }
+        (data.len(), false)
+    }
+}
+
+// the top bits of the fingerprint depend on the most bytes
+fn high_bits(n: u32) -> u64 {
+    !0u64 << (64 - n)
+}
+
+// the gear table is 256 random values, these come from splitmix64 so every
+// build cuts the same chunks
+const GEAR: [u64; 256] = gear_table();
+
+const fn gear_table() -> [u64; 256] {
+    let mut table = [0u64; 256];
+    let mut state: u64 = 0x9e37_79b9_7f4a_7c15;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+}
+
+fn new_hasher(algorithm: Algorithm, key: Option<&[u8]>) -> Result<Hasher> {
+    match key {
+        Some(key) => algorithm.keyed_hasher(key),
+        None => Ok(algorithm.hasher())
+    }
+}
+
+fn leaf_hasher(algorithm: Algorithm, key: Option<&[u8]>) -> Result<Hasher> {
+    let mut hasher = new_hasher(algorithm, key)?;
+    hasher.update(&[LEAF_TAG]);
+    Ok(hasher)
+}
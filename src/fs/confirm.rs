@@ -0,0 +1,246 @@
+use crate::{
+    error::Error,
+    Result,
+    fs::{
+        Algorithm,
+        Chunking,
+        DigestEncoding,
+        ItemPath,
+        TreeIndex,
+        TreeIndexHeader,
+        TreeItem,
+        TreeItemBuilder,
+        TreeItemDupes,
+        os_path
+    },
+    fs::stats::ScanCounters
+};
+use log::debug;
+use std::fmt::{Display, Formatter};
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+// the files are compared this much at a time
+const COMPARE_BUFFER: usize = 1024 * 1024;
+
+/// How confirm checks that a dupe matches its original. Digest hashes the
+/// dupe in full, ByteCompare reads it next to the original and stops at the
+/// first byte that differs, so there is no doubt about hash collisions and
+/// files that differ are usually rejected without reading all of them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ConfirmMode {
+    #[default]
+    Digest,
+    ByteCompare
+}
+
+impl FromStr for ConfirmMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "digest" => Ok(ConfirmMode::Digest),
+            "bytes" | "byte-compare" => Ok(ConfirmMode::ByteCompare),
+            _ => Err(Error::InvalidArgument(format!("unknown confirm mode {}", s)))
+        }
+    }
+}
+
+impl Display for ConfirmMode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error>
+    {
+        match self {
+            ConfirmMode::Digest => write!(f, "digest"),
+            ConfirmMode::ByteCompare => write!(f, "bytes")
+        }
+    }
+}
+
+/// Returns true if the two files have the same contents, comparing them a
+/// MiB at a time and stopping at the first difference
+pub fn same_contents(a: &Path, b: &Path) -> Result<bool> {
+    let (mut fa, mut fb) = (File::open(os_path(a))?, File::open(os_path(b))?);
+    if fa.metadata()?.len() != fb.metadata()?.len() {
+        return Ok(false);
+    }
+    let (mut ba, mut bb) = (vec![0; COMPARE_BUFFER], vec![0; COMPARE_BUFFER]);
+    loop {
+        let n = read_full(&mut fa, &mut ba)?;
+        if n != read_full(&mut fb, &mut bb)? || ba[..n] != bb[..n] {
+            return Ok(false);
+        }
+        if n == 0 {
+            return Ok(true);
+        }
+    }
+}
+
+// fills the buffer unless the file ends first, returns how much was read
+fn read_full(f: &mut File, buf: &mut [u8]) -> Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match f.read(&mut buf[filled..])? {
+            0 => break,
+            n => filled += n
+        }
+    }
+    Ok(filled)
+}
+
+// re-digests the groups of an index in full to find the dupes that really
+// are dupes. the groups don't depend on each other so they are spread over
+// the threads.
+pub(crate) struct Confirmer {
+    algorithm: Algorithm,
+    encoding: DigestEncoding,
+    chunking: Option<Chunking>,
+    prefilter: bool,
+    mode: ConfirmMode,
+    pub(crate) counters: ScanCounters
+}
+
+impl Confirmer {
+    pub(crate) fn new(header: &TreeIndexHeader, prefilter: bool, mode: ConfirmMode) -> Result<Self> {
+        Ok(Self {
+            algorithm: header.algorithm()?,
+            encoding: header.encoding()?,
+            chunking: header.chunking()?,
+            prefilter,
+            mode,
+            counters: ScanCounters::default()
+        })
+    }
+
+    // the header of the confirmed index, its digests are full and unkeyed
+    // and the items only have chunk digests
+    pub(crate) fn header(&self, from: &TreeIndexHeader) -> TreeIndexHeader {
+        let mut header = from.clone();
+        header.set_fast(false);
+        header.set_keyed(false);
+        header.set_metadata(false);
+        header.set_fuzzy(false);
+        header.set_image_hashes(None);
+        header
+    }
+
+    // confirms every group on the given number of threads, 0 is one per CPU.
+    // the first error stops the other threads.
+    pub(crate) fn run(&self, ti: &TreeIndex, threads: usize) -> Result<Vec<TreeItemDupes>> {
+        let groups: Vec<&TreeItemDupes> = ti.idx.values().collect();
+        let threads = match threads {
+            0 => thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+            n => n
+        }.min(groups.len().max(1));
+
+        let next = AtomicUsize::new(0);
+        let stop = AtomicBool::new(false);
+        let results = Mutex::new(Vec::new());
+        let first_err = Mutex::new(None);
+        thread::scope(|s| {
+            for _ in 0..threads {
+                s.spawn(|| {
+                    let mut confirmed = Vec::new();
+                    while !stop.load(Ordering::Relaxed) {
+                        let g = match groups.get(next.fetch_add(1, Ordering::Relaxed)) {
+                            Some(g) => g,
+                            None => break
+                        };
+                        match self.group(g) {
+                            Ok(Some(entry)) => confirmed.push(entry),
+                            Ok(None) => {},
+                            Err(e) => {
+                                stop.store(true, Ordering::Relaxed);
+                                first_err.lock().unwrap().get_or_insert(e);
+                            }
+                        }
+                    }
+                    results.lock().unwrap().extend(confirmed);
+                });
+            }
+        });
+
+        if let Some(e) = first_err.into_inner().unwrap() {
+            return Err(e);
+        }
+        Ok(results.into_inner().unwrap())
+    }
+
+    // the original digested in full with the dupes that match it. returns
+    // None when the prefilter rules out every dupe, the original isn't read
+    // in full then.
+    fn group(&self, entry: &TreeItemDupes) -> Result<Option<TreeItemDupes>> {
+        // dupes that aren't the size of the original can't match it
+        let mut dupes: Vec<PathBuf> = entry.dupes.iter()
+            .map(|p| p.path())
+            .filter(|p| fs::metadata(os_path(p)).map(|m| m.len()).ok() == Some(entry.item.size))
+            .collect();
+
+        // nor can dupes whose first and last MiB are different
+        if self.prefilter {
+            if dupes.is_empty() {
+                return Ok(None);
+            }
+            let original = self.builder(true).path(&entry.item.path()).build()?;
+            self.counters.file(original.size);
+            let mut plausible = Vec::new();
+            for p in dupes {
+                let dupe = self.builder(true).path(&p).build()?;
+                self.counters.file(dupe.size);
+                if dupe.digest == original.digest {
+                    plausible.push(p);
+                } else {
+                    debug!("invalid dupe {} {}", entry.item.path().to_string_lossy(), p.to_string_lossy());
+                }
+            }
+            if plausible.is_empty() {
+                return Ok(None);
+            }
+            dupes = plausible;
+        }
+
+        // do a full digest of the original, in chunks if the index has them
+        let item = self.builder(false).path(&entry.item.path()).build()?;
+        self.counters.file(item.size);
+        let mut confirmed = TreeItemDupes::from(&item);
+
+        for p in dupes {
+            self.counters.file(item.size);
+            if self.matches(&item, &p)? {
+                debug!("confirmed dupe {} {}", item.path().to_string_lossy(), p.to_string_lossy());
+                confirmed.push(ItemPath::new(&p));
+            } else {
+                debug!("invalid dupe {} {}", item.path().to_string_lossy(), p.to_string_lossy());
+            }
+        }
+        Ok(Some(confirmed))
+    }
+
+    // compares the dupe a chunk at a time if there are chunk digests so a
+    // file that differs early on isn't read to the end
+    fn matches(&self, item: &TreeItem, path: &PathBuf) -> Result<bool> {
+        if self.mode == ConfirmMode::ByteCompare {
+            return same_contents(&item.path(), path);
+        }
+        if let Some(chunks) = &item.chunks {
+            return chunks.matches(path, self.algorithm, None);
+        }
+        let dupe = self.builder(false).path(path).build()?;
+        Ok(dupe.digest.same_as(&item.digest))
+    }
+
+    fn builder(&self, fast: bool) -> TreeItemBuilder<'_> {
+        let mut builder = TreeItemBuilder::new()
+            .fast(fast)
+            .algorithm(self.algorithm)
+            .encoding(self.encoding);
+        if let (false, Some(c)) = (fast, self.chunking) {
+            builder = builder.chunking(c);
+        }
+        builder
+    }
+}
@@ -0,0 +1,133 @@
+use crate::{
+    error::Error,
+    Result,
+    fs::{
+        treeindex::IndexLine,
+        treeitem::{index_path, native_path},
+        TreeIndexHeader,
+        TreeItem,
+        TreeItemDupes
+    }
+};
+use std::borrow::Cow;
+use std::io::{BufRead, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+/// The first row of an index written as CSV. Readers recognize CSV indexes
+/// by it.
+pub const CSV_HEADER: &str = "digest,size,path,dupe_of";
+
+// spreadsheets often save CSV files with a byte order mark
+const BOM: &str = "\u{feff}";
+
+/// Returns true if the bytes are the start of a CSV index
+pub fn is_csv(start: &[u8]) -> bool {
+    start.strip_prefix(BOM.as_bytes()).unwrap_or(start).starts_with(CSV_HEADER.as_bytes())
+}
+
+/// Writes the header row
+pub fn write_csv_header(w: &mut dyn Write) -> Result<()> {
+    writeln!(w, "{}", CSV_HEADER)?;
+    Ok(())
+}
+
+/// Writes an item as a row, dupe_of is the path of the item it is a copy of
+pub fn write_csv_item(w: &mut dyn Write, item: &TreeItem, dupe_of: Option<&Path>) -> Result<()> {
+    let dupe_of = match dupe_of {
+        Some(p) => index_path(p)?,
+        None => String::new()
+    };
+    writeln!(w, "{},{},{},{}", field(&item.digest.to_string()), item.size, field(&index_path(&item.path())?), field(&dupe_of))?;
+    Ok(())
+}
+
+/// Writes a row for the item and one for each of its dupes
+pub fn write_csv_entry(w: &mut dyn Write, entry: &TreeItemDupes) -> Result<()> {
+    write_csv_item(w, &entry.item, None)?;
+    let path = entry.item.path();
+    for d in &entry.dupes {
+        write_csv_item(w, &TreeItem::new(&entry.item.digest, d, entry.item.size), Some(&path))?;
+    }
+    Ok(())
+}
+
+// fields with separators, quotes or line breaks are quoted and their quotes
+// doubled, the same as RFC 4180
+fn field(s: &str) -> Cow<'_, str> {
+    if s.contains([',', '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", s.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(s)
+    }
+}
+
+// reads a CSV index and hands every row to the function as an item. CSV has
+// nowhere to keep the header fields so the index gets a default header. The
+// dupe_of column is ignored, rows with the same digest are dupes either way.
+pub(crate) fn read_csv<R: BufRead>(mut r: R, f: &mut dyn FnMut(IndexLine) -> Result<()>) -> Result<TreeIndexHeader> {
+    let header = TreeIndexHeader::default();
+    let mut record = String::new();
+    let mut row = 0;
+    let mut n = 0;
+
+    // the line breaks are kept, a quoted field can go over several lines
+    // and the ones in it are part of the path
+    while r.read_line(&mut record)? > 0 {
+        n += 1;
+        let line = record.strip_suffix('\n').map(|l| l.strip_suffix('\r').unwrap_or(l)).unwrap_or(&record);
+        let fields = match parse_record(line) {
+            Some(fields) => fields,
+            None => continue
+        };
+        record.clear();
+        row += 1;
+
+        if row == 1 {
+            if fields.join(",").trim_start_matches(BOM) != CSV_HEADER {
+                return Err(Error::InvalidFormat("missing CSV header row".to_string()));
+            }
+            continue;
+        }
+        if fields.len() == 1 && fields[0].is_empty() {
+            continue;
+        }
+        let invalid = || Error::InvalidFormat(format!("invalid CSV row on line {}", n));
+        if fields.len() != 4 || fields[0].is_empty() {
+            return Err(invalid());
+        }
+        let size = fields[1].parse::<u64>().map_err(|_| invalid())?;
+        let path = Arc::new(native_path(fields[2].clone(), &header));
+        f(IndexLine::Item(TreeItem::new(&fields[0], &path, size)))?;
+    }
+    if !record.is_empty() {
+        return Err(Error::InvalidFormat("unterminated quote in CSV index".to_string()));
+    }
+    Ok(header)
+}
+
+// splits a record into its fields, None if a quoted field isn't closed yet
+fn parse_record(record: &str) -> Option<Vec<String>> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = record.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (quoted, c) {
+            (true, '"') if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            },
+            (true, '"') => quoted = false,
+            (true, c) => field.push(c),
+            (false, '"') if field.is_empty() => quoted = true,
+            (false, ',') => fields.push(std::mem::take(&mut field)),
+            (false, c) => field.push(c)
+        }
+    }
+    if quoted {
+        return None;
+    }
+    fields.push(field);
+    Some(fields)
+}
@@ -0,0 +1,765 @@
+use crate::{
+    error::Error,
+    Result,
+    fs::{fuzzy_groups, is_mac_metadata, os_path, AppleDoublePolicy, FuzzyGroup, ItemDigest, TempDir, TempFile, TreeIndex, TreeIndexHeader, TreeItemBuilder},
+    units::IntoBytes
+};
+#[cfg(feature = "archive")]
+use crate::fs::archive::split_member_path;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use log::{debug, warn};
+use std::collections::{BinaryHeap, HashSet};
+use std::ffi::OsString;
+use std::fmt::{Display, Formatter};
+use std::fs::{self, FileTimes, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+
+// the first line of a move manifest
+const MANIFEST_HEADER: &str = "#treemoves v1";
+
+// the line after a moved file that says where it went
+const MOVED_TAG: &str = "-> ";
+
+// the fuzzy score near duplicates need when the planner isn't told one
+const DEFAULT_FUZZY_SCORE: u32 = 50;
+
+/// A DedupPlanner picks the duplicate groups of an index that are worth
+/// acting on. Groups with fewer dupes than min_dupes or that would free
+/// less than min_savings are left out, when both are set a group has to
+/// meet both. Protected files stay in the plan so they are reported, the
+/// actions that delete or move files check is_protected before touching
+/// each one so a plan file can't get around it. Near duplicates found by
+/// their fuzzy digests have to score at least min_fuzzy_score. Unless
+/// apple_double is AppleDoublePolicy::Keep the AppleDouble and .DS_Store
+/// files are taken out of the groups before they are looked at.
+#[derive(Clone, Debug, Default)]
+pub struct DedupPlanner {
+    pub min_dupes: usize,
+    pub min_savings: u64,
+    pub min_fuzzy_score: u32,
+    pub apple_double: AppleDoublePolicy,
+    protected_paths: Vec<PathBuf>,
+    protected_globs: Option<Gitignore>,
+    // relative paths are taken from the directory the planner was built in
+    cwd: PathBuf
+}
+
+impl DedupPlanner {
+
+    /// Returns true if the path is in a protected tree or matches a
+    /// protected glob. Members of archives are always protected, they can't
+    /// be removed or moved on their own. A relative path is taken from the
+    /// current directory, see DedupPlannerBuilder::protect.
+    pub fn is_protected(&self, path: &Path) -> bool {
+        #[cfg(feature = "archive")]
+        if !path.exists() && split_member_path(path).is_some() {
+            return true;
+        }
+        let lexical = lexical_path(path, &self.cwd);
+        if self.protected_paths.iter().any(|p| lexical.starts_with(p)) {
+            return true;
+        }
+        match &self.protected_globs {
+            Some(g) => g.matched_path_or_any_parents(glob_path(&lexical), path.is_dir()).is_ignore(),
+            None => false
+        }
+    }
+
+    /// Returns true if pruning has to leave the directory, because it is
+    /// protected or has a protected path in it
+    pub fn is_protected_dir(&self, dir: &Path) -> bool {
+        let lexical = lexical_path(dir, &self.cwd);
+        self.protected_paths.iter().any(|p| p.starts_with(&lexical)) || self.is_protected(dir)
+    }
+
+    /// Returns the protected paths that aren't globs, made absolute
+    pub fn protected_paths(&self) -> &[PathBuf] {
+        &self.protected_paths
+    }
+
+    /// Returns the index with only the groups the plan keeps, items without
+    /// dupes are left out too
+    pub fn plan(&self, ti: &TreeIndex) -> TreeIndex {
+        let mut plan = TreeIndex {
+            header: ti.header.clone(),
+            stats: ti.stats,
+            ..Default::default()
+        };
+        for (key, entry) in &ti.idx {
+            let mut entry = entry.clone();
+            if self.apple_double != AppleDoublePolicy::Keep {
+                entry.dupes.retain(|d| !is_mac_metadata(&d.path()));
+                entry.dupe_metadata.retain(|d, _| !is_mac_metadata(&d.path()));
+                if is_mac_metadata(&entry.item.path()) {
+                    if entry.dupes.is_empty() {
+                        continue;
+                    }
+                    let dupe = entry.dupes.remove(0);
+                    entry.item.metadata = entry.dupe_metadata.remove(&dupe);
+                    entry.item.path = dupe;
+                }
+            }
+            let dupes = entry.dupes.len();
+            if dupes == 0 || dupes < self.min_dupes || entry.item.size.saturating_mul(dupes as u64) < self.min_savings {
+                continue;
+            }
+            plan.idx.insert(key.clone(), entry);
+        }
+        plan
+    }
+
+    /// Returns the groups of files that are alike by their fuzzy digests,
+    /// see fuzzy_groups. Groups with fewer files than min_dupes besides the
+    /// first are left out.
+    pub fn near_dupes(&self, ti: &TreeIndex) -> Vec<FuzzyGroup> {
+        fuzzy_groups(ti, self.min_fuzzy_score)
+            .into_iter()
+            .filter(|g| g.files.len() > self.min_dupes)
+            .collect()
+    }
+}
+
+// the path made absolute against the directory with . and .. taken out,
+// without looking at the file system, so a file is the same path however it
+// was written. A .. that can't be taken out is kept.
+fn lexical_path(path: &Path, cwd: &Path) -> PathBuf {
+    let mut lexical = PathBuf::new();
+    for c in cwd.join(path).components() {
+        match c {
+            Component::CurDir => {},
+            Component::ParentDir => match lexical.components().next_back() {
+                Some(Component::Normal(_)) => { lexical.pop(); },
+                Some(Component::RootDir) | Some(Component::Prefix(_)) => {},
+                _ => lexical.push(c)
+            },
+            c => lexical.push(c)
+        }
+    }
+    lexical
+}
+
+// the path without its prefix and root so a glob like /backups/** matches
+// C:\backups\x too, the globs are rooted at / and ignore panics on paths
+// that have a root of their own
+fn glob_path(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|c| matches!(c, Component::Normal(_) | Component::ParentDir))
+        .collect()
+}
+
+#[derive(Default)]
+pub struct DedupPlannerBuilder {
+    min_dupes: usize,
+    min_savings: u64,
+    min_fuzzy_score: Option<u32>,
+    apple_double: AppleDoublePolicy,
+    protected_paths: Vec<PathBuf>,
+    protected_globs: Vec<String>,
+    error: Option<Error>
+}
+
+impl DedupPlannerBuilder {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only keep groups with at least this many dupes, not counting the
+    /// original
+    pub fn min_dupes(mut self, dupes: usize) -> Self {
+        self.min_dupes = dupes;
+        self
+    }
+
+    /// Only keep groups that free at least this many bytes when their dupes
+    /// are removed. This takes either a u64 or a human readable string such
+    /// as "100MiB".
+    pub fn min_savings<B: IntoBytes>(mut self, savings: B) -> Self {
+        match savings.into_bytes() {
+            Ok(savings) => self.min_savings = savings,
+            Err(e) => self.error = Some(e)
+        }
+        self
+    }
+
+    /// Near duplicates have to score at least this from 0 to 100 when their
+    /// fuzzy digests are compared, the default is 50
+    pub fn min_fuzzy_score(mut self, score: u32) -> Self {
+        if score > 100 {
+            self.error = Some(Error::InvalidArgument(format!("fuzzy scores go up to 100, not {}", score)));
+        } else {
+            self.min_fuzzy_score = Some(score);
+        }
+        self
+    }
+
+    /// Whether AppleDouble and .DS_Store files are left out of the plan,
+    /// they are kept by default
+    pub fn apple_double(mut self, policy: AppleDoublePolicy) -> Self {
+        self.apple_double = policy;
+        self
+    }
+
+    /// Never delete or move files under this path. Anything with *, ? or [
+    /// in it is a glob in the gitignore syntax, e.g. "/backups/**", other
+    /// values protect the directory or file they name. Relative paths, of
+    /// the files checked and the ones protected, are taken from the current
+    /// directory, so "photos" protects /home/me/photos/x when run in
+    /// /home/me and a glob like "/home/me/**" matches "x".
+    pub fn protect(mut self, glob_or_path: &str) -> Self {
+        if glob_or_path.contains(['*', '?', '[']) {
+            self.protected_globs.push(glob_or_path.to_string());
+        } else {
+            self.protected_paths.push(PathBuf::from(glob_or_path));
+        }
+        self
+    }
+
+    pub fn build(self) -> Result<DedupPlanner> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+        let protected_globs = if self.protected_globs.is_empty() {
+            None
+        } else {
+            let mut builder = GitignoreBuilder::new("/");
+            for g in &self.protected_globs {
+                builder.add_line(None, g)
+                    .map_err(|e| Error::InvalidArgument(format!("invalid protected glob {}: {}", g, e)))?;
+            }
+            Some(builder.build()
+                 .map_err(|e| Error::InvalidArgument(format!("invalid protected globs: {}", e)))?)
+        };
+        // without a current directory relative paths compare as they are
+        let cwd = std::env::current_dir().unwrap_or_default();
+        Ok(DedupPlanner {
+            min_dupes: self.min_dupes,
+            min_savings: self.min_savings,
+            min_fuzzy_score: self.min_fuzzy_score.unwrap_or(DEFAULT_FUZZY_SCORE),
+            apple_double: self.apple_double,
+            protected_paths: self.protected_paths.iter().map(|p| lexical_path(p, &cwd)).collect(),
+            protected_globs,
+            cwd
+        })
+    }
+}
+
+/// Where copied dupes land in the destination directory
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CopyLayout {
+    /// Every file is named after its digest and keeps its extension
+    #[default]
+    Flat,
+    /// The directories of the files are recreated under the destination,
+    /// relative to the deepest directory holding every file in the index
+    PreservePaths
+}
+
+impl FromStr for CopyLayout {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "flat" => Ok(CopyLayout::Flat),
+            "preserve" | "preserve-paths" => Ok(CopyLayout::PreservePaths),
+            _ => Err(Error::InvalidArgument(format!("unknown copy layout {}", s)))
+        }
+    }
+}
+
+/// CopyTargets picks the destination path for each file copied out of an
+/// index. A name that is already taken, on disk or by an earlier target,
+/// gets a counter before its extension so nothing is overwritten.
+pub struct CopyTargets {
+    dest: PathBuf,
+    layout: CopyLayout,
+    root: PathBuf,
+    used: HashSet<PathBuf>
+}
+
+impl CopyTargets {
+
+    pub fn new(dest: &Path, layout: CopyLayout, ti: &TreeIndex) -> Self {
+        let root = match layout {
+            CopyLayout::Flat => PathBuf::new(),
+            CopyLayout::PreservePaths => common_root(ti.idx.values()
+                .flat_map(|v| std::iter::once(v.item.item_path()).chain(v.dupes.iter()))
+                .map(|p| p.path()))
+        };
+        Self {
+            dest: dest.to_path_buf(),
+            layout,
+            root,
+            used: HashSet::new()
+        }
+    }
+
+    /// Returns the destination for a copy of the file with the digest
+    pub fn target(&mut self, digest: &str, path: &Path) -> PathBuf {
+        let target = match self.layout {
+            CopyLayout::Flat => {
+                let f = self.dest.join(digest);
+                match path.extension() {
+                    Some(ext) => f.with_extension(ext),
+                    None => f
+                }
+            },
+            CopyLayout::PreservePaths => {
+                let rel: PathBuf = path.strip_prefix(&self.root).unwrap_or(path)
+                    .components()
+                    .filter(|c| matches!(c, Component::Normal(_)))
+                    .collect();
+                self.dest.join(rel)
+            }
+        };
+        let target = self.unique(target);
+        self.used.insert(target.clone());
+        target
+    }
+
+    // adds -1, -2, ... to the file stem until the name isn't taken
+    fn unique(&self, target: PathBuf) -> PathBuf {
+        let taken = |p: &Path| self.used.contains(p) || p.symlink_metadata().is_ok();
+        if !taken(&target) {
+            return target;
+        }
+        let stem = target.file_stem().map(|s| s.to_os_string()).unwrap_or_default();
+        let mut n = 1;
+        loop {
+            let mut name = OsString::from(&stem);
+            name.push(format!("-{}", n));
+            if let Some(ext) = target.extension() {
+                name.push(".");
+                name.push(ext);
+            }
+            let candidate = target.with_file_name(name);
+            if !taken(&candidate) {
+                return candidate;
+            }
+            n += 1;
+        }
+    }
+}
+
+/// The metadata copy_file carries over from the source to the copy.
+/// Permissions are the mode bits on unix and the read only flag on Windows,
+/// the copy gets the mode bits on unix either way.
+/// Owners can only be changed by root so failing to set them is logged and
+/// isn't an error. Extended attributes are copied on Linux and macOS.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PreserveOptions {
+    pub times: bool,
+    pub permissions: bool,
+    pub owner: bool,
+    pub xattrs: bool
+}
+
+impl PreserveOptions {
+
+    /// Preserves everything
+    pub fn all() -> Self {
+        Self {
+            times: true,
+            permissions: true,
+            owner: true,
+            xattrs: true
+        }
+    }
+}
+
+// a comma separated list of times, mode, owner and xattrs, or all or none
+impl FromStr for PreserveOptions {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut opts = Self::default();
+        for part in s.split(',').map(|p| p.trim().to_lowercase()) {
+            match part.as_str() {
+                "all" => opts = Self::all(),
+                "none" | "" => {},
+                "times" | "timestamps" => opts.times = true,
+                "mode" | "permissions" => opts.permissions = true,
+                "owner" | "ownership" => opts.owner = true,
+                "xattrs" | "xattr" => opts.xattrs = true,
+                _ => return Err(Error::InvalidArgument(format!("unknown metadata to preserve {}", part)))
+            }
+        }
+        Ok(opts)
+    }
+}
+
+/// Copies the file and then the metadata the options ask for, returns the
+/// number of bytes copied
+pub fn copy_file(from: &Path, to: &Path, preserve: &PreserveOptions) -> Result<u64> {
+    let (from, to) = (&*os_path(from), &*os_path(to));
+    let n = fs::copy(from, to)?;
+    let meta = fs::metadata(from)?;
+    if preserve.xattrs {
+        copy_xattrs(from, to)?;
+    }
+    #[cfg(unix)]
+    if preserve.owner {
+        use std::os::unix::fs::MetadataExt;
+        if let Err(e) = std::os::unix::fs::chown(to, Some(meta.uid()), Some(meta.gid())) {
+            warn!("failed to set the owner of {}: {}", to.to_string_lossy(), e);
+        }
+    }
+
+    // the times are set through a writable handle so they go before the
+    // permissions, which can make the copy read only
+    if preserve.times {
+        let mut times = FileTimes::new();
+        if let Ok(t) = meta.accessed() {
+            times = times.set_accessed(t);
+        }
+        if let Ok(t) = meta.modified() {
+            times = times.set_modified(t);
+        }
+        #[cfg(windows)]
+        if let Ok(t) = meta.created() {
+            use std::os::windows::fs::FileTimesExt;
+            times = times.set_created(t);
+        }
+        OpenOptions::new().write(true).open(to)?.set_times(times)?;
+    }
+    if preserve.permissions {
+        fs::set_permissions(to, meta.permissions())?;
+    }
+    Ok(n)
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn copy_xattrs(from: &Path, to: &Path) -> Result<()> {
+    use std::ffi::CString;
+    use std::io;
+    use std::os::unix::ffi::OsStrExt;
+
+    let c = |p: &Path| CString::new(p.as_os_str().as_bytes())
+        .map_err(|_| Error::InvalidArgument(format!("invalid path {}", p.to_string_lossy())));
+    let (src, dst) = (c(from)?, c(to)?);
+
+    // the names come back as one buffer of nul terminated strings
+    let len = unsafe { xattr::list(src.as_ptr(), std::ptr::null_mut(), 0) };
+    if len < 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    let mut names = vec![0u8; len as usize];
+    let len = unsafe { xattr::list(src.as_ptr(), names.as_mut_ptr() as *mut libc::c_char, names.len()) };
+    if len < 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    names.truncate(len as usize);
+
+    for name in names.split(|b| *b == 0).filter(|n| !n.is_empty()) {
+        let name = CString::new(name).map_err(|_| Error::InvalidFormat("invalid xattr name".to_string()))?;
+        let len = unsafe { xattr::get(src.as_ptr(), name.as_ptr(), std::ptr::null_mut(), 0) };
+        if len < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        let mut value = vec![0u8; len as usize];
+        let len = unsafe { xattr::get(src.as_ptr(), name.as_ptr(), value.as_mut_ptr() as *mut libc::c_void, value.len()) };
+        if len < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        let ret = unsafe { xattr::set(dst.as_ptr(), name.as_ptr(), value.as_ptr() as *const libc::c_void, len as usize) };
+        if ret != 0 {
+            // some namespaces need privileges the copy doesn't have
+            warn!("failed to copy xattr {} to {}: {}", name.to_string_lossy(), to.to_string_lossy(),
+                  io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn copy_xattrs(_from: &Path, _to: &Path) -> Result<()> {
+    Ok(())
+}
+
+// the xattr calls take extra position and option arguments on macOS
+#[cfg(target_os = "linux")]
+mod xattr {
+    use libc::{c_char, c_void, size_t, ssize_t};
+
+    pub unsafe fn list(path: *const c_char, names: *mut c_char, size: size_t) -> ssize_t {
+        libc::listxattr(path, names, size)
+    }
+
+    pub unsafe fn get(path: *const c_char, name: *const c_char, value: *mut c_void, size: size_t) -> ssize_t {
+        libc::getxattr(path, name, value, size)
+    }
+
+    pub unsafe fn set(path: *const c_char, name: *const c_char, value: *const c_void, size: size_t) -> i32 {
+        libc::setxattr(path, name, value, size, 0)
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod xattr {
+    use libc::{c_char, c_void, size_t, ssize_t};
+
+    pub unsafe fn list(path: *const c_char, names: *mut c_char, size: size_t) -> ssize_t {
+        libc::listxattr(path, names, size, 0)
+    }
+
+    pub unsafe fn get(path: *const c_char, name: *const c_char, value: *mut c_void, size: size_t) -> ssize_t {
+        libc::getxattr(path, name, value, size, 0, 0)
+    }
+
+    pub unsafe fn set(path: *const c_char, name: *const c_char, value: *const c_void, size: size_t) -> i32 {
+        libc::setxattr(path, name, value, size, 0, 0)
+    }
+}
+
+/// Returns true if the file digests to the digest when it is digested the
+/// way the header says the index was. Keyed indexes need their key.
+pub fn digest_matches(header: &TreeIndexHeader, digest: &str, path: &Path, key: Option<&[u8]>) -> Result<bool> {
+    if header.is_keyed() != key.is_some() {
+        return Err(Error::InvalidArgument(
+            "a key is needed for keyed indexes and only for keyed indexes".to_string()));
+    }
+    let path = path.to_path_buf();
+    let mut builder = TreeItemBuilder::new()
+        .fast(header.is_fast())
+        .algorithm(header.algorithm()?)
+        .encoding(header.encoding()?);
+    if let Some(key) = key {
+        builder = builder.key(key);
+    }
+    Ok(builder.path(&path).build()?.digest.same_as(&ItemDigest::from(digest)))
+}
+
+/// Copies the file and digests the copy to check it against the digest
+/// from the index, a copy that doesn't match is made again up to retries
+/// times. The copy is made in a temp file next to the destination that only
+/// replaces it once it matches. Returns false if it never matched.
+///
+/// The copy is flushed to the disk before it is checked. On Linux and
+/// Android its pages are dropped from the page cache too so the check reads
+/// back what the disk has. Elsewhere the check can be served from the cache,
+/// which catches a short or corrupted copy but not one the disk wrote badly,
+/// and on Windows a read only copy isn't flushed until the system gets to it.
+pub fn copy_verified(header: &TreeIndexHeader, digest: &str, from: &Path, to: &Path,
+                     key: Option<&[u8]>, retries: usize, preserve: &PreserveOptions) -> Result<bool> {
+    for attempt in 0..=retries {
+        let tmp = TempFile::next_to(to)?;
+        copy_file(from, tmp.path(), preserve)?;
+        sync_uncached(tmp.path())?;
+        if digest_matches(header, digest, tmp.path(), key)? {
+            tmp.persist(to)?;
+            return Ok(true);
+        }
+        warn!("copy {} of {} doesn't match its digest (attempt {} of {})",
+              to.to_string_lossy(), from.to_string_lossy(), attempt + 1, retries + 1);
+    }
+    Ok(false)
+}
+
+// flushes the file to the disk and drops it from the page cache where that
+// can be done, so reading it again goes to the disk
+#[cfg(unix)]
+fn sync_uncached(path: &Path) -> Result<()> {
+    let f = fs::File::open(os_path(path))?;
+    f.sync_all()?;
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        use std::os::unix::io::AsRawFd;
+        let ret = unsafe { libc::posix_fadvise(f.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED) };
+        if ret != 0 {
+            debug!("failed to drop {} from the page cache: {}", path.to_string_lossy(),
+                   io::Error::from_raw_os_error(ret));
+        }
+    }
+    Ok(())
+}
+
+// flushing takes a writable handle, which a read only copy won't give
+#[cfg(not(unix))]
+fn sync_uncached(path: &Path) -> Result<()> {
+    match OpenOptions::new().write(true).open(os_path(path)) {
+        Ok(f) => f.sync_all()?,
+        Err(e) => debug!("not flushing {}: {}", path.to_string_lossy(), e)
+    }
+    Ok(())
+}
+
+/// Moves the file, renaming it when the destination is on the same file
+/// system and otherwise copying it, verifying the copy against the digest
+/// and then deleting the original. Returns false and leaves the original
+/// alone if the copy never verified.
+pub fn move_file(header: &TreeIndexHeader, digest: &str, from: &Path, to: &Path,
+                 key: Option<&[u8]>, retries: usize, preserve: &PreserveOptions) -> Result<bool> {
+    match fs::rename(os_path(from), os_path(to)) {
+        Ok(()) => {
+            debug!("renamed {} to {}", from.to_string_lossy(), to.to_string_lossy());
+            return Ok(true);
+        },
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {},
+        Err(e) => return Err(e.into())
+    }
+    if !copy_verified(header, digest, from, to, key, retries, preserve)? {
+        return Ok(false);
+    }
+    debug!("copied {} to {}", from.to_string_lossy(), to.to_string_lossy());
+    fs::remove_file(os_path(from))?;
+    Ok(true)
+}
+
+/// Replaces dst with a hard link to src. The link is made next to dst and
+/// renamed over it so dst is never missing, if anything fails it is left as
+/// it was. Both have to be on the same file system.
+pub fn hardlink_file(src: &Path, dst: &Path) -> Result<()> {
+    let tmp = TempDir::next_to(dst)?;
+    let link = tmp.path().join("link");
+    fs::hard_link(os_path(src), os_path(&link))?;
+    fs::rename(os_path(&link), os_path(dst))?;
+    debug!("linked {} to {}", dst.to_string_lossy(), src.to_string_lossy());
+    Ok(())
+}
+
+/// A file moved out of an index, a move manifest is a list of these so the
+/// files can be put back
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MoveRecord {
+    pub digest: String,
+    pub size: u64,
+    pub from: PathBuf,
+    pub to: PathBuf
+}
+
+impl MoveRecord {
+
+    /// Returns the header line written at the top of a manifest
+    pub fn header() -> String {
+        format!("{}\n", MANIFEST_HEADER)
+    }
+}
+
+// the moved file as an index item line followed by where it went
+impl Display for MoveRecord {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error>
+    {
+        writeln!(f, "{} {} {}", self.digest, self.size, self.from.to_string_lossy())?;
+        writeln!(f, "{}{}", MOVED_TAG, self.to.to_string_lossy())
+    }
+}
+
+/// Reads a move manifest written with MoveRecord::header and the records
+pub fn read_manifest<R: Read>(r: R) -> Result<Vec<MoveRecord>> {
+    let mut records = Vec::new();
+    let mut pending: Option<(String, u64, PathBuf)> = None;
+    for (n, line) in BufReader::new(r).lines().enumerate() {
+        let line = line?;
+        if line.starts_with('#') {
+            continue;
+        }
+        let invalid = || Error::InvalidFormat(format!("invalid move manifest line {}", n + 1));
+        match (line.strip_prefix(MOVED_TAG), pending.take()) {
+            (Some(to), Some((digest, size, from))) => records.push(MoveRecord {
+                digest,
+                size,
+                from,
+                to: PathBuf::from(to)
+            }),
+            (None, None) => {
+                let mut parts = line.splitn(3, ' ');
+                let digest = parts.next().ok_or_else(invalid)?.to_string();
+                let size = parts.next().and_then(|s| s.parse().ok()).ok_or_else(invalid)?;
+                let from = PathBuf::from(parts.next().ok_or_else(invalid)?);
+                pending = Some((digest, size, from));
+            },
+            _ => return Err(invalid())
+        }
+    }
+    if pending.is_some() {
+        return Err(Error::InvalidFormat("move manifest ends without a destination".to_string()));
+    }
+    Ok(records)
+}
+
+/// Removes the directories that are empty and then each parent that is
+/// left empty, deepest first. The directories is_protected is true for are
+/// never removed, e.g. those DedupPlanner::is_protected_dir is true for,
+/// and the root of the tree should be one so the pruning stops there. A dry
+/// run removes nothing and returns what would be removed.
+pub fn prune_empty_dirs<I: IntoIterator<Item = PathBuf>>(dirs: I, is_protected: &dyn Fn(&Path) -> bool,
+                                                         dry_run: bool) -> Result<Vec<PathBuf>> {
+    prune_empty_dirs_without(dirs, &[], is_protected, dry_run)
+}
+
+/// Works like prune_empty_dirs but counts the files as already gone, so a
+/// dry run of deleting them can say which directories would be left empty
+pub fn prune_empty_dirs_without<I: IntoIterator<Item = PathBuf>>(dirs: I, files: &[PathBuf],
+                                                                 is_protected: &dyn Fn(&Path) -> bool,
+                                                                 dry_run: bool) -> Result<Vec<PathBuf>> {
+    // deepest first so children are gone before their parents are looked at
+    let mut queue: BinaryHeap<(usize, PathBuf)> = dirs.into_iter()
+        .map(|d| (d.components().count(), d))
+        .collect();
+    let mut seen = HashSet::new();
+    let mut removed = Vec::new();
+    let mut gone: HashSet<PathBuf> = files.iter().cloned().collect();
+    while let Some((_, d)) = queue.pop() {
+        if !seen.insert(d.clone()) || is_protected(&d) || !os_path(&d).is_dir() {
+            continue;
+        }
+
+        // a dry run counts the directories it would have removed as gone
+        let mut empty = true;
+        for entry in fs::read_dir(os_path(&d))? {
+            if !gone.contains(&d.join(entry?.file_name())) {
+                empty = false;
+                break;
+            }
+        }
+        if !empty {
+            continue;
+        }
+        if !dry_run {
+            fs::remove_dir(os_path(&d))?;
+        }
+        debug!("pruned {}", d.to_string_lossy());
+        if let Some(parent) = d.parent() {
+            queue.push((parent.components().count(), parent.to_path_buf()));
+        }
+        gone.insert(d.clone());
+        removed.push(d);
+    }
+    Ok(removed)
+}
+
+/// Returns every directory under root, not counting root and not following
+/// symlinks
+pub fn subdirs(root: &Path) -> Result<Vec<PathBuf>> {
+    let mut dirs = Vec::new();
+    let mut stack = vec![root.to_path_buf()];
+    while let Some(d) = stack.pop() {
+        for entry in fs::read_dir(&d)? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                dirs.push(entry.path());
+                stack.push(entry.path());
+            }
+        }
+    }
+    Ok(dirs)
+}
+
+/// Returns the deepest directory that holds all of the paths
+pub fn common_root<P: AsRef<Path>, I: IntoIterator<Item = P>>(paths: I) -> PathBuf {
+    let mut root: Option<PathBuf> = None;
+    for p in paths {
+        let dir = p.as_ref().parent().unwrap_or_else(|| Path::new(""));
+        root = Some(match root {
+            None => dir.to_path_buf(),
+            Some(r) => r.components()
+                .zip(dir.components())
+                .take_while(|(a, b)| a == b)
+                .map(|(a, _)| a)
+                .collect()
+        });
+    }
+    root.unwrap_or_default()
+}
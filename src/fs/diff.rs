@@ -0,0 +1,157 @@
+use crate::fs::{
+    ItemDigest,
+    ItemPath,
+    TreeIndex,
+    TreeItem
+};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Display, Formatter};
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// A TreeIndexDiff compares two indexes path by path. A path in both with
+/// the same digest is matched, with a different digest it is modified, only
+/// in the old index it is missing and only in the new index it was added.
+/// When both indexes have chunk digests, regions has the byte ranges that
+/// changed in each modified file. When both have file metadata, a path with
+/// the same digest and different metadata is in changed with the names of
+/// the fields that differ. renamed is empty until detect_renames is called.
+#[derive(Clone, Default)]
+pub struct TreeIndexDiff {
+    pub matched: Vec<TreeItem>,
+    pub modified: Vec<TreeItem>,
+    pub missing: Vec<TreeItem>,
+    pub added: Vec<TreeItem>,
+    pub regions: BTreeMap<Arc<PathBuf>, Vec<Range<u64>>>,
+    pub changed: Vec<(TreeItem, Vec<&'static str>)>,
+    pub renamed: Vec<(TreeItem, TreeItem)>
+}
+
+impl TreeIndexDiff {
+
+    /// Compares the old index to the new one. The lists are sorted by path.
+    pub fn between(old: &TreeIndex, new: &TreeIndex) -> Self {
+        let old_paths = items(old);
+        let mut new_paths = items(new);
+
+        let mut diff = Self::default();
+        for (path, item) in old_paths {
+            match new_paths.remove(&path) {
+                Some(n) if n.digest.same_as(&item.digest) => {
+                    let changes = match (&item.metadata, &n.metadata) {
+                        (Some(old), Some(new)) => old.changes(new),
+                        _ => Vec::new()
+                    };
+                    if changes.is_empty() {
+                        diff.matched.push(n);
+                    } else {
+                        diff.changed.push((n, changes));
+                    }
+                },
+                Some(n) => {
+                    if let (Some(old), Some(new)) = (&item.chunks, &n.chunks) {
+                        if let Some(regions) = old.changed_regions(new, item.size.max(n.size)) {
+                            diff.regions.insert(Arc::new(path.path()), regions);
+                        }
+                    }
+                    diff.modified.push(n)
+                },
+                None => diff.missing.push(item)
+            }
+        }
+        diff.added.extend(new_paths.into_values());
+
+        for list in [&mut diff.matched, &mut diff.modified, &mut diff.missing, &mut diff.added] {
+            list.sort_by_cached_key(TreeItem::path);
+        }
+        diff.changed.sort_by_cached_key(|c| c.0.path());
+        diff
+    }
+
+    /// Pairs the missing items with added items that have the same digest
+    /// and size and moves them to renamed as (old, new) pairs, so a file
+    /// that was moved shows up as one rename instead of a missing and a new
+    /// file. When several missing files have the digest, one with the same
+    /// file name is paired first. Returns the number of renames found.
+    pub fn detect_renames(&mut self) -> usize {
+        let mut gone: HashMap<(ItemDigest, u64), Vec<TreeItem>> = HashMap::new();
+        for i in self.missing.drain(..) {
+            gone.entry((i.digest.clone(), i.size)).or_default().push(i);
+        }
+
+        let mut added = Vec::new();
+        let mut found = 0;
+        for n in self.added.drain(..) {
+            let candidates = match gone.get_mut(&(n.digest.clone(), n.size)) {
+                Some(c) if !c.is_empty() => c,
+                _ => {
+                    added.push(n);
+                    continue;
+                }
+            };
+            let name = n.path().file_name().map(|name| name.to_os_string());
+            let pick = candidates.iter()
+                .position(|o| o.path().file_name() == name.as_deref())
+                .unwrap_or(0);
+            self.renamed.push((candidates.remove(pick), n));
+            found += 1;
+        }
+        self.added = added;
+        self.missing = gone.into_values().flatten().collect();
+        self.missing.sort_by_cached_key(TreeItem::path);
+        self.renamed.sort_by_cached_key(|r| r.0.path());
+        found
+    }
+
+    /// Returns true if nothing was modified, missing, added, changed or
+    /// renamed
+    pub fn is_clean(&self) -> bool {
+        self.modified.is_empty() && self.missing.is_empty() && self.added.is_empty() && self.changed.is_empty()
+            && self.renamed.is_empty()
+    }
+}
+
+// the Display impl lists everything that isn't matched, one path per line
+// with the kind of change first
+impl Display for TreeIndexDiff {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error>
+    {
+        for i in &self.modified {
+            match self.regions.get(&i.path()) {
+                Some(regions) if !regions.is_empty() => {
+                    let regions: Vec<String> = regions.iter()
+                        .map(|r| format!("{}..{}", r.start, r.end))
+                        .collect();
+                    writeln!(f, "modified {} bytes {}", i.path().to_string_lossy(), regions.join(","))?;
+                },
+                _ => writeln!(f, "modified {}", i.path().to_string_lossy())?
+            }
+        }
+        for (i, changes) in &self.changed {
+            writeln!(f, "changed {} {}", i.path().to_string_lossy(), changes.join(","))?;
+        }
+        for (old, new) in &self.renamed {
+            writeln!(f, "renamed {} -> {}", old.path().to_string_lossy(), new.path().to_string_lossy())?;
+        }
+        for i in &self.missing {
+            writeln!(f, "missing {}", i.path().to_string_lossy())?;
+        }
+        for i in &self.added {
+            writeln!(f, "new {}", i.path().to_string_lossy())?;
+        }
+        Ok(())
+    }
+}
+
+// flattens an index into one item per path
+fn items(ti: &TreeIndex) -> HashMap<ItemPath, TreeItem> {
+    let mut items = HashMap::new();
+    for v in ti.idx.values() {
+        items.insert(v.item.path.clone(), v.item.clone());
+        for d in &v.dupes {
+            items.insert(d.clone(), v.dupe_item(d));
+        }
+    }
+    items
+}
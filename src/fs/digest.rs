@@ -0,0 +1,191 @@
+use crate::{
+    error::Error,
+    Result,
+    fs::{
+        decode_multihash,
+        decode_multihash_variant,
+        multihash::hex,
+        Algorithm,
+        DigestEncoding,
+        DigestVariant
+    }
+};
+use std::convert::TryInto;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// The length in bytes of the digests made by every supported algorithm
+pub const DIGEST_LEN: usize = 32;
+
+/// A Digest is the raw bytes of a file digest. It takes half the memory of
+/// the hex string and compares as one slice, entries in a TreeIndex are
+/// keyed by it. It displays as hex and parses from hex or from a multibase
+/// multihash, the text formats keep the string the index was written with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Digest([u8; DIGEST_LEN]);
+
+impl Digest {
+
+    /// Makes a digest from raw bytes, which have to be DIGEST_LEN long
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let bytes: [u8; DIGEST_LEN] = bytes.try_into()
+            .map_err(|_| Error::InvalidFormat(format!("a digest is {} bytes, not {}", DIGEST_LEN, bytes.len())))?;
+        Ok(Self(bytes))
+    }
+
+    pub fn as_bytes(&self) -> &[u8; DIGEST_LEN] {
+        &self.0
+    }
+
+    /// Writes the digest the way an index with the encoding does
+    pub fn encode(&self, algorithm: Algorithm, encoding: DigestEncoding) -> String {
+        encoding.encode(algorithm, &self.0)
+    }
+}
+
+/// The digest of a TreeItem. Digests the supported algorithms make are kept
+/// as the raw bytes of a Digest along with how they were written, a hex
+/// digest or a multihash with its algorithm and variant, and are only turned
+/// back into text when they are written out. That comes out the same as it
+/// went in. Anything else, like the empty digest of special files or a
+/// digest written in upper case, is kept as the text.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ItemDigest {
+    Hex(Digest),
+    Multihash(Algorithm, DigestVariant, Digest),
+    Text(Box<str>)
+}
+
+impl ItemDigest {
+
+    /// The digest written in the encoding, the algorithm and variant are
+    /// only written by multihashes
+    pub fn new(encoding: DigestEncoding, algorithm: Algorithm, variant: DigestVariant, digest: Digest) -> Self {
+        match encoding {
+            DigestEncoding::Hex => ItemDigest::Hex(digest),
+            DigestEncoding::Multihash => ItemDigest::Multihash(algorithm, variant, digest)
+        }
+    }
+
+    /// The raw bytes, if it is a digest the supported algorithms make
+    pub fn digest(&self) -> Option<Digest> {
+        match self {
+            ItemDigest::Hex(d) | ItemDigest::Multihash(_, _, d) => Some(*d),
+            ItemDigest::Text(s) => s.parse().ok()
+        }
+    }
+
+    /// Returns true for the empty digest of special files
+    pub fn is_empty(&self) -> bool {
+        matches!(self, ItemDigest::Text(s) if s.is_empty())
+    }
+
+    /// Returns true if the two are the same bytes, however they are written,
+    /// or the same text when they aren't digests the supported algorithms
+    /// make. An index written before fast and keyed multihashes had codes
+    /// of their own still matches.
+    pub fn same_as(&self, other: &ItemDigest) -> bool {
+        match (self.digest(), other.digest()) {
+            (Some(a), Some(b)) => a == b,
+            _ => self == other
+        }
+    }
+}
+
+impl Default for ItemDigest {
+    fn default() -> Self {
+        ItemDigest::Text(Box::from(""))
+    }
+}
+
+impl From<&str> for ItemDigest {
+    fn from(s: &str) -> Self {
+        // only the forms that are written back the same are kept as bytes
+        if s.len() == DIGEST_LEN * 2 {
+            if let Some(d) = from_hex(s.as_bytes()).filter(|_| !s.bytes().any(|c| c.is_ascii_uppercase())) {
+                return ItemDigest::Hex(d);
+            }
+        }
+        if s.starts_with('b') {
+            if let Ok((algorithm, variant, bytes)) = decode_multihash_variant(s) {
+                if let Ok(d) = Digest::from_bytes(&bytes) {
+                    let digest = ItemDigest::Multihash(algorithm, variant, d);
+                    if digest.to_string() == s {
+                        return digest;
+                    }
+                }
+            }
+        }
+        ItemDigest::Text(Box::from(s))
+    }
+}
+
+impl From<&String> for ItemDigest {
+    fn from(s: &String) -> Self {
+        ItemDigest::from(s.as_str())
+    }
+}
+
+impl From<&ItemDigest> for ItemDigest {
+    fn from(d: &ItemDigest) -> Self {
+        d.clone()
+    }
+}
+
+impl Display for ItemDigest {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error>
+    {
+        match self {
+            ItemDigest::Hex(d) => write!(f, "{}", d),
+            ItemDigest::Multihash(algorithm, variant, d) => {
+                write!(f, "{}", DigestEncoding::Multihash.encode_variant(*algorithm, *variant, &d.0))
+            },
+            ItemDigest::Text(s) => write!(f, "{}", s)
+        }
+    }
+}
+
+impl From<[u8; DIGEST_LEN]> for Digest {
+    fn from(bytes: [u8; DIGEST_LEN]) -> Self {
+        Self(bytes)
+    }
+}
+
+impl FromStr for Digest {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s.len() == DIGEST_LEN * 2 {
+            if let Some(digest) = from_hex(s.as_bytes()) {
+                return Ok(digest);
+            }
+        }
+        let (_, bytes) = decode_multihash(s)
+            .map_err(|_| Error::InvalidFormat(format!("invalid digest {}", s)))?;
+        Self::from_bytes(&bytes)
+    }
+}
+
+impl Display for Digest {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error>
+    {
+        write!(f, "{}", hex(&self.0))
+    }
+}
+
+// decodes a hex digest without allocating, every index key is made this way
+fn from_hex(s: &[u8]) -> Option<Digest> {
+    fn nibble(c: u8) -> Option<u8> {
+        match c {
+            b'0'..=b'9' => Some(c - b'0'),
+            b'a'..=b'f' => Some(c - b'a' + 10),
+            b'A'..=b'F' => Some(c - b'A' + 10),
+            _ => None
+        }
+    }
+    let mut bytes = [0u8; DIGEST_LEN];
+    for (b, pair) in bytes.iter_mut().zip(s.chunks_exact(2)) {
+        *b = nibble(pair[0])? << 4 | nibble(pair[1])?;
+    }
+    Some(Digest(bytes))
+}
@@ -0,0 +1,306 @@
+use crate::{
+    fs::{ItemDigest, ItemPath, TreeIndex, TreeItemDupes, treeitem::escape_path},
+    units::{format_bytes, Units}
+};
+use blake2b_simd::Params;
+use std::collections::{HashMap, HashSet};
+use std::convert::From;
+use std::fmt::{Display, Formatter};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+// A DirItem is a directory with the aggregate digest of everything under it,
+// the total size of its files and how many files it contains
+#[derive(Clone)]
+pub struct DirItem {
+    pub digest: String,
+    pub path: Arc<PathBuf>,
+    pub size: u64,
+    pub files: usize
+}
+
+impl Display for DirItem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error>
+    {
+        writeln!(f, "{} {} {}", self.digest, self.size, escape_path(&self.path)?)
+    }
+}
+
+// a child entry of a directory, files and dirs are tagged so a directory
+// holding one file never has the same digest as the file
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+enum Child {
+    Dir(String),
+    File(String)
+}
+
+/// A DirIndex holds an aggregate digest for every directory that contains a
+/// file in a TreeIndex. A directory's digest is a Merkle digest over the
+/// sorted digests of its files and subdirectories, so two directories have
+/// the same digest when they hold the same contents no matter what the
+/// files are named. Only files in the index are counted, so make sure the
+/// index was built with dupes and without filters that skip files.
+#[derive(Clone, Default)]
+pub struct DirIndex {
+    pub dirs: HashMap<Arc<PathBuf>, DirItem>
+}
+
+impl DirIndex {
+
+    /// Returns groups of directories that are exact duplicates of each other,
+    /// largest first. Each group is a TreeItemDupes with the total size of one
+    /// directory, so it can be written out like an index. Groups of
+    /// subdirectories are left out when their parents are duplicates too.
+    pub fn dupes(&self) -> Vec<TreeItemDupes> {
+        let mut groups: HashMap<&str, Vec<&DirItem>> = HashMap::new();
+        for d in self.dirs.values() {
+            groups.entry(d.digest.as_str()).or_default().push(d);
+        }
+
+        let mut dupes = Vec::new();
+        for (digest, mut dirs) in groups {
+            if dirs.len() < 2 || self.parents_are_dupes(&dirs) {
+                continue;
+            }
+            dirs.sort_by(|a, b| a.path.cmp(&b.path));
+            let mut entry = TreeItemDupes::new(digest, &dirs[0].path, dirs[0].size);
+            for d in &dirs[1..] {
+                entry.push(ItemPath::from(&d.path));
+            }
+            dupes.push(entry);
+        }
+        dupes.sort_by_cached_key(|d| (std::cmp::Reverse(d.item.size), d.item.path()));
+        dupes
+    }
+
+    // if the directories have different parents that all have the same
+    // digest, the parents are a duplicate group and this group is already
+    // covered by it
+    fn parents_are_dupes(&self, dirs: &[&DirItem]) -> bool {
+        let mut digest = None;
+        let mut seen = HashSet::new();
+        for d in dirs {
+            let parent = match d.path.parent().and_then(|p| self.dirs.get(&PathBuf::from(p))) {
+                Some(p) => p,
+                None => return false
+            };
+            if !seen.insert(parent.path.clone()) {
+                return false;
+            }
+            match digest {
+                None => digest = Some(&parent.digest),
+                Some(dg) if dg == &parent.digest => {},
+                Some(_) => return false
+            }
+        }
+        true
+    }
+}
+
+impl From<&TreeIndex> for DirIndex {
+    fn from(ti: &TreeIndex) -> Self {
+        // gather the files in each directory along with their sizes
+        let mut children: HashMap<PathBuf, (Vec<Child>, u64, usize)> = HashMap::new();
+        for v in ti.idx.values() {
+            for p in std::iter::once(v.item.item_path()).chain(v.dupes.iter()) {
+                if let Some(parent) = p.path().parent() {
+                    let c = children.entry(parent.to_path_buf()).or_default();
+                    c.0.push(Child::File(v.item.digest.to_string()));
+                    c.1 += v.item.size;
+                    c.2 += 1;
+                }
+            }
+        }
+
+        // make sure every ancestor has an entry
+        let dirs: Vec<PathBuf> = children.keys().cloned().collect();
+        for d in dirs {
+            for a in d.ancestors().skip(1) {
+                children.entry(a.to_path_buf()).or_default();
+            }
+        }
+
+        // digest the deepest directories first so each directory's children
+        // are done before it is
+        let mut order: Vec<PathBuf> = children.keys().cloned().collect();
+        order.sort_by_key(|p| std::cmp::Reverse(p.components().count()));
+
+        let mut di = DirIndex::default();
+        for path in order {
+            let (mut kids, size, files) = match children.remove(&path) {
+                Some(c) => c,
+                None => continue
+            };
+            kids.sort();
+
+            let mut hash = Params::new().hash_length(32).to_state();
+            for k in &kids {
+                match k {
+                    Child::Dir(d) => { hash.update(b"d"); hash.update(d.as_bytes()); },
+                    Child::File(d) => { hash.update(b"f"); hash.update(d.as_bytes()); }
+                }
+                hash.update(b"\n");
+            }
+            let digest = hash.finalize().to_hex().to_string();
+
+            // roll this directory up into its parent
+            if let Some(parent) = path.parent() {
+                if let Some(c) = children.get_mut(parent) {
+                    c.0.push(Child::Dir(digest.clone()));
+                    c.1 += size;
+                    c.2 += files;
+                }
+            }
+
+            let path = Arc::new(path);
+            di.dirs.insert(path.clone(), DirItem { digest, path, size, files });
+        }
+        di
+    }
+}
+
+// A DirSimilarity says how much of one directory's content is also in another
+// directory. The score is the fraction of the directory's bytes that are
+// shared, so it isn't symmetric, a small directory can be entirely contained
+// in a big one.
+#[derive(Clone)]
+pub struct DirSimilarity {
+    pub dir: Arc<PathBuf>,
+    pub other: Arc<PathBuf>,
+    pub shared: u64,
+    pub size: u64
+}
+
+impl DirSimilarity {
+    pub fn score(&self) -> f64 {
+        if self.size == 0 {
+            0.0
+        } else {
+            self.shared as f64 / self.size as f64
+        }
+    }
+}
+
+impl Display for DirSimilarity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error>
+    {
+        writeln!(f, "{} is {:.0}% contained in {}",
+                 self.dir.to_string_lossy(),
+                 self.score() * 100.0,
+                 self.other.to_string_lossy())
+    }
+}
+
+/// Scores every pair of directories that share content by the fraction of
+/// the first directory's bytes that are also in the second, and returns the
+/// pairs scoring at least min_score, best first. Only the files directly in
+/// each directory are compared and each distinct digest is counted once, so
+/// a directory full of copies of one file doesn't look bigger than it is.
+/// Use DirIndex for whole trees that are exact duplicates.
+pub fn dir_similarity(ti: &TreeIndex, min_score: f64) -> Vec<DirSimilarity> {
+    // the distinct digests in each directory and which directories hold
+    // each digest
+    let mut sizes: HashMap<PathBuf, u64> = HashMap::new();
+    let mut holders: HashMap<&ItemDigest, (u64, Vec<PathBuf>)> = HashMap::new();
+    for v in ti.idx.values() {
+        let mut dirs: Vec<PathBuf> = std::iter::once(v.item.item_path())
+            .chain(v.dupes.iter())
+            .filter_map(|p| p.path().parent().map(|p| p.to_path_buf()))
+            .collect();
+        dirs.sort();
+        dirs.dedup();
+        for d in &dirs {
+            *sizes.entry(d.clone()).or_default() += v.item.size;
+        }
+        // empty files don't add anything to a score
+        if dirs.len() > 1 && v.item.size > 0 {
+            holders.insert(&v.item.digest, (v.item.size, dirs));
+        }
+    }
+
+    // add up the bytes every ordered pair of directories shares
+    let mut shared: HashMap<(&PathBuf, &PathBuf), u64> = HashMap::new();
+    for (size, dirs) in holders.values() {
+        for a in dirs {
+            for b in dirs {
+                if a != b {
+                    *shared.entry((a, b)).or_default() += size;
+                }
+            }
+        }
+    }
+
+    let mut pairs: Vec<DirSimilarity> = shared.into_iter()
+        .map(|((a, b), s)| DirSimilarity {
+            dir: Arc::new(a.clone()),
+            other: Arc::new(b.clone()),
+            shared: s,
+            size: sizes.get(a).copied().unwrap_or(0)
+        })
+        .filter(|p| p.score() >= min_score)
+        .collect();
+    pairs.sort_by(|a, b| {
+        b.score().partial_cmp(&a.score()).unwrap_or(std::cmp::Ordering::Equal)
+            .then_with(|| b.shared.cmp(&a.shared))
+            .then_with(|| a.dir.cmp(&b.dir))
+    });
+    pairs
+}
+
+// A DirPair is a directory holding dupes of files in another directory, the
+// original is the directory of the first path in each group
+#[derive(Clone)]
+pub struct DirPair {
+    pub original: Arc<PathBuf>,
+    pub dupe: Arc<PathBuf>,
+    pub files: u64,
+    pub bytes: u64
+}
+
+impl Display for DirPair {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error>
+    {
+        writeln!(f, "{} duplicates {} in {} files of {}",
+                 self.dupe.to_string_lossy(),
+                 format_bytes(self.bytes, Units::Binary),
+                 self.files,
+                 self.original.to_string_lossy())
+    }
+}
+
+/// Adds up the dupes in the index by the directory of the original and the
+/// directory of the dupe and returns the pairs, the most bytes first. This
+/// turns a flat list of dupes into something like "Backup/2019 duplicates
+/// 12 GiB of Photos/2019".
+pub fn dir_pairs(ti: &TreeIndex) -> Vec<DirPair> {
+    let mut pairs: HashMap<(PathBuf, PathBuf), (u64, u64)> = HashMap::new();
+    for v in ti.idx.values() {
+        let original = match v.item.path().parent() {
+            Some(p) => p.to_path_buf(),
+            None => continue
+        };
+        for d in &v.dupes {
+            if let Some(dupe) = d.path().parent() {
+                let p = pairs.entry((original.clone(), dupe.to_path_buf())).or_default();
+                p.0 += 1;
+                p.1 += v.item.size;
+            }
+        }
+    }
+
+    let mut pairs: Vec<DirPair> = pairs.into_iter()
+        .map(|((original, dupe), (files, bytes))| DirPair {
+            original: Arc::new(original),
+            dupe: Arc::new(dupe),
+            files,
+            bytes
+        })
+        .collect();
+    pairs.sort_by(|a, b| {
+        b.bytes.cmp(&a.bytes)
+            .then_with(|| b.files.cmp(&a.files))
+            .then_with(|| a.original.cmp(&b.original))
+            .then_with(|| a.dupe.cmp(&b.dupe))
+    });
+    pairs
+}
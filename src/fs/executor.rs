@@ -0,0 +1,398 @@
+use crate::{
+    error::Error,
+    Result,
+    fs::{
+        apple_double_path,
+        apply_action,
+        copy_file,
+        copy_verified,
+        dedupe_file,
+        digest_matches,
+        hardlink_file,
+        move_file,
+        os_path,
+        prune_empty_dirs_without,
+        Action,
+        ActionLog,
+        AppleDoublePolicy,
+        ActionRecord,
+        ActionResult,
+        Metrics,
+        PreserveOptions,
+        ScriptShell,
+        ScriptWriter,
+        TreeIndexHeader,
+        metrics,
+        open_locked
+    }
+};
+use log::{debug, error, warn};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// How a DedupExecutor carries out its actions
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum ExecutionMode {
+    /// The actions are done
+    #[default]
+    Execute,
+
+    /// Nothing is touched, the actions are only logged
+    DryRun,
+
+    /// The actions are written to a script at the path instead of being
+    /// done and are logged as a dry run
+    Script(PathBuf)
+}
+
+/// A DedupExecutor carries out the actions on duplicate files and writes
+/// every one to an action log, the same way in every mode. An action that
+/// fails is logged as failed and counted and the next one goes ahead.
+pub struct DedupExecutor {
+    execute: bool,
+    log: ActionLog,
+    script: Option<ScriptWriter>,
+    header: TreeIndexHeader,
+    key: Option<Vec<u8>>,
+    verify: bool,
+    retries: usize,
+    preserve: PreserveOptions,
+    apple_double: AppleDoublePolicy,
+
+    // the files a dry run would have removed, for working out which
+    // directories it would have left empty
+    removed: Vec<PathBuf>,
+    failed: usize,
+    deduped: u64,
+    metrics: Option<Arc<dyn Metrics>>
+}
+
+impl DedupExecutor {
+
+    /// Copies from to to
+    pub fn copy(&mut self, digest: &str, size: u64, from: &Path, to: &Path) -> Result<ActionResult> {
+        let record = ActionRecord::new(Action::Copy, from, ActionResult::Done).dst(to).file(digest, size);
+        let result = self.run(record, |x| {
+            make_parent(to)?;
+            if !x.verify {
+                copy_file(from, to, &x.preserve)?;
+                return Ok(true);
+            }
+            let ok = copy_verified(&x.header, digest, from, to, x.key.as_deref(), x.retries, &x.preserve)?;
+            if !ok {
+                error!("failed to verify the copy of {}", from.to_string_lossy());
+            }
+            Ok(ok)
+        })?;
+        self.pair(Action::Copy, result, from, Some(to))?;
+        Ok(result)
+    }
+
+    /// Moves from to to, a move between file systems is always verified
+    pub fn move_to(&mut self, digest: &str, size: u64, from: &Path, to: &Path) -> Result<ActionResult> {
+        let record = ActionRecord::new(Action::Move, from, ActionResult::Done).dst(to).file(digest, size);
+        let result = self.run(record, |x| {
+            make_parent(to)?;
+            let ok = move_file(&x.header, digest, from, to, x.key.as_deref(), x.retries, &x.preserve)?;
+            if !ok {
+                error!("failed to verify the copy of {}, it wasn't moved", from.to_string_lossy());
+            }
+            Ok(ok)
+        })?;
+        self.removed(result, from);
+        self.pair(Action::Move, result, from, Some(to))?;
+        Ok(result)
+    }
+
+    /// Deletes the file
+    pub fn delete(&mut self, digest: &str, size: u64, path: &Path) -> Result<ActionResult> {
+        let record = ActionRecord::new(Action::Delete, path, ActionResult::Done).file(digest, size);
+        let result = self.run(record, |_| {
+            fs::remove_file(os_path(path))?;
+            Ok(true)
+        })?;
+        self.removed(result, path);
+        self.pair(Action::Delete, result, path, None)?;
+        Ok(result)
+    }
+
+    /// Has the file system share the data of dupe with keep, this is what
+    /// reflinking files that already exist comes down to. The kernel checks
+    /// that the files match so a dupe that changed is only logged as failed.
+    pub fn dedupe(&mut self, digest: &str, size: u64, keep: &Path, dupe: &Path) -> Result<ActionResult> {
+        let record = ActionRecord::new(Action::Dedupe, keep, ActionResult::Done).dst(dupe).file(digest, size);
+        self.run(record, |x| match dedupe_file(keep, dupe)? {
+            Some(n) => {
+                x.deduped += n;
+                x.add(metrics::BYTES_DEDUPED, n);
+                Ok(true)
+            },
+            None => {
+                warn!("{} differs from {}, it wasn't de-duped", dupe.to_string_lossy(), keep.to_string_lossy());
+                Ok(false)
+            }
+        })
+    }
+
+    /// Replaces dupe with a hard link to keep. The dupe has to still be the
+    /// size in the index, and match its digest too when verifying.
+    pub fn hardlink(&mut self, digest: &str, size: u64, keep: &Path, dupe: &Path) -> Result<ActionResult> {
+        let record = ActionRecord::new(Action::Hardlink, keep, ActionResult::Done).dst(dupe).file(digest, size);
+        self.run(record, |x| {
+            let changed = fs::metadata(os_path(dupe))?.len() != size
+                || (x.verify && !digest_matches(&x.header, digest, dupe, x.key.as_deref())?);
+            if changed {
+                warn!("{} has changed since it was indexed, it wasn't linked", dupe.to_string_lossy());
+                return Ok(false);
+            }
+            hardlink_file(keep, dupe)?;
+            Ok(true)
+        })
+    }
+
+    /// Removes the directories left empty by the deletes and moves, the
+    /// same way prune_empty_dirs does, and logs each one
+    pub fn prune(&mut self, dirs: Vec<PathBuf>, is_protected: &dyn Fn(&Path) -> bool) -> Result<()> {
+        let result = if self.execute { ActionResult::Done } else { ActionResult::DryRun };
+        for d in prune_empty_dirs_without(dirs, &self.removed, is_protected, !self.execute)? {
+            let record = ActionRecord::new(Action::Rmdir, &d, result);
+            if let Some(s) = &mut self.script {
+                s.action(&record)?;
+            }
+            self.log.record(&record)?;
+        }
+        Ok(())
+    }
+
+    /// The number of actions that failed so far
+    pub fn failed(&self) -> usize {
+        self.failed
+    }
+
+    /// The bytes de-duped so far
+    pub fn deduped(&self) -> u64 {
+        self.deduped
+    }
+
+    /// Finishes the script and returns the number of actions that failed
+    pub fn finish(self) -> Result<usize> {
+        if let Some(s) = self.script {
+            s.finish()?;
+        }
+        Ok(self.failed)
+    }
+
+    // does the action, f returns false if it failed in a way it has
+    // already explained
+    fn run<F>(&mut self, record: ActionRecord, f: F) -> Result<ActionResult>
+        where F: FnOnce(&mut Self) -> Result<bool>
+    {
+        if !self.execute {
+            let record = ActionRecord { result: ActionResult::DryRun, ..record };
+            if let Some(s) = &mut self.script {
+                s.action(&record)?;
+            }
+            self.log.record(&record)?;
+            return Ok(ActionResult::DryRun);
+        }
+        let result = match f(self) {
+            Ok(true) => {
+                debug!("{} {}", record.action, record.src.to_string_lossy());
+                ActionResult::Done
+            },
+            Ok(false) => ActionResult::Failed,
+            Err(e) => {
+                error!("failed to {} {}: {}", record.action, record.src.to_string_lossy(), e);
+                ActionResult::Failed
+            }
+        };
+        if result == ActionResult::Failed {
+            self.failed += 1;
+            self.add(metrics::ACTIONS_FAILED, 1);
+        } else {
+            self.add(metrics::ACTIONS_DONE, 1);
+        }
+        self.log.record(&ActionRecord { result, ..record })?;
+        Ok(result)
+    }
+
+    fn add(&self, name: &'static str, value: u64) {
+        if let Some(m) = &self.metrics {
+            m.add(name, value);
+        }
+    }
+
+    // with AppleDoublePolicy::Pair the AppleDouble file of a file that was
+    // deleted, moved or copied goes the same way, logged as its own action
+    // so it is undone with the file
+    fn pair(&mut self, action: Action, result: ActionResult, from: &Path, to: Option<&Path>) -> Result<()> {
+        if self.apple_double != AppleDoublePolicy::Pair || result == ActionResult::Failed {
+            return Ok(());
+        }
+        let side = match apple_double_path(from) {
+            Some(side) if os_path(&side).is_file() => side,
+            _ => return Ok(())
+        };
+        let size = fs::metadata(os_path(&side))?.len();
+        let mut record = ActionRecord::new(action, &side, ActionResult::Done).file("", size);
+        if let Some(to) = to.and_then(apple_double_path) {
+            record = record.dst(&to);
+        }
+        let result = self.run(record.clone(), |x| {
+            apply_action(&record, None, None, &x.preserve)?;
+            Ok(true)
+        })?;
+        if action != Action::Copy {
+            self.removed(result, &side);
+        }
+        Ok(())
+    }
+
+    // remembers a file a dry run would have removed
+    fn removed(&mut self, result: ActionResult, path: &Path) {
+        if result == ActionResult::DryRun {
+            self.removed.push(path.to_path_buf());
+        }
+    }
+}
+
+// makes the directory a file is going in
+fn make_parent(path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(os_path(parent))?;
+    }
+    Ok(())
+}
+
+#[derive(Default)]
+pub struct DedupExecutorBuilder {
+    mode: ExecutionMode,
+    shell: ScriptShell,
+    log: Option<Box<dyn Write>>,
+    script: Option<Box<dyn Write>>,
+    header: Option<TreeIndexHeader>,
+    key: Option<Vec<u8>>,
+    verify: bool,
+    retries: usize,
+    preserve: PreserveOptions,
+    apple_double: AppleDoublePolicy,
+    metrics: Option<Arc<dyn Metrics>>
+}
+
+impl DedupExecutorBuilder {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether the actions are done, only logged or written to a script
+    pub fn mode(mut self, mode: ExecutionMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// The shell scripts are written for
+    pub fn shell(mut self, shell: ScriptShell) -> Self {
+        self.shell = shell;
+        self
+    }
+
+    /// Where the action log goes, without one the actions aren't logged
+    pub fn log(mut self, w: Box<dyn Write>) -> Self {
+        self.log = Some(w);
+        self
+    }
+
+    /// Where the script goes in ExecutionMode::Script instead of the file
+    /// at its path, for writing it somewhere other than a file
+    pub fn script(mut self, w: Box<dyn Write>) -> Self {
+        self.script = Some(w);
+        self
+    }
+
+    /// The header of the index the digests are from, the files are
+    /// digested the way it says to verify them. It is written at the top of
+    /// the log so undoing the actions can check the files too.
+    pub fn header(mut self, header: &TreeIndexHeader) -> Self {
+        self.header = Some(header.clone());
+        self
+    }
+
+    /// The secret for keyed indexes
+    pub fn key(mut self, key: &[u8]) -> Self {
+        self.key = Some(key.to_vec());
+        self
+    }
+
+    /// Digest the copies, and the dupes before they are linked, to check
+    /// them against the index
+    pub fn verify(mut self, verify: bool) -> Self {
+        self.verify = verify;
+        self
+    }
+
+    /// How many more times to copy a file that doesn't verify
+    pub fn retries(mut self, retries: usize) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// The metadata to keep on copies
+    pub fn preserve(mut self, preserve: PreserveOptions) -> Self {
+        self.preserve = preserve;
+        self
+    }
+
+    /// With AppleDoublePolicy::Pair the ._name AppleDouble file of each
+    /// file deleted, moved or copied is deleted, moved or copied with it
+    pub fn apple_double(mut self, policy: AppleDoublePolicy) -> Self {
+        self.apple_double = policy;
+        self
+    }
+
+    /// Tells the metrics about the actions carried out and the ones that
+    /// failed, dry runs and scripts don't count
+    pub fn metrics(mut self, metrics: Arc<dyn Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    pub fn build(mut self) -> Result<DedupExecutor> {
+        let logged = self.header.is_some();
+        let header = self.header.take().unwrap_or_default();
+        if header.is_keyed() && self.key.is_none() && self.verify {
+            return Err(Error::InvalidArgument("verifying a keyed index needs its key".to_string()));
+        }
+        let script = match &self.mode {
+            ExecutionMode::Script(p) => {
+                let w = match self.script.take() {
+                    Some(w) => w,
+                    None => Box::new(open_locked(p, false)?)
+                };
+                Some(ScriptWriter::new(w, self.shell)?)
+            },
+            _ => None
+        };
+        let mut log = ActionLog::new(self.log.unwrap_or_else(|| Box::new(io::sink())));
+        if logged {
+            log.header(&header)?;
+        }
+        Ok(DedupExecutor {
+            execute: self.mode == ExecutionMode::Execute,
+            log,
+            script,
+            header,
+            key: self.key,
+            verify: self.verify,
+            retries: self.retries,
+            preserve: self.preserve,
+            apple_double: self.apple_double,
+            removed: Vec::new(),
+            failed: 0,
+            deduped: 0,
+            metrics: self.metrics
+        })
+    }
+}
@@ -0,0 +1,237 @@
+#[cfg(not(target_os = "linux"))]
+use crate::error::Error;
+use crate::Result;
+use std::path::Path;
+
+/// A run of a file's bytes and where they are stored on the device
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Extent {
+    pub logical: u64,
+    pub physical: u64,
+    pub length: u64,
+    pub flags: u32
+}
+
+impl Extent {
+
+    /// Returns true if the file system says other files use the extent too
+    pub fn is_shared(&self) -> bool {
+        self.flags & FIEMAP_EXTENT_SHARED != 0
+    }
+
+    // extents that aren't mapped to the device yet or are encoded can't be
+    // compared by where they are
+    fn has_location(&self) -> bool {
+        self.flags & (FIEMAP_EXTENT_UNKNOWN | FIEMAP_EXTENT_DELALLOC | FIEMAP_EXTENT_ENCODED) == 0
+    }
+}
+
+// the extent flags from linux/fiemap.h
+const FIEMAP_EXTENT_UNKNOWN: u32 = 0x2;
+const FIEMAP_EXTENT_DELALLOC: u32 = 0x4;
+const FIEMAP_EXTENT_ENCODED: u32 = 0x8;
+const FIEMAP_EXTENT_SHARED: u32 = 0x2000;
+
+/// Returns the extents of the file. Only Linux file systems that support
+/// FIEMAP, like btrfs, XFS and ext4, can say where a file is stored, this
+/// fails everywhere else.
+#[cfg(target_os = "linux")]
+pub fn extents(path: &Path) -> Result<Vec<Extent>> {
+    fiemap::extents(path)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn extents(path: &Path) -> Result<Vec<Extent>> {
+    Err(Error::InvalidArgument(format!("can't read the extents of {} on this platform", path.to_string_lossy())))
+}
+
+/// Returns how many bytes of the two files are stored in the same place on
+/// the device, i.e. were reflinked or de-duplicated by the file system.
+/// Removing one of them frees none of those bytes.
+pub fn shared_bytes(a: &Path, b: &Path) -> Result<u64> {
+    let mut a: Vec<_> = extents(a)?.into_iter().filter(|e| e.has_location()).collect();
+    let mut b: Vec<_> = extents(b)?.into_iter().filter(|e| e.has_location()).collect();
+    a.sort_by_key(|e| e.physical);
+    b.sort_by_key(|e| e.physical);
+
+    // walk both lists in device order adding up the overlaps
+    let (mut i, mut j, mut shared) = (0, 0, 0);
+    while i < a.len() && j < b.len() {
+        let (x, y) = (&a[i], &b[j]);
+        let start = x.physical.max(y.physical);
+        let end = (x.physical + x.length).min(y.physical + y.length);
+        if start < end {
+            shared += end - start;
+        }
+        if x.physical + x.length < y.physical + y.length {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    Ok(shared)
+}
+
+/// Asks the kernel to make dest share the extents of src. The kernel locks
+/// both files, compares them byte for byte and only shares the ranges that
+/// are identical, so files that are open or changing can't be corrupted.
+/// Returns the bytes that were de-duplicated or None if the files differ.
+/// This needs a file system with FIDEDUPERANGE like btrfs or XFS, it fails
+/// everywhere else.
+#[cfg(target_os = "linux")]
+pub fn dedupe_file(src: &Path, dest: &Path) -> Result<Option<u64>> {
+    fiemap::dedupe_file(src, dest)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn dedupe_file(src: &Path, dest: &Path) -> Result<Option<u64>> {
+    Err(Error::InvalidArgument(format!("can't de-duplicate {} and {} on this platform",
+                                       src.to_string_lossy(), dest.to_string_lossy())))
+}
+
+#[cfg(target_os = "linux")]
+mod fiemap {
+    use super::Extent;
+    use crate::Result;
+    use std::fs::{File, OpenOptions};
+    use std::os::unix::io::AsRawFd;
+    use std::path::Path;
+
+    // _IOWR('f', 11, struct fiemap)
+    const FS_IOC_FIEMAP: libc::c_ulong = 0xc020_660b;
+
+    // sync the file first so delayed allocations have a place on the device
+    const FIEMAP_FLAG_SYNC: u32 = 0x1;
+
+    // set on the last extent of the file
+    const FIEMAP_EXTENT_LAST: u32 = 0x1;
+
+    // how many extents are asked for at a time
+    const BATCH: usize = 128;
+
+    #[repr(C)]
+    #[derive(Clone, Copy, Default)]
+    struct FiemapExtent {
+        fe_logical: u64,
+        fe_physical: u64,
+        fe_length: u64,
+        fe_reserved64: [u64; 2],
+        fe_flags: u32,
+        fe_reserved: [u32; 3]
+    }
+
+    #[repr(C)]
+    struct Fiemap {
+        fm_start: u64,
+        fm_length: u64,
+        fm_flags: u32,
+        fm_mapped_extents: u32,
+        fm_extent_count: u32,
+        fm_reserved: u32,
+        fm_extents: [FiemapExtent; BATCH]
+    }
+
+    // _IOWR(0x94, 54, struct file_dedupe_range)
+    const FIDEDUPERANGE: libc::c_ulong = 0xc018_9436;
+
+    // the dedupe status when the ranges differ, errors are negative errnos
+    const FILE_DEDUPE_RANGE_DIFFERS: i32 = 1;
+
+    // btrfs won't dedupe more than this in one call
+    const DEDUPE_STEP: u64 = 16 * 1024 * 1024;
+
+    #[repr(C)]
+    struct FileDedupeRange {
+        src_offset: u64,
+        src_length: u64,
+        dest_count: u16,
+        reserved1: u16,
+        reserved2: u32,
+        info: [FileDedupeRangeInfo; 1]
+    }
+
+    #[repr(C)]
+    struct FileDedupeRangeInfo {
+        dest_fd: i64,
+        dest_offset: u64,
+        bytes_deduped: u64,
+        status: i32,
+        reserved: u32
+    }
+
+    pub fn dedupe_file(src: &Path, dest: &Path) -> Result<Option<u64>> {
+        let s = File::open(src)?;
+        let d = OpenOptions::new().write(true).open(dest)?;
+        let size = s.metadata()?.len();
+        if d.metadata()?.len() != size {
+            return Ok(None);
+        }
+        let mut offset = 0;
+        let mut deduped = 0;
+        while offset < size {
+            let mut range = FileDedupeRange {
+                src_offset: offset,
+                src_length: DEDUPE_STEP.min(size - offset),
+                dest_count: 1,
+                reserved1: 0,
+                reserved2: 0,
+                info: [FileDedupeRangeInfo {
+                    dest_fd: d.as_raw_fd() as i64,
+                    dest_offset: offset,
+                    bytes_deduped: 0,
+                    status: 0,
+                    reserved: 0
+                }]
+            };
+            if unsafe { libc::ioctl(s.as_raw_fd(), FIDEDUPERANGE as _, &mut range as *mut FileDedupeRange) } < 0 {
+                return Err(std::io::Error::last_os_error().into());
+            }
+            let info = &range.info[0];
+            if info.status == FILE_DEDUPE_RANGE_DIFFERS {
+                return Ok(None);
+            }
+            if info.status < 0 {
+                return Err(std::io::Error::from_raw_os_error(-info.status).into());
+            }
+
+            // the kernel may do less than was asked, the rest goes next time
+            if info.bytes_deduped == 0 {
+                break;
+            }
+            offset += info.bytes_deduped;
+            deduped += info.bytes_deduped;
+        }
+        Ok(Some(deduped))
+    }
+
+    pub fn extents(path: &Path) -> Result<Vec<Extent>> {
+        let f = File::open(path)?;
+        let mut extents = Vec::new();
+        let mut start = 0;
+        loop {
+            let mut map = Fiemap {
+                fm_start: start,
+                fm_length: u64::MAX - start,
+                fm_flags: FIEMAP_FLAG_SYNC,
+                fm_mapped_extents: 0,
+                fm_extent_count: BATCH as u32,
+                fm_reserved: 0,
+                fm_extents: [FiemapExtent::default(); BATCH]
+            };
+            if unsafe { libc::ioctl(f.as_raw_fd(), FS_IOC_FIEMAP as _, &mut map as *mut Fiemap) } < 0 {
+                return Err(std::io::Error::last_os_error().into());
+            }
+            let mapped = &map.fm_extents[..map.fm_mapped_extents as usize];
+            extents.extend(mapped.iter().map(|e| Extent {
+                logical: e.fe_logical,
+                physical: e.fe_physical,
+                length: e.fe_length,
+                flags: e.fe_flags
+            }));
+            match mapped.last() {
+                Some(last) if last.fe_flags & FIEMAP_EXTENT_LAST == 0 => start = last.fe_logical + last.fe_length,
+                _ => return Ok(extents)
+            }
+        }
+    }
+}
@@ -0,0 +1,169 @@
+use crate::{
+    error::Error,
+    Result,
+    fs::{
+        chunks::CHUNKS_TAG,
+        fuzzy::FUZZY_TAG,
+        media::IMAGE_HASH_TAG,
+        metadata::METADATA_TAG,
+        treeitem::{SPECIAL_TAG, native_path, read_path, unescape_path},
+        TreeIndexHeader,
+        TreeItem
+    },
+    fs::treeindex::IndexLine
+};
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::PathBuf;
+use std::str;
+use std::sync::Arc;
+
+// Reads an index in the text format the same way read_lines does but from
+// one line buffer that is used again for every line. Item lines are split
+// as bytes, only the digest is checked to be UTF-8 and on unix the path is
+// taken as it is, so a line costs the allocations of the item it makes.
+pub(crate) fn read_lines_fast<R: Read>(r: R, f: &mut dyn FnMut(IndexLine) -> Result<()>) -> Result<TreeIndexHeader> {
+    let mut r = BufReader::new(r);
+    let mut buf: Vec<u8> = Vec::new();
+    let mut last_digest = String::from("-");
+    let mut last_size = 0u64;
+
+    // files without a header are version 1
+    let mut header = TreeIndexHeader::v1();
+    let mut raw = raw_paths(&header);
+
+    // items are held until the next line in case it has their chunk digests
+    let mut pending: Option<TreeItem> = None;
+
+    let mut line_count = 0;
+    loop {
+        buf.clear();
+        if r.read_until(b'\n', &mut buf)? == 0 {
+            break;
+        }
+        line_count += 1;
+        let line = trim_newline(&buf);
+
+        // the header may only be on the first line, other lines starting
+        // with # are comments
+        if line.starts_with(b"#") {
+            if line_count == 1 {
+                let line = utf8(line)?;
+                if TreeIndexHeader::is_header(line) {
+                    header = line.parse()?;
+                    raw = raw_paths(&header);
+                }
+            }
+            continue;
+        }
+
+        // the lines that belong to the item on the line before are rare
+        // enough to be parsed as strings
+        if line.starts_with(CHUNKS_TAG.as_bytes()) {
+            match pending.as_mut() {
+                Some(item) if item.chunks.is_none() => item.chunks = Some(Arc::new(utf8(line)?.parse()?)),
+                _ => return Err(Error::InvalidFormat(format!("unexpected chunk digests on line {}", line_count)))
+            }
+            continue;
+        }
+        if line.starts_with(FUZZY_TAG.as_bytes()) {
+            match pending.as_mut() {
+                Some(item) if item.fuzzy.is_none() => item.fuzzy = Some(Arc::new(utf8(line)?.parse()?)),
+                _ => return Err(Error::InvalidFormat(format!("unexpected fuzzy digest on line {}", line_count)))
+            }
+            continue;
+        }
+        if line.starts_with(IMAGE_HASH_TAG.as_bytes()) {
+            match pending.as_mut() {
+                Some(item) if item.image_hash.is_none() => item.image_hash = Some(utf8(line)?.parse()?),
+                _ => return Err(Error::InvalidFormat(format!("unexpected image hash on line {}", line_count)))
+            }
+            continue;
+        }
+        if line.starts_with(METADATA_TAG.as_bytes()) {
+            match pending.as_mut() {
+                Some(item) if item.metadata.is_none() => item.metadata = Some(Arc::new(utf8(line)?.parse()?)),
+                _ => return Err(Error::InvalidFormat(format!("unexpected file metadata on line {}", line_count)))
+            }
+            continue;
+        }
+        if let Some(item) = pending.take() {
+            f(IndexLine::Item(item))?;
+        }
+
+        // special files recorded by a scan don't have digests so they
+        // aren't part of an index
+        if line.starts_with(SPECIAL_TAG.as_bytes()) {
+            continue;
+        }
+
+        // a removal line written by an index watcher
+        if let Some(p) = line.strip_prefix(b"! ") {
+            f(IndexLine::Remove(read_path(utf8(p)?.to_string(), &header)?))?;
+            continue;
+        }
+
+        // read the digest, a dupe line has - instead and no size
+        let (digest, rest) = split_word(line)
+            .ok_or_else(|| Error::InvalidFormat(format!("missing digest on line {}", line_count)))?;
+        let rest = if digest == b"-" {
+            rest
+        } else {
+            let (size, rest) = split_word(rest)
+                .ok_or_else(|| Error::InvalidFormat(format!("missing size on line {}", line_count)))?;
+            last_digest.clear();
+            last_digest.push_str(utf8(digest)?);
+            // a size that doesn't parse is 0, as read_lines has it
+            last_size = utf8(size)?.parse().unwrap_or(0);
+            rest
+        };
+
+        // escaped paths without a backslash have nothing to unescape
+        let path = if header.is_escaped() && rest.contains(&b'\\') {
+            unescape_path(rest)?
+        } else if raw {
+            raw_path(rest)
+        } else {
+            native_path(utf8(rest)?.to_string(), &header)
+        };
+        pending = Some(TreeItem::new(&last_digest, &path, last_size));
+    }
+    if let Some(item) = pending.take() {
+        f(IndexLine::Item(item))?;
+    }
+    Ok(header)
+}
+
+// the paths can be taken as bytes on unix unless backslashes in them have to
+// be turned into separators
+fn raw_paths(header: &TreeIndexHeader) -> bool {
+    cfg!(unix) && header.os() != Some("windows")
+}
+
+#[cfg(unix)]
+fn raw_path(bytes: &[u8]) -> PathBuf {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+    PathBuf::from(OsStr::from_bytes(bytes))
+}
+
+#[cfg(not(unix))]
+fn raw_path(_bytes: &[u8]) -> PathBuf {
+    unreachable!("paths are only read raw on unix")
+}
+
+// drops the line ending the way BufRead::lines does
+fn trim_newline(line: &[u8]) -> &[u8] {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    line.strip_suffix(b"\r").unwrap_or(line)
+}
+
+// splits off the first word and the one whitespace byte after it
+fn split_word(line: &[u8]) -> Option<(&[u8], &[u8])> {
+    let idx = line.iter().position(|b| b.is_ascii_whitespace())?;
+    Some((&line[..idx], &line[idx + 1..]))
+}
+
+fn utf8(bytes: &[u8]) -> Result<&str> {
+    str::from_utf8(bytes)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "stream did not contain valid UTF-8").into())
+}
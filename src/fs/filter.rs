@@ -0,0 +1,121 @@
+use crate::fs::ItemDigest;
+use std::fmt::{Display, Formatter};
+
+// the false positive rate used by TreeIndex::size_filter
+pub const DEFAULT_FP_RATE: f64 = 0.01;
+
+/// A SizeFilter is a bloom filter over file sizes and the pairs of a size and
+/// the first 8 bytes of a digest. It never says a size or a pair is
+/// missing when it was inserted, but it can say one is present when it
+/// wasn't, at roughly the false positive rate it was created with. A filter
+/// for millions of files fits in a few MB, so a scanner can check every file
+/// against it, only digest the files whose size might match and only keep
+/// the ones whose digest might match too.
+#[derive(Clone, Debug)]
+pub struct SizeFilter {
+    bits: Vec<u64>,
+    nbits: u64,
+    hashes: u32,
+    count: usize
+}
+
+impl SizeFilter {
+
+    /// Creates a filter sized for the expected number of sizes and pairs
+    /// with the given false positive rate, e.g. 0.01 for 1%
+    pub fn new(expected: usize, fp_rate: f64) -> Self {
+        let n = expected.max(1) as f64;
+        let p = fp_rate.clamp(1e-9, 0.5);
+        let ln2 = std::f64::consts::LN_2;
+
+        // the standard optimal sizes for a bloom filter
+        let nbits = ((-n * p.ln()) / (ln2 * ln2)).ceil().max(64.0) as u64;
+        let hashes = ((nbits as f64 / n) * ln2).round().clamp(1.0, 16.0) as u32;
+        Self {
+            bits: vec![0; nbits.div_ceil(64) as usize],
+            nbits,
+            hashes,
+            count: 0
+        }
+    }
+
+    pub fn insert(&mut self, size: u64) {
+        self.set(mix(size));
+    }
+
+    /// Returns false if the size was definitely never inserted
+    pub fn contains(&self, size: u64) -> bool {
+        self.test(mix(size))
+    }
+
+    /// Inserts the pair of the size and the start of the digest
+    pub fn insert_digest(&mut self, size: u64, digest: &ItemDigest) {
+        self.set(pair(size, digest));
+    }
+
+    /// Returns false if the pair of the size and the start of the digest
+    /// was definitely never inserted
+    pub fn contains_digest(&self, size: u64, digest: &ItemDigest) -> bool {
+        self.test(pair(size, digest))
+    }
+
+    /// The number of sizes and pairs inserted
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    fn set(&mut self, h: u64) {
+        for bit in self.bits_for(h) {
+            self.bits[(bit / 64) as usize] |= 1 << (bit % 64);
+        }
+        self.count += 1;
+    }
+
+    fn test(&self, h: u64) -> bool {
+        self.bits_for(h).all(|bit| self.bits[(bit / 64) as usize] & (1 << (bit % 64)) != 0)
+    }
+
+    // double hashing gives k independent enough bit positions from two
+    // mixes of the key
+    fn bits_for(&self, h1: u64) -> impl Iterator<Item = u64> {
+        let h2 = mix(h1 ^ 0x9e37_79b9_7f4a_7c15) | 1;
+        let nbits = self.nbits;
+        (0..self.hashes as u64).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % nbits)
+    }
+}
+
+impl Display for SizeFilter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error>
+    {
+        write!(f, "{} sizes and digests in {} bits with {} hashes", self.count, self.nbits, self.hashes)
+    }
+}
+
+// the key of a size and the first 8 bytes of a digest, mixed apart from the
+// keys of sizes. Digests are taken as their raw bytes so a hex digest and
+// the multihash of the same file make the same pair.
+fn pair(size: u64, digest: &ItemDigest) -> u64 {
+    let mut prefix = [0u8; 8];
+    let text;
+    let b: &[u8] = match digest {
+        ItemDigest::Hex(d) | ItemDigest::Multihash(_, _, d) => d.as_bytes(),
+        ItemDigest::Text(s) => {
+            text = s;
+            text.as_bytes()
+        }
+    };
+    let n = b.len().min(8);
+    prefix[..n].copy_from_slice(&b[..n]);
+    mix(mix(size) ^ u64::from_le_bytes(prefix) ^ 0xd1b5_4a32_d192_ed03)
+}
+
+// the splitmix64 finalizer
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
@@ -0,0 +1,345 @@
+use crate::{
+    error::Error,
+    Result,
+    fs::TreeIndex,
+    fs::stats::group_root
+};
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+
+// index lines holding the fuzzy digest of a file start with this
+pub(crate) const FUZZY_TAG: &str = "& ";
+
+// the characters of a signature, one for each piece of the file
+const B64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+// the longest signature, the one for twice the block size is half as long
+const SPAMSUM_LENGTH: usize = 64;
+
+// the smallest block size, each one after is twice the one before
+const MIN_BLOCK_SIZE: u32 = 3;
+
+// the bytes the rolling hash looks at, two signatures have to have a run of
+// this many characters in common to be compared at all
+const ROLLING_WINDOW: usize = 7;
+
+const HASH_PRIME: u32 = 0x0100_0193;
+const HASH_INIT: u32 = 0x2802_1967;
+
+/// A context triggered piecewise hash of a file in the format of ssdeep,
+/// e.g. 96:AXGBicFlgVNhBGcL6wCrFQE:AXGHsNhxLsr2s. The file is cut where a
+/// rolling hash of its bytes hits a value so an edit only changes the
+/// pieces around it, two files with minor edits have mostly the same
+/// signatures. The second signature is for twice the block size so files
+/// whose sizes put them in neighbouring block sizes can be compared.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct FuzzyDigest {
+    pub block_size: u32,
+    pub sig1: String,
+    pub sig2: String
+}
+
+impl FuzzyDigest {
+
+    /// Returns how alike the two files are from 0, nothing in common, to
+    /// 100, the same or very nearly the same. Digests with block sizes that
+    /// aren't the same or a factor of two apart always score 0.
+    pub fn compare(&self, other: &FuzzyDigest) -> u32 {
+        if self.block_size == other.block_size && self.sig1 == other.sig1 {
+            return 100;
+        }
+        let (a1, a2) = (eliminate_runs(&self.sig1), eliminate_runs(&self.sig2));
+        let (b1, b2) = (eliminate_runs(&other.sig1), eliminate_runs(&other.sig2));
+        let (x, y) = (self.block_size as u64, other.block_size as u64);
+        if x == y {
+            score_strings(&a1, &b1, x).max(score_strings(&a2, &b2, x * 2))
+        } else if x == y * 2 {
+            score_strings(&a1, &b2, x)
+        } else if y == x * 2 {
+            score_strings(&a2, &b1, y)
+        } else {
+            0
+        }
+    }
+
+    // the runs of characters two digests need to share to be compared,
+    // keyed by the block size of the signature they are in
+    fn windows(&self) -> HashSet<(u64, String)> {
+        let mut windows = HashSet::new();
+        for (bs, sig) in [(self.block_size as u64, &self.sig1), (self.block_size as u64 * 2, &self.sig2)] {
+            let sig: Vec<char> = eliminate_runs(sig).chars().collect();
+            for w in sig.windows(ROLLING_WINDOW) {
+                windows.insert((bs, w.iter().collect()));
+            }
+        }
+        windows
+    }
+}
+
+// the line written after an item in an index
+impl Display for FuzzyDigest {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error>
+    {
+        writeln!(f, "{}{}:{}:{}", FUZZY_TAG, self.block_size, self.sig1, self.sig2)
+    }
+}
+
+impl FromStr for FuzzyDigest {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || Error::InvalidFormat(format!("invalid fuzzy digest {}", s));
+        let mut parts = s.strip_prefix(FUZZY_TAG).unwrap_or(s).trim().split(':');
+        let block_size = parts.next().and_then(|b| b.parse::<u32>().ok()).ok_or_else(invalid)?;
+        let sig1 = parts.next().ok_or_else(invalid)?.to_string();
+        let sig2 = parts.next().ok_or_else(invalid)?.to_string();
+        if block_size < MIN_BLOCK_SIZE || parts.next().is_some() || !valid_signature(&sig1) || !valid_signature(&sig2) {
+            return Err(invalid());
+        }
+        Ok(Self { block_size, sig1, sig2 })
+    }
+}
+
+fn valid_signature(sig: &str) -> bool {
+    sig.len() <= SPAMSUM_LENGTH && sig.bytes().all(|b| B64.contains(&b))
+}
+
+// the rolling hash over the last ROLLING_WINDOW bytes
+#[derive(Default)]
+struct Roll {
+    window: [u8; ROLLING_WINDOW],
+    n: usize,
+    h1: u32,
+    h2: u32,
+    h3: u32
+}
+
+impl Roll {
+    fn update(&mut self, c: u8) -> u32 {
+        self.h2 = self.h2.wrapping_sub(self.h1).wrapping_add(ROLLING_WINDOW as u32 * c as u32);
+        self.h1 = self.h1.wrapping_add(c as u32).wrapping_sub(self.window[self.n % ROLLING_WINDOW] as u32);
+        self.window[self.n % ROLLING_WINDOW] = c;
+        self.n += 1;
+        self.h3 = (self.h3 << 5) ^ c as u32;
+        self.sum()
+    }
+
+    fn sum(&self) -> u32 {
+        self.h1.wrapping_add(self.h2).wrapping_add(self.h3)
+    }
+}
+
+// the signature for one block size, the last character covers whatever is
+// left once the signature is full
+struct Piecewise {
+    block_size: u32,
+    max: usize,
+    h: u32,
+    sig: Vec<u8>
+}
+
+impl Piecewise {
+    fn new(block_size: u32, max: usize) -> Self {
+        Self { block_size, max, h: HASH_INIT, sig: Vec::with_capacity(max) }
+    }
+
+    fn update(&mut self, c: u8, roll: u32) {
+        self.h = self.h.wrapping_mul(HASH_PRIME) ^ c as u32;
+        if roll % self.block_size == self.block_size - 1 && self.sig.len() < self.max - 1 {
+            self.sig.push(B64[(self.h % 64) as usize]);
+            self.h = HASH_INIT;
+        }
+    }
+
+    fn finish(&self, roll: u32) -> String {
+        let mut sig = self.sig.clone();
+        if roll != 0 {
+            sig.push(B64[(self.h % 64) as usize]);
+        }
+        String::from_utf8(sig).unwrap_or_default()
+    }
+}
+
+// computes the fuzzy digest of a file as its bytes are read. The block size
+// is picked from the file size so the signature is close to its full
+// length, signatures for the smaller block sizes are kept too in case the
+// file has fewer trigger points than expected, so it is only read once.
+pub(crate) struct FuzzyHasher {
+    roll: Roll,
+    levels: Vec<(Piecewise, Piecewise)>
+}
+
+impl FuzzyHasher {
+    pub(crate) fn new(size: u64) -> Self {
+        let mut top = MIN_BLOCK_SIZE;
+        while (top as u64) * (SPAMSUM_LENGTH as u64) < size && top < u32::MAX / 4 {
+            top *= 2;
+        }
+        let mut levels = Vec::new();
+        let mut bs = top;
+        loop {
+            levels.push((Piecewise::new(bs, SPAMSUM_LENGTH), Piecewise::new(bs * 2, SPAMSUM_LENGTH / 2)));
+            if bs == MIN_BLOCK_SIZE {
+                break;
+            }
+            bs /= 2;
+        }
+        Self { roll: Roll::default(), levels }
+    }
+
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        for c in data {
+            let roll = self.roll.update(*c);
+            for (full, half) in self.levels.iter_mut() {
+                full.update(*c, roll);
+                half.update(*c, roll);
+            }
+        }
+    }
+
+    // uses the biggest block size whose signature is at least half full
+    pub(crate) fn finish(self) -> FuzzyDigest {
+        let roll = self.roll.sum();
+        let last = self.levels.len() - 1;
+        for (i, (full, half)) in self.levels.iter().enumerate() {
+            let sig1 = full.finish(roll);
+            if sig1.len() >= SPAMSUM_LENGTH / 2 || i == last {
+                return FuzzyDigest { block_size: full.block_size, sig1, sig2: half.finish(roll) };
+            }
+        }
+        unreachable!("there is always a level for the smallest block size")
+    }
+}
+
+// runs of more than three of the same character say little about a file,
+// they are cut down to three
+fn eliminate_runs(sig: &str) -> String {
+    let b = sig.as_bytes();
+    let mut out = String::with_capacity(b.len());
+    for (i, c) in b.iter().enumerate() {
+        if i < 3 || *c != b[i - 1] || *c != b[i - 2] || *c != b[i - 3] {
+            out.push(*c as char);
+        }
+    }
+    out
+}
+
+// scores two signatures for the same block size by their edit distance
+fn score_strings(a: &str, b: &str, block_size: u64) -> u32 {
+    if a.len() < ROLLING_WINDOW || b.len() < ROLLING_WINDOW || !has_common_window(a, b) {
+        return 0;
+    }
+    let distance = edit_distance(a.as_bytes(), b.as_bytes()) as u64;
+    let score = distance * SPAMSUM_LENGTH as u64 / (a.len() + b.len()) as u64;
+    let score = 100 * score / SPAMSUM_LENGTH as u64;
+    if score >= 100 {
+        return 0;
+    }
+    let score = 100 - score;
+
+    // short signatures at small block sizes match by chance too easily
+    let cap = (99 + ROLLING_WINDOW as u64) / ROLLING_WINDOW as u64 * MIN_BLOCK_SIZE as u64;
+    if block_size >= cap {
+        return score as u32;
+    }
+    let limit = block_size / MIN_BLOCK_SIZE as u64 * a.len().min(b.len()) as u64;
+    score.min(limit) as u32
+}
+
+fn has_common_window(a: &str, b: &str) -> bool {
+    let windows: HashSet<&[u8]> = a.as_bytes().windows(ROLLING_WINDOW).collect();
+    b.as_bytes().windows(ROLLING_WINDOW).any(|w| windows.contains(w))
+}
+
+// insertions and deletions cost one, changing a character costs two
+fn edit_distance(a: &[u8], b: &[u8]) -> usize {
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    let mut cur = vec![0; b.len() + 1];
+    for (i, x) in a.iter().enumerate() {
+        cur[0] = i + 1;
+        for (j, y) in b.iter().enumerate() {
+            let change = if x == y { 0 } else { 2 };
+            cur[j + 1] = (prev[j + 1] + 1).min(cur[j] + 1).min(prev[j] + change);
+        }
+        std::mem::swap(&mut prev, &mut cur);
+    }
+    prev[b.len()]
+}
+
+/// A group of files that are alike by their fuzzy digests. Every file
+/// scores at least the threshold against one other file in the group,
+/// score is the lowest of those.
+#[derive(Clone, Debug)]
+pub struct FuzzyGroup {
+    pub files: Vec<Arc<PathBuf>>,
+    pub score: u32
+}
+
+impl Display for FuzzyGroup {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error>
+    {
+        writeln!(f, "{} similar files, score at least {}", self.files.len(), self.score)?;
+        for p in &self.files {
+            writeln!(f, "  {}", p.to_string_lossy())?;
+        }
+        Ok(())
+    }
+}
+
+/// Groups the files in the index whose fuzzy digests score at least
+/// min_score against each other, biggest groups first. Identical copies are
+/// already dupes so each group lists one path for each of them. Only the
+/// files that share a run of their signatures are scored, the others can't
+/// score above 0.
+pub fn fuzzy_groups(ti: &TreeIndex, min_score: u32) -> Vec<FuzzyGroup> {
+    let mut files: Vec<(Arc<PathBuf>, &FuzzyDigest)> = ti.idx.values()
+        .filter_map(|v| v.item.fuzzy.as_ref().map(|d| (Arc::new(v.item.path()), &**d)))
+        .collect();
+    files.sort_by(|a, b| a.0.cmp(&b.0));
+
+    // the files each run of a signature is in
+    let mut holders: HashMap<(u64, String), Vec<usize>> = HashMap::new();
+    for (i, (_, d)) in files.iter().enumerate() {
+        for w in d.windows() {
+            holders.entry(w).or_default().push(i);
+        }
+    }
+    let mut candidates: HashSet<(usize, usize)> = HashSet::new();
+    for h in holders.values() {
+        for (n, a) in h.iter().enumerate() {
+            for b in &h[n + 1..] {
+                candidates.insert((*a, *b));
+            }
+        }
+    }
+
+    // join the files that score high enough into groups
+    let mut candidates: Vec<_> = candidates.into_iter().collect();
+    candidates.sort_unstable();
+    let mut parent: Vec<usize> = (0..files.len()).collect();
+    let mut lowest: Vec<u32> = vec![100; files.len()];
+    for (a, b) in candidates {
+        let score = files[a].1.compare(files[b].1);
+        if score < min_score {
+            continue;
+        }
+        let (ra, rb) = (group_root(&mut parent, a), group_root(&mut parent, b));
+        let l = lowest[ra].min(lowest[rb]).min(score);
+        parent[rb] = ra;
+        lowest[ra] = l;
+    }
+
+    let mut groups: HashMap<usize, Vec<Arc<PathBuf>>> = HashMap::new();
+    for (i, (path, _)) in files.iter().enumerate() {
+        groups.entry(group_root(&mut parent, i)).or_default().push(path.clone());
+    }
+    let mut groups: Vec<FuzzyGroup> = groups.into_iter()
+        .filter(|(_, files)| files.len() > 1)
+        .map(|(r, files)| FuzzyGroup { files, score: lowest[r] })
+        .collect();
+    groups.sort_by(|a, b| b.files.len().cmp(&a.files.len()).then_with(|| a.files[0].cmp(&b.files[0])));
+    groups
+}
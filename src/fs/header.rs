@@ -0,0 +1,382 @@
+use crate::{
+    error::Error,
+    Result,
+    fs::{Algorithm, Chunking, DigestEncoding, ImageHashKind}
+};
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// The current version of the index file format. Version 1 files have no
+/// header line. Version 2 fast digests include the file size. Version 3
+/// text indexes escape their paths, so readers that would take the escapes
+/// as part of the names refuse them.
+pub const INDEX_VERSION: u32 = 3;
+
+// the first version whose text indexes escape their paths
+const ESCAPED_VERSION: u32 = 3;
+
+/// The header field set on indexes built with fast digests
+pub const FAST_FIELD: &str = "fast";
+
+/// The header field naming the digest algorithm, it is left out for Blake2b
+pub const ALGO_FIELD: &str = "algo";
+
+/// The header field naming the digest encoding, it is left out for hex
+pub const ENCODING_FIELD: &str = "encoding";
+
+/// The header field set on indexes built with keyed digests. The key itself
+/// is never written, the same key is needed to check the digests.
+pub const KEYED_FIELD: &str = "keyed";
+
+/// The header field holding the key id of indexes built with keyed digests,
+/// see Algorithm::key_id, so indexes made with different keys can be told
+/// apart
+pub const KEY_ID_FIELD: &str = "keyid";
+
+/// The header field holding the chunk size of indexes with chunk digests
+pub const CHUNKS_FIELD: &str = "chunks";
+
+/// The header field set when the chunks are content defined, the chunk size
+/// is their average then
+pub const CDC_FIELD: &str = "cdc";
+
+/// The header field set on indexes that have the metadata of each file
+pub const METADATA_FIELD: &str = "metadata";
+
+/// The header field set on indexes that have the fuzzy digest of each file
+pub const FUZZY_FIELD: &str = "fuzzy";
+
+/// The header field naming the perceptual hash kept for images, it is left
+/// out when images aren't hashed
+pub const IMAGES_FIELD: &str = "images";
+
+/// The header field set on indexes that have the members of zip and tar
+/// archives as well as the archives themselves
+pub const ARCHIVES_FIELD: &str = "archives";
+
+/// The header field set when some item sizes aren't known, e.g. for indexes
+/// imported from checksum files
+pub const SIZES_FIELD: &str = "sizes";
+
+/// The header field naming the operating system the index was written on,
+/// e.g. linux or windows. Paths are always written with forward slashes, the
+/// tag says how to read indexes written before that or by other tools.
+pub const OS_FIELD: &str = "os";
+
+/// The header field naming the Unicode normalization form of the paths, it
+/// is left out when the paths are as the file system returned them
+pub const UNICODE_FIELD: &str = "unicode";
+
+/// The header field saying how the paths of a text index are written. With
+/// paths=escaped, backslashes, control characters and bytes that aren't
+/// UTF-8 are escaped so any path survives a round trip. Indexes without it
+/// have their paths as they are. Escaped indexes are version 3 or later.
+pub const PATHS_FIELD: &str = "paths";
+
+// every header line starts with this
+const HEADER_TAG: &str = "#treeindex";
+
+// A TreeIndexHeader is the first line of an index or list file. It contains
+// the format version followed by optional key=value fields. Fields this
+// version doesn't know about are kept so they survive a round trip.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TreeIndexHeader {
+    pub version: u32,
+    pub fields: BTreeMap<String, String>
+}
+
+impl TreeIndexHeader {
+
+    pub fn new(version: u32) -> Self {
+        Self {
+            version,
+            fields: BTreeMap::new()
+        }
+    }
+
+    /// The header assumed for files that don't have one
+    pub fn v1() -> Self {
+        Self::new(1)
+    }
+
+    /// Returns true if the index was built with fast digests
+    pub fn is_fast(&self) -> bool {
+        self.fields.get(FAST_FIELD).map(|v| v == "true").unwrap_or(false)
+    }
+
+    pub fn set_fast(&mut self, fast: bool) {
+        if fast {
+            self.fields.insert(FAST_FIELD.to_string(), "true".to_string());
+        } else {
+            self.fields.remove(FAST_FIELD);
+        }
+    }
+
+    /// Returns the digest algorithm the index was built with
+    pub fn algorithm(&self) -> Result<Algorithm> {
+        match self.fields.get(ALGO_FIELD) {
+            Some(a) => a.parse(),
+            None => Ok(Algorithm::default())
+        }
+    }
+
+    pub fn set_algorithm(&mut self, algorithm: Algorithm) {
+        if algorithm == Algorithm::default() {
+            self.fields.remove(ALGO_FIELD);
+        } else {
+            self.fields.insert(ALGO_FIELD.to_string(), algorithm.to_string());
+        }
+    }
+
+    /// Returns how the digests in the index are written
+    pub fn encoding(&self) -> Result<DigestEncoding> {
+        match self.fields.get(ENCODING_FIELD) {
+            Some(e) => e.parse(),
+            None => Ok(DigestEncoding::default())
+        }
+    }
+
+    pub fn set_encoding(&mut self, encoding: DigestEncoding) {
+        if encoding == DigestEncoding::default() {
+            self.fields.remove(ENCODING_FIELD);
+        } else {
+            self.fields.insert(ENCODING_FIELD.to_string(), encoding.to_string());
+        }
+    }
+
+    /// Returns true if the index was built with keyed digests
+    pub fn is_keyed(&self) -> bool {
+        self.fields.get(KEYED_FIELD).map(|v| v == "true").unwrap_or(false)
+    }
+
+    /// Clearing keyed clears the key id too
+    pub fn set_keyed(&mut self, keyed: bool) {
+        if keyed {
+            self.fields.insert(KEYED_FIELD.to_string(), "true".to_string());
+        } else {
+            self.fields.remove(KEYED_FIELD);
+            self.fields.remove(KEY_ID_FIELD);
+        }
+    }
+
+    /// Returns the id of the key the digests were made with, keyed indexes
+    /// written before key ids were added don't have one
+    pub fn key_id(&self) -> Option<&str> {
+        self.fields.get(KEY_ID_FIELD).map(|v| v.as_str())
+    }
+
+    pub fn set_key_id(&mut self, key_id: Option<&str>) {
+        match key_id {
+            Some(k) => { self.fields.insert(KEY_ID_FIELD.to_string(), k.to_string()); },
+            None => { self.fields.remove(KEY_ID_FIELD); }
+        }
+    }
+
+    /// Returns the chunk size if the items have chunk digests
+    pub fn chunk_size(&self) -> Result<Option<u64>> {
+        match self.fields.get(CHUNKS_FIELD) {
+            Some(c) => c.parse::<u64>()
+                .map(Some)
+                .map_err(|_| Error::InvalidFormat(format!("invalid chunk size {}", c))),
+            None => Ok(None)
+        }
+    }
+
+    /// Sets the size of fixed chunks, None also clears content chunking
+    pub fn set_chunk_size(&mut self, chunk_size: Option<u64>) {
+        match chunk_size {
+            Some(c) => { self.fields.insert(CHUNKS_FIELD.to_string(), c.to_string()); },
+            None => {
+                self.fields.remove(CHUNKS_FIELD);
+                self.fields.remove(CDC_FIELD);
+            }
+        }
+    }
+
+    /// Returns how the items were chunked if they have chunk digests
+    pub fn chunking(&self) -> Result<Option<Chunking>> {
+        let cdc = self.fields.get(CDC_FIELD).map(|v| v == "true").unwrap_or(false);
+        Ok(self.chunk_size()?.map(|c| if cdc { Chunking::Content(c) } else { Chunking::Fixed(c) }))
+    }
+
+    pub fn set_chunking(&mut self, chunking: Option<Chunking>) {
+        self.set_chunk_size(chunking.map(|c| c.size()));
+        if let Some(Chunking::Content(_)) = chunking {
+            self.fields.insert(CDC_FIELD.to_string(), "true".to_string());
+        }
+    }
+
+    /// Returns true if the items have fuzzy digests
+    pub fn has_fuzzy(&self) -> bool {
+        self.fields.get(FUZZY_FIELD).map(|v| v == "true").unwrap_or(false)
+    }
+
+    pub fn set_fuzzy(&mut self, fuzzy: bool) {
+        if fuzzy {
+            self.fields.insert(FUZZY_FIELD.to_string(), "true".to_string());
+        } else {
+            self.fields.remove(FUZZY_FIELD);
+        }
+    }
+
+    /// Returns how images were hashed if they have perceptual hashes
+    pub fn image_hashes(&self) -> Result<Option<ImageHashKind>> {
+        self.fields.get(IMAGES_FIELD).map(|k| k.parse()).transpose()
+    }
+
+    pub fn set_image_hashes(&mut self, kind: Option<ImageHashKind>) {
+        match kind {
+            Some(k) => { self.fields.insert(IMAGES_FIELD.to_string(), k.to_string()); },
+            None => { self.fields.remove(IMAGES_FIELD); }
+        }
+    }
+
+    /// Returns true if the items have file metadata
+    pub fn has_metadata(&self) -> bool {
+        self.fields.get(METADATA_FIELD).map(|v| v == "true").unwrap_or(false)
+    }
+
+    pub fn set_metadata(&mut self, metadata: bool) {
+        if metadata {
+            self.fields.insert(METADATA_FIELD.to_string(), "true".to_string());
+        } else {
+            self.fields.remove(METADATA_FIELD);
+        }
+    }
+
+    /// Returns true if the scan looked inside archives
+    pub fn has_archives(&self) -> bool {
+        self.fields.get(ARCHIVES_FIELD).map(|v| v == "true").unwrap_or(false)
+    }
+
+    pub fn set_archives(&mut self, archives: bool) {
+        if archives {
+            self.fields.insert(ARCHIVES_FIELD.to_string(), "true".to_string());
+        } else {
+            self.fields.remove(ARCHIVES_FIELD);
+        }
+    }
+
+    /// Returns false if some of the item sizes are unknown, size based
+    /// filtering can't be used with these indexes
+    pub fn has_sizes(&self) -> bool {
+        self.fields.get(SIZES_FIELD).map(|v| v != "unknown").unwrap_or(true)
+    }
+
+    /// Returns the operating system the index was written on, indexes
+    /// without the tag are read as if they were written on this one
+    pub fn os(&self) -> Option<&str> {
+        self.fields.get(OS_FIELD).map(|v| v.as_str())
+    }
+
+    pub fn set_os(&mut self, os: &str) {
+        self.fields.insert(OS_FIELD.to_string(), os.to_string());
+    }
+
+    /// Returns true if the paths were normalized to Unicode NFC
+    pub fn is_nfc(&self) -> bool {
+        self.fields.get(UNICODE_FIELD).map(|v| v == "nfc").unwrap_or(false)
+    }
+
+    pub fn set_nfc(&mut self, nfc: bool) {
+        if nfc {
+            self.fields.insert(UNICODE_FIELD.to_string(), "nfc".to_string());
+        } else {
+            self.fields.remove(UNICODE_FIELD);
+        }
+    }
+
+    /// Returns true if the paths of a text index are escaped
+    pub fn is_escaped(&self) -> bool {
+        self.version >= ESCAPED_VERSION || self.fields.get(PATHS_FIELD).map(|v| v == "escaped").unwrap_or(false)
+    }
+
+    /// Sets whether the paths are escaped. Escaping a version 2 header
+    /// makes it version 3 and not escaping a version 3 one makes it version
+    /// 2, nothing else changed between them.
+    pub fn set_escaped(&mut self, escaped: bool) {
+        if escaped {
+            self.fields.insert(PATHS_FIELD.to_string(), "escaped".to_string());
+            if self.version == ESCAPED_VERSION - 1 {
+                self.version = ESCAPED_VERSION;
+            }
+        } else {
+            self.fields.remove(PATHS_FIELD);
+            if self.version == ESCAPED_VERSION {
+                self.version = ESCAPED_VERSION - 1;
+            }
+        }
+    }
+
+    /// Returns the header to write a text index with, the paths item lines
+    /// are displayed with are always escaped
+    pub fn for_text(&self) -> Self {
+        let mut header = self.clone();
+        header.set_escaped(true);
+        header
+    }
+
+    /// Returns true if the header is for the current version of the format
+    pub fn is_current(&self) -> bool {
+        self.version == INDEX_VERSION
+    }
+
+    /// Returns true if the line looks like a header line
+    pub fn is_header(line: &str) -> bool {
+        line.starts_with(HEADER_TAG)
+    }
+}
+
+// new indexes are tagged with the operating system they are written on and
+// escape their paths
+impl Default for TreeIndexHeader {
+    fn default() -> Self {
+        let mut header = Self::new(INDEX_VERSION);
+        header.set_os(std::env::consts::OS);
+        header.set_escaped(true);
+        header
+    }
+}
+
+impl FromStr for TreeIndexHeader {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.split_whitespace();
+        if parts.next() != Some(HEADER_TAG) {
+            return Err(Error::InvalidFormat(format!("invalid header {}", s)));
+        }
+        let version = match parts.next() {
+            Some(v) if v.starts_with('v') => v[1..].parse::<u32>()
+                .map_err(|_| Error::InvalidFormat(format!("invalid header version {}", v)))?,
+            _ => return Err(Error::InvalidFormat(format!("missing header version {}", s)))
+        };
+        if version > INDEX_VERSION {
+            return Err(Error::InvalidFormat(format!("unsupported index version {}", version)));
+        }
+
+        let mut header = Self::new(version);
+        for field in parts {
+            match field.split_once('=') {
+                Some((k, v)) => {
+                    header.fields.insert(k.to_string(), v.to_string());
+                },
+                None => return Err(Error::InvalidFormat(format!("invalid header field {}", field)))
+            }
+        }
+        Ok(header)
+    }
+}
+
+impl Display for TreeIndexHeader {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error>
+    {
+        write!(f, "{} v{}", HEADER_TAG, self.version)?;
+        for (k, v) in &self.fields {
+            write!(f, " {}={}", k, v)?;
+        }
+        writeln!(f)?;
+        Ok(())
+    }
+}
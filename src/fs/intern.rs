@@ -0,0 +1,320 @@
+use crate::{
+    error::Error,
+    Result
+};
+use lazy_static::lazy_static;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fmt::{Debug, Formatter};
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+// a rough guess at what one node costs beyond its name, this covers the Vec
+// slots and the hash table slot that finds it again
+const NODE_OVERHEAD: u64 = 48;
+
+// the parent of the first component of a path, and the end of a chain
+const NO_PARENT: u32 = u32::MAX;
+
+lazy_static! {
+    // the paths of every TreeItem and its dupes
+    static ref ITEM_PATHS: RwLock<PathArena> = RwLock::new(PathArena::new());
+}
+
+/// Names a path interned in a PathArena
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PathId(u32);
+
+/// A PathArena stores paths as a tree of components, each one the id of its
+/// parent directory and its own name, so the files in a directory share one
+/// copy of the directory's path. The paths of every TreeItem are kept in one
+/// the items share, see ItemPath, and a spilling TreeIndexBuilder keeps the
+/// paths it holds in one of its own.
+///
+/// Paths come back exactly as they went in. The ones that components would
+/// change, like "a//b" and "a/./b", are kept whole rather than split up so
+/// they don't come back as "a/b", the paths of scanned files never are.
+#[derive(Debug, Default)]
+pub struct PathArena {
+    nodes: Vec<Node>,
+    // the ids interned and the child nodes that use each node, ItemPath
+    // counts its clones here with only a read lock
+    refs: Vec<AtomicU32>,
+    // the first node in the chain of each hash of a parent and a name, so a
+    // name is found again without keeping a second copy of it
+    slots: HashMap<u32, u32>,
+    hasher: RandomState,
+    free: Vec<u32>,
+    names: u64
+}
+
+// a node freed has no name until it is used again. A path kept whole is a
+// node without a parent, its name can't be the same as a component's.
+#[derive(Debug)]
+struct Node {
+    parent: u32,
+    next: u32,
+    name: Option<Box<OsStr>>
+}
+
+impl PathArena {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stores the path, or finds it if it is already stored, and returns its
+    /// id. An arena holds up to 4294967294 directories and files, more
+    /// than that is an error.
+    pub fn intern(&mut self, path: &Path) -> Result<PathId> {
+        if path.components().collect::<PathBuf>().as_os_str() != path.as_os_str() {
+            let id = self.find_or_add(NO_PARENT, path.as_os_str())?;
+            self.retain(id);
+            return Ok(PathId(id));
+        }
+        let mut parent = NO_PARENT;
+        for c in path.components() {
+            // the directories added for a path that didn't fit are taken out
+            // again
+            parent = self.find_or_add(parent, c.as_os_str()).inspect_err(|_| self.free_unused(parent))?;
+        }
+        self.retain(parent);
+        Ok(PathId(parent))
+    }
+
+    // finds the node, or adds it in the slot of one that was freed if there
+    // is one, a node added is a use of its parent
+    fn find_or_add(&mut self, parent: u32, name: &OsStr) -> Result<u32> {
+        let slot = self.slot(parent, name);
+        let head = self.slots.get(&slot).copied().unwrap_or(NO_PARENT);
+        let mut next = head;
+        while next != NO_PARENT {
+            let node = &self.nodes[next as usize];
+            if node.parent == parent && node.name.as_deref() == Some(name) {
+                return Ok(next);
+            }
+            next = node.next;
+        }
+        let node = Node { parent, next: head, name: Some(name.into()) };
+        let id = match self.free.pop() {
+            Some(id) => {
+                self.nodes[id as usize] = node;
+                id
+            },
+            None => {
+                let id = self.nodes.len() as u32;
+                if id == NO_PARENT {
+                    return Err(too_many());
+                }
+                self.nodes.push(node);
+                self.refs.push(AtomicU32::new(0));
+                id
+            }
+        };
+        self.slots.insert(slot, id);
+        self.names += name.len() as u64;
+        self.retain(parent);
+        Ok(id)
+    }
+
+    fn slot(&self, parent: u32, name: &OsStr) -> u32 {
+        let mut h = self.hasher.build_hasher();
+        parent.hash(&mut h);
+        name.hash(&mut h);
+        h.finish() as u32
+    }
+
+    // counts a use of the node, the empty path isn't a node
+    fn retain(&self, id: u32) {
+        if id != NO_PARENT {
+            self.refs[id as usize].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    // frees the node if nothing uses it, and then its parent if that was the
+    // last thing that did. A node freed already is left alone.
+    fn free_unused(&mut self, mut id: u32) {
+        while id != NO_PARENT && *self.refs[id as usize].get_mut() == 0 {
+            let node = &mut self.nodes[id as usize];
+            let name = match node.name.take() {
+                Some(name) => name,
+                None => return
+            };
+            let (parent, next) = (node.parent, node.next);
+            self.names -= name.len() as u64;
+            let slot = self.slot(parent, &name);
+            match self.slots.get(&slot).copied() {
+                Some(head) if head == id => {
+                    if next == NO_PARENT {
+                        self.slots.remove(&slot);
+                    } else {
+                        self.slots.insert(slot, next);
+                    }
+                },
+                Some(mut prev) => {
+                    while self.nodes[prev as usize].next != id {
+                        prev = self.nodes[prev as usize].next;
+                    }
+                    self.nodes[prev as usize].next = next;
+                },
+                None => {}
+            }
+            self.free.push(id);
+            if parent != NO_PARENT {
+                *self.refs[parent as usize].get_mut() -= 1;
+            }
+            id = parent;
+        }
+    }
+
+    /// Puts the path back together. The empty path has an id too.
+    pub fn path(&self, id: PathId) -> PathBuf {
+        let mut names = Vec::new();
+        let mut next = id.0;
+        while next != NO_PARENT {
+            let node = &self.nodes[next as usize];
+            names.push(node.name.as_deref().unwrap_or_default());
+            next = node.parent;
+        }
+        names.iter().rev().collect()
+    }
+
+    /// The number of distinct components stored, this is the number of
+    /// distinct directories and files
+    pub fn len(&self) -> usize {
+        self.nodes.len() - self.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Roughly how many bytes the arena takes in memory
+    pub fn memory_estimate(&self) -> u64 {
+        self.len() as u64 * NODE_OVERHEAD + self.names
+    }
+
+    /// Forgets every path, the ids handed out before mean nothing afterwards
+    pub fn clear(&mut self) {
+        self.nodes.clear();
+        self.refs.clear();
+        self.slots.clear();
+        self.free.clear();
+        self.names = 0;
+    }
+}
+
+/// The path of a TreeItem or of one of its dupes. The paths are interned in
+/// a PathArena every item shares, so an index of a deep tree keeps one copy
+/// of each directory's path rather than one in every file's path, and two
+/// ItemPaths are the same path when they have the same id. The path is put
+/// back together by path(). A path is freed from the arena when the last
+/// ItemPath for it is dropped.
+pub struct ItemPath(PathId);
+
+impl ItemPath {
+
+    pub fn new(path: &Path) -> Self {
+        // the names alone would fill the memory long before the ids run out
+        ItemPath(item_paths_mut().intern(path).expect("item paths fit in a path arena"))
+    }
+
+    /// Puts the path back together
+    pub fn path(&self) -> PathBuf {
+        item_paths().path(self.0)
+    }
+
+    /// Returns true for the empty path, the path of an item that doesn't
+    /// have one yet
+    pub fn is_empty(&self) -> bool {
+        self.0 .0 == NO_PARENT
+    }
+}
+
+impl Default for ItemPath {
+    fn default() -> Self {
+        ItemPath(PathId(NO_PARENT))
+    }
+}
+
+impl Clone for ItemPath {
+    fn clone(&self) -> Self {
+        item_paths().retain(self.0 .0);
+        ItemPath(self.0)
+    }
+}
+
+impl Drop for ItemPath {
+    fn drop(&mut self) {
+        let id = self.0 .0;
+        if id == NO_PARENT {
+            return;
+        }
+        // the node is only freed under the write lock, by then another
+        // intern of the path may have used it again
+        if item_paths().refs[id as usize].fetch_sub(1, Ordering::AcqRel) == 1 {
+            item_paths_mut().free_unused(id);
+        }
+    }
+}
+
+impl PartialEq for ItemPath {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl Eq for ItemPath {}
+
+impl Hash for ItemPath {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.hash(state)
+    }
+}
+
+impl Debug for ItemPath {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.path())
+    }
+}
+
+impl From<&Path> for ItemPath {
+    fn from(path: &Path) -> Self {
+        Self::new(path)
+    }
+}
+
+impl From<&PathBuf> for ItemPath {
+    fn from(path: &PathBuf) -> Self {
+        Self::new(path)
+    }
+}
+
+impl From<&Arc<PathBuf>> for ItemPath {
+    fn from(path: &Arc<PathBuf>) -> Self {
+        Self::new(path)
+    }
+}
+
+impl From<&ItemPath> for ItemPath {
+    fn from(path: &ItemPath) -> Self {
+        path.clone()
+    }
+}
+
+// the arena is only changed by the code here, none of which panics part way
+// through, so a lock poisoned by a panic elsewhere still guards good paths
+fn item_paths() -> RwLockReadGuard<'static, PathArena> {
+    ITEM_PATHS.read().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn item_paths_mut() -> RwLockWriteGuard<'static, PathArena> {
+    ITEM_PATHS.write().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn too_many() -> Error {
+    Error::InvalidArgument("too many paths for a path arena".to_string())
+}
@@ -0,0 +1,165 @@
+use crate::{
+    Result,
+    fs::{
+        IndexStore,
+        MemoryStore,
+        TempFile,
+        TreeIndex,
+        TreeIndexHeader,
+        TreeItem,
+        TreeItemDupes
+    }
+};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+// the on-disk layout of a json index, entries are sorted by digest so the
+// file diffs cleanly between runs
+#[derive(Deserialize, Serialize)]
+struct JsonIndex {
+    version: u32,
+    #[serde(default)]
+    fields: BTreeMap<String, String>,
+    items: Vec<JsonEntry>
+}
+
+#[derive(Deserialize, Serialize)]
+struct JsonEntry {
+    digest: String,
+    size: u64,
+    paths: Vec<PathBuf>
+}
+
+impl From<&TreeIndex> for JsonIndex {
+    fn from(index: &TreeIndex) -> Self {
+        let mut items: Vec<JsonEntry> = index.idx.values()
+            .map(|e| {
+                let mut paths = vec![e.item.path()];
+                paths.extend(e.dupes.iter().map(|d| d.path()));
+                JsonEntry {
+                    digest: e.item.digest.to_string(),
+                    size: e.item.size,
+                    paths
+                }
+            })
+            .collect();
+        items.sort_by(|a, b| a.digest.cmp(&b.digest));
+        Self {
+            version: index.header.version,
+            fields: index.header.fields.clone(),
+            items
+        }
+    }
+}
+
+impl From<JsonIndex> for TreeIndex {
+    fn from(json: JsonIndex) -> Self {
+        let mut ti = TreeIndex {
+            header: TreeIndexHeader {
+                version: json.version,
+                fields: json.fields
+            },
+            ..Default::default()
+        };
+        for entry in json.items {
+            for p in entry.paths {
+                ti.insert(TreeItem::new(&entry.digest, &p, entry.size));
+            }
+        }
+        ti
+    }
+}
+
+/// A JsonStore keeps an index in a JSON file. The index is held in memory
+/// and the file is rewritten by flush, which also happens when the store is
+/// dropped.
+pub struct JsonStore {
+    path: PathBuf,
+    mem: MemoryStore,
+    dirty: bool
+}
+
+impl JsonStore {
+
+    /// Opens the JSON index at the path, it is created by the first flush if
+    /// it doesn't exist
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut store = Self {
+            path: path.to_path_buf(),
+            mem: MemoryStore::default(),
+            dirty: false
+        };
+        if path.is_file() {
+            store.reload()?;
+        }
+        Ok(store)
+    }
+
+    fn reload(&mut self) -> Result<()> {
+        debug!("reading json index {}", self.path.to_string_lossy());
+        let json: JsonIndex = serde_json::from_reader(File::open(&self.path)?)?;
+        self.mem = MemoryStore::new(json.into());
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+impl IndexStore for JsonStore {
+
+    fn load(&mut self) -> Result<TreeIndex> {
+        self.mem.load()
+    }
+
+    fn save_item(&mut self, item: &TreeItem) -> Result<()> {
+        self.dirty = true;
+        self.mem.save_item(item)
+    }
+
+    fn remove_path(&mut self, path: &Path) -> Result<bool> {
+        let removed = self.mem.remove_path(path)?;
+        self.dirty |= removed;
+        Ok(removed)
+    }
+
+    fn lookup_digest(&self, digest: &str) -> Result<Option<TreeItemDupes>> {
+        self.mem.lookup_digest(digest)
+    }
+
+    fn lookup_path(&self, path: &Path) -> Result<Option<TreeItem>> {
+        self.mem.lookup_path(path)
+    }
+
+    fn iter(&self) -> Result<Box<dyn Iterator<Item = Result<TreeItemDupes>> + '_>> {
+        self.mem.iter()
+    }
+
+    fn save(&mut self, index: &TreeIndex) -> Result<()> {
+        self.mem.save(index)?;
+        self.dirty = true;
+        self.flush()
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if self.dirty {
+            debug!("writing json index {}", self.path.to_string_lossy());
+            let mut w = TempFile::next_to(&self.path)?;
+            serde_json::to_writer_pretty(&mut w, &JsonIndex::from(self.mem.index()))?;
+            writeln!(w)?;
+            w.persist(&self.path)?;
+            self.dirty = false;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for JsonStore {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            warn!("failed to write json index {}: {}", self.path.to_string_lossy(), e);
+        }
+    }
+}
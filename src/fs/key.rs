@@ -0,0 +1,89 @@
+use crate::fs::{Algorithm, Digest, DigestEncoding, DigestVariant, ItemDigest, TreeItem};
+use std::fmt::{Display, Formatter};
+
+/// The digest part of a DigestKey. Digests that don't parse, like the empty
+/// digest of special files, are kept as they were written.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum KeyDigest {
+    Bytes(Digest),
+    Text(Box<str>)
+}
+
+impl Display for KeyDigest {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error>
+    {
+        match self {
+            KeyDigest::Bytes(d) => write!(f, "{}", d),
+            KeyDigest::Text(s) => write!(f, "{}", s)
+        }
+    }
+}
+
+/// The key of an entry in a TreeIndex. Files are only dupes when they have
+/// the same digest made with the same algorithm and the same size, so a fast
+/// digest that collides with the digest of a file of another size keeps its
+/// own entry instead of being merged. The digest is kept as a Digest, a hex
+/// digest and the multihash of the same bytes make the same key.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct DigestKey {
+    pub algo: Algorithm,
+    pub digest: KeyDigest,
+    pub size: u64
+}
+
+impl DigestKey {
+    pub fn new(algo: Algorithm, digest: &str, size: u64) -> Self {
+        let digest = match digest.parse::<Digest>() {
+            Ok(d) => KeyDigest::Bytes(d),
+            Err(_) => KeyDigest::Text(digest.into())
+        };
+        Self {
+            algo,
+            digest,
+            size
+        }
+    }
+
+    /// The key of an item digested with the algorithm
+    pub fn of(algo: Algorithm, item: &TreeItem) -> Self {
+        let digest = match &item.digest {
+            ItemDigest::Hex(d) | ItemDigest::Multihash(_, _, d) => KeyDigest::Bytes(*d),
+            ItemDigest::Text(s) => return Self::new(algo, s, item.size)
+        };
+        Self {
+            algo,
+            digest,
+            size: item.size
+        }
+    }
+
+    /// The digest, if it is one the supported algorithms make
+    pub fn digest(&self) -> Option<Digest> {
+        match &self.digest {
+            KeyDigest::Bytes(d) => Some(*d),
+            KeyDigest::Text(_) => None
+        }
+    }
+
+    /// Returns true if the digest starts with the prefix, short digests like
+    /// the ones other tools print can be looked up this way. The prefix can
+    /// be of the hex or the multihash.
+    pub fn has_prefix(&self, prefix: &str) -> bool {
+        match &self.digest {
+            KeyDigest::Bytes(d) => {
+                d.to_string().starts_with(&prefix.to_lowercase())
+                    || DigestVariant::ALL.iter().any(|v| {
+                        DigestEncoding::Multihash.encode_variant(self.algo, *v, d.as_bytes()).starts_with(prefix)
+                    })
+            },
+            KeyDigest::Text(s) => s.starts_with(prefix)
+        }
+    }
+}
+
+impl Display for DigestKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error>
+    {
+        write!(f, "{}:{}:{}", self.algo, self.digest, self.size)
+    }
+}
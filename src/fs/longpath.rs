@@ -0,0 +1,133 @@
+use std::borrow::Cow;
+use std::path::Path;
+
+/// Paths this long or longer need the extended length form on Windows.
+/// MAX_PATH is 260 but directories are created with 12 fewer to leave room
+/// for a short file name.
+pub const MAX_SHORT_PATH: usize = 248;
+
+/// Returns the path in the form to hand to the operating system. On Windows
+/// a path that is too long for MAX_PATH or has a name in it that Win32
+/// would change, a reserved device name like CON or aux.txt or one ending
+/// in a dot or a space, is put in the extended length form, see
+/// extended_path. Any other path, and every path elsewhere, is returned as
+/// it is. Paths in indexes and logs stay in the form they were given in.
+pub fn os_path(path: &Path) -> Cow<'_, Path> {
+    #[cfg(windows)]
+    if let Some(s) = path.to_str() {
+        if needs_extended(s) {
+            if let Ok(cwd) = std::env::current_dir() {
+                let cwd = cwd.to_string_lossy();
+                return Cow::Owned(std::path::PathBuf::from(extended_path(s, &cwd)));
+            }
+        }
+    }
+    Cow::Borrowed(path)
+}
+
+/// Returns true if the Windows path is too long for MAX_PATH or has a name
+/// in it that Win32 would change, a reserved device name or one ending in a
+/// dot or a space
+pub fn needs_extended(path: &str) -> bool {
+    if is_verbatim(path) {
+        return false;
+    }
+    path.len() >= MAX_SHORT_PATH || path.split(['\\', '/'])
+        .filter(|name| !name.is_empty() && *name != "." && *name != "..")
+        .filter(|name| !(name.len() == 2 && has_drive(name)))
+        .any(|name| is_reserved_name(name) || name.ends_with('.') || name.ends_with(' '))
+}
+
+/// Returns the Windows path in the extended length form, \\?\C:\dir\name or
+/// \\?\UNC\server\share\name. Windows hands these to the file system as
+/// they are, so they can be up to 32767 characters long and have any name
+/// in them. Because nothing is normalized in that form this makes the path
+/// absolute against cwd, turns forward slashes into backslashes and takes
+/// out the . and .. components first. Paths that are already verbatim are
+/// returned as they are, and so are relative ones when cwd isn't absolute.
+pub fn extended_path(path: &str, cwd: &str) -> String {
+    if is_verbatim(path) {
+        return path.to_string();
+    }
+    let path = absolute(&path.replace('/', "\\"), &cwd.replace('/', "\\"));
+    let (prefix, rest) = if let Some(unc) = path.strip_prefix("\\\\") {
+        let mut parts = unc.splitn(3, '\\');
+        let server = parts.next().unwrap_or("");
+        let share = parts.next().unwrap_or("");
+        (format!("\\\\?\\UNC\\{}\\{}", server, share), parts.next().unwrap_or(""))
+    } else if has_drive(&path) && path[2..].starts_with('\\') {
+        (format!("\\\\?\\{}", path[..2].to_ascii_uppercase()), &path[3..])
+    } else {
+        return path;
+    };
+
+    let mut names: Vec<&str> = Vec::new();
+    for name in rest.split('\\') {
+        match name {
+            "" | "." => {},
+            ".." => {
+                names.pop();
+            },
+            name => names.push(name)
+        }
+    }
+    format!("{}\\{}", prefix, names.join("\\"))
+}
+
+/// Returns true if Windows reserves the name for a device. The name is
+/// reserved whatever its case and extension, and with spaces before the
+/// extension, so con, AUX.txt and "nul .log" are all devices.
+pub fn is_reserved_name(name: &str) -> bool {
+    let stem = name.split('.').next().unwrap_or("").trim_end_matches(' ').to_ascii_uppercase();
+    match stem.as_str() {
+        "CON" | "PRN" | "AUX" | "NUL" | "CONIN$" | "CONOUT$" => true,
+        s if s.starts_with("COM") || s.starts_with("LPT") => {
+            matches!(&s[3..], "0" | "1" | "2" | "3" | "4" | "5" | "6" | "7" | "8" | "9" | "\u{b9}" | "\u{b2}" | "\u{b3}")
+        },
+        _ => false
+    }
+}
+
+// \\?\ paths and \\.\ device paths aren't normalized by Windows
+fn is_verbatim(path: &str) -> bool {
+    path.starts_with("\\\\?\\") || path.starts_with("\\\\.\\")
+}
+
+// starts with a drive letter and a colon
+fn has_drive(path: &str) -> bool {
+    let b = path.as_bytes();
+    b.len() >= 2 && b[0].is_ascii_alphabetic() && b[1] == b':'
+}
+
+// joins a relative path to the current dir. C:name is relative to the
+// current dir when it is on that drive and the root of the drive otherwise,
+// \name is relative to the root of the current drive or share.
+fn absolute(path: &str, cwd: &str) -> String {
+    if path.starts_with("\\\\") || (has_drive(path) && path[2..].starts_with('\\')) {
+        path.to_string()
+    } else if has_drive(path) {
+        if has_drive(cwd) && cwd[..2].eq_ignore_ascii_case(&path[..2]) {
+            format!("{}\\{}", cwd, &path[2..])
+        } else {
+            format!("{}\\{}", &path[..2], &path[2..])
+        }
+    } else if path.starts_with('\\') {
+        format!("{}{}", root(cwd), path)
+    } else if cwd.is_empty() {
+        path.to_string()
+    } else {
+        format!("{}\\{}", cwd, path)
+    }
+}
+
+// the drive or the \\server\share of an absolute path
+fn root(cwd: &str) -> &str {
+    if let Some(unc) = cwd.strip_prefix("\\\\") {
+        let end = unc.match_indices('\\').nth(1).map(|(i, _)| i + 2).unwrap_or(cwd.len());
+        &cwd[..end]
+    } else if has_drive(cwd) {
+        &cwd[..2]
+    } else {
+        ""
+    }
+}
@@ -0,0 +1,228 @@
+use crate::{
+    error::Error,
+    Result,
+    fs::TreeIndex,
+    fs::stats::group_root
+};
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+
+// index lines holding the perceptual hash of an image start with this
+pub(crate) const IMAGE_HASH_TAG: &str = "% ";
+
+// the extensions of the images that are hashed
+const IMAGE_EXTENSIONS: [&str; 8] = ["bmp", "gif", "jpeg", "jpg", "png", "tif", "tiff", "webp"];
+
+// the pHash is made from the lowest 8x8 frequencies of a 32x32 thumbnail
+#[cfg(feature = "media")]
+const DCT_SIZE: usize = 32;
+#[cfg(feature = "media")]
+const HASH_SIZE: usize = 8;
+
+/// How the perceptual hash of an image is computed. Average hashes (aHash)
+/// compare an 8x8 thumbnail to its mean brightness, they are quick but get
+/// fooled by changes to the contrast. DCT hashes (pHash) compare the low
+/// frequencies of the image and survive resizing, re-compression and small
+/// color changes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ImageHashKind {
+    Average,
+    #[default]
+    Dct
+}
+
+impl FromStr for ImageHashKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "ahash" | "average" => Ok(ImageHashKind::Average),
+            "phash" | "dct" => Ok(ImageHashKind::Dct),
+            _ => Err(Error::InvalidArgument(format!("unknown image hash {}", s)))
+        }
+    }
+}
+
+impl Display for ImageHashKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error>
+    {
+        match self {
+            ImageHashKind::Average => write!(f, "ahash"),
+            ImageHashKind::Dct => write!(f, "phash")
+        }
+    }
+}
+
+/// The 64-bit perceptual hash of an image. Images that look alike have
+/// hashes that differ in few bits even when their bytes are different, like
+/// a resized or re-saved photo.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ImageHash {
+    pub kind: ImageHashKind,
+    pub bits: u64
+}
+
+impl ImageHash {
+
+    /// Decodes the image and hashes it, this fails if it isn't an image in
+    /// one of the supported formats
+    #[cfg(feature = "media")]
+    pub fn of(path: &Path, kind: ImageHashKind) -> Result<Self> {
+        let img = image::ImageReader::open(path)?.with_guessed_format()?.decode()?;
+        let bits = match kind {
+            ImageHashKind::Average => average_hash(&img),
+            ImageHashKind::Dct => dct_hash(&img)
+        };
+        Ok(Self { kind, bits })
+    }
+
+    /// Returns the number of bits that differ, hashes of different kinds
+    /// can't be compared so this returns None for them
+    pub fn distance(&self, other: &ImageHash) -> Option<u32> {
+        if self.kind == other.kind {
+            Some((self.bits ^ other.bits).count_ones())
+        } else {
+            None
+        }
+    }
+}
+
+// the line written after an item in an index
+impl Display for ImageHash {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error>
+    {
+        writeln!(f, "{}{} {:016x}", IMAGE_HASH_TAG, self.kind, self.bits)
+    }
+}
+
+impl FromStr for ImageHash {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || Error::InvalidFormat(format!("invalid image hash {}", s));
+        let mut parts = s.strip_prefix(IMAGE_HASH_TAG).unwrap_or(s).split_whitespace();
+        let kind = parts.next().ok_or_else(invalid)?.parse()?;
+        let bits = parts.next()
+            .and_then(|b| u64::from_str_radix(b, 16).ok())
+            .ok_or_else(invalid)?;
+        if parts.next().is_some() {
+            return Err(invalid());
+        }
+        Ok(Self { kind, bits })
+    }
+}
+
+/// Returns true if the file has the extension of an image that can be
+/// hashed, the contents aren't looked at
+pub fn is_image(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .map(|e| IMAGE_EXTENSIONS.contains(&e.to_lowercase().as_str()))
+        .unwrap_or(false)
+}
+
+// compares each pixel of an 8x8 thumbnail to the mean
+#[cfg(feature = "media")]
+fn average_hash(img: &image::DynamicImage) -> u64 {
+    let thumb = img.resize_exact(HASH_SIZE as u32, HASH_SIZE as u32, image::imageops::FilterType::Triangle)
+        .to_luma8();
+    let pixels: Vec<u64> = thumb.pixels().map(|p| p.0[0] as u64).collect();
+    let mean = pixels.iter().sum::<u64>() / pixels.len() as u64;
+    pixels.iter().enumerate().fold(0, |bits, (i, p)| if *p > mean { bits | 1 << i } else { bits })
+}
+
+// compares the lowest frequencies of a 32x32 thumbnail to their median, the
+// first one is the mean brightness and is left out of the median
+#[cfg(feature = "media")]
+fn dct_hash(img: &image::DynamicImage) -> u64 {
+    let thumb = img.resize_exact(DCT_SIZE as u32, DCT_SIZE as u32, image::imageops::FilterType::Triangle)
+        .to_luma8();
+    let pixels: Vec<f64> = thumb.pixels().map(|p| p.0[0] as f64).collect();
+
+    // the 2D DCT is a 1D DCT of the rows and then of the columns, only the
+    // low frequencies are needed
+    let cos: Vec<f64> = (0..HASH_SIZE * DCT_SIZE)
+        .map(|i| {
+            let (u, x) = (i / DCT_SIZE, i % DCT_SIZE);
+            ((2 * x + 1) as f64 * u as f64 * std::f64::consts::PI / (2 * DCT_SIZE) as f64).cos()
+        })
+        .collect();
+    let mut rows = vec![0.0; DCT_SIZE * HASH_SIZE];
+    for y in 0..DCT_SIZE {
+        for u in 0..HASH_SIZE {
+            rows[y * HASH_SIZE + u] = (0..DCT_SIZE).map(|x| pixels[y * DCT_SIZE + x] * cos[u * DCT_SIZE + x]).sum();
+        }
+    }
+    let mut coeffs = vec![0.0; HASH_SIZE * HASH_SIZE];
+    for v in 0..HASH_SIZE {
+        for u in 0..HASH_SIZE {
+            coeffs[v * HASH_SIZE + u] = (0..DCT_SIZE).map(|y| rows[y * HASH_SIZE + u] * cos[v * DCT_SIZE + y]).sum();
+        }
+    }
+
+    let mut sorted = coeffs[1..].to_vec();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    let median = sorted[sorted.len() / 2];
+    coeffs.iter().enumerate().fold(0, |bits, (i, c)| if *c > median { bits | 1 << i } else { bits })
+}
+
+/// A group of images that look alike. Every image is within the distance of
+/// at least one other image in the group, distance is the largest of those.
+#[derive(Clone, Debug)]
+pub struct ImageGroup {
+    pub images: Vec<Arc<PathBuf>>,
+    pub distance: u32
+}
+
+impl Display for ImageGroup {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error>
+    {
+        writeln!(f, "{} similar images, distance up to {}", self.images.len(), self.distance)?;
+        for i in &self.images {
+            writeln!(f, "  {}", i.to_string_lossy())?;
+        }
+        Ok(())
+    }
+}
+
+/// Groups the images in the index whose perceptual hashes differ in at most
+/// max_distance bits, biggest groups first. Identical copies of an image
+/// are already dupes so each group lists one path for each of them. Every
+/// pair of hashes is compared, which is fine for photo collections of tens
+/// of thousands of images.
+pub fn similar_images(ti: &TreeIndex, max_distance: u32) -> Vec<ImageGroup> {
+    let mut images: Vec<(Arc<PathBuf>, ImageHash)> = ti.idx.values()
+        .filter_map(|v| v.item.image_hash.map(|h| (Arc::new(v.item.path()), h)))
+        .collect();
+    images.sort_by(|a, b| a.0.cmp(&b.0));
+
+    // join the images closer than the distance into groups
+    let mut parent: Vec<usize> = (0..images.len()).collect();
+    let mut widest: Vec<u32> = vec![0; images.len()];
+    for a in 0..images.len() {
+        for b in a + 1..images.len() {
+            let d = match images[a].1.distance(&images[b].1) {
+                Some(d) if d <= max_distance => d,
+                _ => continue
+            };
+            let (ra, rb) = (group_root(&mut parent, a), group_root(&mut parent, b));
+            let w = widest[ra].max(widest[rb]).max(d);
+            parent[rb] = ra;
+            widest[ra] = w;
+        }
+    }
+
+    let mut groups: HashMap<usize, Vec<Arc<PathBuf>>> = HashMap::new();
+    for (i, (path, _)) in images.iter().enumerate() {
+        groups.entry(group_root(&mut parent, i)).or_default().push(path.clone());
+    }
+    let mut groups: Vec<ImageGroup> = groups.into_iter()
+        .filter(|(_, images)| images.len() > 1)
+        .map(|(r, images)| ImageGroup { images, distance: widest[r] })
+        .collect();
+    groups.sort_by(|a, b| b.images.len().cmp(&a.images.len()).then_with(|| a.images[0].cmp(&b.images[0])));
+    groups
+}
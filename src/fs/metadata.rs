@@ -0,0 +1,250 @@
+use crate::{
+    error::Error,
+    Result,
+    fs::os_path
+};
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// index lines holding file metadata start with this
+pub(crate) const METADATA_TAG: &str = "@ ";
+
+/// The type of a file system entry. Junction, MountPoint and Placeholder
+/// are the Windows reparse points a scan tells apart, see reparse_kind.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum FileKind {
+    #[default]
+    File,
+    Dir,
+    Symlink,
+    Fifo,
+    Socket,
+    BlockDevice,
+    CharDevice,
+    Junction,
+    MountPoint,
+    Placeholder,
+    Unknown
+}
+
+impl From<fs::FileType> for FileKind {
+    fn from(t: fs::FileType) -> Self {
+        if t.is_file() {
+            return FileKind::File;
+        }
+        if t.is_dir() {
+            return FileKind::Dir;
+        }
+        if t.is_symlink() {
+            return FileKind::Symlink;
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::FileTypeExt;
+            if t.is_fifo() {
+                return FileKind::Fifo;
+            }
+            if t.is_socket() {
+                return FileKind::Socket;
+            }
+            if t.is_block_device() {
+                return FileKind::BlockDevice;
+            }
+            if t.is_char_device() {
+                return FileKind::CharDevice;
+            }
+        }
+        FileKind::Unknown
+    }
+}
+
+impl FileKind {
+
+    /// Returns true for the kinds of Windows reparse point
+    pub fn is_reparse_point(&self) -> bool {
+        matches!(self, FileKind::Junction | FileKind::MountPoint | FileKind::Placeholder)
+    }
+}
+
+impl FromStr for FileKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "file" => Ok(FileKind::File),
+            "dir" => Ok(FileKind::Dir),
+            "symlink" => Ok(FileKind::Symlink),
+            "fifo" => Ok(FileKind::Fifo),
+            "socket" => Ok(FileKind::Socket),
+            "block" => Ok(FileKind::BlockDevice),
+            "char" => Ok(FileKind::CharDevice),
+            "junction" => Ok(FileKind::Junction),
+            "mount" => Ok(FileKind::MountPoint),
+            "placeholder" => Ok(FileKind::Placeholder),
+            "unknown" => Ok(FileKind::Unknown),
+            _ => Err(Error::InvalidArgument(format!("unknown file kind {}", s)))
+        }
+    }
+}
+
+impl Display for FileKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error>
+    {
+        match self {
+            FileKind::File => write!(f, "file"),
+            FileKind::Dir => write!(f, "dir"),
+            FileKind::Symlink => write!(f, "symlink"),
+            FileKind::Fifo => write!(f, "fifo"),
+            FileKind::Socket => write!(f, "socket"),
+            FileKind::BlockDevice => write!(f, "block"),
+            FileKind::CharDevice => write!(f, "char"),
+            FileKind::Junction => write!(f, "junction"),
+            FileKind::MountPoint => write!(f, "mount"),
+            FileKind::Placeholder => write!(f, "placeholder"),
+            FileKind::Unknown => write!(f, "unknown")
+        }
+    }
+}
+
+/// The metadata of a file that can change without its contents changing.
+/// Fields the platform doesn't have, like owners on Windows, are None and
+/// are left out of comparisons. Allocated is the bytes the file takes up on
+/// disk, less than its size for sparse files, it isn't compared because it
+/// changes when a file is copied.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FileMetadata {
+    pub kind: FileKind,
+    pub mtime: Option<SystemTime>,
+    pub mode: Option<u32>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub allocated: Option<u64>
+}
+
+impl FileMetadata {
+
+    /// Reads the metadata of the path. The kind comes from the path itself
+    /// so a symlink to a file is a symlink, everything else is of the file
+    /// it points at.
+    pub fn read(path: &Path) -> Result<Self> {
+        let path = os_path(path);
+        let kind = FileKind::from(fs::symlink_metadata(&path)?.file_type());
+        let meta = fs::metadata(&path)?;
+        let mut m = Self {
+            kind,
+            mtime: meta.modified().ok(),
+            ..Self::default()
+        };
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            m.mode = Some(meta.mode() & 0o7777);
+            m.uid = Some(meta.uid());
+            m.gid = Some(meta.gid());
+
+            // st_blocks is always in 512 byte units
+            m.allocated = Some(meta.blocks() * 512);
+        }
+        #[cfg(not(unix))]
+        {
+            m.mode = Some(if meta.permissions().readonly() { 0o444 } else { 0o644 });
+        }
+        Ok(m)
+    }
+
+    /// Returns the names of the fields that differ, fields missing from
+    /// either side aren't compared
+    pub fn changes(&self, other: &FileMetadata) -> Vec<&'static str> {
+        fn differs<T: PartialEq>(a: &Option<T>, b: &Option<T>) -> bool {
+            matches!((a, b), (Some(a), Some(b)) if a != b)
+        }
+        let mut changes = Vec::new();
+        if self.kind != other.kind {
+            changes.push("kind");
+        }
+        if differs(&self.mtime, &other.mtime) {
+            changes.push("mtime");
+        }
+        if differs(&self.mode, &other.mode) {
+            changes.push("mode");
+        }
+        if differs(&self.uid, &other.uid) {
+            changes.push("owner");
+        }
+        if differs(&self.gid, &other.gid) {
+            changes.push("group");
+        }
+        changes
+    }
+}
+
+// the line written after an item in an index, key=value pairs with the mtime
+// in seconds and nanoseconds since the epoch and the mode in octal
+impl Display for FileMetadata {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error>
+    {
+        write!(f, "{}kind={}", METADATA_TAG, self.kind)?;
+        if let Some(t) = self.mtime {
+            match t.duration_since(UNIX_EPOCH) {
+                Ok(d) => write!(f, " mtime={}.{:09}", d.as_secs(), d.subsec_nanos())?,
+                Err(e) => {
+                    let d = e.duration();
+                    write!(f, " mtime=-{}.{:09}", d.as_secs(), d.subsec_nanos())?
+                }
+            }
+        }
+        if let Some(mode) = self.mode {
+            write!(f, " mode={:o}", mode)?;
+        }
+        if let Some(uid) = self.uid {
+            write!(f, " uid={}", uid)?;
+        }
+        if let Some(gid) = self.gid {
+            write!(f, " gid={}", gid)?;
+        }
+        if let Some(allocated) = self.allocated {
+            write!(f, " alloc={}", allocated)?;
+        }
+        writeln!(f)
+    }
+}
+
+// fields this version doesn't know about are skipped
+impl FromStr for FileMetadata {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || Error::InvalidFormat(format!("invalid file metadata {}", s));
+        let mut m = Self::default();
+        for field in s.strip_prefix(METADATA_TAG).unwrap_or(s).split_whitespace() {
+            let (k, v) = field.split_once('=').ok_or_else(invalid)?;
+            match k {
+                "kind" => m.kind = v.parse()?,
+                "mtime" => m.mtime = Some(parse_time(v).ok_or_else(invalid)?),
+                "mode" => m.mode = Some(u32::from_str_radix(v, 8).map_err(|_| invalid())?),
+                "uid" => m.uid = Some(v.parse().map_err(|_| invalid())?),
+                "gid" => m.gid = Some(v.parse().map_err(|_| invalid())?),
+                "alloc" => m.allocated = Some(v.parse().map_err(|_| invalid())?),
+                _ => {}
+            }
+        }
+        Ok(m)
+    }
+}
+
+fn parse_time(v: &str) -> Option<SystemTime> {
+    let (neg, v) = match v.strip_prefix('-') {
+        Some(v) => (true, v),
+        None => (false, v)
+    };
+    let (secs, nanos) = v.split_once('.').unwrap_or((v, "0"));
+    let d = Duration::new(secs.parse().ok()?, nanos.parse().ok()?);
+    if neg {
+        UNIX_EPOCH.checked_sub(d)
+    } else {
+        UNIX_EPOCH.checked_add(d)
+    }
+}
@@ -0,0 +1,149 @@
+use crate::{
+    Result,
+    fs::TempFile
+};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// The files a scan digested
+pub const FILES_SCANNED: &str = "files_scanned_total";
+
+/// The bytes of the files a scan digested
+pub const BYTES_HASHED: &str = "bytes_hashed_total";
+
+/// The directories a scan read
+pub const DIRS_SCANNED: &str = "dirs_scanned_total";
+
+/// The files a scan left out because of its filters
+pub const FILES_SKIPPED: &str = "files_skipped_total";
+
+/// The problems a scan logged and carried on from
+pub const SCAN_ERRORS: &str = "scan_errors_total";
+
+/// The directories and files waiting in a scan's work queue
+pub const QUEUE_DEPTH: &str = "scan_queue_depth";
+
+/// The changes a TreeWatcher applied to its index
+pub const WATCH_UPDATES: &str = "watch_updates_total";
+
+/// The digests in the index a TreeWatcher keeps up to date
+pub const INDEX_ENTRIES: &str = "index_entries";
+
+/// The actions a DedupExecutor carried out
+pub const ACTIONS_DONE: &str = "dedup_actions_total";
+
+/// The actions a DedupExecutor tried that failed
+pub const ACTIONS_FAILED: &str = "dedup_actions_failed_total";
+
+/// The bytes the file system shared between kept copies and their dupes
+pub const BYTES_DEDUPED: &str = "dedup_bytes_total";
+
+/// Metrics are told about the work a scan or a DedupExecutor does as it
+/// goes, so a long running service can show how fast it is going and how
+/// far behind it is. Counters only go up and are added to, gauges are set
+/// to what they are now. The names are the constants in this module. They
+/// are called from the worker threads of a parallel scan so they have to
+/// be cheap and thread safe.
+pub trait Metrics: Send + Sync {
+
+    /// Adds to the counter
+    fn add(&self, name: &'static str, value: u64);
+
+    /// Sets the gauge
+    fn set(&self, name: &'static str, value: i64);
+}
+
+/// MemoryMetrics keeps the counters and gauges in memory, for checking on
+/// in-process or writing out with to_prometheus
+#[derive(Debug)]
+pub struct MemoryMetrics {
+    start: Instant,
+    values: Mutex<MetricValues>
+}
+
+#[derive(Debug, Default)]
+struct MetricValues {
+    counters: BTreeMap<&'static str, u64>,
+    gauges: BTreeMap<&'static str, i64>
+}
+
+impl Default for MemoryMetrics {
+    fn default() -> Self {
+        Self {
+            start: Instant::now(),
+            values: Mutex::new(MetricValues::default())
+        }
+    }
+}
+
+impl MemoryMetrics {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The counter's value, zero if it was never added to
+    pub fn counter(&self, name: &str) -> u64 {
+        self.values.lock().unwrap().counters.get(name).copied().unwrap_or(0)
+    }
+
+    /// The gauge's value if it was ever set
+    pub fn gauge(&self, name: &str) -> Option<i64> {
+        self.values.lock().unwrap().gauges.get(name).copied()
+    }
+
+    /// How long ago the metrics were made
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    /// The counter's average rate per second since the metrics were made,
+    /// like files per second for FILES_SCANNED
+    pub fn rate(&self, name: &str) -> f64 {
+        let secs = self.elapsed().as_secs_f64();
+        if secs > 0.0 {
+            self.counter(name) as f64 / secs
+        } else {
+            0.0
+        }
+    }
+
+    /// Returns the counters and gauges in the Prometheus text exposition
+    /// format with the prefix on their names, for serving on a /metrics
+    /// endpoint or writing to a file for the node exporter's textfile
+    /// collector
+    pub fn to_prometheus(&self, prefix: &str) -> String {
+        let values = self.values.lock().unwrap();
+        let mut out = String::new();
+        for (name, value) in &values.counters {
+            let _ = writeln!(out, "# TYPE {}{} counter\n{}{} {}", prefix, name, prefix, name, value);
+        }
+        for (name, value) in &values.gauges {
+            let _ = writeln!(out, "# TYPE {}{} gauge\n{}{} {}", prefix, name, prefix, name, value);
+        }
+        out
+    }
+
+    /// Writes to_prometheus to the file, replacing it in one go so a
+    /// collector reading it never sees half of it
+    pub fn write_prometheus(&self, path: &Path, prefix: &str) -> Result<()> {
+        let tmp = TempFile::next_to(path)?;
+        tmp.as_file().write_all(self.to_prometheus(prefix).as_bytes())?;
+        tmp.persist(path)
+    }
+}
+
+impl Metrics for MemoryMetrics {
+
+    fn add(&self, name: &'static str, value: u64) {
+        *self.values.lock().unwrap().counters.entry(name).or_insert(0) += value;
+    }
+
+    fn set(&self, name: &'static str, value: i64) {
+        self.values.lock().unwrap().gauges.insert(name, value);
+    }
+}
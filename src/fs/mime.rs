@@ -0,0 +1,83 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+// the number of bytes read from the start of a file to sniff its type, this
+// is big enough to see the "ustar" magic in a tar header
+const SNIFF_LEN: usize = 512;
+
+// A magic byte signature: the offset of the magic bytes, the bytes and the
+// MIME type they indicate
+struct Magic {
+    offset: usize,
+    bytes: &'static [u8],
+    mime: &'static str
+}
+
+const MAGIC: &[Magic] = &[
+    Magic { offset: 0, bytes: b"\xff\xd8\xff", mime: "image/jpeg" },
+    Magic { offset: 0, bytes: b"\x89PNG\r\n\x1a\n", mime: "image/png" },
+    Magic { offset: 0, bytes: b"GIF87a", mime: "image/gif" },
+    Magic { offset: 0, bytes: b"GIF89a", mime: "image/gif" },
+    Magic { offset: 8, bytes: b"WEBP", mime: "image/webp" },
+    Magic { offset: 0, bytes: b"BM", mime: "image/bmp" },
+    Magic { offset: 0, bytes: b"II*\x00", mime: "image/tiff" },
+    Magic { offset: 0, bytes: b"MM\x00*", mime: "image/tiff" },
+    Magic { offset: 4, bytes: b"ftypheic", mime: "image/heic" },
+    Magic { offset: 4, bytes: b"ftypmif1", mime: "image/heif" },
+    Magic { offset: 4, bytes: b"ftypqt", mime: "video/quicktime" },
+    Magic { offset: 4, bytes: b"ftyp", mime: "video/mp4" },
+    Magic { offset: 8, bytes: b"AVI ", mime: "video/x-msvideo" },
+    Magic { offset: 0, bytes: b"\x1a\x45\xdf\xa3", mime: "video/x-matroska" },
+    Magic { offset: 8, bytes: b"WAVE", mime: "audio/wav" },
+    Magic { offset: 0, bytes: b"ID3", mime: "audio/mpeg" },
+    Magic { offset: 0, bytes: b"\xff\xfb", mime: "audio/mpeg" },
+    Magic { offset: 0, bytes: b"fLaC", mime: "audio/flac" },
+    Magic { offset: 0, bytes: b"OggS", mime: "audio/ogg" },
+    Magic { offset: 0, bytes: b"%PDF-", mime: "application/pdf" },
+    Magic { offset: 0, bytes: b"PK\x03\x04", mime: "application/zip" },
+    Magic { offset: 0, bytes: b"\x1f\x8b", mime: "application/gzip" },
+    Magic { offset: 0, bytes: b"BZh", mime: "application/x-bzip2" },
+    Magic { offset: 0, bytes: b"\xfd7zXZ\x00", mime: "application/x-xz" },
+    Magic { offset: 0, bytes: b"7z\xbc\xaf\x27\x1c", mime: "application/x-7z-compressed" },
+    Magic { offset: 257, bytes: b"ustar", mime: "application/x-tar" },
+    Magic { offset: 0, bytes: b"\x7fELF", mime: "application/x-executable" },
+];
+
+/// This function guesses the MIME type of the data from its magic bytes.
+/// Data that doesn't match any known signature but is valid UTF-8 without
+/// control characters is reported as text/plain. Anything else is
+/// application/octet-stream.
+pub fn sniff(data: &[u8]) -> &'static str {
+    for m in MAGIC {
+        if data.len() >= m.offset + m.bytes.len() && &data[m.offset..m.offset + m.bytes.len()] == m.bytes {
+            return m.mime;
+        }
+    }
+    match std::str::from_utf8(data) {
+        Ok(s) if !s.chars().any(|c| c.is_control() && !c.is_whitespace()) => "text/plain",
+        _ => "application/octet-stream"
+    }
+}
+
+/// This function reads the start of the file and guesses its MIME type. If
+/// the file can't be read, None is returned.
+pub fn sniff_path(path: &Path) -> Option<&'static str> {
+    let mut f = File::open(path).ok()?;
+    let mut buf = [0u8; SNIFF_LEN];
+    let mut len = 0;
+    while len < SNIFF_LEN {
+        match f.read(&mut buf[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(_) => return None
+        }
+    }
+    // a multi-byte char may have been cut off at the end of the buffer
+    let data = &buf[..len];
+    let data = match std::str::from_utf8(data) {
+        Err(e) if e.error_len().is_none() => &data[..e.valid_up_to()],
+        _ => data
+    };
+    Some(sniff(data))
+}
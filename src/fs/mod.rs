@@ -0,0 +1,122 @@
+use lazy_static::lazy_static;
+use std::path::PathBuf;
+
+lazy_static! {
+    pub(crate) static ref EMPTY_PATHBUF: PathBuf = {
+        PathBuf::new()
+    };
+}
+
+pub mod actionlog;
+pub mod algo;
+pub mod appledouble;
+#[cfg(feature = "archive")]
+pub mod archive;
+pub mod binary;
+pub mod checksum;
+pub mod chunks;
+mod confirm;
+pub mod csv;
+pub mod dedup;
+pub mod diff;
+pub mod digest;
+pub mod dirindex;
+pub mod executor;
+pub mod extents;
+mod fastread;
+pub mod filter;
+pub mod fuzzy;
+pub mod header;
+pub mod intern;
+#[cfg(feature = "json")]
+pub mod json;
+pub mod key;
+pub mod longpath;
+pub mod media;
+pub mod metadata;
+pub mod metrics;
+pub mod mime;
+pub mod multihash;
+pub mod ndjson;
+pub mod remote;
+pub mod reparse;
+#[cfg(feature = "s3")]
+pub mod s3;
+pub mod savings;
+pub mod script;
+#[cfg(all(feature = "service", unix))]
+pub mod service;
+mod spill;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+#[cfg(feature = "sign")]
+pub mod sign;
+pub mod stats;
+pub mod store;
+pub mod sync;
+pub mod temp;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+pub mod text;
+pub mod throttle;
+pub mod treeitem;
+pub mod treelist;
+pub mod treeindex;
+#[cfg(feature = "watch")]
+pub mod watch;
+pub use actionlog::*;
+pub use algo::*;
+pub use appledouble::{apple_double_path, is_mac_metadata, AppleDoublePolicy};
+#[cfg(feature = "archive")]
+pub use archive::*;
+pub use binary::{BINARY_MAGIC, BINARY_VERSION, is_binary};
+pub use checksum::ChecksumFormat;
+pub use chunks::{ChunkDigests, Chunking, SimilarFiles, similar_files};
+pub use confirm::{ConfirmMode, same_contents};
+pub use csv::{CSV_HEADER, is_csv, write_csv_entry, write_csv_header, write_csv_item};
+pub use dedup::*;
+pub use diff::*;
+pub use digest::{Digest, ItemDigest, DIGEST_LEN};
+pub use dirindex::*;
+pub use executor::*;
+pub use extents::*;
+pub use filter::*;
+pub use fuzzy::{FuzzyDigest, FuzzyGroup, fuzzy_groups};
+pub use header::*;
+pub use intern::{ItemPath, PathArena, PathId};
+pub use key::{DigestKey, KeyDigest};
+pub use crate::lock::{lock_exclusive, open_locked, try_lock_exclusive};
+pub use longpath::{extended_path, is_reserved_name, os_path};
+pub use media::*;
+pub use metadata::*;
+pub use metrics::{MemoryMetrics, Metrics};
+pub use multihash::{decode_multihash, decode_multihash_variant, digest_hex, DigestEncoding, DigestVariant};
+pub use ndjson::{write_ndjson_entry, write_ndjson_item};
+pub use remote::{REMOTE_MAGIC, REMOTE_VERSION, serve, serve_tcp};
+pub use reparse::reparse_kind;
+#[cfg(feature = "json")]
+pub use json::*;
+#[cfg(feature = "sqlite")]
+pub use sqlite::*;
+#[cfg(feature = "sign")]
+pub use sign::*;
+#[cfg(feature = "s3")]
+pub use s3::S3SourceBuilder;
+pub use savings::*;
+pub use script::*;
+#[cfg(all(feature = "service", unix))]
+pub use service::{bind_socket, IndexService, ServiceSocket};
+pub use spill::MemoryUsage;
+pub use stats::{IndexStats, ScanStats, SizeBucket};
+pub use store::*;
+pub use sync::*;
+pub use temp::{TempDir, TempFile};
+#[cfg(any(test, feature = "testing"))]
+pub use testing::{TreeFixture, TreeFixtureBuilder, WEIRD_NAMES};
+pub use text::*;
+pub use throttle::{set_io_priority, IoPriority};
+pub use treeitem::*;
+pub use treelist::*;
+pub use treeindex::*;
+#[cfg(feature = "watch")]
+pub use watch::*;
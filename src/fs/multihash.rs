@@ -0,0 +1,244 @@
+use crate::{
+    error::Error,
+    Result,
+    fs::Algorithm
+};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+// the multicodec table codes for the supported algorithms
+const SHA2_256: u64 = 0x12;
+const BLAKE2B_256: u64 = 0xb220;
+
+// fast and keyed digests aren't digests of the file the table has codes
+// for, they get codes of their own in its private use range by adding one
+// of these to the code of the algorithm
+const FAST: u64 = 0x30_0000;
+const KEYED: u64 = 0x31_0000;
+const FAST_KEYED: u64 = 0x32_0000;
+
+// RFC 4648 base32 alphabet, lower case as multibase 'b' uses it
+const BASE32: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+
+/// How digests are written in TreeItems and indexes. Hex is a bare hex
+/// string and is what indexes without an encoding header field use.
+/// Multihash prefixes the digest with its algorithm and length and writes it
+/// as a multibase base32 string, so every digest says how it was made. Fast
+/// and keyed digests have codes in the private use range of the multicodec
+/// table so they can't pass for digests of the whole file by anyone.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DigestEncoding {
+    #[default]
+    Hex,
+    Multihash
+}
+
+impl DigestEncoding {
+
+    /// Encodes the raw digest bytes of the whole file
+    pub fn encode(&self, algorithm: Algorithm, digest: &[u8]) -> String {
+        self.encode_variant(algorithm, DigestVariant::Full, digest)
+    }
+
+    /// Encodes the raw digest bytes made the way the variant says
+    pub fn encode_variant(&self, algorithm: Algorithm, variant: DigestVariant, digest: &[u8]) -> String {
+        match self {
+            DigestEncoding::Hex => hex(digest),
+            DigestEncoding::Multihash => {
+                let mut mh = Vec::with_capacity(digest.len() + 4);
+                varint(code(algorithm) + variant.offset(), &mut mh);
+                varint(digest.len() as u64, &mut mh);
+                mh.extend_from_slice(digest);
+                format!("b{}", base32(&mh))
+            }
+        }
+    }
+}
+
+impl FromStr for DigestEncoding {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "hex" => Ok(DigestEncoding::Hex),
+            "multihash" => Ok(DigestEncoding::Multihash),
+            _ => Err(Error::InvalidArgument(format!("unknown digest encoding {}", s)))
+        }
+    }
+}
+
+impl Display for DigestEncoding {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error>
+    {
+        match self {
+            DigestEncoding::Hex => write!(f, "hex"),
+            DigestEncoding::Multihash => write!(f, "multihash")
+        }
+    }
+}
+
+/// What a digest is of besides its algorithm. Full digests are digests of
+/// the whole file, Fast ones of its first and last MiB and its size, and the
+/// keyed ones were made with a secret key as well.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum DigestVariant {
+    #[default]
+    Full,
+    Fast,
+    Keyed,
+    FastKeyed
+}
+
+impl DigestVariant {
+
+    /// The variant of a digest that was or wasn't fast and keyed
+    pub fn new(fast: bool, keyed: bool) -> Self {
+        match (fast, keyed) {
+            (false, false) => DigestVariant::Full,
+            (true, false) => DigestVariant::Fast,
+            (false, true) => DigestVariant::Keyed,
+            (true, true) => DigestVariant::FastKeyed
+        }
+    }
+
+    pub(crate) const ALL: [DigestVariant; 4] =
+        [DigestVariant::Full, DigestVariant::Fast, DigestVariant::Keyed, DigestVariant::FastKeyed];
+
+    // what is added to the code of the algorithm
+    fn offset(&self) -> u64 {
+        match self {
+            DigestVariant::Full => 0,
+            DigestVariant::Fast => FAST,
+            DigestVariant::Keyed => KEYED,
+            DigestVariant::FastKeyed => FAST_KEYED
+        }
+    }
+}
+
+/// Decodes a multibase multihash string into its algorithm and digest bytes.
+/// The base16 ('f', 'F') and base32 ('b', 'B') multibase encodings are
+/// understood.
+pub fn decode_multihash(s: &str) -> Result<(Algorithm, Vec<u8>)> {
+    decode_multihash_variant(s).map(|(algorithm, _, digest)| (algorithm, digest))
+}
+
+/// Decodes a multibase multihash string like decode_multihash, along with
+/// the variant of the digest
+pub fn decode_multihash_variant(s: &str) -> Result<(Algorithm, DigestVariant, Vec<u8>)> {
+    let invalid = || Error::InvalidFormat(format!("invalid multihash {}", s));
+    let mut chars = s.chars();
+    let bytes = match chars.next() {
+        Some('f') | Some('F') => unhex(chars.as_str()).ok_or_else(invalid)?,
+        Some('b') | Some('B') => unbase32(chars.as_str()).ok_or_else(invalid)?,
+        _ => return Err(invalid())
+    };
+
+    let mut rest = bytes.as_slice();
+    let code = read_varint(&mut rest).ok_or_else(invalid)?;
+    let len = read_varint(&mut rest).ok_or_else(invalid)? as usize;
+    if rest.len() != len {
+        return Err(invalid());
+    }
+    let variant = match code & !0xffff {
+        0 => DigestVariant::Full,
+        FAST => DigestVariant::Fast,
+        KEYED => DigestVariant::Keyed,
+        FAST_KEYED => DigestVariant::FastKeyed,
+        _ => return Err(Error::InvalidFormat(format!("unsupported multihash code {:#x}", code)))
+    };
+    let algorithm = match code - variant.offset() {
+        SHA2_256 => Algorithm::Sha256,
+        BLAKE2B_256 => Algorithm::Blake2b,
+        _ => return Err(Error::InvalidFormat(format!("unsupported multihash code {:#x}", code)))
+    };
+    Ok((algorithm, variant, rest.to_vec()))
+}
+
+/// Returns a digest from an index as bare hex, decoding it first if it is a
+/// multihash
+pub fn digest_hex(digest: &str, encoding: DigestEncoding) -> Result<String> {
+    match encoding {
+        DigestEncoding::Hex => Ok(digest.to_string()),
+        DigestEncoding::Multihash => Ok(hex(&decode_multihash(digest)?.1))
+    }
+}
+
+fn code(algorithm: Algorithm) -> u64 {
+    match algorithm {
+        Algorithm::Blake2b => BLAKE2B_256,
+        Algorithm::Sha256 => SHA2_256
+    }
+}
+
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub(crate) fn unhex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len()).step_by(2)
+        .map(|i| s.get(i..i + 2).and_then(|b| u8::from_str_radix(b, 16).ok()))
+        .collect()
+}
+
+// unsigned LEB128 as used by multiformats
+pub(crate) fn varint(mut n: u64, out: &mut Vec<u8>) {
+    loop {
+        let b = (n & 0x7f) as u8;
+        n >>= 7;
+        if n == 0 {
+            out.push(b);
+            return;
+        }
+        out.push(b | 0x80);
+    }
+}
+
+fn read_varint(bytes: &mut &[u8]) -> Option<u64> {
+    let mut n = 0u64;
+    for (i, b) in bytes.iter().enumerate().take(9) {
+        n |= ((b & 0x7f) as u64) << (7 * i);
+        if b & 0x80 == 0 {
+            *bytes = &bytes[i + 1..];
+            return Some(n);
+        }
+    }
+    None
+}
+
+// base32 without padding
+fn base32(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(5) * 8);
+    let mut buf = 0u64;
+    let mut bits = 0;
+    for b in bytes {
+        buf = (buf << 8) | *b as u64;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(BASE32[((buf >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(BASE32[((buf << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    out
+}
+
+fn unbase32(s: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(s.len() * 5 / 8);
+    let mut buf = 0u64;
+    let mut bits = 0;
+    for c in s.chars() {
+        let v = BASE32.iter().position(|&a| a as char == c.to_ascii_lowercase())? as u64;
+        buf = (buf << 5) | v;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out.push(((buf >> bits) & 0xff) as u8);
+        }
+    }
+    Some(out)
+}
@@ -0,0 +1,36 @@
+use crate::{
+    Result,
+    fs::{
+        FileKind,
+        TreeItem,
+        TreeItemDupes
+    },
+    fs::stats::json_string,
+    fs::treeitem::escape_path
+};
+use std::io::Write;
+
+/// Writes the item as one line of JSON. Files have their digest, size and
+/// path, special files their kind and path. Paths are escaped the way text
+/// indexes escape them so ones that aren't UTF-8 aren't mangled.
+pub fn write_ndjson_item(w: &mut dyn Write, item: &TreeItem) -> Result<()> {
+    let path = json_string(&escape_path(&item.path())?);
+    if item.kind != FileKind::File {
+        writeln!(w, "{{\"kind\":{},\"path\":{}}}", json_string(&item.kind.to_string()), path)?;
+    } else {
+        writeln!(w, "{{\"digest\":{},\"size\":{},\"path\":{}}}", json_string(&item.digest.to_string()), item.size, path)?;
+    }
+    Ok(())
+}
+
+/// Writes the item and its dupes as one line of JSON, the first path is the
+/// item's
+pub fn write_ndjson_entry(w: &mut dyn Write, entry: &TreeItemDupes) -> Result<()> {
+    let paths = std::iter::once(entry.item.item_path())
+        .chain(entry.dupes.iter())
+        .map(|p| Ok(json_string(&escape_path(&p.path())?)))
+        .collect::<Result<Vec<String>>>()?;
+    writeln!(w, "{{\"digest\":{},\"size\":{},\"paths\":[{}]}}",
+             json_string(&entry.item.digest.to_string()), entry.item.size, paths.join(","))?;
+    Ok(())
+}
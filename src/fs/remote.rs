@@ -0,0 +1,171 @@
+use crate::{
+    error::Error,
+    Result,
+    fs::{
+        binary::{byte, bytes, read_varint, side_line, side_lines, string},
+        multihash::varint,
+        treeindex::IndexLine,
+        treeitem::{index_path, native_path},
+        ScanStats,
+        TreeIndexHeader,
+        TreeItem,
+        TreeListBuilder
+    }
+};
+use log::{debug, info, warn};
+use std::io::{BufReader, BufWriter, Cursor, Read, Write};
+use std::net::TcpListener;
+use std::time::Duration;
+
+/// Remote scan streams start with these bytes followed by the protocol
+/// version
+pub const REMOTE_MAGIC: &[u8; 4] = b"TSCN";
+
+/// The version of the remote scan protocol that is spoken
+pub const REMOTE_VERSION: u8 = 1;
+
+// the kinds of frame. a stream is the magic bytes and version, a header
+// frame, an item frame for every file and then a done frame with the stats
+// of the scan or an error frame if the scan failed.
+const HEADER: u8 = b'H';
+const ITEM: u8 = b'I';
+const DONE: u8 = b'D';
+const ERROR: u8 = b'E';
+
+// writes frames to a stream, a frame is its kind, the length of its payload
+// as a varint and the payload
+struct FrameWriter<'a> {
+    w: &'a mut dyn Write,
+    buf: Vec<u8>
+}
+
+impl<'a> FrameWriter<'a> {
+
+    fn frame(&mut self, kind: u8, payload: &[u8]) -> Result<()> {
+        self.buf.clear();
+        self.buf.push(kind);
+        varint(payload.len() as u64, &mut self.buf);
+        self.w.write_all(&self.buf)?;
+        self.w.write_all(payload)?;
+        Ok(())
+    }
+
+    // an item is its digest, size, path and side lines
+    fn item(&mut self, item: &TreeItem) -> Result<()> {
+        let mut payload = Vec::new();
+        put_bytes(item.digest.to_string().as_bytes(), &mut payload);
+        varint(item.size, &mut payload);
+        put_bytes(index_path(&item.path())?.as_bytes(), &mut payload);
+        let mut side = String::new();
+        side_lines(item, &mut side);
+        put_bytes(side.as_bytes(), &mut payload);
+        self.frame(ITEM, &payload)
+    }
+}
+
+/// Scans with the builder and streams the files to w as they are digested,
+/// for a client to build an index from with TreeIndexBuilder::from_remote.
+/// If the scan fails the client is sent the error before it is returned.
+pub fn serve(builder: TreeListBuilder, w: &mut dyn Write) -> Result<ScanStats> {
+    w.write_all(REMOTE_MAGIC)?;
+    w.write_all(&[REMOTE_VERSION])?;
+    let mut fw = FrameWriter { w, buf: Vec::new() };
+    let mut payload = Vec::new();
+    put_bytes(builder.header().to_string().trim_end().as_bytes(), &mut payload);
+    fw.frame(HEADER, &payload)?;
+
+    match builder.build_each(&mut |item| fw.item(item)) {
+        Ok(tl) => {
+            let s = tl.stats;
+            let mut payload = Vec::new();
+            for n in [s.dirs, s.files, s.bytes, s.skipped, s.errors, s.elapsed.as_millis() as u64] {
+                varint(n, &mut payload);
+            }
+            fw.frame(DONE, &payload)?;
+            fw.w.flush()?;
+            Ok(s)
+        },
+        Err(e) => {
+            let mut payload = Vec::new();
+            put_bytes(format!("{:?}", e).as_bytes(), &mut payload);
+            // the client may be what failed
+            let _ = fw.frame(ERROR, &payload).and_then(|_| Ok(fw.w.flush()?));
+            Err(e)
+        }
+    }
+}
+
+/// Serves a scan to every client that connects, one at a time. scan makes
+/// the builder for each one. A scan that fails is logged and the next
+/// client is served, only failing to accept a connection is returned.
+pub fn serve_tcp<'a, F>(listener: &TcpListener, scan: F) -> Result<()>
+    where F: Fn() -> Result<TreeListBuilder<'a>>
+{
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let peer = stream.peer_addr().map(|a| a.to_string()).unwrap_or_default();
+        info!("serving a scan to {}", peer);
+        let mut w = BufWriter::new(stream);
+        match scan().and_then(|builder| serve(builder, &mut w)) {
+            Ok(stats) => debug!("sent {} files to {}", stats.files, peer),
+            Err(e) => warn!("failed to serve {}: {:?}", peer, e)
+        }
+    }
+    Ok(())
+}
+
+// reads a remote scan stream and hands every file to the function as an
+// item, the same as reading an index does. returns the header and the
+// stats of the scan.
+pub(crate) fn read_remote<R: Read>(r: R, f: &mut dyn FnMut(IndexLine) -> Result<()>) -> Result<(TreeIndexHeader, ScanStats)> {
+    let mut r = BufReader::new(r);
+    let mut magic = [0u8; 5];
+    r.read_exact(&mut magic)?;
+    if !magic.starts_with(REMOTE_MAGIC) {
+        return Err(Error::InvalidFormat("not a remote scan stream".to_string()));
+    }
+    if magic[4] != REMOTE_VERSION {
+        return Err(Error::InvalidFormat(format!("unsupported remote scan version {}", magic[4])));
+    }
+
+    let mut header: Option<TreeIndexHeader> = None;
+    loop {
+        let kind = byte(&mut r)?;
+        let mut payload = Cursor::new(bytes(&mut r)?);
+        match (kind, &header) {
+            (HEADER, None) => header = Some(string(&mut payload)?.parse()?),
+            (ITEM, Some(h)) => {
+                let digest = string(&mut payload)?;
+                let size = read_varint(&mut payload)?;
+                let path = native_path(string(&mut payload)?, h);
+                let mut item = TreeItem::new(&digest, &path, size);
+                for line in string(&mut payload)?.lines() {
+                    side_line(&mut item, line)?;
+                }
+                f(IndexLine::Item(item))?;
+            },
+            (DONE, Some(h)) => {
+                let mut n = [0u64; 6];
+                for v in n.iter_mut() {
+                    *v = read_varint(&mut payload)?;
+                }
+                let stats = ScanStats {
+                    dirs: n[0],
+                    files: n[1],
+                    bytes: n[2],
+                    skipped: n[3],
+                    errors: n[4],
+                    elapsed: Duration::from_millis(n[5])
+                };
+                return Ok((h.clone(), stats));
+            },
+            (ERROR, _) => return Err(Error::RemoteError(string(&mut payload)?)),
+            _ => return Err(Error::InvalidFormat(format!("unexpected frame {} in remote scan", kind)))
+        }
+    }
+}
+
+fn put_bytes(b: &[u8], out: &mut Vec<u8>) {
+    varint(b.len() as u64, out);
+    out.extend_from_slice(b);
+}
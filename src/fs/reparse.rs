@@ -0,0 +1,138 @@
+use crate::fs::FileKind;
+use std::path::Path;
+
+/// Returns the kind of the path if it is a Windows reparse point a scan
+/// shouldn't treat like the directory or file it stands for: a junction, a
+/// volume mount point, or a cloud placeholder like a OneDrive file that is
+/// only online and would be downloaded by reading it. Symlinks, cloud files
+/// that are on the disk and everything else are None, and so is every path
+/// on other platforms.
+#[cfg(windows)]
+pub fn reparse_kind(path: &Path) -> Option<FileKind> {
+    let (attributes, tag) = find_data(path)?;
+    if attributes & (FILE_ATTRIBUTE_RECALL_ON_DATA_ACCESS | FILE_ATTRIBUTE_RECALL_ON_OPEN | FILE_ATTRIBUTE_OFFLINE) != 0 {
+        return Some(FileKind::Placeholder);
+    }
+    if attributes & FILE_ATTRIBUTE_REPARSE_POINT == 0 || tag != IO_REPARSE_TAG_MOUNT_POINT {
+        return None;
+    }
+    // junctions and mount points share a tag, a mount point's target is a
+    // volume rather than a directory
+    match std::fs::read_link(crate::fs::os_path(path)) {
+        Ok(target) if target.to_string_lossy().contains("Volume{") => Some(FileKind::MountPoint),
+        _ => Some(FileKind::Junction)
+    }
+}
+
+/// Returns the kind of the path if it is a Windows reparse point a scan
+/// shouldn't treat like the directory or file it stands for, there are none
+/// on other platforms
+#[cfg(not(windows))]
+pub fn reparse_kind(_path: &Path) -> Option<FileKind> {
+    None
+}
+
+#[cfg(windows)]
+const FILE_ATTRIBUTE_OFFLINE: u32 = 0x1000;
+#[cfg(windows)]
+const FILE_ATTRIBUTE_REPARSE_POINT: u32 = 0x400;
+#[cfg(windows)]
+const FILE_ATTRIBUTE_RECALL_ON_OPEN: u32 = 0x4_0000;
+#[cfg(windows)]
+const FILE_ATTRIBUTE_RECALL_ON_DATA_ACCESS: u32 = 0x40_0000;
+#[cfg(windows)]
+const IO_REPARSE_TAG_MOUNT_POINT: u32 = 0xA000_0003;
+
+// the attributes of the path itself and its reparse tag, std doesn't give
+// out the tag but FindFirstFileW has it in dwReserved0
+#[cfg(windows)]
+fn find_data(path: &Path) -> Option<(u32, u32)> {
+    use std::ffi::c_void;
+    use std::os::windows::ffi::OsStrExt;
+
+    #[repr(C)]
+    struct Win32FindDataW {
+        file_attributes: u32,
+        creation_time: [u32; 2],
+        last_access_time: [u32; 2],
+        last_write_time: [u32; 2],
+        file_size_high: u32,
+        file_size_low: u32,
+        reserved0: u32,
+        reserved1: u32,
+        file_name: [u16; 260],
+        alternate_file_name: [u16; 14]
+    }
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn FindFirstFileW(name: *const u16, data: *mut Win32FindDataW) -> *mut c_void;
+        fn FindClose(find: *mut c_void) -> i32;
+    }
+
+    let name: Vec<u16> = crate::fs::os_path(path).as_os_str().encode_wide().chain(Some(0)).collect();
+    let mut data = std::mem::MaybeUninit::<Win32FindDataW>::uninit();
+    let find = unsafe { FindFirstFileW(name.as_ptr(), data.as_mut_ptr()) };
+    if find as isize == -1 {
+        return None;
+    }
+    let data = unsafe {
+        FindClose(find);
+        data.assume_init()
+    };
+    Some((data.file_attributes, data.reserved0))
+}
+
+// the volume serial number and file index of the directory or file, what
+// tells a directory reached through a junction from one already scanned
+#[cfg(windows)]
+pub(crate) fn file_id(path: &Path) -> Option<(u64, u64)> {
+    use std::ffi::c_void;
+    use std::os::windows::{fs::OpenOptionsExt, io::AsRawHandle};
+
+    // directories can only be opened with this flag
+    const FILE_FLAG_BACKUP_SEMANTICS: u32 = 0x0200_0000;
+
+    #[repr(C)]
+    struct ByHandleFileInformation {
+        file_attributes: u32,
+        creation_time: [u32; 2],
+        last_access_time: [u32; 2],
+        last_write_time: [u32; 2],
+        volume_serial_number: u32,
+        file_size_high: u32,
+        file_size_low: u32,
+        number_of_links: u32,
+        file_index_high: u32,
+        file_index_low: u32
+    }
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetFileInformationByHandle(file: *mut c_void, info: *mut ByHandleFileInformation) -> i32;
+    }
+
+    let f = std::fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(FILE_FLAG_BACKUP_SEMANTICS)
+        .open(crate::fs::os_path(path))
+        .ok()?;
+    let mut info = std::mem::MaybeUninit::<ByHandleFileInformation>::uninit();
+    if unsafe { GetFileInformationByHandle(f.as_raw_handle(), info.as_mut_ptr()) } == 0 {
+        return None;
+    }
+    let info = unsafe { info.assume_init() };
+    Some((info.volume_serial_number as u64, (info.file_index_high as u64) << 32 | info.file_index_low as u64))
+}
+
+// the device and inode of the directory or file
+#[cfg(unix)]
+pub(crate) fn file_id(path: &Path) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    std::fs::metadata(crate::fs::os_path(path)).ok().map(|m| (m.dev(), m.ino()))
+}
+
+#[cfg(not(any(unix, windows)))]
+pub(crate) fn file_id(_path: &Path) -> Option<(u64, u64)> {
+    None
+}
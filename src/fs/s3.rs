@@ -0,0 +1,401 @@
+use crate::{
+    error::Error,
+    Result,
+    fs::{
+        multihash::hex,
+        stats::ScanCounters,
+        treeitem::FAST_CHUNK,
+        Algorithm,
+        Digest,
+        DigestEncoding,
+        DigestVariant,
+        ItemDigest,
+        TreeIndexHeader,
+        TreeItem,
+        TreeItemBuilder,
+        TreeList
+    },
+    units::UtcTime
+};
+use log::{debug, warn};
+use sha2::{Digest as _, Sha256};
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Instant, SystemTime};
+
+// the payload hash S3 accepts for requests whose body isn't signed
+const UNSIGNED_PAYLOAD: &str = "UNSIGNED-PAYLOAD";
+
+// an object in a bucket listing
+#[derive(Clone, Debug, Default)]
+struct S3Object {
+    key: String,
+    size: u64,
+    etag: String
+}
+
+/// Lists the objects in an S3 compatible bucket and digests them into a
+/// TreeList, so they can be indexed and deduped against local trees. The
+/// objects get paths of the form s3://bucket/key. Buckets are addressed
+/// path style, which AWS and the S3 compatible servers all understand.
+/// Objects with the same ETag and size are treated as unchanged copies of
+/// each other and only the first of them is downloaded. An ETag isn't a
+/// digest of the contents, objects uploaded in parts get one that depends on
+/// how they were split, so copies uploaded differently are still digested.
+/// Fast digests fetch just the head and tail of large objects with range
+/// requests and match the fast digests of the same files on disk.
+pub struct S3SourceBuilder {
+    endpoint: String,
+    region: String,
+    bucket: String,
+    prefix: String,
+    access_key: Option<String>,
+    secret_key: Option<String>,
+    session_token: Option<String>,
+    fast: bool,
+    algorithm: Algorithm,
+    encoding: DigestEncoding,
+    counters: ScanCounters,
+    error: Option<Error>
+}
+
+impl S3SourceBuilder {
+
+    pub fn new(bucket: &str) -> Self {
+        let mut error = None;
+        if bucket.is_empty() || bucket.contains('/') {
+            error = Some(Error::InvalidArgument(format!("invalid bucket name {}", bucket)));
+        }
+        S3SourceBuilder {
+            endpoint: "https://s3.amazonaws.com".to_string(),
+            region: "us-east-1".to_string(),
+            bucket: bucket.to_string(),
+            prefix: String::new(),
+            access_key: None,
+            secret_key: None,
+            session_token: None,
+            fast: false,
+            algorithm: Algorithm::default(),
+            encoding: DigestEncoding::default(),
+            counters: ScanCounters::default(),
+            error
+        }
+    }
+
+    /// The URL of the server, e.g. http://localhost:9000 for a local MinIO.
+    /// The default is AWS.
+    pub fn endpoint(mut self, endpoint: &str) -> Self {
+        if !endpoint.starts_with("http://") && !endpoint.starts_with("https://") {
+            self.error = Some(Error::InvalidArgument(format!("the endpoint {} isn't an http URL", endpoint)));
+        }
+        self.endpoint = endpoint.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// The region requests are signed for, us-east-1 by default
+    pub fn region(mut self, region: &str) -> Self {
+        self.region = region.to_string();
+        self
+    }
+
+    /// Only lists the keys that start with the prefix
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        self.prefix = prefix.to_string();
+        self
+    }
+
+    /// Signs the requests with the access key, without credentials the
+    /// bucket has to be public
+    pub fn credentials(mut self, access_key: &str, secret_key: &str) -> Self {
+        self.access_key = Some(access_key.to_string());
+        self.secret_key = Some(secret_key.to_string());
+        self
+    }
+
+    /// The token that goes with temporary credentials
+    pub fn session_token(mut self, token: &str) -> Self {
+        self.session_token = Some(token.to_string());
+        self
+    }
+
+    pub fn fast(mut self, fast: bool) -> Self {
+        self.fast = fast;
+        self
+    }
+
+    pub fn algorithm(mut self, algorithm: Algorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    pub fn encoding(mut self, encoding: DigestEncoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    // returns every object in the bucket under the prefix. keys that end in
+    // a slash are the folder markers some tools make and are left out.
+    fn list(&self) -> Result<Vec<S3Object>> {
+        let mut objects = Vec::new();
+        let mut token: Option<String> = None;
+        loop {
+            let mut query = vec![("list-type", "2".to_string())];
+            if !self.prefix.is_empty() {
+                query.push(("prefix", self.prefix.clone()));
+            }
+            if let Some(t) = &token {
+                query.push(("continuation-token", t.clone()));
+            }
+            let body = self.get("", &query, None)?.into_string().map_err(Error::IoError)?;
+            for contents in elements(&body, "Contents") {
+                let key = element(contents, "Key").map(unescape).unwrap_or_default();
+                if key.is_empty() || key.ends_with('/') {
+                    continue;
+                }
+                let size = element(contents, "Size").and_then(|s| s.parse().ok())
+                    .ok_or_else(|| Error::InvalidFormat(format!("object {} has no size", key)))?;
+                let etag = element(contents, "ETag").map(unescape).unwrap_or_default();
+                objects.push(S3Object { key, size, etag: etag.trim_matches('"').to_string() });
+            }
+            token = match element(&body, "IsTruncated") {
+                Some("true") => element(&body, "NextContinuationToken").map(unescape),
+                _ => None
+            };
+            if token.is_none() {
+                return Ok(objects);
+            }
+        }
+    }
+
+    pub fn build(mut self) -> Result<TreeList> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        let start = Instant::now();
+        let objects = self.list()?;
+        debug!("{} objects in {}", objects.len(), self.bucket);
+        let mut tl = TreeList::default();
+        let mut seen: HashMap<(String, u64), ItemDigest> = HashMap::new();
+        for object in &objects {
+            let path = Arc::new(PathBuf::from(format!("s3://{}/{}", self.bucket, object.key)));
+            let etag = (object.etag.clone(), object.size);
+            if let Some(digest) = seen.get(&etag).filter(|_| !object.etag.is_empty()) {
+                debug!("[SAME] {} has the etag {}", path.to_string_lossy(), object.etag);
+                tl.list.push(TreeItem::new(digest, &path, object.size));
+                self.counters.file(object.size);
+                continue;
+            }
+            match self.digest(object, &path) {
+                Ok(item) => {
+                    self.counters.file(item.size);
+                    seen.insert(etag, item.digest.clone());
+                    tl.list.push(item);
+                },
+                Err(e) => {
+                    warn!("failed to digest {}: {:?}", path.to_string_lossy(), e);
+                    self.counters.error();
+                }
+            }
+        }
+        tl.header = TreeIndexHeader::default();
+        tl.header.set_fast(self.fast);
+        tl.header.set_algorithm(self.algorithm);
+        tl.header.set_encoding(self.encoding);
+        tl.stats = self.counters.stats(start.elapsed());
+        Ok(tl)
+    }
+
+    // digests an object the same way a file of the same size is digested
+    fn digest(&self, object: &S3Object, path: &Arc<PathBuf>) -> Result<TreeItem> {
+        debug!("[DGST] {}", path.to_string_lossy());
+        if self.fast && object.size > FAST_CHUNK {
+            let head = self.range(&object.key, 0, FAST_CHUNK)?;
+            let tail = self.range(&object.key, object.size - (FAST_CHUNK - 1), object.size)?;
+            let mut hash = self.algorithm.hasher();
+            hash.update(&head);
+            hash.update(&tail);
+            hash.update(&object.size.to_le_bytes());
+            let digest = Digest::from_bytes(&hash.finalize())?;
+            let digest = ItemDigest::new(self.encoding, self.algorithm, DigestVariant::Fast, digest);
+            return Ok(TreeItem::new(digest, path, object.size));
+        }
+        let mut r = self.get(&object.key, &[], None)?.into_reader();
+        TreeItemBuilder::new()
+            .fast(self.fast)
+            .algorithm(self.algorithm)
+            .encoding(self.encoding)
+            .from_reader(&path.to_string_lossy(), &mut r)
+            .build()
+    }
+
+    // reads the bytes of an object from start up to end
+    fn range(&self, key: &str, start: u64, end: u64) -> Result<Vec<u8>> {
+        let range = format!("bytes={}-{}", start, end - 1);
+        let mut data = Vec::new();
+        self.get(key, &[], Some(&range))?.into_reader().read_to_end(&mut data)?;
+        if data.len() as u64 != end - start {
+            return Err(Error::InvalidFormat(format!("{} sent {} bytes for the range {}", key, data.len(), range)));
+        }
+        Ok(data)
+    }
+
+    // makes a signed GET request for a key in the bucket, or the bucket
+    // itself if the key is empty
+    fn get(&self, key: &str, query: &[(&str, String)], range: Option<&str>) -> Result<ureq::Response> {
+        let mut path = format!("/{}", self.bucket);
+        if !key.is_empty() {
+            path.push('/');
+            path.push_str(&uri_encode(key, false));
+        }
+        let mut query: Vec<(String, String)> = query.iter()
+            .map(|(k, v)| (uri_encode(k, true), uri_encode(v, true)))
+            .collect();
+        query.sort();
+        let query = query.iter().map(|(k, v)| format!("{}={}", k, v)).collect::<Vec<_>>().join("&");
+        let url = if query.is_empty() {
+            format!("{}{}", self.endpoint, path)
+        } else {
+            format!("{}{}?{}", self.endpoint, path, query)
+        };
+
+        let mut request = ureq::get(&url);
+        if let Some(r) = range {
+            request = request.set("range", r);
+        }
+        if let (Some(access), Some(secret)) = (&self.access_key, &self.secret_key) {
+            let now = amz_date(SystemTime::now());
+            let mut headers = vec![("host", self.host().to_string())];
+            if let Some(r) = range {
+                headers.push(("range", r.to_string()));
+            }
+            headers.push(("x-amz-content-sha256", UNSIGNED_PAYLOAD.to_string()));
+            headers.push(("x-amz-date", now.clone()));
+            if let Some(t) = &self.session_token {
+                headers.push(("x-amz-security-token", t.clone()));
+            }
+            let auth = authorization(access, secret, &self.region, &now, &path, &query, &headers)?;
+            for (k, v) in headers.iter().filter(|(k, _)| *k != "host" && *k != "range") {
+                request = request.set(k, v);
+            }
+            request = request.set("authorization", &auth);
+        }
+        request.call().map_err(|e| Error::HttpError(e.to_string()))
+    }
+
+    // the host and port of the endpoint, the way the host header has it
+    fn host(&self) -> &str {
+        let rest = self.endpoint.split_once("://").map(|(_, r)| r).unwrap_or(&self.endpoint);
+        rest.split('/').next().unwrap_or(rest)
+    }
+}
+
+// the AWS signature version 4 authorization header of a request. the headers
+// are the ones to sign, lowercase and in sorted order.
+fn authorization(access: &str, secret: &str, region: &str, now: &str, path: &str, query: &str,
+                 headers: &[(&str, String)]) -> Result<String> {
+    let date = &now[..8];
+    let scope = format!("{}/{}/s3/aws4_request", date, region);
+    let signed: Vec<&str> = headers.iter().map(|(k, _)| *k).collect();
+    let signed = signed.join(";");
+    let canonical_headers: String = headers.iter().map(|(k, v)| format!("{}:{}\n", k, v.trim())).collect();
+    let payload = headers.iter().find(|(k, _)| *k == "x-amz-content-sha256")
+        .map(|(_, v)| v.as_str())
+        .unwrap_or(UNSIGNED_PAYLOAD);
+    let canonical = format!("GET\n{}\n{}\n{}\n{}\n{}", path, query, canonical_headers, signed, payload);
+    let to_sign = format!("AWS4-HMAC-SHA256\n{}\n{}\n{}", now, scope, hex(&Sha256::digest(canonical.as_bytes())));
+
+    let mut key = hmac(format!("AWS4{}", secret).as_bytes(), date.as_bytes())?;
+    for part in [region, "s3", "aws4_request"] {
+        key = hmac(&key, part.as_bytes())?;
+    }
+    let signature = hex(&hmac(&key, to_sign.as_bytes())?);
+    Ok(format!("AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}", access, scope, signed, signature))
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+    let mut h = Algorithm::Sha256.keyed_hasher(key)?;
+    h.update(data);
+    Ok(h.finalize())
+}
+
+// percent encodes everything but the unreserved characters, slashes are
+// kept in paths
+fn uri_encode(s: &str, encode_slash: bool) -> String {
+    let mut out = String::new();
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => out.push(b as char),
+            b'/' if !encode_slash => out.push('/'),
+            _ => out.push_str(&format!("%{:02X}", b))
+        }
+    }
+    out
+}
+
+// the time as YYYYMMDDTHHMMSSZ in UTC
+fn amz_date(t: SystemTime) -> String {
+    let u = UtcTime::from(t);
+    format!("{:04}{:02}{:02}T{:02}{:02}{:02}Z", u.year, u.month, u.day, u.hour, u.minute, u.second)
+}
+
+// the contents of every element with the name, S3 listings are simple
+// enough that they don't need a real XML parser
+fn elements<'a>(xml: &'a str, name: &str) -> Vec<&'a str> {
+    let (open, close) = (format!("<{}>", name), format!("</{}>", name));
+    let mut found = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find(&open) {
+        let after = &rest[start + open.len()..];
+        match after.find(&close) {
+            Some(end) => {
+                found.push(&after[..end]);
+                rest = &after[end + close.len()..];
+            },
+            None => break
+        }
+    }
+    found
+}
+
+fn element<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    elements(xml, name).into_iter().next()
+}
+
+// replaces the XML entities with the characters they stand for
+fn unescape(s: &str) -> String {
+    let mut out = String::new();
+    let mut rest = s;
+    while let Some(i) = rest.find('&') {
+        out.push_str(&rest[..i]);
+        rest = &rest[i..];
+        let end = match rest.find(';') {
+            Some(end) => end,
+            None => break
+        };
+        let c = match &rest[1..end] {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            e if e.starts_with("#x") => u32::from_str_radix(&e[2..], 16).ok().and_then(char::from_u32),
+            e if e.starts_with('#') => e[1..].parse().ok().and_then(char::from_u32),
+            _ => None
+        };
+        match c {
+            Some(c) => {
+                out.push(c);
+                rest = &rest[end + 1..];
+            },
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
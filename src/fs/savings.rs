@@ -0,0 +1,190 @@
+use crate::{
+    error::Error,
+    Result,
+    fs::{shared_bytes, stats::json_string, TreeIndex},
+    units::{format_bytes, Units}
+};
+use log::debug;
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// Which size of a file to count. Logical is its length, allocated is what
+/// it takes up on disk and is only known for files indexed with metadata,
+/// the length is used for the others.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SizeMode {
+    #[default]
+    Logical,
+    Allocated
+}
+
+impl FromStr for SizeMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "logical" => Ok(SizeMode::Logical),
+            "allocated" => Ok(SizeMode::Allocated),
+            _ => Err(Error::InvalidArgument(format!("unknown size mode {}", s)))
+        }
+    }
+}
+
+impl Display for SizeMode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error>
+    {
+        match self {
+            SizeMode::Logical => write!(f, "logical"),
+            SizeMode::Allocated => write!(f, "allocated")
+        }
+    }
+}
+
+/// The dupes in one directory and the bytes removing them would free.
+/// Shared is the bytes the dupes already share with the copies that are
+/// kept, through reflinks or file system de-duplication.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DirSavings {
+    pub files: u64,
+    pub bytes: u64,
+    pub allocated: u64,
+    pub shared: u64
+}
+
+impl DirSavings {
+
+    /// Returns the bytes of the dupes counted the given way
+    pub fn bytes(&self, mode: SizeMode) -> u64 {
+        match mode {
+            SizeMode::Logical => self.bytes,
+            SizeMode::Allocated => self.allocated
+        }
+    }
+
+    /// Returns the bytes removing the dupes would actually free
+    pub fn saved(&self, mode: SizeMode) -> u64 {
+        self.bytes(mode).saturating_sub(self.shared)
+    }
+}
+
+/// The exact number of bytes de-duping an index would free. The first path
+/// of each group is the one kept, the dupes are counted against the
+/// directories they are in. Bytes are the lengths of the dupes, allocated
+/// is the disk space they take up, which is much less for sparse files
+/// like VM images. The mode picks which one is displayed. Shared is only
+/// known once the files have been probed, see probe_shared.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SavingsReport {
+    pub files: u64,
+    pub bytes: u64,
+    pub allocated: u64,
+    pub shared: u64,
+    pub dirs: BTreeMap<PathBuf, DirSavings>,
+    pub mode: SizeMode
+}
+
+impl SavingsReport {
+
+    /// Asks the file system which bytes of each dupe are already stored in
+    /// the same place as the kept copy, those aren't freed by removing the
+    /// dupe. The index paths have to be valid from the current directory.
+    /// Files whose extents can't be read are counted as not shared.
+    pub fn probe_shared(mut self, ti: &TreeIndex) -> Self {
+        for entry in ti.idx.values() {
+            let path = entry.item.path();
+            for d in &entry.dupes {
+                let dupe = d.path();
+                let shared = match shared_bytes(&path, &dupe) {
+                    Ok(shared) => shared.min(entry.allocated(d)),
+                    Err(e) => {
+                        debug!("failed to compare the extents of {}: {}", dupe.to_string_lossy(), e);
+                        0
+                    }
+                };
+                let dir = dupe.parent().map(|p| p.to_path_buf()).unwrap_or_default();
+                self.dirs.entry(dir).or_default().shared += shared;
+                self.shared += shared;
+            }
+        }
+        self
+    }
+
+    /// Returns the bytes removing the dupes would actually free, counted the
+    /// way the mode says
+    pub fn saved(&self) -> u64 {
+        let bytes = match self.mode {
+            SizeMode::Logical => self.bytes,
+            SizeMode::Allocated => self.allocated
+        };
+        bytes.saturating_sub(self.shared)
+    }
+
+    /// Sets which size the report displays and orders directories by
+    pub fn mode(mut self, mode: SizeMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Returns the directories ordered by the bytes they would free, largest
+    /// first
+    pub fn largest_dirs(&self) -> Vec<(&PathBuf, &DirSavings)> {
+        let mut dirs: Vec<_> = self.dirs.iter().collect();
+        dirs.sort_by(|a, b| b.1.saved(self.mode).cmp(&a.1.saved(self.mode)).then_with(|| a.0.cmp(b.0)));
+        dirs
+    }
+
+    /// Returns the report as a JSON object with the directories in path order
+    pub fn to_json(&self) -> String {
+        let mut s = format!("{{\"files\":{},\"bytes\":{},\"allocated\":{},\"shared\":{},\"dirs\":[",
+                            self.files, self.bytes, self.allocated, self.shared);
+        for (i, (d, savings)) in self.dirs.iter().enumerate() {
+            let _ = write!(s, "{}{{\"path\":{},\"files\":{},\"bytes\":{},\"allocated\":{},\"shared\":{}}}",
+                           if i > 0 { "," } else { "" }, json_string(&d.to_string_lossy()),
+                           savings.files, savings.bytes, savings.allocated, savings.shared);
+        }
+        s.push_str("]}");
+        s
+    }
+}
+
+impl From<&TreeIndex> for SavingsReport {
+    fn from(ti: &TreeIndex) -> Self {
+        let mut report = Self::default();
+        for entry in ti.idx.values() {
+            for d in &entry.dupes {
+                let allocated = entry.allocated(d);
+                let dir = d.path().parent().map(|p| p.to_path_buf()).unwrap_or_default();
+                let savings = report.dirs.entry(dir).or_default();
+                savings.files += 1;
+                savings.bytes += entry.item.size;
+                savings.allocated += allocated;
+                report.files += 1;
+                report.bytes += entry.item.size;
+                report.allocated += allocated;
+            }
+        }
+        report
+    }
+}
+
+// the total in exact bytes and human readable units, allocated bytes are
+// labeled as such and bytes already shared are left out of the total
+impl Display for SavingsReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error>
+    {
+        let label = match self.mode {
+            SizeMode::Logical => "",
+            SizeMode::Allocated => " allocated"
+        };
+        let saved = self.saved();
+        writeln!(f, "Total saved {}{} bytes ({}) in {} files", saved, label,
+                 format_bytes(saved, Units::Binary), self.files)?;
+        if self.shared > 0 {
+            writeln!(f, "{} bytes ({}) are already shared with the kept copies", self.shared,
+                     format_bytes(self.shared, Units::Binary))?;
+        }
+        Ok(())
+    }
+}
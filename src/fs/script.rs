@@ -0,0 +1,168 @@
+use crate::{
+    error::Error,
+    Result,
+    fs::{Action, ActionRecord}
+};
+use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// The shell a script of actions is written for
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ScriptShell {
+    #[default]
+    Posix,
+    PowerShell
+}
+
+impl FromStr for ScriptShell {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "posix" | "sh" => Ok(ScriptShell::Posix),
+            "powershell" | "pwsh" => Ok(ScriptShell::PowerShell),
+            _ => Err(Error::InvalidArgument(format!("unknown script shell {}", s)))
+        }
+    }
+}
+
+impl Display for ScriptShell {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error>
+    {
+        match self {
+            ScriptShell::Posix => write!(f, "posix"),
+            ScriptShell::PowerShell => write!(f, "powershell")
+        }
+    }
+}
+
+/// A ScriptWriter turns actions into the shell commands that carry them
+/// out, so they can be reviewed and run later instead of being done now.
+/// The script stops at the first command that fails.
+pub struct ScriptWriter {
+    w: Box<dyn Write>,
+    shell: ScriptShell,
+    dirs: HashSet<PathBuf>
+}
+
+impl ScriptWriter {
+
+    /// Starts the script on w
+    pub fn new(mut w: Box<dyn Write>, shell: ScriptShell) -> Result<Self> {
+        match shell {
+            ScriptShell::Posix => writeln!(w, "#!/bin/sh\nset -e")?,
+            ScriptShell::PowerShell => writeln!(w, "$ErrorActionPreference = 'Stop'")?
+        }
+        Ok(Self { w, shell, dirs: HashSet::new() })
+    }
+
+    /// Writes the commands for the action. De-duping can't be done from a
+    /// script and is written as a comment.
+    pub fn action(&mut self, record: &ActionRecord) -> Result<()> {
+        let src = self.quote(&record.src)?;
+        let dst = match &record.dst {
+            Some(d) => Some(self.quote(d)?),
+            None => None
+        };
+        match (record.action, dst) {
+            (Action::Copy, Some(dst)) => {
+                self.parent(record.dst.as_deref())?;
+                match self.shell {
+                    ScriptShell::Posix => writeln!(self.w, "cp -p -- {} {}", src, dst)?,
+                    ScriptShell::PowerShell => writeln!(self.w, "Copy-Item -LiteralPath {} -Destination {}", src, dst)?
+                }
+            },
+            (Action::Move, Some(dst)) => {
+                self.parent(record.dst.as_deref())?;
+                match self.shell {
+                    ScriptShell::Posix => writeln!(self.w, "mv -- {} {}", src, dst)?,
+                    ScriptShell::PowerShell => writeln!(self.w, "Move-Item -LiteralPath {} -Destination {}", src, dst)?
+                }
+            },
+            (Action::Hardlink, Some(dst)) => match self.shell {
+                ScriptShell::Posix => writeln!(self.w, "ln -f -- {} {}", src, dst)?,
+                ScriptShell::PowerShell => writeln!(self.w, "New-Item -ItemType HardLink -Force -Path {} -Target {} | Out-Null", dst, src)?
+            },
+            (Action::Delete, _) => match self.shell {
+                ScriptShell::Posix => writeln!(self.w, "rm -f -- {}", src)?,
+                ScriptShell::PowerShell => writeln!(self.w, "Remove-Item -LiteralPath {} -Force", src)?
+            },
+            (Action::Rmdir, _) => match self.shell {
+                ScriptShell::Posix => writeln!(self.w, "rmdir -- {}", src)?,
+                ScriptShell::PowerShell => writeln!(self.w, "Remove-Item -LiteralPath {}", src)?
+            },
+            (Action::Mkdir, _) => self.mkdir(&record.src)?,
+            (action, _) => {
+                let mut line = format!("{} {}", action, record.src.to_string_lossy());
+                if let Some(d) = &record.dst {
+                    line = format!("{} {}", line, d.to_string_lossy());
+                }
+                // a path with a newline in it would end the comment
+                writeln!(self.w, "# can't be done from a script: {}", line.replace(['\n', '\r'], " "))?
+            }
+        }
+        Ok(())
+    }
+
+    /// Flushes the script
+    pub fn finish(mut self) -> Result<()> {
+        self.w.flush()?;
+        Ok(())
+    }
+
+    // makes the directory a file goes in, once
+    fn parent(&mut self, path: Option<&Path>) -> Result<()> {
+        match path.and_then(|p| p.parent()) {
+            Some(p) if !p.as_os_str().is_empty() => self.mkdir(p),
+            _ => Ok(())
+        }
+    }
+
+    fn mkdir(&mut self, dir: &Path) -> Result<()> {
+        if !self.dirs.insert(dir.to_path_buf()) {
+            return Ok(());
+        }
+        let dir = self.quote(dir)?;
+        match self.shell {
+            ScriptShell::Posix => writeln!(self.w, "mkdir -p -- {}", dir)?,
+            ScriptShell::PowerShell => writeln!(self.w, "New-Item -ItemType Directory -Force -Path {} | Out-Null", dir)?
+        }
+        Ok(())
+    }
+
+    // a path that isn't unicode can't be written in the script without
+    // naming some other file
+    fn quote(&self, path: &Path) -> Result<String> {
+        let p = path.to_str()
+            .ok_or_else(|| Error::InvalidArgument(format!("{} can't be written to a script", path.to_string_lossy())))?;
+        Ok(match self.shell {
+            ScriptShell::Posix => posix_quote(p),
+            ScriptShell::PowerShell => powershell_quote(p)
+        })
+    }
+}
+
+/// Quotes the string for a POSIX shell. Everything inside single quotes is
+/// literal, a single quote is closed, escaped and opened again.
+pub fn posix_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+/// Quotes the string for PowerShell, a single quote inside single quotes is
+/// doubled. PowerShell also takes the curly single quotes as quotes so they
+/// are doubled too.
+pub fn powershell_quote(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('\'');
+    for c in s.chars() {
+        if matches!(c, '\'' | '\u{2018}' | '\u{2019}' | '\u{201a}' | '\u{201b}') {
+            out.push(c);
+        }
+        out.push(c);
+    }
+    out.push('\'');
+    out
+}
@@ -0,0 +1,211 @@
+use crate::{
+    error::Error,
+    Result,
+    fs::{
+        IndexStats,
+        IndexUpdate,
+        TreeItemDupes,
+        TreeWatcher
+    }
+};
+use log::{debug, warn};
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::thread;
+
+// the JSON-RPC 2.0 error codes
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const INTERNAL_ERROR: i64 = -32603;
+
+// the longest request a client can send, a client sending more is answered
+// with an error and hung up on rather than read into memory
+const MAX_REQUEST: u64 = 1024 * 1024;
+
+/// An IndexService keeps an index warm with a TreeWatcher and answers
+/// queries about it over a unix socket, so other tools get answers without
+/// scanning the tree again. Requests and responses are JSON-RPC 2.0, one
+/// object per line. The methods are:
+///
+/// * `lookup` with a `digest`, the entries whose digest starts with it
+/// * `dupes` with a `path` as it is in the index, the entry with the path
+///   in it or null
+/// * `stats` with an optional `top`, the IndexStats of the index
+///
+/// Entries are objects with the digest, the size and the paths with the
+/// primary first. Clones share the same watcher.
+#[derive(Clone)]
+pub struct IndexService {
+    watcher: Arc<RwLock<TreeWatcher>>
+}
+
+impl IndexService {
+
+    pub fn new(watcher: TreeWatcher) -> Self {
+        Self {
+            watcher: Arc::new(RwLock::new(watcher))
+        }
+    }
+
+    /// Blocks until something in the tree changes, applies the changes to
+    /// the index and returns them, like TreeWatcher::wait. Queries are
+    /// answered while it waits, they only hold off while the changed files
+    /// are digested.
+    pub fn wait(&self) -> Result<Vec<IndexUpdate>> {
+        let changed = self.watcher.read().unwrap().changes()?;
+        self.watcher.write().unwrap().apply(changed)
+    }
+
+    /// Answers one request, returns the response or None for a
+    /// notification, which is a request without an id
+    pub fn handle(&self, request: &str) -> Option<String> {
+        let request: Value = match serde_json::from_str(request) {
+            Ok(request) => request,
+            Err(e) => return Some(error(Value::Null, PARSE_ERROR, &e.to_string()))
+        };
+        let id = request.get("id").cloned();
+        let response = match self.call(&request) {
+            Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}).to_string(),
+            Err((code, message)) => error(id.clone().unwrap_or(Value::Null), code, &message)
+        };
+        id.map(|_| response)
+    }
+
+    /// Answers the clients that connect to the listener, each on its own
+    /// thread. Only failing to accept a connection is returned.
+    pub fn serve(&self, listener: &UnixListener) -> Result<()> {
+        for stream in listener.incoming() {
+            let service = self.clone();
+            let stream = stream?;
+            thread::spawn(move || {
+                if let Err(e) = service.client(stream) {
+                    warn!("failed to answer a client: {:?}", e);
+                }
+            });
+        }
+        Ok(())
+    }
+
+    // answers the requests from one client until it hangs up
+    fn client(&self, stream: UnixStream) -> Result<()> {
+        debug!("client connected");
+        let mut w = BufWriter::new(stream.try_clone()?);
+        let mut r = BufReader::new(stream);
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            if r.by_ref().take(MAX_REQUEST + 1).read_until(b'\n', &mut line)? == 0 {
+                break;
+            }
+            if line.len() as u64 > MAX_REQUEST {
+                warn!("hanging up on a client that sent a request over {} bytes", MAX_REQUEST);
+                writeln!(w, "{}", error(Value::Null, INVALID_REQUEST, "the request is too long"))?;
+                w.flush()?;
+                return Ok(());
+            }
+            let response = match std::str::from_utf8(&line) {
+                Ok(line) if line.trim().is_empty() => continue,
+                Ok(line) => self.handle(line),
+                Err(e) => Some(error(Value::Null, PARSE_ERROR, &e.to_string()))
+            };
+            if let Some(response) = response {
+                writeln!(w, "{}", response)?;
+                w.flush()?;
+            }
+        }
+        debug!("client hung up");
+        Ok(())
+    }
+
+    // runs the method, a failure is the JSON-RPC error code and message
+    fn call(&self, request: &Value) -> std::result::Result<Value, (i64, String)> {
+        let method = request.get("method").and_then(Value::as_str)
+            .ok_or((INVALID_REQUEST, "the request has no method".to_string()))?;
+        let params = request.get("params").unwrap_or(&Value::Null);
+        let watcher = self.watcher.read()
+            .map_err(|_| (INTERNAL_ERROR, "the index was left broken by a failed update".to_string()))?;
+        match method {
+            "lookup" => {
+                let digest = param(params, "digest")?;
+                Ok(watcher.index().find_prefix(digest).into_iter().map(entry).collect())
+            },
+            "dupes" => {
+                let path = param(params, "path")?;
+                Ok(watcher.entry(Path::new(path)).map(entry).unwrap_or(Value::Null))
+            },
+            "stats" => {
+                let top = params.get("top").and_then(Value::as_u64).unwrap_or(0) as usize;
+                serde_json::from_str(&IndexStats::new(watcher.index(), top).to_json())
+                    .map_err(|e| (INTERNAL_ERROR, e.to_string()))
+            },
+            _ => Err((METHOD_NOT_FOUND, format!("unknown method {}", method)))
+        }
+    }
+}
+
+/// The unix socket an IndexService listens on. The socket file is removed
+/// when it is dropped so a service that stops doesn't leave it behind.
+pub struct ServiceSocket {
+    listener: UnixListener,
+    path: PathBuf
+}
+
+impl ServiceSocket {
+
+    pub fn listener(&self) -> &UnixListener {
+        &self.listener
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for ServiceSocket {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            warn!("failed to remove socket {}: {}", self.path.to_string_lossy(), e);
+        }
+    }
+}
+
+/// Binds the unix socket for an IndexService to listen on. A socket left
+/// behind by a service that is gone is removed first, one that a service
+/// is still listening on and any other kind of file are errors.
+pub fn bind_socket(path: &Path) -> Result<ServiceSocket> {
+    if let Ok(md) = path.symlink_metadata() {
+        if !md.file_type().is_socket() {
+            return Err(Error::InvalidArgument(format!("{} is not a socket", path.to_string_lossy())));
+        }
+        if UnixStream::connect(path).is_ok() {
+            return Err(Error::InvalidArgument(format!("a service is already listening on {}", path.to_string_lossy())));
+        }
+        std::fs::remove_file(path)?;
+    }
+    Ok(ServiceSocket { listener: UnixListener::bind(path)?, path: path.to_path_buf() })
+}
+
+// a named string parameter
+fn param<'a>(params: &'a Value, name: &str) -> std::result::Result<&'a str, (i64, String)> {
+    params.get(name).and_then(Value::as_str)
+        .ok_or((INVALID_PARAMS, format!("the request needs a {}", name)))
+}
+
+// an entry as the digest, the size and the paths with the primary first
+fn entry(e: &TreeItemDupes) -> Value {
+    let paths: Vec<String> = std::iter::once(e.item.item_path())
+        .chain(e.dupes.iter())
+        .map(|p| p.path().to_string_lossy().into_owned())
+        .collect();
+    json!({"digest": e.item.digest.to_string(), "size": e.item.size, "paths": paths})
+}
+
+fn error(id: Value, code: i64, message: &str) -> String {
+    json!({"jsonrpc": "2.0", "id": id, "error": {"code": code, "message": message}}).to_string()
+}
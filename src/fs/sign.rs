@@ -0,0 +1,175 @@
+use crate::{
+    error::Error,
+    Result,
+    fs::multihash::{hex, unhex}
+};
+use blake2b_simd::{Params, State};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use rand_core::OsRng;
+use std::convert::TryInto;
+use std::io::{self, BufRead, BufReader, Read, Write};
+
+// the signature trailer is a comment line so readers that don't check
+// signatures skip it
+const SIGNATURE_TAG: &str = "#signature ed25519";
+
+// indexes are signed over a Blake2b-512 digest of their lines so they can be
+// signed and checked while streaming
+fn content_hash() -> State {
+    Params::new().hash_length(64).to_state()
+}
+
+/// Generates a new random signing key
+pub fn generate_key() -> SigningKey {
+    SigningKey::generate(&mut OsRng)
+}
+
+/// Parses a signing key from 64 hex characters, whitespace around them is
+/// ignored
+pub fn parse_signing_key(s: &str) -> Result<SigningKey> {
+    Ok(SigningKey::from_bytes(&key_bytes(s)?))
+}
+
+/// Parses a public key from 64 hex characters, whitespace around them is
+/// ignored
+pub fn parse_verifying_key(s: &str) -> Result<VerifyingKey> {
+    VerifyingKey::from_bytes(&key_bytes(s)?)
+        .map_err(|e| Error::SignatureError(e.to_string()))
+}
+
+/// Returns the key as hex for writing to a key file
+pub fn key_hex(key: &[u8; 32]) -> String {
+    hex(key)
+}
+
+fn key_bytes(s: &str) -> Result<[u8; 32]> {
+    unhex(s.trim())
+        .and_then(|k| k.try_into().ok())
+        .ok_or_else(|| Error::SignatureError("keys must be 64 hex characters".to_string()))
+}
+
+/// A SigningWriter passes everything through to the inner writer and
+/// appends a signature trailer line when it is finished. Anything written
+/// must be whole lines.
+pub struct SigningWriter<'a> {
+    inner: &'a mut dyn Write,
+    key: &'a SigningKey,
+    hash: State
+}
+
+impl<'a> SigningWriter<'a> {
+
+    pub fn new(inner: &'a mut dyn Write, key: &'a SigningKey) -> Self {
+        Self {
+            inner,
+            key,
+            hash: content_hash()
+        }
+    }
+
+    /// Signs what was written and writes the trailer
+    pub fn finish(self) -> Result<()> {
+        let sig = self.key.sign(self.hash.finalize().as_bytes());
+        writeln!(self.inner, "{} {} {}", SIGNATURE_TAG,
+                 hex(self.key.verifying_key().as_bytes()),
+                 hex(&sig.to_bytes()))?;
+        self.inner.flush()?;
+        Ok(())
+    }
+}
+
+impl<'a> Write for SigningWriter<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hash.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// A VerifyingReader passes the lines of a signed index through to its
+/// reader while it hashes them. Once the index has been read, finish checks
+/// the signature trailer against the key. Lines are passed on with \n line
+/// endings so a file that got \r\n endings along the way still verifies.
+pub struct VerifyingReader<R: Read> {
+    inner: BufReader<R>,
+    key: VerifyingKey,
+    hash: State,
+    line: Vec<u8>,
+    pos: usize,
+    signature: Option<String>,
+    trailing: bool
+}
+
+impl<R: Read> VerifyingReader<R> {
+
+    pub fn new(inner: R, key: VerifyingKey) -> Self {
+        Self {
+            inner: BufReader::new(inner),
+            key,
+            hash: content_hash(),
+            line: Vec::new(),
+            pos: 0,
+            signature: None,
+            trailing: false
+        }
+    }
+
+    /// Checks the signature, this fails if the index wasn't signed, was
+    /// signed by a different key, or was changed after it was signed
+    pub fn finish(mut self) -> Result<()> {
+        // drain anything the caller didn't read
+        io::copy(&mut self, &mut io::sink())?;
+
+        if self.trailing {
+            return Err(Error::SignatureError("lines after the signature".to_string()));
+        }
+        let sig = match &self.signature {
+            Some(s) => s.split_whitespace().last().unwrap_or(""),
+            None => return Err(Error::SignatureError("the index isn't signed".to_string()))
+        };
+        let bytes: [u8; 64] = unhex(sig)
+            .and_then(|s| s.try_into().ok())
+            .ok_or_else(|| Error::SignatureError("invalid signature".to_string()))?;
+        self.key
+            .verify(self.hash.finalize().as_bytes(), &Signature::from_bytes(&bytes))
+            .map_err(|_| Error::SignatureError("the signature doesn't match".to_string()))
+    }
+
+    // loads the next line that is part of the signed content
+    fn fill(&mut self) -> io::Result<bool> {
+        loop {
+            let mut line = String::new();
+            if self.inner.read_line(&mut line)? == 0 {
+                return Ok(false);
+            }
+            let trimmed = line.trim_end_matches(['\r', '\n']);
+            if trimmed.starts_with(SIGNATURE_TAG) {
+                self.signature = Some(trimmed.to_string());
+                continue;
+            }
+            if self.signature.is_some() {
+                self.trailing = true;
+            }
+            self.line = format!("{}\n", trimmed).into_bytes();
+            self.pos = 0;
+            self.hash.update(&self.line);
+            return Ok(true);
+        }
+    }
+}
+
+impl<R: Read> Read for VerifyingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.line.len() && !self.fill()? {
+            return Ok(0);
+        }
+        let n = buf.len().min(self.line.len() - self.pos);
+        buf[..n].copy_from_slice(&self.line[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
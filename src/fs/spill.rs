@@ -0,0 +1,303 @@
+use crate::{
+    error::Error,
+    Result,
+    fs::{
+        FileMetadata,
+        ItemDigest,
+        ItemPath,
+        PathArena,
+        PathId,
+        TreeItem,
+        TreeItemDupes,
+        treeitem::unescape_path
+    }
+};
+use log::{debug, warn};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Lines, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+// a rough guess at what one path costs in the map beyond its bytes, this
+// covers the hash map slot, the Arc and the Vec entries
+const ENTRY_OVERHEAD: u64 = 96;
+
+// a rough guess at what the metadata of a file costs
+const METADATA_COST: u64 = 64;
+
+/// A MemoryUsage is given to TreeIndexBuilder::memory_usage to find out
+/// roughly how much memory the index took at its peak while it was built.
+/// Clones share the same count.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryUsage {
+    peak: Arc<AtomicU64>
+}
+
+impl MemoryUsage {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The most bytes the index took, estimated from its paths, digests,
+    /// chunk digests and metadata
+    pub fn peak(&self) -> u64 {
+        self.peak.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn record(&self, used: u64) {
+        self.peak.fetch_max(used, Ordering::Relaxed);
+    }
+}
+
+/// The estimated bytes an index entry takes in memory
+pub(crate) fn entry_cost(entry: &TreeItemDupes) -> u64 {
+    let dupes: u64 = entry.dupes.iter().map(|d| path_cost(&d.path())).sum();
+    let metadata = entry.dupe_metadata.len() as u64 * METADATA_COST;
+    item_cost(&entry.item) + digest_cost(&entry.item.digest) + dupes + metadata
+}
+
+// digests are kept as bytes in the item, only the ones kept as text take
+// more memory
+fn digest_cost(digest: &ItemDigest) -> u64 {
+    match digest {
+        ItemDigest::Text(s) => s.len() as u64,
+        _ => 0
+    }
+}
+
+fn path_cost(path: &Path) -> u64 {
+    ENTRY_OVERHEAD + path.as_os_str().len() as u64
+}
+
+fn item_cost(item: &TreeItem) -> u64 {
+    path_cost(&item.path()) + detail_cost(item)
+}
+
+// the chunk digests and metadata are only kept until the first shard is
+// written but they count against the budget up to then
+fn detail_cost(item: &TreeItem) -> u64 {
+    let mut cost = 0;
+    if item.metadata.is_some() {
+        cost += METADATA_COST;
+    }
+    if let Some(c) = &item.chunks {
+        cost += c.root.len() as u64 + c.leaves.iter().map(|l| l.len() as u64 + 8).sum::<u64>();
+    }
+    cost
+}
+
+// keeps shard names unique when several indexes spill at the same time
+static SPILL_ID: AtomicUsize = AtomicUsize::new(0);
+
+// an entry held by a Spill, the item's path is taken out and kept in the
+// arena with the dupes' paths
+struct SpillEntry {
+    item: TreeItem,
+    path: PathId,
+    dupes: Vec<(PathId, Option<Arc<FileMetadata>>)>
+}
+
+// A Spill collects index entries in memory until they exceed the byte budget
+// and then writes them to a shard file sorted by digest and size. When all
+// of the items are in, the shards are merged back together in that order.
+// The paths are interned while they are in memory so the directories they
+// share are only counted once. The shard files are removed when the Spill
+// is dropped.
+pub(crate) struct Spill {
+    budget: u64,
+    dir: PathBuf,
+    with_dupes: bool,
+    id: usize,
+    used: u64,
+    peak: u64,
+    idx: HashMap<(ItemDigest, u64), SpillEntry>,
+    paths: PathArena,
+    shards: Vec<PathBuf>
+}
+
+impl Spill {
+
+    pub(crate) fn new(budget: u64, dir: PathBuf, with_dupes: bool) -> Self {
+        Self {
+            budget,
+            dir,
+            with_dupes,
+            id: SPILL_ID.fetch_add(1, Ordering::Relaxed),
+            used: 0,
+            peak: 0,
+            idx: HashMap::new(),
+            paths: PathArena::new(),
+            shards: Vec::new()
+        }
+    }
+
+    pub(crate) fn insert(&mut self, mut item: TreeItem) -> Result<()> {
+        let cost = ENTRY_OVERHEAD + detail_cost(&item);
+        // the items are all from one index so the algorithm isn't needed
+        let key = (item.digest.clone(), item.size);
+        match self.idx.get_mut(&key) {
+            Some(entry) => {
+                if self.with_dupes {
+                    entry.dupes.push((self.paths.intern(&item.path())?, item.metadata));
+                    self.used += cost;
+                }
+            },
+            None => {
+                self.used += cost + digest_cost(&item.digest);
+                let path = self.paths.intern(&item.path())?;
+                item.path = ItemPath::default();
+                self.idx.insert(key, SpillEntry { item, path, dupes: Vec::new() });
+            }
+        }
+        let used = self.used + self.paths.memory_estimate();
+        self.peak = self.peak.max(used);
+        if used > self.budget {
+            self.write_shard()?;
+        }
+        Ok(())
+    }
+
+    /// The most bytes the entries took in memory at once
+    pub(crate) fn peak(&self) -> u64 {
+        self.peak
+    }
+
+    /// Whether anything was written to a shard, until then the entries
+    /// keep their chunk digests and metadata
+    pub(crate) fn spilled(&self) -> bool {
+        !self.shards.is_empty()
+    }
+
+    /// Hands every entry to the function in digest and size order
+    pub(crate) fn finish(mut self, f: &mut dyn FnMut(TreeItemDupes) -> Result<()>) -> Result<()> {
+        // everything fit in memory
+        if self.shards.is_empty() {
+            for entry in self.drain() {
+                f(entry)?;
+            }
+            return Ok(());
+        }
+
+        self.write_shard()?;
+        debug!("merging {} index shards", self.shards.len());
+
+        // the shard number and then the line number in the shard break ties
+        // ahead of the path, so the paths of an entry come out in the order
+        // they went in and the first path seen stays the main item, the same
+        // as when the index is built in memory
+        let mut shards = Vec::new();
+        let mut heap = BinaryHeap::new();
+        for (n, path) in self.shards.iter().enumerate() {
+            let mut lines = BufReader::new(File::open(path)?).lines();
+            if let Some(line) = next_line(&mut lines)? {
+                heap.push(Reverse((line.0, line.1, n, 0usize, line.2)));
+            }
+            shards.push(lines);
+        }
+
+        let mut current: Option<TreeItemDupes> = None;
+        while let Some(Reverse((digest, size, n, seq, path))) = heap.pop() {
+            if let Some(line) = next_line(&mut shards[n])? {
+                heap.push(Reverse((line.0, line.1, n, seq + 1, line.2)));
+            }
+
+            let path = ItemPath::new(&unescape_path(path.as_bytes())?);
+            let digest = ItemDigest::from(digest.as_str());
+            match current.as_mut() {
+                Some(entry) if entry.item.digest == digest && entry.item.size == size => {
+                    if self.with_dupes {
+                        entry.push(path);
+                    }
+                },
+                _ => {
+                    if let Some(entry) = current.take() {
+                        f(entry)?;
+                    }
+                    current = Some(TreeItemDupes::new(&digest, &path, size));
+                }
+            }
+        }
+        if let Some(entry) = current.take() {
+            f(entry)?;
+        }
+        Ok(())
+    }
+
+    fn write_shard(&mut self) -> Result<()> {
+        if self.idx.is_empty() {
+            return Ok(());
+        }
+        let path = self.dir.join(format!("treeindex-{}-{}-{}.shard", process::id(), self.id, self.shards.len()));
+        debug!("[SPIL] {} entries to {}", self.idx.len(), path.to_string_lossy());
+
+        let entries = self.drain();
+
+        // push the path before writing so a failed write is still cleaned up
+        self.shards.push(path.clone());
+        // shards only hold item lines, chunk digests and metadata aren't kept
+        let mut w = BufWriter::new(File::create(&path)?);
+        for entry in entries {
+            write!(w, "{}", TreeItem::new(&entry.item.digest, entry.item.item_path(), entry.item.size))?;
+            for d in &entry.dupes {
+                write!(w, "{}", TreeItem::new(&entry.item.digest, d, entry.item.size))?;
+            }
+        }
+        w.flush()?;
+        Ok(())
+    }
+
+    // empties the spill and puts the entries back together, sorted by
+    // digest and size
+    fn drain(&mut self) -> Vec<TreeItemDupes> {
+        let paths = &self.paths;
+        let mut entries: Vec<TreeItemDupes> = self.idx.drain()
+            .map(|(_, e)| {
+                let mut item = e.item;
+                item.path = ItemPath::new(&paths.path(e.path));
+                let mut entry = TreeItemDupes::from(&item);
+                for (id, metadata) in e.dupes {
+                    let path = ItemPath::new(&paths.path(id));
+                    if let Some(m) = metadata {
+                        entry.dupe_metadata.insert(path.clone(), m);
+                    }
+                    entry.dupes.push(path);
+                }
+                entry
+            })
+            .collect();
+        // in the order of the text of the digests, which is the order the
+        // shards are merged in
+        entries.sort_by_cached_key(|e| (e.item.digest.to_string(), e.item.size));
+        self.paths.clear();
+        self.used = 0;
+        entries
+    }
+}
+
+impl Drop for Spill {
+    fn drop(&mut self) {
+        for path in &self.shards {
+            if let Err(e) = fs::remove_file(path) {
+                warn!("failed to remove index shard {}: {}", path.to_string_lossy(), e);
+            }
+        }
+    }
+}
+
+// reads the next "digest size path" line from a shard
+fn next_line(lines: &mut Lines<BufReader<File>>) -> Result<Option<(String, u64, String)>> {
+    let line = match lines.next() {
+        Some(line) => line?,
+        None => return Ok(None)
+    };
+    let mut parts = line.splitn(3, ' ');
+    match (parts.next(), parts.next().and_then(|s| s.parse::<u64>().ok()), parts.next()) {
+        (Some(digest), Some(size), Some(path)) => Ok(Some((digest.to_string(), size, path.to_string()))),
+        _ => Err(Error::InvalidFormat(format!("corrupt index shard line {}", line)))
+    }
+}
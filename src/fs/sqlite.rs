@@ -0,0 +1,248 @@
+use crate::{
+    error::Error,
+    Result,
+    fs::{
+        IndexStore,
+        ItemPath,
+        TreeIndex,
+        TreeIndexHeader,
+        TreeItem,
+        TreeItemDupes
+    }
+};
+use log::debug;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::collections::VecDeque;
+use std::path::Path;
+
+// the header fields are stored as key/value rows, the paths are unique and
+// the digest is indexed so both kinds of lookup avoid a table scan. the id
+// keeps the insertion order so the first path for a digest stays the main
+// item.
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS header (
+        key TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS items (
+        id INTEGER PRIMARY KEY,
+        path TEXT NOT NULL UNIQUE,
+        digest TEXT NOT NULL,
+        size INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS items_digest ON items (digest);
+";
+
+// how many digests the iterator fetches at a time
+const ITER_BATCH: i64 = 1024;
+
+/// A SqliteStore keeps an index in a SQLite database. Items are upserted one
+/// at a time, so an index can be built and queried without ever holding the
+/// whole thing in memory.
+pub struct SqliteStore {
+    conn: Connection
+}
+
+impl SqliteStore {
+
+    /// Opens the database at the path, creating it if it doesn't exist
+    pub fn open(path: &Path) -> Result<Self> {
+        debug!("opening sqlite index {}", path.to_string_lossy());
+        let conn = Connection::open(path)?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.execute_batch(SCHEMA)?;
+
+        let store = Self { conn };
+        if store.version()?.is_none() {
+            store.set_header(&TreeIndexHeader::default())?;
+        }
+        Ok(store)
+    }
+
+    /// Reads the header stored with the index
+    pub fn header(&self) -> Result<TreeIndexHeader> {
+        let mut header = TreeIndexHeader::new(self.version()?.unwrap_or(1));
+        let mut stmt = self.conn.prepare("SELECT key, value FROM header WHERE key != 'version'")?;
+        let rows = stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?;
+        for row in rows {
+            let (k, v) = row?;
+            header.fields.insert(k, v);
+        }
+        Ok(header)
+    }
+
+    /// Replaces the header stored with the index
+    pub fn set_header(&self, header: &TreeIndexHeader) -> Result<()> {
+        write_header(&self.conn, header)
+    }
+
+    /// The number of paths in the store
+    pub fn len(&self) -> Result<usize> {
+        let n: i64 = self.conn.query_row("SELECT COUNT(*) FROM items", [], |row| row.get(0))?;
+        Ok(n as usize)
+    }
+
+    pub fn is_empty(&self) -> Result<bool> {
+        Ok(self.len()? == 0)
+    }
+
+    fn version(&self) -> Result<Option<u32>> {
+        let v: Option<String> = self.conn
+            .query_row("SELECT value FROM header WHERE key = 'version'", [], |row| row.get(0))
+            .optional()?;
+        match v {
+            Some(v) => Ok(Some(v.parse::<u32>()
+                .map_err(|_| Error::InvalidFormat(format!("invalid header version {}", v)))?)),
+            None => Ok(None)
+        }
+    }
+}
+
+impl IndexStore for SqliteStore {
+
+    fn load(&mut self) -> Result<TreeIndex> {
+        let mut ti = TreeIndex {
+            header: self.header()?,
+            ..Default::default()
+        };
+        let mut stmt = self.conn.prepare("SELECT path, digest, size FROM items ORDER BY id")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, i64>(2)?))
+        })?;
+        for row in rows {
+            let (path, digest, size) = row?;
+            ti.insert(TreeItem::new(&digest, Path::new(&path), size as u64));
+        }
+        Ok(ti)
+    }
+
+    fn save_item(&mut self, item: &TreeItem) -> Result<()> {
+        upsert(&self.conn, item)
+    }
+
+    fn remove_path(&mut self, path: &Path) -> Result<bool> {
+        let n = self.conn.execute("DELETE FROM items WHERE path = ?1", params![path_str(path)?])?;
+        Ok(n > 0)
+    }
+
+    fn lookup_digest(&self, digest: &str) -> Result<Option<TreeItemDupes>> {
+        let mut stmt = self.conn.prepare_cached("SELECT path, size FROM items WHERE digest = ?1 ORDER BY id")?;
+        let rows = stmt.query_map(params![digest], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
+        })?;
+
+        let mut entry: Option<TreeItemDupes> = None;
+        for row in rows {
+            let (path, size) = row?;
+            let path = ItemPath::new(Path::new(&path));
+            match entry.as_mut() {
+                Some(e) if e.item.size == size as u64 => e.push(path),
+                Some(_) => {},
+                None => entry = Some(TreeItemDupes::new(digest, &path, size as u64))
+            }
+        }
+        Ok(entry)
+    }
+
+    fn lookup_path(&self, path: &Path) -> Result<Option<TreeItem>> {
+        let mut stmt = self.conn.prepare_cached("SELECT digest, size FROM items WHERE path = ?1")?;
+        let item = stmt
+            .query_row(params![path_str(path)?], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
+            })
+            .optional()?;
+        Ok(item.map(|(digest, size)| TreeItem::new(&digest, path, size as u64)))
+    }
+
+    fn iter(&self) -> Result<Box<dyn Iterator<Item = Result<TreeItemDupes>> + '_>> {
+        Ok(Box::new(SqliteIter {
+            store: self,
+            last: String::new(),
+            batch: VecDeque::new(),
+            done: false
+        }))
+    }
+
+    fn save(&mut self, index: &TreeIndex) -> Result<()> {
+        // a single transaction is orders of magnitude faster than one per item,
+        // and readers never see the old items mixed with the new ones
+        let tx = self.conn.transaction()?;
+        tx.execute("DELETE FROM items", [])?;
+        for entry in index.idx.values() {
+            upsert(&tx, &entry.item)?;
+            for d in &entry.dupes {
+                upsert(&tx, &TreeItem::new(&entry.item.digest, d, entry.item.size))?;
+            }
+        }
+        write_header(&tx, &index.header)?;
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+// walks the digests in order a batch at a time so the whole index is never
+// loaded at once
+struct SqliteIter<'a> {
+    store: &'a SqliteStore,
+    last: String,
+    batch: VecDeque<String>,
+    done: bool
+}
+
+impl<'a> SqliteIter<'a> {
+
+    fn fetch(&mut self) -> Result<()> {
+        let mut stmt = self.store.conn.prepare_cached(
+            "SELECT DISTINCT digest FROM items WHERE digest > ?1 ORDER BY digest LIMIT ?2")?;
+        let rows = stmt.query_map(params![self.last, ITER_BATCH], |row| row.get::<_, String>(0))?;
+        for row in rows {
+            self.batch.push_back(row?);
+        }
+        if (self.batch.len() as i64) < ITER_BATCH {
+            self.done = true;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Iterator for SqliteIter<'a> {
+    type Item = Result<TreeItemDupes>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.batch.is_empty() && !self.done {
+            if let Err(e) = self.fetch() {
+                self.done = true;
+                return Some(Err(e));
+            }
+        }
+        let digest = self.batch.pop_front()?;
+        let entry = self.store.lookup_digest(&digest).transpose();
+        self.last = digest;
+        entry
+    }
+}
+
+// replaces the header rows
+fn write_header(conn: &Connection, header: &TreeIndexHeader) -> Result<()> {
+    conn.execute("DELETE FROM header", [])?;
+    conn.execute("INSERT INTO header (key, value) VALUES ('version', ?1)", params![header.version.to_string()])?;
+    for (k, v) in &header.fields {
+        conn.execute("INSERT INTO header (key, value) VALUES (?1, ?2)", params![k, v])?;
+    }
+    Ok(())
+}
+
+fn upsert(conn: &Connection, item: &TreeItem) -> Result<()> {
+    let mut stmt = conn.prepare_cached(
+        "INSERT INTO items (path, digest, size) VALUES (?1, ?2, ?3)
+         ON CONFLICT(path) DO UPDATE SET digest = excluded.digest, size = excluded.size")?;
+    stmt.execute(params![path_str(&item.path())?, item.digest.to_string(), item.size as i64])?;
+    Ok(())
+}
+
+// paths are stored as text so they must be valid unicode, the same as the
+// text index format
+fn path_str(path: &Path) -> Result<&str> {
+    path.to_str()
+        .ok_or_else(|| Error::InvalidFormat(format!("non-unicode path {}", path.to_string_lossy())))
+}
@@ -0,0 +1,268 @@
+use crate::{
+    fs::{metrics::{self, Metrics}, TreeIndex, TreeItemDupes},
+    units::{format_bytes, Units}
+};
+pub(crate) use crate::json::json_string;
+use std::fmt::{Display, Formatter, Write};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// What a scan did. Bytes are the sizes of the files that were hashed,
+/// skipped counts the files left out by the filters and errors counts the
+/// problems that were logged and didn't stop the scan, like ignore files
+/// that couldn't be parsed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ScanStats {
+    pub dirs: u64,
+    pub files: u64,
+    pub bytes: u64,
+    pub skipped: u64,
+    pub errors: u64,
+    pub elapsed: Duration
+}
+
+impl ScanStats {
+
+    /// Adds the counts of another scan to these
+    pub fn merge(&mut self, other: &ScanStats) {
+        self.dirs += other.dirs;
+        self.files += other.files;
+        self.bytes += other.bytes;
+        self.skipped += other.skipped;
+        self.errors += other.errors;
+        self.elapsed += other.elapsed;
+    }
+
+    /// Returns the stats as a JSON object with the elapsed time in seconds
+    pub fn to_json(&self) -> String {
+        format!("{{\"dirs\":{},\"files\":{},\"bytes\":{},\"skipped\":{},\"errors\":{},\"elapsed\":{:.3}}}",
+                self.dirs, self.files, self.bytes, self.skipped, self.errors, self.elapsed.as_secs_f64())
+    }
+
+    // fills in the fields of a span made by scan_span or index_span
+    #[cfg(feature = "tracing")]
+    pub(crate) fn record(&self, span: &tracing::Span) {
+        span.record("dirs", self.dirs);
+        span.record("files", self.files);
+        span.record("bytes", self.bytes);
+        span.record("skipped", self.skipped);
+        span.record("errors", self.errors);
+    }
+}
+
+// the span a scan runs in, the counts are recorded when it is done
+#[cfg(feature = "tracing")]
+pub(crate) fn scan_span() -> tracing::Span {
+    use tracing::field::Empty;
+    tracing::info_span!("scan", dirs = Empty, files = Empty, bytes = Empty, skipped = Empty, errors = Empty)
+}
+
+// the span an index is built in, with the counts of the scan it came from
+// and how many entries and dupes it ended up with
+#[cfg(feature = "tracing")]
+pub(crate) fn index_span() -> tracing::Span {
+    use tracing::field::Empty;
+    tracing::info_span!("index", entries = Empty, dupes = Empty, dirs = Empty, files = Empty, bytes = Empty,
+                        skipped = Empty, errors = Empty)
+}
+
+impl Display for ScanStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error>
+    {
+        write!(f, "{} dirs, {} files, {} hashed, {} skipped, {} errors in {:.2}s",
+               self.dirs, self.files, format_bytes(self.bytes, Units::default()), self.skipped, self.errors,
+               self.elapsed.as_secs_f64())
+    }
+}
+
+// the counters a scan updates as it goes, they are shared by the worker
+// threads of a parallel scan and passed on to the metrics if there are any
+#[derive(Default)]
+pub(crate) struct ScanCounters {
+    dirs: AtomicU64,
+    files: AtomicU64,
+    bytes: AtomicU64,
+    skipped: AtomicU64,
+    errors: AtomicU64,
+    metrics: Option<Arc<dyn Metrics>>
+}
+
+impl ScanCounters {
+
+    pub(crate) fn set_metrics(&mut self, metrics: Arc<dyn Metrics>) {
+        self.metrics = Some(metrics);
+    }
+
+    pub(crate) fn dir(&self) {
+        self.dirs.fetch_add(1, Ordering::Relaxed);
+        self.add(metrics::DIRS_SCANNED, 1);
+    }
+
+    pub(crate) fn file(&self, size: u64) {
+        self.files.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(size, Ordering::Relaxed);
+        self.add(metrics::FILES_SCANNED, 1);
+        self.add(metrics::BYTES_HASHED, size);
+    }
+
+    pub(crate) fn skip(&self) {
+        self.skipped.fetch_add(1, Ordering::Relaxed);
+        self.add(metrics::FILES_SKIPPED, 1);
+    }
+
+    pub(crate) fn error(&self) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+        self.add(metrics::SCAN_ERRORS, 1);
+    }
+
+    // the work waiting in the queue, only the metrics keep it
+    pub(crate) fn queue(&self, depth: usize) {
+        if let Some(m) = &self.metrics {
+            m.set(metrics::QUEUE_DEPTH, depth as i64);
+        }
+    }
+
+    fn add(&self, name: &'static str, value: u64) {
+        if let Some(m) = &self.metrics {
+            m.add(name, value);
+        }
+    }
+
+    pub(crate) fn stats(&self, elapsed: Duration) -> ScanStats {
+        ScanStats {
+            dirs: self.dirs.load(Ordering::Relaxed),
+            files: self.files.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+            skipped: self.skipped.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            elapsed
+        }
+    }
+}
+
+/// A bucket of the file size histogram holding the files whose sizes are at
+/// least min and less than max
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SizeBucket {
+    pub min: u64,
+    pub max: u64,
+    pub files: u64,
+    pub bytes: u64
+}
+
+/// The totals of an index, its largest duplicate groups and a histogram of
+/// its file sizes. Every copy of a file is counted, reclaimable is what
+/// removing all but one copy in each group would free.
+#[derive(Clone, Default)]
+pub struct IndexStats {
+    pub files: u64,
+    pub bytes: u64,
+    pub groups: u64,
+    pub dupes: u64,
+    pub reclaimable: u64,
+    pub top: Vec<TreeItemDupes>,
+    pub histogram: Vec<SizeBucket>
+}
+
+impl IndexStats {
+
+    /// Gathers the stats of the index keeping the top largest duplicate
+    /// groups, ordered by the bytes they waste
+    pub fn new(ti: &TreeIndex, top: usize) -> Self {
+        let mut stats = Self::default();
+
+        // the buckets are powers of two with empty files in the first
+        let mut buckets = [SizeBucket::default(); 65];
+        for (i, b) in buckets.iter_mut().enumerate() {
+            b.min = if i == 0 { 0 } else { 1 << (i - 1) };
+            b.max = if i == 0 { 1 } else { 1u64.checked_shl(i as u32).unwrap_or(u64::MAX) };
+        }
+
+        let mut groups = Vec::new();
+        for entry in ti.idx.values() {
+            let copies = entry.dupes.len() as u64 + 1;
+            let size = entry.item.size;
+            stats.files += copies;
+            // the sizes are whatever the index says, so the sums saturate
+            // rather than overflow on a bogus one
+            let bytes = size.saturating_mul(copies);
+            stats.bytes = stats.bytes.saturating_add(bytes);
+            let b = &mut buckets[(u64::BITS - size.leading_zeros()) as usize];
+            b.files += copies;
+            b.bytes = b.bytes.saturating_add(bytes);
+            if !entry.dupes.is_empty() {
+                stats.groups += 1;
+                stats.dupes += copies - 1;
+                stats.reclaimable = stats.reclaimable.saturating_add(size.saturating_mul(copies - 1));
+                groups.push(entry);
+            }
+        }
+        groups.sort_by_cached_key(|g| (std::cmp::Reverse(savings(g)), g.item.path()));
+        stats.top = groups.into_iter().take(top).cloned().collect();
+        stats.histogram = buckets.iter().filter(|b| b.files > 0).copied().collect();
+        stats
+    }
+
+    /// Returns the stats as a JSON object, groups list all of their paths
+    /// with the primary first
+    pub fn to_json(&self) -> String {
+        let mut s = format!("{{\"files\":{},\"bytes\":{},\"groups\":{},\"dupes\":{},\"reclaimable\":{},\"top\":[",
+                            self.files, self.bytes, self.groups, self.dupes, self.reclaimable);
+        for (i, g) in self.top.iter().enumerate() {
+            let paths: Vec<String> = std::iter::once(g.item.item_path())
+                .chain(g.dupes.iter())
+                .map(|p| json_string(&p.path().to_string_lossy()))
+                .collect();
+            let _ = write!(s, "{}{{\"digest\":{},\"size\":{},\"copies\":{},\"reclaimable\":{},\"paths\":[{}]}}",
+                           if i > 0 { "," } else { "" }, json_string(&g.item.digest.to_string()), g.item.size,
+                           g.dupes.len() + 1, savings(g), paths.join(","));
+        }
+        s.push_str("],\"histogram\":[");
+        for (i, b) in self.histogram.iter().enumerate() {
+            let _ = write!(s, "{}{{\"min\":{},\"max\":{},\"files\":{},\"bytes\":{}}}",
+                           if i > 0 { "," } else { "" }, b.min, b.max, b.files, b.bytes);
+        }
+        s.push_str("]}");
+        s
+    }
+}
+
+impl Display for IndexStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error>
+    {
+        writeln!(f, "files: {} ({})", self.files, format_bytes(self.bytes, Units::Binary))?;
+        writeln!(f, "duplicate groups: {} with {} extra copies", self.groups, self.dupes)?;
+        writeln!(f, "reclaimable: {} ({} bytes)", format_bytes(self.reclaimable, Units::Binary), self.reclaimable)?;
+        if !self.top.is_empty() {
+            writeln!(f, "largest duplicate groups:")?;
+            for g in &self.top {
+                writeln!(f, "  {:>10}  {} x{}", format_bytes(savings(g), Units::Binary),
+                         g.item.path().to_string_lossy(), g.dupes.len() + 1)?;
+            }
+        }
+        if !self.histogram.is_empty() {
+            writeln!(f, "file sizes:")?;
+            for b in &self.histogram {
+                writeln!(f, "  {:>10} - {:<10} {:>8} files {:>10}", format_bytes(b.min, Units::Binary),
+                         format_bytes(b.max, Units::Binary), b.files, format_bytes(b.bytes, Units::Binary))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// the bytes freed by removing the dupes of a group
+pub(crate) fn savings(g: &TreeItemDupes) -> u64 {
+    g.item.size.saturating_mul(g.dupes.len() as u64)
+}
+
+// finds the group of an item in a union find forest, shortening the path to
+// it as it goes
+pub(crate) fn group_root(parent: &mut [usize], mut i: usize) -> usize {
+    while parent[i] != i {
+        parent[i] = parent[parent[i]];
+        i = parent[i];
+    }
+    i
+}
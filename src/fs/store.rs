@@ -0,0 +1,133 @@
+use crate::{
+    Result,
+    fs::{
+        DigestKey,
+        TreeIndex,
+        TreeItem,
+        TreeItemDupes
+    }
+};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// An IndexStore holds a TreeIndex somewhere, in memory, in a file or in a
+/// database. Stores that can answer queries directly, like a database, let
+/// callers look up digests and paths without loading the whole index first.
+pub trait IndexStore {
+
+    /// Reads the entire store into an in-memory index
+    fn load(&mut self) -> Result<TreeIndex>;
+
+    /// Adds an item to the store, or updates it if the path is already there
+    fn save_item(&mut self, item: &TreeItem) -> Result<()>;
+
+    /// Removes a path from the store, returns true if it was there
+    fn remove_path(&mut self, path: &Path) -> Result<bool>;
+
+    /// Returns the entry for a digest with all of the paths that share it.
+    /// If the digest collides across files of different sizes this is only
+    /// one of the entries, TreeIndex::get_digest returns all of them.
+    fn lookup_digest(&self, digest: &str) -> Result<Option<TreeItemDupes>>;
+
+    /// Returns the item stored for a path
+    fn lookup_path(&self, path: &Path) -> Result<Option<TreeItem>>;
+
+    /// Iterates over every entry in the store
+    fn iter(&self) -> Result<Box<dyn Iterator<Item = Result<TreeItemDupes>> + '_>>;
+
+    /// Makes sure every change has been written out. Stores that write
+    /// through on every change don't need to do anything.
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Writes a whole index to the store. Stores should override this when
+    /// they can batch the writes.
+    fn save(&mut self, index: &TreeIndex) -> Result<()> {
+        for entry in index.idx.values() {
+            self.save_item(&entry.item)?;
+            for d in &entry.dupes {
+                self.save_item(&TreeItem::new(&entry.item.digest, d, entry.item.size))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A MemoryStore keeps the index in memory with a reverse map from path to
+/// digest key so path lookups and updates don't have to search the whole index.
+/// The file backed stores use it to hold the index between writes.
+#[derive(Clone, Default)]
+pub struct MemoryStore {
+    index: TreeIndex,
+    paths: HashMap<PathBuf, DigestKey>
+}
+
+impl MemoryStore {
+
+    pub fn new(index: TreeIndex) -> Self {
+        let mut paths = HashMap::new();
+        for (key, entry) in &index.idx {
+            paths.insert(entry.item.path(), key.clone());
+            for d in &entry.dupes {
+                paths.insert(d.path(), key.clone());
+            }
+        }
+        Self { index, paths }
+    }
+
+    pub fn index(&self) -> &TreeIndex {
+        &self.index
+    }
+
+    /// Returns the key of the entry a path is stored in
+    pub fn key_of(&self, path: &Path) -> Option<&DigestKey> {
+        self.paths.get(path)
+    }
+}
+
+impl IndexStore for MemoryStore {
+
+    fn load(&mut self) -> Result<TreeIndex> {
+        Ok(self.index.clone())
+    }
+
+    fn save_item(&mut self, item: &TreeItem) -> Result<()> {
+        let key = self.index.key(item);
+        let path = item.path();
+        if let Some(old) = self.paths.insert(path.clone(), key.clone()) {
+            if old == key {
+                return Ok(());
+            }
+            self.index.remove(&old, &path);
+        }
+        self.index.insert(item.clone());
+        Ok(())
+    }
+
+    fn remove_path(&mut self, path: &Path) -> Result<bool> {
+        match self.paths.remove(path) {
+            Some(key) => Ok(self.index.remove(&key, path)),
+            None => Ok(false)
+        }
+    }
+
+    fn lookup_digest(&self, digest: &str) -> Result<Option<TreeItemDupes>> {
+        Ok(self.index.get_digest(digest).first().map(|e| (*e).clone()))
+    }
+
+    fn lookup_path(&self, path: &Path) -> Result<Option<TreeItem>> {
+        Ok(self.paths.get(path)
+            .and_then(|k| self.index.idx.get(k))
+            .map(|e| TreeItem::new(&e.item.digest, path, e.item.size)))
+    }
+
+    fn iter(&self) -> Result<Box<dyn Iterator<Item = Result<TreeItemDupes>> + '_>> {
+        Ok(Box::new(self.index.idx.values().cloned().map(Ok)))
+    }
+
+    fn save(&mut self, index: &TreeIndex) -> Result<()> {
+        *self = Self::new(index.clone());
+        Ok(())
+    }
+}
@@ -0,0 +1,231 @@
+use crate::{
+    error::Error,
+    Result,
+    fs::{
+        DigestKey,
+        TreeIndex,
+        TreeIndexDiff,
+        TreeIndexHeader,
+        TreeItem
+    }
+};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+
+/// One step of a sync plan. The paths are relative to the roots of the
+/// trees, Copy reads from the source tree and the other steps only touch
+/// the destination.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SyncOp {
+    /// Copies the file at the path in the source to the same path in the
+    /// destination
+    Copy { path: PathBuf, size: u64 },
+
+    /// Copies a file the destination already has to another path in it
+    Duplicate { from: PathBuf, to: PathBuf },
+
+    /// Renames a file in the destination
+    Move { from: PathBuf, to: PathBuf },
+
+    /// Deletes a file from the destination
+    Delete { path: PathBuf }
+}
+
+// one op per line, the paths are separated by tabs since they can have
+// spaces in them
+impl Display for SyncOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error>
+    {
+        match self {
+            SyncOp::Copy { path, .. } => writeln!(f, "copy\t{}", path.to_string_lossy()),
+            SyncOp::Duplicate { from, to } => writeln!(f, "dup\t{}\t{}", from.to_string_lossy(), to.to_string_lossy()),
+            SyncOp::Move { from, to } => writeln!(f, "move\t{}\t{}", from.to_string_lossy(), to.to_string_lossy()),
+            SyncOp::Delete { path } => writeln!(f, "delete\t{}", path.to_string_lossy())
+        }
+    }
+}
+
+/// The steps that make a destination tree match a source tree, in the order
+/// they have to run in: moves first so the files they take are still there,
+/// then duplicates and copies and the deletes last.
+#[derive(Clone, Debug, Default)]
+pub struct SyncPlan {
+    pub ops: Vec<SyncOp>
+}
+
+impl SyncPlan {
+
+    /// Returns true if the destination already matches the source
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    /// The number of bytes the copies read from the source
+    pub fn copy_bytes(&self) -> u64 {
+        self.ops.iter()
+            .map(|op| match op {
+                SyncOp::Copy { size, .. } => *size,
+                _ => 0
+            })
+            .sum()
+    }
+}
+
+impl Display for SyncPlan {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error>
+    {
+        for op in &self.ops {
+            write!(f, "{}", op)?;
+        }
+        Ok(())
+    }
+}
+
+/// A SyncPlanner works out what to do to a destination tree to make it match
+/// a source tree from an index of each, the way rsync would but without
+/// reading either tree. The paths in each index are taken relative to its
+/// root. Content addressed plans look for the files that have to be copied
+/// in the destination first, so renamed files are moved and files that
+/// only got another copy are duplicated instead of being sent again.
+#[derive(Clone, Debug, Default)]
+pub struct SyncPlanner {
+    content_addressed: bool,
+    source_root: PathBuf,
+    dest_root: PathBuf
+}
+
+impl SyncPlanner {
+
+    /// Plans the sync from the index of the source to the index of the
+    /// destination. The indexes have to be made with the same digests and
+    /// with dupes so every path is in them.
+    pub fn plan(&self, source: &TreeIndex, dest: &TreeIndex) -> Result<SyncPlan> {
+        check_headers(&source.header, &dest.header)?;
+        let source = relative(source, &self.source_root);
+        let dest = relative(dest, &self.dest_root);
+
+        // the destination is the old index and the source the new one
+        let diff = TreeIndexDiff::between(&dest, &source);
+
+        let key = |i: &TreeItem| DigestKey::of(dest.header.algorithm().unwrap_or_default(), i);
+        let mut deletes: BTreeMap<PathBuf, TreeItem> = diff.missing.iter()
+            .map(|i| (i.path(), i.clone()))
+            .collect();
+
+        // the files already in place that others can be copied from, and
+        // the ones that are going away that can be moved instead
+        let mut kept: HashMap<DigestKey, PathBuf> = HashMap::new();
+        let mut leaving: HashMap<DigestKey, Vec<PathBuf>> = HashMap::new();
+        if self.content_addressed {
+            for i in diff.matched.iter().chain(diff.changed.iter().map(|(i, _)| i)) {
+                kept.entry(key(i)).or_insert_with(|| i.path());
+            }
+            for i in diff.missing.iter().rev() {
+                leaving.entry(key(i)).or_default().push(i.path());
+            }
+        }
+
+        let mut moves = Vec::new();
+        let mut copies = Vec::new();
+        let mut wanted: Vec<&TreeItem> = diff.added.iter().chain(diff.modified.iter()).collect();
+        wanted.sort_by_cached_key(|i| i.path());
+        for i in wanted {
+            let k = key(i);
+            let to = i.path();
+            if let Some(from) = leaving.get_mut(&k).and_then(|v| v.pop()) {
+                deletes.remove(&from);
+                kept.entry(k).or_insert_with(|| to.clone());
+                moves.push(SyncOp::Move { from, to });
+            } else if let Some(from) = kept.get(&k) {
+                copies.push(SyncOp::Duplicate { from: from.clone(), to });
+            } else {
+                if self.content_addressed {
+                    kept.insert(k, to.clone());
+                }
+                copies.push(SyncOp::Copy { path: to, size: i.size });
+            }
+        }
+
+        // a file copied from inside the destination has to be there before
+        // it is read
+        copies.sort_by_key(|op| matches!(op, SyncOp::Duplicate { .. }));
+
+        let mut plan = SyncPlan::default();
+        plan.ops.extend(moves);
+        plan.ops.extend(copies);
+        plan.ops.extend(deletes.into_keys().map(|path| SyncOp::Delete { path }));
+        Ok(plan)
+    }
+}
+
+#[derive(Default)]
+pub struct SyncPlannerBuilder {
+    content_addressed: bool,
+    source_root: PathBuf,
+    dest_root: PathBuf
+}
+
+impl SyncPlannerBuilder {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Move and duplicate files the destination already has instead of
+    /// copying them from the source
+    pub fn content_addressed(mut self, content_addressed: bool) -> Self {
+        self.content_addressed = content_addressed;
+        self
+    }
+
+    /// The directory the source index was made from, its paths are taken
+    /// relative to it
+    pub fn source_root(mut self, root: &Path) -> Self {
+        self.source_root = root.to_path_buf();
+        self
+    }
+
+    /// The directory the destination index was made from
+    pub fn dest_root(mut self, root: &Path) -> Self {
+        self.dest_root = root.to_path_buf();
+        self
+    }
+
+    pub fn build(self) -> Result<SyncPlanner> {
+        Ok(SyncPlanner {
+            content_addressed: self.content_addressed,
+            source_root: self.source_root,
+            dest_root: self.dest_root
+        })
+    }
+}
+
+// the digests in the two indexes have to be comparable and cover the whole
+// of each file, fast digests only cover part of it so files that differ in
+// the middle would look the same and not be copied. Keyed digests are only
+// comparable when they were made with the same key.
+fn check_headers(source: &TreeIndexHeader, dest: &TreeIndexHeader) -> Result<()> {
+    if source.is_fast() || dest.is_fast() {
+        return Err(Error::InvalidArgument("indexes with fast digests can't be synced, they don't cover the whole file".to_string()));
+    }
+    if (source.is_keyed() && source.key_id().is_none()) || (dest.is_keyed() && dest.key_id().is_none()) {
+        return Err(Error::InvalidArgument("keyed indexes without a key id can't be synced, there's no telling if they have the same key".to_string()));
+    }
+    if source.is_keyed() != dest.is_keyed()
+        || source.key_id() != dest.key_id()
+        || source.algorithm()? != dest.algorithm()?
+        || source.encoding()? != dest.encoding()? {
+        return Err(Error::InvalidArgument("the source and destination indexes have different digests".to_string()));
+    }
+    Ok(())
+}
+
+// a copy of the index with the root taken off its paths
+fn relative(ti: &TreeIndex, root: &Path) -> TreeIndex {
+    let mut ti = ti.clone();
+    if !root.as_os_str().is_empty() {
+        ti.rewrite_prefix(root, Path::new(""));
+    }
+    ti
+}
@@ -0,0 +1,177 @@
+use crate::{
+    Result,
+    fs::os_path
+};
+use log::warn;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// keeps temp names unique within the process, the pid keeps them unique
+// between processes
+static TEMP_ID: AtomicUsize = AtomicUsize::new(0);
+
+// how many names to try before giving up on a directory
+const ATTEMPTS: usize = 64;
+
+// the most of the target's name a temp name starts with, what the pid and
+// the counter add keeps it well under the 255 bytes file systems allow
+const MAX_STEM: usize = 200;
+
+// the hidden name for the next temp file or directory for the target
+fn temp_name(dir: &Path, target: Option<&Path>) -> PathBuf {
+    let mut stem = target
+        .and_then(|t| t.file_name())
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "tmp".to_string());
+    if stem.len() > MAX_STEM {
+        let mut end = MAX_STEM;
+        while !stem.is_char_boundary(end) {
+            end -= 1;
+        }
+        stem.truncate(end);
+    }
+    dir.join(format!(".{}.{}.{}.tmp", stem, process::id(), TEMP_ID.fetch_add(1, Ordering::Relaxed)))
+}
+
+// the directory a temp for the target goes in so it can be renamed over it
+fn dir_of(target: &Path) -> PathBuf {
+    match target.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p.to_path_buf(),
+        _ => PathBuf::from(".")
+    }
+}
+
+// tries names until create makes one that didn't exist
+fn create<T, F>(dir: &Path, target: Option<&Path>, create: F) -> Result<(PathBuf, T)>
+    where F: Fn(&Path) -> io::Result<T>
+{
+    for _ in 0..ATTEMPTS {
+        let path = temp_name(dir, target);
+        match create(&path) {
+            Ok(t) => return Ok((path, t)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e.into())
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::AlreadyExists,
+                       format!("failed to make a temp name in {}", dir.to_string_lossy())).into())
+}
+
+/// A TempFile is a new file that is deleted when it is dropped unless it is
+/// persisted. Made next to the file it is going to replace, it is on the
+/// same file system so persisting it is an atomic rename and readers see
+/// either the old file or the whole new one.
+#[derive(Debug)]
+pub struct TempFile {
+    path: PathBuf,
+    file: Option<File>
+}
+
+impl TempFile {
+
+    /// Makes a temp file in the same directory as the target
+    pub fn next_to(target: &Path) -> Result<Self> {
+        Self::create(&dir_of(target), Some(target))
+    }
+
+    /// Makes a temp file in the directory
+    pub fn in_dir(dir: &Path) -> Result<Self> {
+        Self::create(dir, None)
+    }
+
+    fn create(dir: &Path, target: Option<&Path>) -> Result<Self> {
+        let (path, file) = create(dir, target, |p| OpenOptions::new().write(true).create_new(true).open(os_path(p)))?;
+        Ok(Self { path, file: Some(file) })
+    }
+
+    /// The path of the temp file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The open handle to the temp file
+    pub fn as_file(&self) -> &File {
+        self.file.as_ref().expect("temp file is open until it is persisted")
+    }
+
+    /// Renames the temp file to the path, replacing what is there, and keeps
+    /// it
+    pub fn persist(mut self, path: &Path) -> Result<()> {
+        if let Some(mut f) = self.file.take() {
+            f.flush()?;
+        }
+        fs::rename(os_path(&self.path), os_path(path))?;
+        self.path = PathBuf::new();
+        Ok(())
+    }
+}
+
+impl Write for TempFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.as_file().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.as_file().flush()
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        // closed first, windows won't delete an open file
+        self.file.take();
+        if !self.path.as_os_str().is_empty() {
+            if let Err(e) = fs::remove_file(os_path(&self.path)) {
+                warn!("failed to remove temp file {}: {}", self.path.to_string_lossy(), e);
+            }
+        }
+    }
+}
+
+/// A TempDir is a new directory that is deleted along with everything in it
+/// when it is dropped unless it is persisted
+#[derive(Debug)]
+pub struct TempDir {
+    path: PathBuf
+}
+
+impl TempDir {
+
+    /// Makes a temp directory in the same directory as the target
+    pub fn next_to(target: &Path) -> Result<Self> {
+        let (path, _) = create(&dir_of(target), Some(target), |p| fs::create_dir(os_path(p)))?;
+        Ok(Self { path })
+    }
+
+    /// Makes a temp directory in the directory
+    pub fn in_dir(dir: &Path) -> Result<Self> {
+        let (path, _) = create(dir, None, |p| fs::create_dir(os_path(p)))?;
+        Ok(Self { path })
+    }
+
+    /// The path of the temp directory
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Renames the temp directory to the path and keeps it. The path must
+    /// not exist or be an empty directory.
+    pub fn persist(mut self, path: &Path) -> Result<()> {
+        fs::rename(os_path(&self.path), os_path(path))?;
+        self.path = PathBuf::new();
+        Ok(())
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        if !self.path.as_os_str().is_empty() {
+            if let Err(e) = fs::remove_dir_all(os_path(&self.path)) {
+                warn!("failed to remove temp directory {}: {}", self.path.to_string_lossy(), e);
+            }
+        }
+    }
+}
@@ -0,0 +1,312 @@
+use crate::{
+    error::Error,
+    Result,
+    cli::bench::write_generated,
+    fs::TempDir,
+    units::IntoBytes
+};
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Component, Path, PathBuf};
+
+/// The names weird_names makes, each one trips up something that splits
+/// on whitespace, quotes for a shell or treats a leading character as
+/// syntax. The ones Windows doesn't allow are left out there.
+#[cfg(not(windows))]
+pub const WEIRD_NAMES: &[&str] = &[
+    "with space",
+    " leading space",
+    "trailing space ",
+    "tab\tin name",
+    "new\nline",
+    "carriage\rreturn",
+    "control\u{1}char",
+    "back\\slash",
+    "single'quote",
+    "double\"quote",
+    "$dollar",
+    "*star?",
+    "-> moved marker",
+    "-leading-dash",
+    "- dupe marker",
+    "# comment marker",
+    "! removal marker",
+    "~ chunk marker",
+    "& fuzzy marker",
+    "% image marker",
+    "@ metadata marker",
+    "? special marker",
+    "caf\u{e9}",
+    "cafe\u{301}",
+    "\u{65e5}\u{672c}\u{8a9e}",
+    "emoji \u{1f600}",
+    "right\u{2019}quote"
+];
+
+/// The names weird_names makes, each one trips up something that splits
+/// on whitespace, quotes for a shell or treats a leading character as
+/// syntax. The ones Windows doesn't allow are left out there.
+#[cfg(windows)]
+pub const WEIRD_NAMES: &[&str] = &[
+    "with space",
+    " leading space",
+    "single'quote",
+    "$dollar",
+    "-leading-dash",
+    "- dupe marker",
+    "# comment marker",
+    "! removal marker",
+    "~ chunk marker",
+    "& fuzzy marker",
+    "% image marker",
+    "@ metadata marker",
+    "? special marker",
+    "caf\u{e9}",
+    "cafe\u{301}",
+    "\u{65e5}\u{672c}\u{8a9e}",
+    "emoji \u{1f600}",
+    "right\u{2019}quote"
+];
+
+// one thing in the tree, made in the order they were added
+#[derive(Clone, Debug)]
+enum FixtureEntry {
+    File(PathBuf, Vec<u8>),
+    Sized(PathBuf, u64),
+    Dupe(PathBuf, PathBuf),
+    Dir(PathBuf),
+    Symlink(PathBuf, PathBuf),
+    Hardlink(PathBuf, PathBuf),
+    #[cfg(unix)]
+    RawName(PathBuf, Vec<u8>)
+}
+
+/// A TreeFixture is a temporary directory tree made from a list of files,
+/// dupes, links and odd names, for testing code that scans and acts on
+/// trees. Generated contents come from the path of the file so the same
+/// spec always makes the same tree. The tree is deleted when the fixture
+/// is dropped.
+#[derive(Debug)]
+pub struct TreeFixture {
+    dir: TempDir,
+    files: Vec<PathBuf>
+}
+
+impl TreeFixture {
+
+    /// The root of the tree
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    /// The path of something in the tree
+    pub fn join<P: AsRef<Path>>(&self, path: P) -> PathBuf {
+        self.dir.path().join(path)
+    }
+
+    /// The regular files made, dupes and hard links included, relative to
+    /// the root and in the order they were made
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+}
+
+#[derive(Default)]
+pub struct TreeFixtureBuilder {
+    entries: Vec<FixtureEntry>,
+    dir: Option<PathBuf>,
+    error: Option<Error>
+}
+
+impl TreeFixtureBuilder {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Makes the tree in this directory instead of the system temp dir
+    pub fn in_dir(mut self, dir: &Path) -> Self {
+        self.dir = Some(dir.to_path_buf());
+        self
+    }
+
+    /// A file with the contents, the directories it is in are made too
+    pub fn file<P: AsRef<Path>, C: AsRef<[u8]>>(mut self, path: P, contents: C) -> Self {
+        if let Some(path) = self.relative(path.as_ref()) {
+            self.entries.push(FixtureEntry::File(path, contents.as_ref().to_vec()));
+        }
+        self
+    }
+
+    /// A file of the size filled with bytes generated from its path. This
+    /// takes either a u64 or a human readable string such as "4MiB".
+    pub fn sized<P: AsRef<Path>, B: IntoBytes>(mut self, path: P, size: B) -> Self {
+        let size = match size.into_bytes() {
+            Ok(size) => size,
+            Err(e) => {
+                self.error = Some(e);
+                return self;
+            }
+        };
+        if let Some(path) = self.relative(path.as_ref()) {
+            self.entries.push(FixtureEntry::Sized(path, size));
+        }
+        self
+    }
+
+    /// A copy of a file added before it
+    pub fn dupe<P: AsRef<Path>, O: AsRef<Path>>(mut self, path: P, of: O) -> Self {
+        if let (Some(path), Some(of)) = (self.relative(path.as_ref()), self.relative(of.as_ref())) {
+            self.entries.push(FixtureEntry::Dupe(path, of));
+        }
+        self
+    }
+
+    /// An empty directory
+    pub fn dir<P: AsRef<Path>>(mut self, path: P) -> Self {
+        if let Some(path) = self.relative(path.as_ref()) {
+            self.entries.push(FixtureEntry::Dir(path));
+        }
+        self
+    }
+
+    /// A symlink to the target, which is used as it is so it can be
+    /// relative, absolute or dangling
+    pub fn symlink<P: AsRef<Path>, T: AsRef<Path>>(mut self, path: P, target: T) -> Self {
+        if let Some(path) = self.relative(path.as_ref()) {
+            self.entries.push(FixtureEntry::Symlink(path, target.as_ref().to_path_buf()));
+        }
+        self
+    }
+
+    /// A hard link to a file added before it
+    pub fn hardlink<P: AsRef<Path>, O: AsRef<Path>>(mut self, path: P, of: O) -> Self {
+        if let (Some(path), Some(of)) = (self.relative(path.as_ref()), self.relative(of.as_ref())) {
+            self.entries.push(FixtureEntry::Hardlink(path, of));
+        }
+        self
+    }
+
+    /// A small file in the directory for every one of WEIRD_NAMES, each
+    /// with contents of its own
+    pub fn weird_names<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        for name in WEIRD_NAMES {
+            let contents = format!("{}\n", name);
+            self = self.file(dir.as_ref().join(name), contents);
+        }
+        self
+    }
+
+    /// A small file in the directory whose name isn't valid UTF-8, which
+    /// only unix file systems allow
+    #[cfg(unix)]
+    pub fn non_utf8_name<P: AsRef<Path>>(mut self, dir: P, name: &[u8]) -> Self {
+        if let Some(dir) = self.relative(dir.as_ref()) {
+            self.entries.push(FixtureEntry::RawName(dir, name.to_vec()));
+        }
+        self
+    }
+
+    pub fn build(self) -> Result<TreeFixture> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+        let dir = match &self.dir {
+            Some(d) => TempDir::in_dir(d)?,
+            None => TempDir::in_dir(&std::env::temp_dir())?
+        };
+        let root = dir.path().to_path_buf();
+        let mut files = Vec::new();
+        for entry in self.entries {
+            match entry {
+                FixtureEntry::File(path, contents) => {
+                    fs::write(make_parent(&root, &path)?, contents)?;
+                    files.push(path);
+                },
+                FixtureEntry::Sized(path, size) => {
+                    write_generated(&make_parent(&root, &path)?, size, seed(&path))?;
+                    files.push(path);
+                },
+                FixtureEntry::Dupe(path, of) => {
+                    let from = made(&root, &files, &of)?;
+                    fs::copy(from, make_parent(&root, &path)?)?;
+                    files.push(path);
+                },
+                FixtureEntry::Dir(path) => {
+                    fs::create_dir_all(root.join(path))?;
+                },
+                FixtureEntry::Symlink(path, target) => {
+                    symlink(&target, &make_parent(&root, &path)?)?;
+                },
+                FixtureEntry::Hardlink(path, of) => {
+                    let from = made(&root, &files, &of)?;
+                    fs::hard_link(from, make_parent(&root, &path)?)?;
+                    files.push(path);
+                },
+                #[cfg(unix)]
+                FixtureEntry::RawName(dir, name) => {
+                    use std::ffi::OsStr;
+                    use std::os::unix::ffi::OsStrExt;
+                    let path = dir.join(OsStr::from_bytes(&name));
+                    fs::write(make_parent(&root, &path)?, &name)?;
+                    files.push(path);
+                }
+            }
+        }
+        Ok(TreeFixture { dir, files })
+    }
+
+    // the paths in a spec have to stay inside the tree
+    fn relative(&mut self, path: &Path) -> Option<PathBuf> {
+        if path.components().all(|c| matches!(c, Component::Normal(_))) && path.components().next().is_some() {
+            Some(path.to_path_buf())
+        } else {
+            self.error = Some(Error::InvalidArgument(
+                format!("fixture path {} has to be relative and stay in the tree", path.to_string_lossy())));
+            None
+        }
+    }
+}
+
+// makes the directories a path in the tree goes in and returns its full path
+fn make_parent(root: &Path, path: &Path) -> Result<PathBuf> {
+    let full = root.join(path);
+    if let Some(parent) = full.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    Ok(full)
+}
+
+// the full path of a file made before, dupes and hard links need one
+fn made(root: &Path, files: &[PathBuf], path: &Path) -> Result<PathBuf> {
+    if !files.iter().any(|f| f == path) {
+        return Err(Error::InvalidArgument(
+            format!("fixture file {} has to be added before it is copied or linked", path.to_string_lossy())));
+    }
+    Ok(root.join(path))
+}
+
+// the seed of the contents of a generated file
+fn seed(path: &Path) -> u64 {
+    let mut h = DefaultHasher::new();
+    path.hash(&mut h);
+    h.finish()
+}
+
+#[cfg(unix)]
+fn symlink(target: &Path, link: &Path) -> Result<()> {
+    std::os::unix::fs::symlink(target, link)?;
+    Ok(())
+}
+
+#[cfg(windows)]
+fn symlink(target: &Path, link: &Path) -> Result<()> {
+    std::os::windows::fs::symlink_file(target, link)?;
+    Ok(())
+}
+
+#[cfg(not(any(unix, windows)))]
+fn symlink(_target: &Path, _link: &Path) -> Result<()> {
+    Err(Error::InvalidArgument("symlinks aren't supported here".to_string()))
+}
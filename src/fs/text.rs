@@ -0,0 +1,136 @@
+use crate::{
+    Result,
+    fs::{
+        IndexStore,
+        MemoryStore,
+        TempFile,
+        TreeIndex,
+        TreeItem,
+        TreeItemDupes,
+        open_locked,
+        treeitem::escape_path
+    }
+};
+use log::debug;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// A TextStore keeps an index in the plain text format written by
+/// TreeIndex::write. The index is held in memory and changes are appended to
+/// the file as update lines, so the file is only rewritten by save.
+pub struct TextStore {
+    path: PathBuf,
+    mem: MemoryStore,
+    out: Option<Box<dyn Write>>,
+    // false for files written before paths were escaped
+    escaped: bool
+}
+
+impl TextStore {
+
+    /// Opens the index file at the path, it is created by the first write if
+    /// it doesn't exist
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut store = Self {
+            path: path.to_path_buf(),
+            mem: MemoryStore::default(),
+            out: None,
+            escaped: true
+        };
+        if path.is_file() {
+            store.reload()?;
+        }
+        Ok(store)
+    }
+
+    fn reload(&mut self) -> Result<()> {
+        debug!("reading text index {}", self.path.to_string_lossy());
+        self.out = None;
+        self.mem = MemoryStore::new(TreeIndex::read(File::open(&self.path)?, true)?);
+        self.escaped = self.mem.index().header.is_escaped();
+        Ok(())
+    }
+
+    // appends lines to the file, writing a header first for a new file. A
+    // file with paths that aren't escaped is rewritten first so the lines
+    // appended to it are read the way they are written.
+    fn append(&mut self, lines: &str) -> Result<()> {
+        if self.out.is_none() && !self.escaped && self.path.is_file() {
+            let index = self.mem.load()?;
+            self.save(&index)?;
+        }
+        if self.out.is_none() {
+            let new = !self.path.is_file();
+            let mut out: Box<dyn Write> = Box::new(open_locked(&self.path, true)?);
+            if new {
+                write!(out, "{}", self.mem.index().header.for_text())?;
+                self.escaped = true;
+            }
+            self.out = Some(out);
+        }
+        if let Some(out) = self.out.as_mut() {
+            out.write_all(lines.as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+impl IndexStore for TextStore {
+
+    fn load(&mut self) -> Result<TreeIndex> {
+        self.flush()?;
+        if self.path.is_file() {
+            self.reload()?;
+        }
+        self.mem.load()
+    }
+
+    fn save_item(&mut self, item: &TreeItem) -> Result<()> {
+        let path = item.path();
+        match self.mem.key_of(&path) {
+            Some(old) if *old == self.mem.index().key(item) => return Ok(()),
+            Some(_) => self.append(&format!("! {}\n{}", escape_path(&path)?, item))?,
+            None => self.append(&item.to_string())?
+        }
+        self.mem.save_item(item)
+    }
+
+    fn remove_path(&mut self, path: &Path) -> Result<bool> {
+        if !self.mem.remove_path(path)? {
+            return Ok(false);
+        }
+        self.append(&format!("! {}\n", escape_path(path)?))?;
+        Ok(true)
+    }
+
+    fn lookup_digest(&self, digest: &str) -> Result<Option<TreeItemDupes>> {
+        self.mem.lookup_digest(digest)
+    }
+
+    fn lookup_path(&self, path: &Path) -> Result<Option<TreeItem>> {
+        self.mem.lookup_path(path)
+    }
+
+    fn iter(&self) -> Result<Box<dyn Iterator<Item = Result<TreeItemDupes>> + '_>> {
+        self.mem.iter()
+    }
+
+    fn save(&mut self, index: &TreeIndex) -> Result<()> {
+        // rewrite the whole file so it doesn't carry any update lines, in a
+        // temp file so a failed write leaves the old one
+        self.out = None;
+        let mut tmp = TempFile::next_to(&self.path)?;
+        index.write(&mut tmp)?;
+        tmp.persist(&self.path)?;
+        self.escaped = true;
+        self.mem.save(index)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if let Some(out) = self.out.as_mut() {
+            out.flush()?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,141 @@
+use crate::{
+    error::Error,
+    Result
+};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// The IO priority to scan with. Background scans only get the disk when
+/// nothing else wants it: the idle IO class on Linux, throttled IO on macOS
+/// and background mode on Windows.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum IoPriority {
+    #[default]
+    Normal,
+    Background
+}
+
+impl FromStr for IoPriority {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "normal" => Ok(IoPriority::Normal),
+            "background" | "idle" => Ok(IoPriority::Background),
+            _ => Err(Error::InvalidArgument(format!("unknown io priority {}", s)))
+        }
+    }
+}
+
+impl Display for IoPriority {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error>
+    {
+        match self {
+            IoPriority::Normal => write!(f, "normal"),
+            IoPriority::Background => write!(f, "background")
+        }
+    }
+}
+
+/// This function lowers the IO priority of the calling thread, and the
+/// threads it starts afterwards, to background. On macOS and Windows the
+/// whole process is lowered. Normal doesn't change anything since raising
+/// the priority again can need privileges.
+pub fn set_io_priority(priority: IoPriority) -> Result<()> {
+    match priority {
+        IoPriority::Normal => Ok(()),
+        IoPriority::Background => background()
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn background() -> Result<()> {
+    // ioprio_set(IOPRIO_WHO_PROCESS, 0, IOPRIO_PRIO_VALUE(IOPRIO_CLASS_IDLE, 0))
+    // where a pid of 0 is the calling thread
+    const IOPRIO_WHO_PROCESS: libc::c_long = 1;
+    const IOPRIO_CLASS_IDLE: libc::c_long = 3;
+    const IOPRIO_CLASS_SHIFT: libc::c_long = 13;
+    let ret = unsafe {
+        libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0 as libc::c_long, IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT)
+    };
+    if ret < 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "macos")]
+fn background() -> Result<()> {
+    const IOPOL_TYPE_DISK: libc::c_int = 0;
+    const IOPOL_SCOPE_PROCESS: libc::c_int = 0;
+    const IOPOL_THROTTLE: libc::c_int = 3;
+    extern "C" {
+        fn setiopolicy_np(iotype: libc::c_int, scope: libc::c_int, policy: libc::c_int) -> libc::c_int;
+    }
+    if unsafe { setiopolicy_np(IOPOL_TYPE_DISK, IOPOL_SCOPE_PROCESS, IOPOL_THROTTLE) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+fn background() -> Result<()> {
+    const PROCESS_MODE_BACKGROUND_BEGIN: u32 = 0x0010_0000;
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetCurrentProcess() -> *mut std::ffi::c_void;
+        fn SetPriorityClass(process: *mut std::ffi::c_void, class: u32) -> i32;
+    }
+    if unsafe { SetPriorityClass(GetCurrentProcess(), PROCESS_MODE_BACKGROUND_BEGIN) } == 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn background() -> Result<()> {
+    Err(Error::InvalidArgument("background io priority isn't supported here".to_string()))
+}
+
+// Paces a scan to at most so many files and bytes a second, averaged from
+// the first file. The threads of a parallel scan share one.
+#[derive(Debug, Default)]
+pub(crate) struct RateLimiter {
+    state: Mutex<RateState>
+}
+
+#[derive(Debug, Default)]
+struct RateState {
+    start: Option<Instant>,
+    files: u64,
+    bytes: u64
+}
+
+impl RateLimiter {
+
+    // counts a file about to be read and returns how long to wait before
+    // reading it to stay under the limits, a limit of 0 is no limit
+    pub(crate) fn take(&self, bytes: u64, max_files: u64, max_bytes: u64) -> Duration {
+        if max_files == 0 && max_bytes == 0 {
+            return Duration::ZERO;
+        }
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        let start = *state.start.get_or_insert(now);
+
+        // the file is paid for once the ones before it are
+        let (files, done) = (state.files, state.bytes);
+        state.files += 1;
+        state.bytes += bytes;
+        let mut due = Duration::ZERO;
+        if max_files > 0 {
+            due = due.max(Duration::from_secs_f64(files as f64 / max_files as f64));
+        }
+        if max_bytes > 0 {
+            due = due.max(Duration::from_secs_f64(done as f64 / max_bytes as f64));
+        }
+        due.saturating_sub(now - start)
+    }
+}
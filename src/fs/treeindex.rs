@@ -0,0 +1,1341 @@
+use crate::{
+    error::Error,
+    Result,
+    fs::{
+        checksum,
+        chunks::CHUNKS_TAG,
+        FileKind,
+        fuzzy::FUZZY_TAG,
+        media::IMAGE_HASH_TAG,
+        metadata::METADATA_TAG,
+        treeitem::{FAST_CHUNK, SPECIAL_TAG, read_path},
+        Algorithm,
+        ChecksumFormat,
+        DigestKey,
+        DEFAULT_FP_RATE,
+        digest_hex,
+        IndexStats,
+        ItemDigest,
+        ScanStats,
+        SizeFilter,
+        TreeIndexDiff,
+        TreeIndexHeader,
+        TreeItem,
+        TreeItemBuilder,
+        TreeItemDupes,
+        TreeList,
+        TreeListBuilder,
+        IndexStore,
+        ScanScope
+    },
+    fs::binary::{BinaryWriter, is_binary, read_binary},
+    fs::confirm::{ConfirmMode, Confirmer},
+    fs::fastread::read_lines_fast,
+    fs::csv::{is_csv, read_csv, write_csv_entry, write_csv_header},
+    fs::ndjson::write_ndjson_entry,
+    fs::remote::read_remote,
+    fs::key::KeyDigest,
+    fs::spill::{entry_cost, MemoryUsage, Spill},
+    fs::treelist::ScanSummary,
+    units::IntoBytes
+};
+#[cfg(feature = "sqlite")]
+use crate::fs::SqliteStore;
+#[cfg(feature = "sign")]
+use crate::fs::{SigningWriter, VerifyingReader};
+#[cfg(feature = "sign")]
+use ed25519_dalek::{SigningKey, VerifyingKey};
+use log::{debug, warn};
+use std::collections::{HashMap, HashSet};
+use std::convert::From;
+use std::env;
+use std::fmt::{Display, Formatter};
+use std::io::{BufReader, BufRead, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Instant;
+
+#[derive(Clone)]
+pub(crate) enum TreeWork {
+    Scan(PathBuf, ScanScope),
+    Digest(PathBuf),
+    #[cfg(feature = "archive")]
+    Archive(PathBuf),
+    Special(PathBuf, FileKind)
+}
+
+/// The formats an index can be written in. Text is one line per path and
+/// can be read by people and tools like grep, binary is smaller and faster
+/// to read. CSV has a row per path for spreadsheets and data pipelines, it
+/// has no header so the header fields are lost. Readers tell those apart by
+/// the first bytes. NDJSON is a JSON object per line for other tools to
+/// read from a pipe, its paths are escaped like the text format's and it
+/// can't be read back.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum IndexFormat {
+    #[default]
+    Text,
+    Binary,
+    Csv,
+    Ndjson
+}
+
+impl FromStr for IndexFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "text" => Ok(IndexFormat::Text),
+            "binary" | "bin" => Ok(IndexFormat::Binary),
+            "csv" => Ok(IndexFormat::Csv),
+            "ndjson" | "jsonl" => Ok(IndexFormat::Ndjson),
+            _ => Err(Error::InvalidArgument(format!("unknown index format {}", s)))
+        }
+    }
+}
+
+impl Display for IndexFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error>
+    {
+        match self {
+            IndexFormat::Text => write!(f, "text"),
+            IndexFormat::Binary => write!(f, "binary"),
+            IndexFormat::Csv => write!(f, "csv"),
+            IndexFormat::Ndjson => write!(f, "ndjson")
+        }
+    }
+}
+
+// A TreeIndex is a map from digest key to TreeItemDupes. stats has what the
+// scan behind the index did, it is empty for indexes that were read.
+#[derive(Clone, Default)]
+pub struct TreeIndex {
+    pub header: TreeIndexHeader,
+    pub idx: HashMap<DigestKey, TreeItemDupes>,
+    pub stats: ScanStats
+}
+
+impl TreeIndex {
+
+    /// Reads an index in the text, binary or CSV format. Without dupes, only
+    /// the first path for each digest is kept. Items whose digest collides with an item of
+    /// a different size get an entry of their own.
+    pub fn read<R: Read>(r: R, with_dupes: bool) -> Result<Self> {
+        Self::read_with(r, with_dupes, false)
+    }
+
+    /// Reads an index like read but parses the text format with the reader
+    /// that allocates less, see TreeIndexBuilder::from_reader_fast
+    pub fn read_fast<R: Read>(r: R, with_dupes: bool) -> Result<Self> {
+        Self::read_with(r, with_dupes, true)
+    }
+
+    fn read_with<R: Read>(r: R, with_dupes: bool, fast: bool) -> Result<Self> {
+        let mut ti = TreeIndex::default();
+        let header = read_index(r, fast, &mut |line| {
+            ti.read_line(line, with_dupes);
+            Ok(())
+        })?;
+        ti.header = header;
+        ti.rekey();
+        Ok(ti)
+    }
+
+    // adds an item from a list or a scan, the first path with a digest is
+    // the main item and the rest are its dupes
+    fn add_listed(&mut self, i: &TreeItem, with_dupes: bool) {
+        let key = self.key(i);
+        match self.idx.get_mut(&key) {
+            Some(item) => {
+                if with_dupes {
+                    item.push_item(i);
+                }
+            },
+            None => {
+                self.idx.insert(key, TreeItemDupes::from(i));
+            }
+        }
+    }
+
+    // adds or removes the item of a line read from an index
+    fn read_line(&mut self, line: IndexLine, with_dupes: bool) {
+        match line {
+            IndexLine::Item(item) => {
+                let key = self.key(&item);
+                match self.idx.get_mut(&key) {
+                    Some(entry) => {
+                        if with_dupes {
+                            entry.push_item(&item);
+                        }
+                    },
+                    None => {
+                        self.idx.insert(key, TreeItemDupes::from(&item));
+                    }
+                }
+            },
+            IndexLine::Remove(path) => {
+                self.remove_path(&path);
+            }
+        }
+    }
+
+    /// Returns the key of the item in this index, the algorithm comes from
+    /// the header
+    pub fn key(&self, item: &TreeItem) -> DigestKey {
+        DigestKey::of(self.header.algorithm().unwrap_or_default(), item)
+    }
+
+    // keys every entry again after the header's algorithm changed
+    pub(crate) fn rekey(&mut self) {
+        let algo = self.header.algorithm().unwrap_or_default();
+        if self.idx.keys().any(|k| k.algo != algo) {
+            self.idx = self.idx.drain().map(|(k, v)| (DigestKey { algo, ..k }, v)).collect();
+        }
+    }
+
+    /// Returns the entries with the digest, there is more than one if the
+    /// digest collides across files of different sizes. The digest can be
+    /// in hex or a multihash.
+    pub fn get_digest(&self, digest: &str) -> Vec<&TreeItemDupes> {
+        let probe = DigestKey::new(Algorithm::default(), digest, 0);
+        let mut found: Vec<&TreeItemDupes> = self.idx.iter()
+            .filter(|(k, _)| k.digest == probe.digest)
+            .map(|(_, v)| v)
+            .collect();
+        found.sort_by_key(|v| v.item.size);
+        found
+    }
+
+    /// Returns the entries whose digest starts with the prefix, sorted by
+    /// digest. This searches the whole index, it is meant for looking up the
+    /// short digests that people and other tools use.
+    pub fn find_prefix(&self, prefix: &str) -> Vec<&TreeItemDupes> {
+        let mut found: Vec<&TreeItemDupes> = self.idx.iter()
+            .filter(|(k, _)| k.has_prefix(prefix))
+            .map(|(_, v)| v)
+            .collect();
+        found.sort_by(|a, b| a.item.digest.cmp(&b.item.digest).then_with(|| a.item.size.cmp(&b.item.size)));
+        found
+    }
+
+    /// Roughly how many bytes the entries take in memory, the same estimate
+    /// a memory limit is checked against
+    pub fn memory_estimate(&self) -> u64 {
+        self.idx.values().map(entry_cost).sum()
+    }
+
+    /// Returns the pairs of entries that have the same digest but different
+    /// sizes. Full digests practically never collide, fast digests only
+    /// cover part of a file and do.
+    pub fn collisions(&self) -> Vec<(&TreeItemDupes, &TreeItemDupes)> {
+        let mut by_digest: HashMap<&KeyDigest, Vec<&TreeItemDupes>> = HashMap::new();
+        for (k, v) in &self.idx {
+            by_digest.entry(&k.digest).or_default().push(v);
+        }
+        let mut pairs = Vec::new();
+        for mut group in by_digest.into_values().filter(|g| g.len() > 1) {
+            group.sort_by_key(|v| v.item.size);
+            for other in &group[1..] {
+                pairs.push((group[0], *other));
+            }
+        }
+        pairs.sort_by(|a, b| a.0.item.digest.cmp(&b.0.item.digest).then_with(|| a.1.item.size.cmp(&b.1.item.size)));
+        pairs
+    }
+
+    /// Writes the header followed by every item and its dupes. The paths
+    /// are always escaped, see PATHS_FIELD, so every path reads back the
+    /// same.
+    pub fn write(&self, w: &mut dyn Write) -> Result<()> {
+        write!(w, "{}", self.header.for_text())?;
+        for item in self.idx.values() {
+            write!(w, "{}", item)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the index in the binary format, which is several times smaller
+    /// than the text format and faster to read. The entries are sorted by
+    /// path so the paths compress well.
+    pub fn write_binary(&self, w: &mut dyn Write) -> Result<()> {
+        let mut entries: Vec<&TreeItemDupes> = self.idx.values().collect();
+        entries.sort_by_cached_key(|e| e.item.path());
+        let mut bw = BinaryWriter::new(w, &self.header)?;
+        for entry in entries {
+            bw.entry(entry)?;
+        }
+        bw.finish()
+    }
+
+    /// Writes the index as CSV with a row for every path, sorted by path.
+    /// The rows of dupes have the path of the item they are a copy of.
+    pub fn write_csv(&self, w: &mut dyn Write) -> Result<()> {
+        let mut entries: Vec<&TreeItemDupes> = self.idx.values().collect();
+        entries.sort_by_cached_key(|e| e.item.path());
+        write_csv_header(w)?;
+        for entry in entries {
+            write_csv_entry(w, entry)?;
+        }
+        Ok(())
+    }
+
+    /// Writes every entry as a line of JSON, sorted by path
+    pub fn write_ndjson(&self, w: &mut dyn Write) -> Result<()> {
+        let mut entries: Vec<&TreeItemDupes> = self.idx.values().collect();
+        entries.sort_by_cached_key(|e| e.item.path());
+        for entry in entries {
+            write_ndjson_entry(w, entry)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the index in the given format
+    pub fn write_format(&self, w: &mut dyn Write, format: IndexFormat) -> Result<()> {
+        match format {
+            IndexFormat::Text => self.write(w),
+            IndexFormat::Binary => self.write_binary(w),
+            IndexFormat::Csv => self.write_csv(w),
+            IndexFormat::Ndjson => self.write_ndjson(w)
+        }
+    }
+
+    /// Writes the index followed by an ed25519 signature trailer line.
+    /// Readers that don't check signatures skip the trailer as a comment.
+    #[cfg(feature = "sign")]
+    pub fn write_signed(&self, w: &mut dyn Write, key: &SigningKey) -> Result<()> {
+        let mut sw = SigningWriter::new(w, key);
+        self.write(&mut sw)?;
+        sw.finish()
+    }
+
+    /// Writes every path in the index as a checksum line, sorted by path, so
+    /// the output can be checked with sha256sum -c or b2sum -c. Fast digests
+    /// only cover part of a file so they can't be written this way.
+    pub fn write_checksums(&self, w: &mut dyn Write, format: ChecksumFormat) -> Result<()> {
+        if self.header.is_fast() {
+            return Err(Error::InvalidArgument("fast digests can't be written as checksums".to_string()));
+        }
+        let algorithm = self.header.algorithm()?;
+        let encoding = self.header.encoding()?;
+        if algorithm == Algorithm::Blake2b && format == ChecksumFormat::Gnu {
+            debug!("blake2b checksums have to be checked with b2sum -l 256");
+        }
+
+        let mut lines: Vec<(PathBuf, &ItemDigest)> = Vec::new();
+        for v in self.idx.values() {
+            lines.push((v.item.path(), &v.item.digest));
+            for d in &v.dupes {
+                lines.push((d.path(), &v.item.digest));
+            }
+        }
+        lines.sort();
+        for (path, digest) in lines {
+            let path = path.to_str().ok_or(std::fmt::Error)?;
+            let hex = match digest.digest() {
+                Some(d) => d.to_string(),
+                None => digest_hex(&digest.to_string(), encoding)?
+            };
+            checksum::write_line(w, format, algorithm, &hex, path)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the duplicate groups in the format used by fdupes and jdupes,
+    /// one path per line with a blank line after each group. With sizes each
+    /// group starts with a "N bytes each:" line like fdupes -S. The groups
+    /// are sorted biggest first.
+    pub fn write_fdupes(&self, w: &mut dyn Write, sizes: bool) -> Result<()> {
+        let mut groups: Vec<&TreeItemDupes> = self.idx.values()
+            .filter(|v| !v.dupes.is_empty())
+            .collect();
+        groups.sort_by_cached_key(|g| (std::cmp::Reverse(g.item.size), g.item.path()));
+        for g in groups {
+            if sizes {
+                writeln!(w, "{} byte{} each:", g.item.size, if g.item.size == 1 { "" } else { "s" })?;
+            }
+            writeln!(w, "{}", g.item.path().to_string_lossy())?;
+            for d in &g.dupes {
+                writeln!(w, "{}", d.path().to_string_lossy())?;
+            }
+            writeln!(w)?;
+        }
+        Ok(())
+    }
+
+    /// Adds an item to the index. If there is already an item with the same
+    /// digest and size then the item's path is added to its dupes.
+    pub fn insert(&mut self, item: TreeItem) {
+        let key = self.key(&item);
+        match self.idx.get_mut(&key) {
+            Some(i) => {
+                if i.item.path != item.path && !i.dupes.contains(&item.path) {
+                    i.push_item(&item);
+                }
+            },
+            None => {
+                self.idx.insert(key, TreeItemDupes::from(&item));
+            }
+        }
+    }
+
+    /// Removes the path from the entry with the given key. If the path is
+    /// the main item, the first dupe takes its place. Entries left without
+    /// any paths are removed. Returns true if the path was found.
+    pub fn remove(&mut self, key: &DigestKey, path: &Path) -> bool {
+        let entry = match self.idx.get_mut(key) {
+            Some(entry) => entry,
+            None => return false
+        };
+        if entry.item.path() == path {
+            if entry.dupes.is_empty() {
+                self.idx.remove(key);
+            } else {
+                let dupe = entry.dupes.remove(0);
+                entry.item.metadata = entry.dupe_metadata.remove(&dupe);
+                entry.item.path = dupe;
+            }
+            true
+        } else if let Some(pos) = entry.dupes.iter().position(|d| d.path() == path) {
+            let dupe = entry.dupes.remove(pos);
+            entry.dupe_metadata.remove(&dupe);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Removes the path from the index without knowing its key. This has to
+    /// search the whole index so prefer remove when the key is known.
+    pub fn remove_path(&mut self, path: &Path) -> bool {
+        let key = self.idx.iter()
+            .find(|(_, v)| v.item.path() == path || v.dupes.iter().any(|d| d.path() == path))
+            .map(|(k, _)| k.clone());
+        match key {
+            Some(k) => self.remove(&k, path),
+            None => false
+        }
+    }
+
+    /// Upgrades a version 1 index to the current version. Version 1 files
+    /// have no header so they don't say if their digests are fast, that has
+    /// to be given. Full digests and fast digests of files up to 1 MiB are
+    /// the same in both versions. Longer fast digests now include the size
+    /// so one copy of each of those files is digested again under root,
+    /// entries without a copy left there are logged and dropped. Returns
+    /// how many files were digested.
+    pub fn upgrade_from_v1(&mut self, root: &Path, fast: bool) -> Result<usize> {
+        if self.header.version != 1 {
+            return Err(Error::InvalidArgument(format!("the index is version {}, not 1", self.header.version)));
+        }
+        let old = std::mem::take(&mut self.idx);
+        self.header = TreeIndexHeader::default();
+        self.header.set_fast(fast);
+
+        let mut digested = 0;
+        for (_, mut entry) in old {
+            if fast && entry.item.size > FAST_CHUNK {
+                let paths: Vec<PathBuf> = std::iter::once(entry.item.item_path())
+                    .chain(entry.dupes.iter())
+                    .map(|p| p.path())
+                    .collect();
+                let digest = paths.iter()
+                    .find_map(|p| TreeItemBuilder::new().fast(true).path(&root.join(p)).build().ok());
+                match digest {
+                    Some(item) => {
+                        digested += 1;
+                        entry.item.digest = item.digest;
+                    },
+                    None => {
+                        warn!("no copy of {} to digest under {}, it was dropped",
+                              entry.item.path().to_string_lossy(), root.to_string_lossy());
+                        continue;
+                    }
+                }
+            }
+            let key = self.key(&entry.item);
+            match self.idx.get_mut(&key) {
+                Some(e) => {
+                    e.push(entry.item.path.clone());
+                    for p in entry.dupes {
+                        e.push(p);
+                    }
+                },
+                None => {
+                    self.idx.insert(key, entry);
+                }
+            }
+        }
+        Ok(digested)
+    }
+
+    /// Replaces the from prefix of every path in the index with to, so an
+    /// index made where the tree was mounted somewhere else can be used
+    /// here. Prefixes match whole path components. Returns how many paths
+    /// were changed.
+    pub fn rewrite_prefix(&mut self, from: &Path, to: &Path) -> usize {
+        self.idx.values_mut().map(|v| v.rewrite_prefix(from, to)).sum()
+    }
+
+    /// Normalizes every path in the index to Unicode NFC so paths from file
+    /// systems that store them decomposed, like on macOS, compare equal to
+    /// the same paths from other systems. Returns how many paths were
+    /// changed.
+    #[cfg(feature = "unicode")]
+    pub fn normalize_unicode(&mut self) -> usize {
+        self.header.set_nfc(true);
+        self.idx.values_mut().map(|v| v.normalize_unicode()).sum()
+    }
+
+    /// Re-hashes the tree under root and compares it to this index. Files are
+    /// digested the same way the index was, fast or full, and every path is
+    /// kept so copies are checked too. The index paths have to be relative
+    /// to the same place as root, i.e. scan the same root the index was made
+    /// from.
+    pub fn verify(&self, root: &Path) -> Result<TreeIndexDiff> {
+        self.verify_with(root, None)
+    }
+
+    /// The same as verify for indexes built with keyed digests, the key
+    /// has to be the one the index was built with
+    pub fn verify_keyed(&self, root: &Path, key: &[u8]) -> Result<TreeIndexDiff> {
+        self.verify_with(root, Some(key))
+    }
+
+    fn verify_with(&self, root: &Path, key: Option<&[u8]>) -> Result<TreeIndexDiff> {
+        if self.header.is_keyed() != key.is_some() {
+            return Err(Error::InvalidArgument(
+                "a key is needed for keyed indexes and only for keyed indexes".to_string()));
+        }
+        debug!("verifying index against {}", root.to_string_lossy());
+        let mut builder = TreeListBuilder::new()
+            .fast(self.header.is_fast())
+            .algorithm(self.header.algorithm()?)
+            .encoding(self.header.encoding()?);
+        if let Some(key) = key {
+            builder = builder.key(key);
+        }
+        if let Some(c) = self.header.chunking()? {
+            builder = builder.chunking(c);
+        }
+        #[cfg(feature = "archive")]
+        {
+            builder = builder.archives(self.header.has_archives());
+        }
+        let scan = builder
+            .with_metadata(self.header.has_metadata())
+            .path(root);
+        let current = TreeIndexBuilder::new()
+            .with_dupes(true)
+            .from_scan(scan)
+            .build()?;
+        Ok(TreeIndexDiff::between(self, &current))
+    }
+
+    /// Returns the set of file sizes in the index
+    pub fn sizes(&self) -> HashSet<u64> {
+        self.idx.values().map(|v| v.item.size).collect()
+    }
+
+    /// Returns a bloom filter of the sizes in the index and the pairs of a
+    /// size and the start of a digest, that a TreeList scan can use to skip
+    /// digesting files that can't match anything and to leave out the ones
+    /// it digested that don't
+    pub fn size_filter(&self) -> SizeFilter {
+        let mut filter = SizeFilter::new(self.idx.len() * 2, DEFAULT_FP_RATE);
+        for v in self.idx.values() {
+            filter.insert(v.item.size);
+            filter.insert_digest(v.item.size, &v.item.digest);
+        }
+        filter
+    }
+
+    pub fn max(&self) -> u64 {
+        let mut max = 0;
+        for (_, v) in self.idx.iter() {
+            if v.item.size > max {
+                max = v.item.size;
+            }
+        }
+        max
+    }
+
+    pub fn count_dupes(&self) -> usize {
+        let mut count = 0;
+        for (_, v) in self.idx.iter() {
+            count += v.dupes.len();
+        }
+        count
+    }
+
+    /// Returns the totals, the top largest duplicate groups and the file
+    /// size histogram of the index
+    pub fn report(&self, top: usize) -> IndexStats {
+        IndexStats::new(self, top)
+    }
+}
+
+#[derive(Default)]
+enum TreeIndexFrom<'a> {
+    #[default]
+    New,
+    List(&'a TreeList),
+    Scan(Box<TreeListBuilder<'a>>),
+    Reader(&'a mut Box<dyn Read>),
+    Checksums(&'a mut Box<dyn Read>, Algorithm),
+    Remote(&'a mut Box<dyn Read>),
+    Store(&'a mut dyn IndexStore),
+    #[cfg(feature = "sqlite")]
+    Sqlite(&'a Path),
+    Confirm(&'a TreeIndex)
+}
+
+#[derive(Default)]
+pub struct TreeIndexBuilder<'a> {
+    with_dupes: bool,
+    from: TreeIndexFrom<'a>,
+    spill: Option<u64>,
+    memory_limit: Option<u64>,
+    memory_usage: Option<MemoryUsage>,
+    scan_summary: Option<ScanSummary>,
+    spill_dir: Option<PathBuf>,
+    #[cfg(feature = "sign")]
+    sign: Option<&'a SigningKey>,
+    #[cfg(feature = "sign")]
+    verify_key: Option<VerifyingKey>,
+    prefixes: Vec<(PathBuf, PathBuf)>,
+    #[cfg(feature = "unicode")]
+    nfc: bool,
+    threads: usize,
+    prefilter: bool,
+    confirm_mode: ConfirmMode,
+    fail_on_collision: bool,
+    format: IndexFormat,
+    fast_reader: bool,
+    error: Option<Error>,
+}
+
+
+impl<'a> TreeIndexBuilder<'a> {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_dupes(mut self, dupes: bool) -> Self {
+        self.with_dupes = dupes;
+        self
+    }
+
+    pub fn from_list(mut self, list: &'a TreeList) -> Self {
+        self.from = TreeIndexFrom::List(list);
+        self
+    }
+
+    /// Scans the tree under the root with the default scan options and puts
+    /// each file into the index as soon as it has been digested, so the
+    /// items aren't held in a TreeList as well
+    pub fn from_path(self, root: &'a Path) -> Self {
+        self.from_scan(TreeListBuilder::new().path(root))
+    }
+
+    /// Scans with the builder like from_path, for scans that need options.
+    /// The stats of the scan end up in the index, see scan_summary for the
+    /// rest of what it found.
+    pub fn from_scan(mut self, scan: TreeListBuilder<'a>) -> Self {
+        self.from = TreeIndexFrom::Scan(Box::new(scan));
+        self
+    }
+
+    /// Gets the header, special file counts and stats of the scan when the
+    /// index is built from a path or a scan
+    pub fn scan_summary(mut self, summary: &ScanSummary) -> Self {
+        self.scan_summary = Some(summary.clone());
+        self
+    }
+
+    pub fn from_reader(mut self, r: &'a mut Box<dyn Read>) -> Self {
+        self.from = TreeIndexFrom::Reader(r);
+        self
+    }
+
+    /// Reads the index like from_reader but parses text indexes from one
+    /// reused buffer, splitting the lines as bytes and taking the paths
+    /// as they are on unix. It makes a fraction of the allocations and
+    /// will become what from_reader does once it has had more use.
+    pub fn from_reader_fast(mut self, r: &'a mut Box<dyn Read>) -> Self {
+        self.from = TreeIndexFrom::Reader(r);
+        self.fast_reader = true;
+        self
+    }
+
+    /// Reads a GNU or BSD style checksum file, like the output of sha256sum.
+    /// BSD lines name their algorithm, the algorithm given here is used for
+    /// GNU lines. The sizes are read from the files, if any are missing the
+    /// header says the sizes are unknown.
+    pub fn from_checksums(mut self, r: &'a mut Box<dyn Read>, algorithm: Algorithm) -> Self {
+        self.from = TreeIndexFrom::Checksums(r, algorithm);
+        self
+    }
+
+    /// Reads the files streamed by remote::serve, e.g. from a TCP stream or
+    /// the output of treetool serve run over ssh. The index gets the stats
+    /// of the remote scan.
+    pub fn from_remote(mut self, r: &'a mut Box<dyn Read>) -> Self {
+        self.from = TreeIndexFrom::Remote(r);
+        self
+    }
+
+    /// Loads the index from any IndexStore
+    pub fn from_store(mut self, store: &'a mut dyn IndexStore) -> Self {
+        self.from = TreeIndexFrom::Store(store);
+        self
+    }
+
+    /// Loads the index from a SQLite database created by SqliteStore
+    #[cfg(feature = "sqlite")]
+    pub fn from_sqlite(mut self, path: &'a Path) -> Self {
+        self.from = TreeIndexFrom::Sqlite(path);
+        self
+    }
+
+    /// Digests the originals and dupes of the index in full and keeps the
+    /// dupes that really match, with the digest algorithm and encoding of
+    /// the index. Dupes whose size changed are dropped without reading them.
+    pub fn confirm(mut self, index: &'a TreeIndex) -> Self {
+        self.from = TreeIndexFrom::Confirm(index);
+        self
+    }
+
+    /// Confirms the groups of the index on this many threads at once, the
+    /// default of 0 uses one per CPU
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    /// How dupes are checked against their original, the default is to
+    /// digest them
+    pub fn confirm_mode(mut self, mode: ConfirmMode) -> Self {
+        self.confirm_mode = mode;
+        self
+    }
+
+    /// Compares fast digests before confirming, so only the dupes whose
+    /// first and last MiB match the original's are read in full. Groups
+    /// left without dupes are dropped from the confirmed index.
+    pub fn prefilter(mut self, prefilter: bool) -> Self {
+        self.prefilter = prefilter;
+        self
+    }
+
+    /// Fail with DigestCollision when two files in the index have the same
+    /// digest but different sizes, by default they are kept as entries of
+    /// their own and only logged
+    pub fn fail_on_collision(mut self, fail: bool) -> Self {
+        self.fail_on_collision = fail;
+        self
+    }
+
+    /// Keep at most this many bytes of index in memory while building from
+    /// a list or a reader. The rest is written to sorted shard files that
+    /// are merged by digest at the end. This takes either a u64 or a human
+    /// readable string such as "2GiB". Use build_to to write the merged
+    /// index without ever holding all of it in memory. The shards don't keep
+    /// chunk digests or metadata, an index that spills is without them.
+    pub fn spill<B: IntoBytes>(mut self, budget: B) -> Self {
+        match budget.into_bytes() {
+            Ok(budget) => self.spill = Some(budget),
+            Err(e) => self.error = Some(e)
+        }
+        self
+    }
+
+    /// Keep the index in memory, chunk digests and metadata and all, until
+    /// it is estimated to take more than this many bytes and then spill it
+    /// the same way spill does. This takes either a u64 or a human readable
+    /// string such as "512MiB". The limit is only for build_to, build holds
+    /// the whole index in the end so it fails with a limit. An index with
+    /// chunk digests or metadata that goes over the limit fails too, the
+    /// shards can't keep them. Only lists, scans and readers can spill, the
+    /// limit is ignored with a warning for the other sources.
+    pub fn memory_limit<B: IntoBytes>(mut self, limit: B) -> Self {
+        match limit.into_bytes() {
+            Ok(limit) => self.memory_limit = Some(limit),
+            Err(e) => self.error = Some(e)
+        }
+        self
+    }
+
+    /// Records the peak memory the index took while it was built in usage.
+    /// Read it once build or build_to returns.
+    pub fn memory_usage(mut self, usage: &MemoryUsage) -> Self {
+        self.memory_usage = Some(usage.clone());
+        self
+    }
+
+    /// Where to write the shard files, the default is the system temp dir
+    pub fn spill_dir(mut self, dir: &Path) -> Self {
+        self.spill_dir = Some(dir.to_path_buf());
+        self
+    }
+
+    /// Signs the index written by build_to with this key
+    #[cfg(feature = "sign")]
+    pub fn sign(mut self, key: &'a SigningKey) -> Self {
+        self.sign = Some(key);
+        self
+    }
+
+    /// Checks that an index read with from_reader was signed by this key,
+    /// the build fails if it wasn't signed, was signed by another key or was
+    /// changed after it was signed
+    #[cfg(feature = "sign")]
+    pub fn verify_signature(mut self, key: VerifyingKey) -> Self {
+        self.verify_key = Some(key);
+        self
+    }
+
+    /// Replaces the from prefix of the paths in the index with to once it is
+    /// built, see TreeIndex::rewrite_prefix. Maps are applied in the order
+    /// they are added.
+    pub fn map_prefix(mut self, from: &Path, to: &Path) -> Self {
+        self.prefixes.push((from.to_path_buf(), to.to_path_buf()));
+        self
+    }
+
+    /// Normalizes the paths in the index to Unicode NFC once it is built and
+    /// before any prefix maps are applied, see TreeIndex::normalize_unicode
+    #[cfg(feature = "unicode")]
+    pub fn normalize_unicode(mut self, nfc: bool) -> Self {
+        self.nfc = nfc;
+        self
+    }
+
+    /// The format build_to writes the index in, the default is text
+    pub fn format(mut self, format: IndexFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Builds the index and writes it out in the format. When spilling,
+    /// entries are written as they come out of the merge, sorted by digest.
+    pub fn build_to(mut self, w: &mut dyn Write) -> Result<()> {
+        self.check()?;
+        #[cfg(feature = "sign")]
+        if let Some(key) = self.sign.take() {
+            if self.format != IndexFormat::Text {
+                return Err(Error::ConflictingOptions("only text indexes can be signed".to_string()));
+            }
+            let mut sw = SigningWriter::new(w, key);
+            self.write_to(&mut sw)?;
+            return sw.finish();
+        }
+        self.write_to(w)
+    }
+
+    fn write_to(mut self, w: &mut dyn Write) -> Result<()> {
+        let format = self.format;
+        if !self.spills() {
+            return self.build()?.write_format(w, format);
+        }
+        let prefixes = std::mem::take(&mut self.prefixes);
+        #[cfg(feature = "unicode")]
+        let nfc = self.nfc;
+        let usage = self.memory_usage.take();
+        #[cfg(feature = "tracing")]
+        let span = crate::fs::stats::index_span().entered();
+        #[cfg(feature = "tracing")]
+        let counts = std::cell::Cell::new((0usize, 0usize));
+        let (header, _stats, spill) = self.fill_spill()?;
+        if let Some(u) = &usage {
+            u.record(spill.peak());
+        }
+        #[cfg(feature = "unicode")]
+        let header = {
+            let mut header = header;
+            header.set_nfc(nfc || header.is_nfc());
+            header
+        };
+        let fix = |entry: &mut TreeItemDupes| {
+            #[cfg(feature = "tracing")]
+            counts.set((counts.get().0 + 1, counts.get().1 + entry.dupes.len()));
+            #[cfg(feature = "unicode")]
+            if nfc {
+                entry.normalize_unicode();
+            }
+            for (from, to) in &prefixes {
+                entry.rewrite_prefix(from, to);
+            }
+        };
+        let written = match format {
+            IndexFormat::Text => {
+                write!(w, "{}", header.for_text())?;
+                spill.finish(&mut |mut entry| {
+                    fix(&mut entry);
+                    write!(w, "{}", entry)?;
+                    Ok(())
+                })
+            },
+            IndexFormat::Binary => {
+                let mut bw = BinaryWriter::new(w, &header)?;
+                spill.finish(&mut |mut entry| {
+                    fix(&mut entry);
+                    bw.entry(&entry)
+                })?;
+                bw.finish()
+            },
+            IndexFormat::Csv => {
+                write_csv_header(w)?;
+                spill.finish(&mut |mut entry| {
+                    fix(&mut entry);
+                    write_csv_entry(w, &entry)
+                })
+            },
+            IndexFormat::Ndjson => {
+                spill.finish(&mut |mut entry| {
+                    fix(&mut entry);
+                    write_ndjson_entry(w, &entry)
+                })
+            }
+        };
+        #[cfg(feature = "tracing")]
+        {
+            let (entries, dupes) = counts.get();
+            span.record("entries", entries);
+            span.record("dupes", dupes);
+            _stats.record(&span);
+        }
+        written
+    }
+
+    pub fn build(mut self) -> Result<TreeIndex> {
+        if self.memory_limit.is_some() && self.spills() {
+            return Err(Error::ConflictingOptions(
+                "build holds the whole index in memory, use build_to to keep it under a memory limit".to_string()));
+        }
+        let prefixes = std::mem::take(&mut self.prefixes);
+        #[cfg(feature = "unicode")]
+        let nfc = self.nfc;
+        let mut ti = self.build_index()?;
+        #[cfg(feature = "unicode")]
+        if nfc {
+            let n = ti.normalize_unicode();
+            debug!("normalized {} paths to NFC", n);
+        }
+        for (from, to) in &prefixes {
+            let n = ti.rewrite_prefix(from, to);
+            debug!("mapped {} paths from {} to {}", n, from.to_string_lossy(), to.to_string_lossy());
+        }
+        Ok(ti)
+    }
+
+    // builds the index in a span of its own with the counts it ends up with
+    fn build_index(self) -> Result<TreeIndex> {
+        #[cfg(feature = "tracing")]
+        let span = crate::fs::stats::index_span().entered();
+        let ti = self.fill_index()?;
+        #[cfg(feature = "tracing")]
+        {
+            span.record("entries", ti.idx.len());
+            span.record("dupes", ti.count_dupes());
+            ti.stats.record(&span);
+        }
+        Ok(ti)
+    }
+
+    fn fill_index(mut self) -> Result<TreeIndex> {
+        self.check()?;
+        if self.spills() {
+            let fail_on_collision = self.fail_on_collision;
+            let usage = self.memory_usage.take();
+            let (header, stats, spill) = self.fill_spill()?;
+            let mut ti = TreeIndex { header, stats, ..Default::default() };
+            spill.finish(&mut |entry| {
+                ti.idx.insert(ti.key(&entry.item), entry);
+                Ok(())
+            })?;
+            // the merged index ends up in memory anyway
+            if let Some(u) = &usage {
+                u.record(ti.memory_estimate());
+            }
+            check_collisions(&ti, fail_on_collision)?;
+            return Ok(ti);
+        }
+        if self.memory_limit.is_some() {
+            warn!("only indexes built from a list, a scan or a reader can be kept under a memory limit");
+        }
+        let usage = self.memory_usage.take();
+        let summary = self.scan_summary.take();
+
+        let mut ti = TreeIndex::default();
+        match self.from {
+
+            // check turns away builders without a source
+            TreeIndexFrom::New => {}
+
+            // build an index from a tree list
+            TreeIndexFrom::List(l) => {
+                debug!("constructing index from list");
+                ti.header = l.header.clone();
+                ti.stats = l.stats;
+                for i in &l.list {
+                    ti.add_listed(i, self.with_dupes);
+                }
+            },
+
+            // build an index from a scan without keeping a list
+            TreeIndexFrom::Scan(scan) => {
+                debug!("constructing index from scan");
+                let with_dupes = self.with_dupes;
+                let tl = scan.build_each(&mut |i| {
+                    if i.kind == FileKind::File {
+                        ti.add_listed(i, with_dupes);
+                    }
+                    Ok(())
+                })?;
+                ti.header = tl.header.clone();
+                ti.stats = tl.stats;
+                if let Some(s) = &summary {
+                    s.record(tl);
+                }
+            },
+
+            TreeIndexFrom::Reader(r) => {
+                debug!("constructing index from reader");
+                #[cfg(feature = "sign")]
+                if let Some(key) = self.verify_key {
+                    let mut vr = VerifyingReader::new(r, key);
+                    ti = TreeIndex::read_with(&mut vr, self.with_dupes, self.fast_reader)?;
+                    vr.finish()?;
+                    return Ok(ti);
+                }
+                ti = TreeIndex::read_with(r, self.with_dupes, self.fast_reader)?;
+            },
+
+            TreeIndexFrom::Checksums(r, algorithm) => {
+                debug!("constructing index from checksums");
+                ti = checksum::read_checksums(r, algorithm, self.with_dupes)?;
+            },
+
+            TreeIndexFrom::Remote(r) => {
+                debug!("constructing index from remote scan");
+                let with_dupes = self.with_dupes;
+                let (header, stats) = read_remote(r, &mut |line| {
+                    ti.read_line(line, with_dupes);
+                    Ok(())
+                })?;
+                ti.header = header;
+                ti.stats = stats;
+                ti.rekey();
+            },
+
+            TreeIndexFrom::Store(store) => {
+                debug!("constructing index from store");
+                ti = store.load()?;
+                if !self.with_dupes {
+                    for entry in ti.idx.values_mut() {
+                        entry.dupes.clear();
+                        entry.dupe_metadata.clear();
+                    }
+                }
+            },
+
+            #[cfg(feature = "sqlite")]
+            TreeIndexFrom::Sqlite(p) => {
+                debug!("constructing index from sqlite");
+                ti = SqliteStore::open(p)?.load()?;
+                if !self.with_dupes {
+                    for entry in ti.idx.values_mut() {
+                        entry.dupes.clear();
+                        entry.dupe_metadata.clear();
+                    }
+                }
+            },
+
+            TreeIndexFrom::Confirm(i) => {
+                debug!("constructing confirmed dupe index from index");
+                let start = Instant::now();
+                let confirmer = Confirmer::new(&i.header, self.prefilter, self.confirm_mode)?;
+                ti.header = confirmer.header(&i.header);
+
+                // the groups are keyed by their new digests, identical
+                // originals in different groups end up in one
+                for entry in confirmer.run(i, self.threads)? {
+                    let key = ti.key(&entry.item);
+                    match ti.idx.get_mut(&key) {
+                        Some(e) => {
+                            e.push(entry.item.path.clone());
+                            for p in entry.dupes {
+                                e.push(p);
+                            }
+                        },
+                        None => {
+                            ti.idx.insert(key, entry);
+                        }
+                    }
+                }
+                ti.stats = confirmer.counters.stats(start.elapsed());
+            }
+        }
+        if let Some(u) = &usage {
+            u.record(ti.memory_estimate());
+        }
+        check_collisions(&ti, self.fail_on_collision)?;
+        Ok(ti)
+    }
+
+    // fails on options that couldn't be parsed, a builder without a source
+    // and options the source can't use
+    fn check(&mut self) -> Result<()> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        if matches!(self.from, TreeIndexFrom::New) {
+            return Err(Error::MissingSource(
+                "an index needs a list, a scan, a reader, checksums, a remote scan or a store to build from".to_string()));
+        }
+        if self.spill.is_some() && !self.spills() {
+            return Err(Error::ConflictingOptions(
+                "only indexes built from a list, a scan or a reader can be spilled".to_string()));
+        }
+        #[cfg(feature = "sign")]
+        if self.verify_key.is_some() && !matches!(self.from, TreeIndexFrom::Reader(_)) {
+            return Err(Error::ConflictingOptions("only indexes read from a reader have a signature to check".to_string()));
+        }
+        Ok(())
+    }
+
+    // only lists, scans and readers can be too big for memory
+    fn spills(&self) -> bool {
+        (self.spill.is_some() || self.memory_limit.is_some())
+            && matches!(self.from, TreeIndexFrom::List(_) | TreeIndexFrom::Scan(_) | TreeIndexFrom::Reader(_))
+    }
+
+    // feeds every item from the source through a spill, returns the header
+    // and the stats of a list or a scan
+    fn fill_spill(self) -> Result<(TreeIndexHeader, ScanStats, Spill)> {
+        let budget = self.spill.unwrap_or(u64::MAX).min(self.memory_limit.unwrap_or(u64::MAX));
+        let limited = self.memory_limit.is_some();
+        let dir = self.spill_dir.unwrap_or_else(env::temp_dir);
+        let mut spill = Spill::new(budget, dir, self.with_dupes);
+        let mut stats = ScanStats::default();
+        let header = match self.from {
+            TreeIndexFrom::List(l) => {
+                debug!("constructing spilled index from list");
+                for i in &l.list {
+                    spill.insert(i.clone())?;
+                }
+                stats = l.stats;
+                l.header.clone()
+            },
+            TreeIndexFrom::Scan(scan) => {
+                debug!("constructing spilled index from scan");
+                let tl = scan.build_each(&mut |i| {
+                    if i.kind == FileKind::File {
+                        spill.insert(i.clone())?;
+                    }
+                    Ok(())
+                })?;
+                stats = tl.stats;
+                let header = tl.header.clone();
+                if let Some(s) = &self.scan_summary {
+                    s.record(tl);
+                }
+                header
+            },
+            TreeIndexFrom::Reader(r) => {
+                debug!("constructing spilled index from reader");
+                let mut f = |line| match line {
+                    IndexLine::Item(item) => spill.insert(item),
+                    // a removal can apply to an item that was already
+                    // written to a shard
+                    IndexLine::Remove(_) => Err(Error::InvalidFormat(
+                        "indexes with removal lines can't be spilled, rewrite the index first".to_string()))
+                };
+                #[cfg(feature = "sign")]
+                if let Some(key) = self.verify_key {
+                    let mut vr = VerifyingReader::new(r, key);
+                    let header = read_index(&mut vr, self.fast_reader, &mut f)?;
+                    vr.finish()?;
+                    return spilled_header(header, limited, stats, spill);
+                }
+                read_index(r, self.fast_reader, &mut f)?
+            },
+            _ => TreeIndexHeader::default()
+        };
+        spilled_header(header, limited, stats, spill)
+    }
+}
+
+// the shards don't keep chunk digests or metadata, they are dropped from an
+// index that spilled unless it was to be kept whole under a memory limit
+fn spilled_header(mut header: TreeIndexHeader, limited: bool, stats: ScanStats, spill: Spill)
+    -> Result<(TreeIndexHeader, ScanStats, Spill)>
+{
+    if spill.spilled() {
+        if limited && (header.chunk_size()?.is_some() || header.has_metadata()) {
+            return Err(Error::ConflictingOptions(
+                "the index went over its memory limit and its chunk digests and metadata can't be spilled".to_string()));
+        }
+        header.set_chunk_size(None);
+        header.set_metadata(false);
+    }
+    Ok((header, stats, spill))
+}
+
+// a line read from an index file. dupe lines are turned into items using the
+// digest and size of the item they follow.
+pub(crate) enum IndexLine {
+    Item(TreeItem),
+    Remove(PathBuf)
+}
+
+// logs the digests that collide across sizes or fails on the first one
+fn check_collisions(ti: &TreeIndex, fail: bool) -> Result<()> {
+    for (a, b) in ti.collisions() {
+        let e = Error::DigestCollision(a.item.digest.to_string(), a.item.path(), b.item.path());
+        if fail {
+            return Err(e);
+        }
+        warn!("{}", e);
+    }
+    Ok(())
+}
+
+// reads an index in any of the formats, whichever it is, and returns the
+// header. Text indexes are read with read_lines_fast when fast is set.
+fn read_index<R: Read>(r: R, fast: bool, f: &mut dyn FnMut(IndexLine) -> Result<()>) -> Result<TreeIndexHeader> {
+    let mut r = BufReader::new(r);
+    let start = r.fill_buf()?;
+    if is_binary(start) {
+        read_binary(r, f)
+    } else if is_csv(start) {
+        read_csv(r, f)
+    } else if fast {
+        read_lines_fast(r, f)
+    } else {
+        read_lines(r, f)
+    }
+}
+
+// reads an index in the text format a line at a time and returns the header
+fn read_lines<R: Read>(r: R, f: &mut dyn FnMut(IndexLine) -> Result<()>) -> Result<TreeIndexHeader> {
+    let r = BufReader::new(r);
+    let mut last_digest = "-".to_string();
+    let mut last_size = 0u64;
+
+    // files without a header are version 1
+    let mut header = TreeIndexHeader::v1();
+
+    // items are held until the next line in case it has their chunk digests
+    let mut pending: Option<TreeItem> = None;
+
+    let mut line_count = 0;
+    for line in r.lines() {
+        line_count += 1;
+        let mut line = line?;
+
+        // the header may only be on the first line, other lines starting
+        // with # are comments
+        if line.starts_with('#') {
+            if line_count == 1 && TreeIndexHeader::is_header(&line) {
+                header = line.parse()?;
+            }
+            continue;
+        }
+
+        // the chunk digests of the item on the line before
+        if line.starts_with(CHUNKS_TAG) {
+            match pending.as_mut() {
+                Some(item) if item.chunks.is_none() => item.chunks = Some(Arc::new(line.parse()?)),
+                _ => return Err(Error::InvalidFormat(format!("unexpected chunk digests on line {}", line_count)))
+            }
+            continue;
+        }
+
+        // the fuzzy digest of the item on the line before
+        if line.starts_with(FUZZY_TAG) {
+            match pending.as_mut() {
+                Some(item) if item.fuzzy.is_none() => item.fuzzy = Some(Arc::new(line.parse()?)),
+                _ => return Err(Error::InvalidFormat(format!("unexpected fuzzy digest on line {}", line_count)))
+            }
+            continue;
+        }
+
+        // the perceptual hash of the image on the line before
+        if line.starts_with(IMAGE_HASH_TAG) {
+            match pending.as_mut() {
+                Some(item) if item.image_hash.is_none() => item.image_hash = Some(line.parse()?),
+                _ => return Err(Error::InvalidFormat(format!("unexpected image hash on line {}", line_count)))
+            }
+            continue;
+        }
+
+        // the metadata of the item or dupe on the line before
+        if line.starts_with(METADATA_TAG) {
+            match pending.as_mut() {
+                Some(item) if item.metadata.is_none() => item.metadata = Some(Arc::new(line.parse()?)),
+                _ => return Err(Error::InvalidFormat(format!("unexpected file metadata on line {}", line_count)))
+            }
+            continue;
+        }
+        if let Some(item) = pending.take() {
+            f(IndexLine::Item(item))?;
+        }
+
+        // special files recorded by a scan don't have digests so they
+        // aren't part of an index
+        if line.starts_with(SPECIAL_TAG) {
+            continue;
+        }
+
+        // a removal line written by an index watcher
+        if let Some(p) = line.strip_prefix("! ") {
+            f(IndexLine::Remove(read_path(p.to_string(), &header)?))?;
+            continue;
+        }
+
+        // read the digest
+        let mut digest = match line.find(char::is_whitespace) {
+            Some(idx) => {
+                let rest = line.split_off(idx);
+                let d = line.clone();
+                line = rest[1..].to_string();
+                d
+            },
+            None => return Err(Error::InvalidFormat(format!("missing digest on line {}", line_count)))
+        };
+
+        // if this is NOT a dupe line, read the file size
+        let size = {
+            if digest != "-" {
+                match line.find(char::is_whitespace) {
+                    Some(idx) => {
+                        let rest = line.split_off(idx);
+                        let s = line.parse::<u64>().unwrap_or(0u64);
+                        line = rest[1..].to_string();
+                        s
+                    },
+                    None => return Err(Error::InvalidFormat(format!("missing size on line {}", line_count)))
+                }
+            } else {
+                last_size
+            }
+        };
+
+        if digest == "-" {
+            digest = last_digest.clone();
+        } else {
+            last_digest = digest.clone();
+            last_size = size;
+        }
+
+        let path = Arc::new(read_path(line, &header)?);
+        pending = Some(TreeItem::new(&digest, &path, size));
+    }
+    if let Some(item) = pending.take() {
+        f(IndexLine::Item(item))?;
+    }
+    Ok(header)
+}
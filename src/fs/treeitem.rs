@@ -0,0 +1,899 @@
+use crate::{
+    error::Error,
+    Result,
+    fs::{
+        Algorithm,
+        ChunkDigests,
+        Chunking,
+        Digest,
+        DigestEncoding,
+        DigestVariant,
+        EMPTY_PATHBUF,
+        FileKind,
+        FileMetadata,
+        FuzzyDigest,
+        ImageHash,
+        ItemDigest,
+        ItemPath,
+        TreeIndexHeader,
+        algo::Hasher,
+        longpath::os_path,
+        chunks::Chunker,
+        fuzzy::FuzzyHasher
+    }
+};
+#[cfg(feature = "media")]
+use crate::fs::{is_image, ImageHashKind};
+use log::debug;
+#[cfg(feature = "media")]
+use log::warn;
+use memmap2::Mmap;
+use std::collections::{HashMap, HashSet};
+use std::convert::From;
+use std::fmt::{Display, Formatter};
+use std::fs::{self, File};
+use std::io::{Seek, SeekFrom, Read};
+use std::path::{Path, PathBuf, MAIN_SEPARATOR, MAIN_SEPARATOR_STR};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+// list lines for special files recorded by a scan start with this
+pub(crate) const SPECIAL_TAG: &str = "? ";
+
+// files modified this recently may still be being written, so they are
+// streamed instead of mapped
+const WRITE_SETTLE: Duration = Duration::from_secs(5);
+
+// A TreeItem is a path to a file with its digest and file size, and the
+// digests of its chunks, its fuzzy digest, its metadata and the perceptual
+// hash of an image if it was hashed with them. Items
+// for special files recorded by a scan have a kind other than File and no
+// digest. The path is interned, see ItemPath.
+#[derive(Clone)]
+pub struct TreeItem {
+    pub digest: ItemDigest,
+    pub(crate) path: ItemPath,
+    pub size: u64,
+    pub kind: FileKind,
+    pub chunks: Option<Arc<ChunkDigests>>,
+    pub metadata: Option<Arc<FileMetadata>>,
+    pub fuzzy: Option<Arc<FuzzyDigest>>,
+    pub image_hash: Option<ImageHash>
+}
+
+impl TreeItem {
+    pub fn new<D: Into<ItemDigest>, P: Into<ItemPath>>(digest: D, path: P, size: u64) -> Self {
+        Self {
+            digest: digest.into(),
+            path: path.into(),
+            size,
+            kind: FileKind::File,
+            chunks: None,
+            metadata: None,
+            fuzzy: None,
+            image_hash: None
+        }
+    }
+
+    /// Creates an item for a special file, e.g. a FIFO or a dangling symlink
+    pub fn special<P: Into<ItemPath>>(path: P, kind: FileKind) -> Self {
+        let mut item = Self::new("", path, 0);
+        item.kind = kind;
+        item
+    }
+
+    /// The path of the file
+    pub fn path(&self) -> PathBuf {
+        self.path.path()
+    }
+
+    /// The interned path, which compares without putting the path together
+    pub fn item_path(&self) -> &ItemPath {
+        &self.path
+    }
+
+    pub fn set_path(&mut self, path: &Path) {
+        self.path = ItemPath::new(path);
+    }
+}
+
+// paths are written with forward slashes whatever the platform, paths that
+// aren't valid UTF-8 can't be written
+pub(crate) fn index_path(path: &Path) -> std::result::Result<String, std::fmt::Error> {
+    let p = path.to_str().ok_or(std::fmt::Error)?;
+    if MAIN_SEPARATOR == '/' {
+        Ok(p.to_string())
+    } else {
+        Ok(p.replace(MAIN_SEPARATOR, "/"))
+    }
+}
+
+// turns a path read from an index into a native one. The backslashes of an
+// index tagged as written on Windows are separators, elsewhere they may be
+// part of a file name.
+pub(crate) fn native_path(path: String, header: &TreeIndexHeader) -> PathBuf {
+    let path = if header.os() == Some("windows") && MAIN_SEPARATOR != '\\' {
+        path.replace('\\', "/")
+    } else {
+        path
+    };
+    if MAIN_SEPARATOR == '/' {
+        PathBuf::from(path)
+    } else {
+        PathBuf::from(path.replace('/', MAIN_SEPARATOR_STR))
+    }
+}
+
+// writes a path for a text index. Separators are forward slashes like
+// index_path, and backslashes, control characters and bytes that aren't
+// UTF-8 are escaped as \\, \n, \r, \t and \xNN so a path can't split its
+// line and any path unix allows survives a round trip. Paths that aren't
+// valid Unicode can't be written on other platforms.
+pub(crate) fn escape_path(path: &Path) -> std::result::Result<String, std::fmt::Error> {
+    let bytes = path_bytes(path)?;
+    let mut s = String::with_capacity(bytes.len());
+    let mut rest = &bytes[..];
+    while !rest.is_empty() {
+        let (valid, invalid) = match std::str::from_utf8(rest) {
+            Ok(valid) => (valid, &[][..]),
+            Err(e) => {
+                let (valid, invalid) = rest.split_at(e.valid_up_to());
+                let len = e.error_len().unwrap_or(invalid.len());
+                (std::str::from_utf8(valid).map_err(|_| std::fmt::Error)?, &invalid[..len])
+            }
+        };
+        for c in valid.chars() {
+            match c {
+                '\\' => s.push_str("\\\\"),
+                '\n' => s.push_str("\\n"),
+                '\r' => s.push_str("\\r"),
+                '\t' => s.push_str("\\t"),
+                c if c.is_ascii_control() => s.push_str(&format!("\\x{:02x}", c as u32)),
+                c => s.push(c)
+            }
+        }
+        for b in invalid {
+            s.push_str(&format!("\\x{:02x}", b));
+        }
+        rest = &rest[valid.len() + invalid.len()..];
+    }
+    Ok(s)
+}
+
+#[cfg(unix)]
+fn path_bytes(path: &Path) -> std::result::Result<std::borrow::Cow<'_, [u8]>, std::fmt::Error> {
+    use std::os::unix::ffi::OsStrExt;
+    Ok(std::borrow::Cow::Borrowed(path.as_os_str().as_bytes()))
+}
+
+#[cfg(not(unix))]
+fn path_bytes(path: &Path) -> std::result::Result<std::borrow::Cow<'_, [u8]>, std::fmt::Error> {
+    Ok(std::borrow::Cow::Owned(index_path(path)?.into_bytes()))
+}
+
+// reads a path escape_path wrote and turns it into a native one
+pub(crate) fn unescape_path(path: &[u8]) -> Result<PathBuf> {
+    let invalid = || Error::InvalidFormat(format!("invalid escape in path {}", String::from_utf8_lossy(path)));
+    let mut bytes = Vec::with_capacity(path.len());
+    let mut iter = path.iter();
+    while let Some(&b) = iter.next() {
+        if b != b'\\' {
+            bytes.push(b);
+            continue;
+        }
+        match iter.next() {
+            Some(b'\\') => bytes.push(b'\\'),
+            Some(b'n') => bytes.push(b'\n'),
+            Some(b'r') => bytes.push(b'\r'),
+            Some(b't') => bytes.push(b'\t'),
+            Some(b'x') => {
+                let hex = [*iter.next().ok_or_else(invalid)?, *iter.next().ok_or_else(invalid)?];
+                let hex = std::str::from_utf8(&hex).map_err(|_| invalid())?;
+                bytes.push(u8::from_str_radix(hex, 16).map_err(|_| invalid())?);
+            },
+            _ => return Err(invalid())
+        }
+    }
+    bytes_path(bytes)
+}
+
+#[cfg(unix)]
+fn bytes_path(bytes: Vec<u8>) -> Result<PathBuf> {
+    use std::ffi::OsString;
+    use std::os::unix::ffi::OsStringExt;
+    Ok(PathBuf::from(OsString::from_vec(bytes)))
+}
+
+#[cfg(not(unix))]
+fn bytes_path(bytes: Vec<u8>) -> Result<PathBuf> {
+    let path = String::from_utf8(bytes)
+        .map_err(|e| Error::InvalidFormat(format!("path {} isn't valid UTF-8", String::from_utf8_lossy(e.as_bytes()))))?;
+    Ok(PathBuf::from(path.replace('/', MAIN_SEPARATOR_STR)))
+}
+
+// reads a path from a line of a text index the way its header says they
+// are written
+pub(crate) fn read_path(path: String, header: &TreeIndexHeader) -> Result<PathBuf> {
+    if header.is_escaped() {
+        unescape_path(path.as_bytes())
+    } else {
+        Ok(native_path(path, header))
+    }
+}
+
+impl Display for TreeItem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error>
+    {
+        let path = escape_path(&self.path())?;
+        if self.kind != FileKind::File {
+            return writeln!(f, "{}{} {}", SPECIAL_TAG, self.kind, path);
+        }
+        writeln!(f, "{} {} {}", self.digest, self.size, path)?;
+        if let Some(chunks) = &self.chunks {
+            write!(f, "{}", chunks)?;
+        }
+        if let Some(fuzzy) = &self.fuzzy {
+            write!(f, "{}", fuzzy)?;
+        }
+        if let Some(hash) = &self.image_hash {
+            write!(f, "{}", hash)?;
+        }
+        if let Some(metadata) = &self.metadata {
+            write!(f, "{}", metadata)?;
+        }
+        Ok(())
+    }
+}
+
+pub struct TreeItemBuilder<'a> {
+    fast: bool,
+    mmap: bool,
+    algorithm: Algorithm,
+    encoding: DigestEncoding,
+    key: Option<&'a [u8]>,
+    chunking: Option<Chunking>,
+    with_metadata: bool,
+    fuzzy: bool,
+    #[cfg(feature = "media")]
+    image_hash: Option<ImageHashKind>,
+    path: &'a PathBuf,
+    stream: Option<(PathBuf, &'a mut dyn Read)>,
+}
+
+// fast mode hashes this much from the start and the end of a file
+pub(crate) const FAST_CHUNK: u64 = 1_048_576;
+
+impl<'a> Default for TreeItemBuilder<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> TreeItemBuilder<'a> {
+
+    pub fn new() -> Self {
+        TreeItemBuilder {
+            fast: false,
+            mmap: false,
+            algorithm: Algorithm::default(),
+            encoding: DigestEncoding::default(),
+            key: None,
+            chunking: None,
+            with_metadata: false,
+            fuzzy: false,
+            #[cfg(feature = "media")]
+            image_hash: None,
+            path: &EMPTY_PATHBUF,
+            stream: None
+        }
+    }
+
+    pub fn fast(mut self, fast: bool) -> Self {
+        self.fast = fast;
+        self
+    }
+
+    /// Memory map the file instead of streaming it through a read buffer.
+    /// This is noticeably faster for multi-GB files on fast storage. If the
+    /// file can't be mapped, the builder falls back to streaming reads. The
+    /// digest is the same either way. A mapped file that is truncated while
+    /// it is hashed kills the process with SIGBUS instead of failing a read,
+    /// so files on network file systems and files modified in the last few
+    /// seconds are streamed even with this on. Only map trees that nothing
+    /// writes to while they are scanned.
+    pub fn mmap(mut self, mmap: bool) -> Self {
+        self.mmap = mmap;
+        self
+    }
+
+    /// The digest algorithm to use, the default is Blake2b
+    pub fn algorithm(mut self, algorithm: Algorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    /// How the digest is written, the default is bare hex
+    pub fn encoding(mut self, encoding: DigestEncoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Key the digest with a secret. Without the key nobody can make a
+    /// digest that matches a file, so an attacker who can write files can't
+    /// forge the entries of an index. Blake2b digests use its built in keyed
+    /// mode and Sha256 digests are HMAC-SHA256.
+    pub fn key(mut self, key: &'a [u8]) -> Self {
+        self.key = Some(key);
+        self
+    }
+
+    /// Also digest the file in chunks of this many bytes and keep the chunk
+    /// digests and their Merkle root with the item, so a later check can
+    /// tell which regions of a large file changed. Chunk digests cover the
+    /// whole file so they can't be combined with fast digests.
+    pub fn chunks(mut self, chunk_size: u64) -> Self {
+        self.chunking = Some(Chunking::Fixed(chunk_size));
+        self
+    }
+
+    /// Also digest the file in chunks cut by its content, see Chunking. The
+    /// same as chunks for Chunking::Fixed.
+    pub fn chunking(mut self, chunking: Chunking) -> Self {
+        self.chunking = Some(chunking);
+        self
+    }
+
+    /// Also keep a fuzzy digest of the file, see FuzzyDigest, so files with
+    /// minor edits can be found. Like chunk digests it covers the whole file
+    /// so it can't be combined with fast digests.
+    pub fn fuzzy(mut self, fuzzy: bool) -> Self {
+        self.fuzzy = fuzzy;
+        self
+    }
+
+    /// Keep the file's modification time, permissions, owner, group and
+    /// kind with the item so changes to them can be found too
+    pub fn with_metadata(mut self, with_metadata: bool) -> Self {
+        self.with_metadata = with_metadata;
+        self
+    }
+
+    /// Also keep the perceptual hash of the file if it is an image, see
+    /// ImageHash. Files that have the extension of an image but can't be
+    /// decoded are logged and kept without one.
+    #[cfg(feature = "media")]
+    pub fn image_hash(mut self, kind: ImageHashKind) -> Self {
+        self.image_hash = Some(kind);
+        self
+    }
+
+    pub fn path(mut self, path: &'a PathBuf) -> Self {
+        self.path = path;
+        self
+    }
+
+    /// Digests what is read from the reader instead of a file, like data
+    /// piped from tar or curl. The item gets the name as its path, nothing
+    /// is looked up on disk so it has no metadata. The stream is read to
+    /// the end to get its size, which fuzzy digests need up front so they
+    /// can't be made this way.
+    pub fn from_reader(mut self, name: &str, r: &'a mut dyn Read) -> Self {
+        self.stream = Some((PathBuf::from(name), r));
+        self
+    }
+
+    pub fn build(mut self) -> Result<TreeItem> {
+        self.check()?;
+        self.check_source()?;
+        if let Some((path, r)) = self.stream.take() {
+            return self.build_stream(path, r);
+        }
+
+        // make sure we have a file
+        let os = os_path(self.path);
+        if !os.is_file() {
+            return Err(Error::NotAFile(self.path.to_path_buf()));
+        }
+
+        // get the file size
+        let size = fs::metadata(&os)?.len();
+        #[cfg(feature = "tracing")]
+        let _digest = tracing::debug_span!("digest", path = %self.path.display(), bytes = size).entered();
+
+        // open the file
+        debug!("[DGST] {}", self.path.to_string_lossy());
+        let f = File::open(&os)?;
+
+        // we're creating a 32-byte digest of the file, Blake2b by default
+        let mut hash = match self.key {
+            Some(key) => self.algorithm.keyed_hasher(key)?,
+            None => self.algorithm.hasher()
+        };
+        let mut chunker = match self.chunking {
+            Some(c) => Some(Chunker::new(c, self.algorithm, self.key)?),
+            None => None
+        };
+        let mut fuzzy = if self.fuzzy { Some(FuzzyHasher::new(size)) } else { None };
+        if !(self.mmap && self.hash_mmap(&f, size, &mut hash, &mut chunker, &mut fuzzy)?) {
+            self.hash_stream(f, size, &mut hash, &mut chunker, &mut fuzzy)?;
+        }
+        let result = self.encode(&hash.finalize(), size)?;
+        let mut item = TreeItem::new(result, self.path, size);
+        if let Some(c) = chunker {
+            item.chunks = Some(Arc::new(c.finish()?));
+        }
+        if let Some(h) = fuzzy {
+            item.fuzzy = Some(Arc::new(h.finish()));
+        }
+        if self.with_metadata {
+            item.metadata = Some(Arc::new(FileMetadata::read(self.path)?));
+        }
+        #[cfg(feature = "media")]
+        if let Some(kind) = self.image_hash.filter(|_| is_image(self.path)) {
+            match ImageHash::of(&os, kind) {
+                Ok(h) => item.image_hash = Some(h),
+                Err(e) => warn!("failed to hash image {}: {}", self.path.to_string_lossy(), e)
+            }
+        }
+        Ok(item)
+    }
+
+    // chunk and fuzzy digests need the whole file
+    fn check(&self) -> Result<()> {
+        if self.fast && self.chunking.is_some() {
+            return Err(Error::ConflictingOptions("chunk digests can't be used with fast digests".to_string()));
+        }
+        if self.fast && self.fuzzy {
+            return Err(Error::ConflictingOptions("fuzzy digests can't be used with fast digests".to_string()));
+        }
+        Ok(())
+    }
+
+    // an item is made of either a file or a stream
+    fn check_source(&self) -> Result<()> {
+        let has_path = !self.path.as_os_str().is_empty();
+        match (&self.stream, has_path) {
+            (None, false) => Err(Error::MissingSource("an item needs a path or a reader to digest".to_string())),
+            (Some(_), true) => Err(Error::ConflictingOptions("an item is digested from a path or a reader, not both".to_string())),
+            _ => Ok(())
+        }
+    }
+
+    // hashes a stream of unknown size, in fast mode the head and a running
+    // tail are held until the end when the size is known
+    fn build_stream(self, path: PathBuf, r: &mut dyn Read) -> Result<TreeItem> {
+        if self.fuzzy {
+            return Err(Error::ConflictingOptions("fuzzy digests can't be made of a stream".to_string()));
+        }
+        debug!("[DGST] {}", path.to_string_lossy());
+        let mut hash = match self.key {
+            Some(key) => self.algorithm.keyed_hasher(key)?,
+            None => self.algorithm.hasher()
+        };
+        let mut chunker = match self.chunking {
+            Some(c) => Some(Chunker::new(c, self.algorithm, self.key)?),
+            None => None
+        };
+        let keep = (FAST_CHUNK - 1) as usize;
+        let mut head = Vec::new();
+        let mut tail = Vec::new();
+        let mut buf = vec![0; 65536];
+        let mut size = 0u64;
+        loop {
+            let n = r.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            let data = &buf[..n];
+            size += n as u64;
+            if self.fast {
+                let take = (FAST_CHUNK as usize).saturating_sub(head.len()).min(n);
+                head.extend_from_slice(&data[..take]);
+                tail.extend_from_slice(data);
+                if tail.len() > 2 * keep {
+                    tail.drain(..tail.len() - keep);
+                }
+            } else {
+                hash.update(data);
+                if let Some(c) = chunker.as_mut() {
+                    c.update(data)?;
+                }
+            }
+        }
+
+        // the same bytes hash_stream would have read from a file this size
+        if self.fast {
+            hash.update(&head);
+            if size > FAST_CHUNK {
+                hash.update(&tail[tail.len() - keep..]);
+                hash.update(&size.to_le_bytes());
+            }
+        }
+        let result = self.encode(&hash.finalize(), size)?;
+        let mut item = TreeItem::new(result, &path, size);
+        if let Some(c) = chunker {
+            item.chunks = Some(Arc::new(c.finish()?));
+        }
+        Ok(item)
+    }
+
+    // hashes size bytes read from r as if they were a file at the path, for
+    // things that can't be opened or seeked like the members of an archive.
+    // the item has no metadata.
+    #[cfg(feature = "archive")]
+    pub(crate) fn build_reader(self, r: &mut dyn Read, size: u64) -> Result<TreeItem> {
+        self.check()?;
+        debug!("[DGST] {}", self.path.to_string_lossy());
+        let mut hash = match self.key {
+            Some(key) => self.algorithm.keyed_hasher(key)?,
+            None => self.algorithm.hasher()
+        };
+        let mut chunker = match self.chunking {
+            Some(c) => Some(Chunker::new(c, self.algorithm, self.key)?),
+            None => None
+        };
+        let mut fuzzy = if self.fuzzy { Some(FuzzyHasher::new(size)) } else { None };
+        self.hash_reader(r, size, &mut hash, &mut chunker, &mut fuzzy)?;
+        let result = self.encode(&hash.finalize(), size)?;
+        let mut item = TreeItem::new(result, self.path, size);
+        if let Some(c) = chunker {
+            item.chunks = Some(Arc::new(c.finish()?));
+        }
+        if let Some(h) = fuzzy {
+            item.fuzzy = Some(Arc::new(h.finish()));
+        }
+        Ok(item)
+    }
+
+    // hashes the same bytes as hash_stream without seeking, in fast mode the
+    // middle is read and thrown away and the tail is held until the end
+    // because it can overlap the head of files under 2 MiB
+    #[cfg(feature = "archive")]
+    fn hash_reader(&self, r: &mut dyn Read, size: u64, hash: &mut Hasher, chunker: &mut Option<Chunker>,
+                   fuzzy: &mut Option<FuzzyHasher>) -> Result<()> {
+        let fast = self.fast && size > FAST_CHUNK;
+        let tail_start = size.saturating_sub(FAST_CHUNK - 1);
+        let mut tail = Vec::new();
+        let mut buf = vec![0; FAST_CHUNK as usize];
+        let mut num = 0;
+        while num < size {
+            let n = r.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            let data = &buf[..n.min((size - num) as usize)];
+            let end = num + data.len() as u64;
+            if fast {
+                if num < FAST_CHUNK {
+                    hash.update(&data[..(FAST_CHUNK.min(end) - num) as usize]);
+                }
+                if end > tail_start {
+                    tail.extend_from_slice(&data[(tail_start.max(num) - num) as usize..]);
+                }
+            } else {
+                hash.update(data);
+                if let Some(c) = chunker.as_mut() {
+                    c.update(data)?;
+                }
+                if let Some(h) = fuzzy.as_mut() {
+                    h.update(data);
+                }
+            }
+            num = end;
+        }
+        if fast {
+            hash.update(&tail);
+            hash.update(&size.to_le_bytes());
+        }
+        Ok(())
+    }
+
+    // hashes the file by mapping it into memory, returns false if the file
+    // couldn't be mapped so the caller can fall back to streaming
+    fn hash_mmap(&self, f: &File, size: u64, hash: &mut Hasher, chunker: &mut Option<Chunker>,
+                 fuzzy: &mut Option<FuzzyHasher>) -> Result<bool> {
+        // empty files can't be mapped
+        if size == 0 {
+            return Ok(false);
+        }
+
+        // reading the pages of a mapping past the end of a file that was
+        // truncated after it was mapped raises SIGBUS, which kills the
+        // process where a read would just come up short. A network file
+        // system can do the same when it loses the server, so files there
+        // and files that may still be being written are streamed.
+        if is_network_fs(f) {
+            debug!("not mapping {}, it is on a network file system", self.path.to_string_lossy());
+            return Ok(false);
+        }
+        if is_being_written(f) {
+            debug!("not mapping {}, it was just modified", self.path.to_string_lossy());
+            return Ok(false);
+        }
+        let map = match unsafe { Mmap::map(f) } {
+            Ok(map) => map,
+            Err(e) => {
+                debug!("failed to mmap {}: {}", self.path.to_string_lossy(), e);
+                return Ok(false);
+            }
+        };
+
+        // the file changed size since it was looked at, streaming it reads
+        // what is there now without slicing past the end of the mapping
+        if map.len() as u64 != size {
+            debug!("not hashing the mapping of {}, it is {} bytes now rather than {}",
+                   self.path.to_string_lossy(), map.len(), size);
+            return Ok(false);
+        }
+
+        // hash the same bytes as the streaming fast mode
+        if self.fast && size > FAST_CHUNK {
+            hash.update(&map[..FAST_CHUNK as usize]);
+            hash.update(&map[(size - (FAST_CHUNK - 1)) as usize..]);
+            hash.update(&size.to_le_bytes());
+        } else {
+            hash.update(&map);
+            if let Some(c) = chunker.as_mut() {
+                c.update(&map)?;
+            }
+            if let Some(h) = fuzzy.as_mut() {
+                h.update(&map);
+            }
+        }
+        Ok(true)
+    }
+
+    // writes the digest in the encoding, only files over a chunk are
+    // digested differently in fast mode
+    fn encode(&self, digest: &[u8], size: u64) -> Result<ItemDigest> {
+        let variant = DigestVariant::new(self.fast && size > FAST_CHUNK, self.key.is_some());
+        Ok(ItemDigest::new(self.encoding, self.algorithm, variant, Digest::from_bytes(digest)?))
+    }
+
+    // hashes the file by streaming it from disk
+    fn hash_stream(&self, mut f: File, size: u64, hash: &mut Hasher, chunker: &mut Option<Chunker>,
+                   fuzzy: &mut Option<FuzzyHasher>) -> Result<()> {
+        let mut buf = vec![0; FAST_CHUNK as usize]; // this streams a file from disk 1M at a time to hash it
+        let mut num = 0;
+        while num < size {
+            let n = match f.read(&mut buf) {
+                Ok(n) => n,
+                Err(e) => {
+                    debug!("failed to read data from {}", self.path.to_string_lossy());
+                    return Err(Error::IoError(e));
+                }
+            };
+
+            // the file got shorter while we were reading it
+            if n == 0 {
+                break;
+            }
+            hash.update(&buf[0..n]);
+            if let Some(c) = chunker.as_mut() {
+                c.update(&buf[0..n])?;
+            }
+            if let Some(h) = fuzzy.as_mut() {
+                h.update(&buf[0..n]);
+            }
+            num += n as u64;
+
+            // fast mode causes the hash to contain only the first 1 MB
+            // and the last 1 MB of a file which is close enough for most
+            // matching and significantly faster than hashing the whole file
+            if self.fast && (num < size) && (size > FAST_CHUNK) {
+                num = match f.seek(SeekFrom::Start(size - (FAST_CHUNK - 1))) {
+                    Ok(n) => n,
+                    Err(e) => {
+                        debug!("failed to seek to {}", size - (FAST_CHUNK - 1));
+                        return Err(Error::IoError(e));
+                    }
+                }
+            }
+        }
+
+        // a fast digest only covers part of the file so the size is mixed in
+        // to keep files with the same head and tail but different lengths
+        // apart. files small enough to be hashed entirely are left alone so
+        // their fast and full digests are the same.
+        if self.fast && size > FAST_CHUNK {
+            hash.update(&size.to_le_bytes());
+        }
+        Ok(())
+    }
+}
+
+// A TreeItemDupes is a tree item with a list of paths to other files with the
+// same digest as the main item, and the metadata of the dupes that have it
+#[derive(Clone)]
+pub struct TreeItemDupes {
+    pub item: TreeItem,
+    pub dupes: Vec<ItemPath>,
+    pub dupe_metadata: HashMap<ItemPath, Arc<FileMetadata>>
+}
+
+impl TreeItemDupes {
+    pub fn new<D: Into<ItemDigest>, P: Into<ItemPath>>(digest: D, path: P, size: u64) -> Self {
+        Self {
+            item: TreeItem::new(digest, path, size),
+            dupes: Vec::new(),
+            dupe_metadata: HashMap::new()
+        }
+    }
+
+    pub fn push(&mut self, dupe: ItemPath) {
+        self.dupes.push(dupe);
+    }
+
+    /// Adds the item's path as a dupe and keeps its metadata
+    pub fn push_item(&mut self, item: &TreeItem) {
+        if let Some(m) = &item.metadata {
+            self.dupe_metadata.insert(item.path.clone(), m.clone());
+        }
+        self.dupes.push(item.path.clone());
+    }
+
+    /// Adds the item like push_item unless its size is different, then it
+    /// isn't a dupe, only its digest collides and this returns
+    /// DigestCollision with both paths
+    pub fn push_checked(&mut self, item: &TreeItem) -> Result<()> {
+        if item.size != self.item.size {
+            return Err(Error::DigestCollision(item.digest.to_string(), self.item.path(), item.path()));
+        }
+        self.push_item(item);
+        Ok(())
+    }
+
+    /// Returns the bytes the copy at the path takes up on disk, which is the
+    /// size unless the copy's metadata says otherwise
+    pub fn allocated(&self, path: &ItemPath) -> u64 {
+        let metadata = if *path == self.item.path {
+            self.item.metadata.as_ref()
+        } else {
+            self.dupe_metadata.get(path)
+        };
+        metadata.and_then(|m| m.allocated).unwrap_or(self.item.size)
+    }
+
+    /// Returns the dupe at the path as an item
+    pub fn dupe_item(&self, path: &ItemPath) -> TreeItem {
+        let mut item = TreeItem::new(&self.item.digest, path, self.item.size);
+        item.chunks = self.item.chunks.clone();
+        item.fuzzy = self.item.fuzzy.clone();
+        item.image_hash = self.item.image_hash;
+        item.metadata = self.dupe_metadata.get(path).cloned();
+        item
+    }
+
+    /// Replaces the from prefix of the item's path and the dupes with to,
+    /// returns how many paths were changed
+    pub fn rewrite_prefix(&mut self, from: &Path, to: &Path) -> usize {
+        self.map_paths(&|p| p.strip_prefix(from).ok().map(|rest| to.join(rest)))
+    }
+
+    /// Normalizes the item's path and the dupes to Unicode NFC, returns how
+    /// many paths were changed
+    #[cfg(feature = "unicode")]
+    pub fn normalize_unicode(&mut self) -> usize {
+        self.map_paths(&nfc_path)
+    }
+
+    // replaces the paths f returns a new path for and counts them. Dupes
+    // that end up the same as the item or an earlier dupe are dropped so a
+    // file is never listed as a dupe of itself.
+    fn map_paths(&mut self, f: &dyn Fn(&Path) -> Option<PathBuf>) -> usize {
+        let mut count = 0;
+        if let Some(p) = f(&self.item.path()) {
+            self.item.path = ItemPath::new(&p);
+            count += 1;
+        }
+        let mut seen = HashSet::new();
+        seen.insert(self.item.path.clone());
+        for d in std::mem::take(&mut self.dupes) {
+            let d = match f(&d.path()) {
+                Some(p) => {
+                    let p = ItemPath::new(&p);
+                    if let Some(m) = self.dupe_metadata.remove(&d) {
+                        self.dupe_metadata.insert(p.clone(), m);
+                    }
+                    count += 1;
+                    p
+                },
+                None => d
+            };
+            if seen.insert(d.clone()) {
+                self.dupes.push(d);
+            }
+        }
+        count
+    }
+}
+
+// true if the file was modified so recently something may still be writing
+// to it
+fn is_being_written(f: &File) -> bool {
+    match f.metadata().and_then(|m| m.modified()) {
+        Ok(modified) => SystemTime::now().duration_since(modified).map(|d| d < WRITE_SETTLE).unwrap_or(true),
+        Err(_) => true
+    }
+}
+
+// true if the file is on NFS, SMB, AFS, Ceph, 9P or FUSE, which covers sshfs
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn is_network_fs(f: &File) -> bool {
+    use std::os::unix::io::AsRawFd;
+    const NETWORK_MAGIC: [u32; 9] = [
+        0x6969,      // NFS
+        0x517b,      // SMB
+        0xff53_4d42, // CIFS
+        0xfe53_4d42, // SMB2
+        0x5346_414f, // AFS
+        0x7375_7245, // Coda
+        0x00c3_6400, // Ceph
+        0x0102_1997, // 9P
+        0x6573_5546  // FUSE
+    ];
+    let mut s = std::mem::MaybeUninit::<libc::statfs>::uninit();
+    if unsafe { libc::fstatfs(f.as_raw_fd(), s.as_mut_ptr()) } != 0 {
+        return false;
+    }
+    let s = unsafe { s.assume_init() };
+    NETWORK_MAGIC.contains(&(s.f_type as u32))
+}
+
+// true if the file is on NFS, SMB, AFP or WebDAV
+#[cfg(target_os = "macos")]
+fn is_network_fs(f: &File) -> bool {
+    use std::ffi::CStr;
+    use std::os::unix::io::AsRawFd;
+    let mut s = std::mem::MaybeUninit::<libc::statfs>::uninit();
+    if unsafe { libc::fstatfs(f.as_raw_fd(), s.as_mut_ptr()) } != 0 {
+        return false;
+    }
+    let s = unsafe { s.assume_init() };
+    let name = unsafe { CStr::from_ptr(s.f_fstypename.as_ptr()) };
+    matches!(name.to_bytes(), b"nfs" | b"smbfs" | b"afpfs" | b"webdav" | b"cifs")
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "macos")))]
+fn is_network_fs(_f: &File) -> bool {
+    false
+}
+
+// the path in NFC or None if it already is, paths that aren't valid UTF-8
+// are left as they are
+#[cfg(feature = "unicode")]
+pub(crate) fn nfc_path(path: &Path) -> Option<PathBuf> {
+    use unicode_normalization::{is_nfc, UnicodeNormalization};
+    let p = path.to_str()?;
+    if is_nfc(p) {
+        None
+    } else {
+        Some(PathBuf::from(p.nfc().collect::<String>()))
+    }
+}
+
+impl From<&TreeItem> for TreeItemDupes {
+    fn from(item: &TreeItem) -> Self {
+        Self {
+            item: item.clone(),
+            dupes: Vec::new(),
+            dupe_metadata: HashMap::new()
+        }
+    }
+}
+
+impl Display for TreeItemDupes {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error>
+    {
+        write!(f, "{}", self.item)?;
+        for d in &self.dupes {
+            writeln!(f, "- {}", escape_path(&d.path())?)?;
+            if let Some(m) = self.dupe_metadata.get(d) {
+                write!(f, "{}", m)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+
@@ -0,0 +1,1574 @@
+use crate::{
+    error::Error,
+    Result,
+    fs::{
+        mime,
+        is_mac_metadata,
+        Algorithm,
+        AppleDoublePolicy,
+        Chunking,
+        DigestEncoding,
+        FileKind,
+        IndexFormat,
+        IoPriority,
+        Metrics,
+        ScanStats,
+        SizeFilter,
+        TreeIndexHeader,
+        TreeItem,
+        TreeItemBuilder,
+        TreeItemDupes,
+        TreeWork,
+        os_path,
+        reparse_kind,
+        set_io_priority
+    },
+    fs::binary::BinaryWriter,
+    fs::csv::{write_csv_header, write_csv_item},
+    fs::ndjson::write_ndjson_item,
+    fs::reparse::file_id,
+    fs::stats::ScanCounters,
+    fs::throttle::RateLimiter,
+    fs::treeitem::FAST_CHUNK,
+    cancel::CancellationToken,
+    units::IntoBytes
+};
+#[cfg(feature = "archive")]
+use crate::fs::archive::{self, member_path, ArchiveKind};
+#[cfg(feature = "media")]
+use crate::fs::ImageHashKind;
+#[cfg(feature = "unicode")]
+use crate::fs::treeitem::nfc_path;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use log::{debug, warn};
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::fs::{self, Metadata};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{mpsc, Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+// A TreeList is just a list of TreeItems and can contain duplicates. The
+// special files found by the scan are counted by kind and, if the scan was
+// told to record them, kept in special. stats has what the scan did.
+#[derive(Clone, Default)]
+pub struct TreeList {
+    pub header: TreeIndexHeader,
+    pub list: Vec<TreeItem>,
+    pub special: Vec<TreeItem>,
+    pub special_counts: BTreeMap<FileKind, usize>,
+    pub stats: ScanStats
+}
+
+/// A ScanSummary is given to TreeIndexBuilder::scan_summary to find out
+/// what the scan of an index built from a path or a scan found. Clones share
+/// the same summary.
+#[derive(Clone, Default)]
+pub struct ScanSummary {
+    list: Arc<Mutex<TreeList>>
+}
+
+impl ScanSummary {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The header, special file counts and stats of the scan in a list
+    /// without the items, which went into the index
+    pub fn list(&self) -> TreeList {
+        self.list.lock().unwrap().clone()
+    }
+
+    pub(crate) fn record(&self, list: TreeList) {
+        *self.list.lock().unwrap() = list;
+    }
+}
+
+impl TreeList {
+
+    /// Writes the header followed by every item and any recorded special files
+    pub fn write(&self, w: &mut dyn Write) -> Result<()> {
+        write!(w, "{}", self.header.for_text())?;
+        for item in &self.list {
+            write!(w, "{}", item)?;
+        }
+        for item in &self.special {
+            write!(w, "{}", item)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the list in the given format. Only the text and NDJSON formats
+    /// keep the special files.
+    pub fn write_format(&self, w: &mut dyn Write, format: IndexFormat) -> Result<()> {
+        match format {
+            IndexFormat::Text => self.write(w),
+            IndexFormat::Binary => {
+                let mut bw = BinaryWriter::new(w, &self.header)?;
+                for item in &self.list {
+                    bw.entry(&TreeItemDupes::from(item))?;
+                }
+                bw.finish()
+            },
+            IndexFormat::Csv => {
+                write_csv_header(w)?;
+                for item in &self.list {
+                    write_csv_item(w, item, None)?;
+                }
+                Ok(())
+            },
+            IndexFormat::Ndjson => {
+                for item in self.list.iter().chain(self.special.iter()) {
+                    write_ndjson_item(w, item)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    // adds a scanned item, special files are counted and recorded by policy.
+    // reparse points only get this far when they are to be recorded.
+    fn push(&mut self, item: TreeItem, policy: SpecialFilePolicy) {
+        if item.kind == FileKind::File {
+            self.list.push(item);
+            return;
+        }
+        *self.special_counts.entry(item.kind).or_insert(0) += 1;
+        if policy == SpecialFilePolicy::Record || item.kind.is_reparse_point() {
+            self.special.push(item);
+        }
+    }
+}
+
+/// What a scan does with FIFOs, sockets, device nodes, dangling symlinks
+/// and anything else that isn't a directory or a regular file. Skip counts
+/// them, Record also keeps them in the list and Error fails the scan.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SpecialFilePolicy {
+    #[default]
+    Skip,
+    Record,
+    Error
+}
+
+impl FromStr for SpecialFilePolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "skip" => Ok(SpecialFilePolicy::Skip),
+            "record" => Ok(SpecialFilePolicy::Record),
+            "error" => Ok(SpecialFilePolicy::Error),
+            _ => Err(Error::InvalidArgument(format!("unknown special file policy {}", s)))
+        }
+    }
+}
+
+/// What a scan does with Windows junctions, volume mount points and cloud
+/// placeholders. Skip leaves them out so a junction back up the tree isn't
+/// walked forever and files that are only in the cloud aren't downloaded
+/// to hash them. Follow treats them like the directories and files they
+/// stand for, a directory already scanned is skipped with a warning when a
+/// junction leads back to it. Record keeps them in the list as special files without going
+/// into them or reading them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ReparsePolicy {
+    #[default]
+    Skip,
+    Follow,
+    Record
+}
+
+impl FromStr for ReparsePolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "skip" => Ok(ReparsePolicy::Skip),
+            "follow" => Ok(ReparsePolicy::Follow),
+            "record" => Ok(ReparsePolicy::Record),
+            _ => Err(Error::InvalidArgument(format!("unknown reparse point policy {}", s)))
+        }
+    }
+}
+
+/// The order in which the directory tree is walked. BreadthFirst visits
+/// each level in turn in directory entry order. DepthFirst finishes each
+/// directory's subtree before moving on, which is friendlier to spinning
+/// disks. SortedDepthFirst also sorts directory entries by name so that the
+/// output is identical from run to run.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TraversalOrder {
+    #[default]
+    BreadthFirst,
+    DepthFirst,
+    SortedDepthFirst
+}
+
+impl FromStr for TraversalOrder {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "bfs" | "breadth-first" => Ok(TraversalOrder::BreadthFirst),
+            "dfs" | "depth-first" => Ok(TraversalOrder::DepthFirst),
+            "sorted" | "sorted-dfs" => Ok(TraversalOrder::SortedDepthFirst),
+            _ => Err(Error::InvalidArgument(format!("unknown traversal order {}", s)))
+        }
+    }
+}
+
+// The state a directory inherits from its parents during a scan
+#[derive(Clone, Default)]
+pub(crate) struct ScanScope {
+    // the ignore file matchers from the outermost to the innermost directory
+    ignores: Arc<Vec<Gitignore>>,
+
+    // the depth of the directory below the root, the root is 0
+    depth: usize,
+
+    // the device of the root when staying on the same file system
+    dev: Option<u64>
+}
+
+pub struct TreeListBuilder<'a> {
+    fast: bool,
+    mmap: bool,
+    algorithm: Algorithm,
+    encoding: DigestEncoding,
+    key: Option<Vec<u8>>,
+    chunking: Option<Chunking>,
+    with_metadata: bool,
+    fuzzy: bool,
+    min_size: u64,
+    max_size: u64,
+    size_filter: Option<SizeFilter>,
+    sizes: Option<HashSet<u64>>,
+    modified_after: Option<SystemTime>,
+    modified_before: Option<SystemTime>,
+    extensions: Vec<String>,
+    mime_prefix: Option<String>,
+    respect_ignore_files: bool,
+    skip_hidden: bool,
+    max_depth: usize,
+    same_file_system: bool,
+    order: TraversalOrder,
+    special_files: SpecialFilePolicy,
+    reparse_points: ReparsePolicy,
+    apple_double: AppleDoublePolicy,
+    threads: usize,
+    paths: Vec<PathBuf>,
+    listed: Option<Vec<PathBuf>>,
+    cancel: Option<CancellationToken>,
+    max_files_per_sec: u64,
+    max_bytes_per_sec: u64,
+    io_priority: IoPriority,
+    limiter: RateLimiter,
+    // the file ids of the directories scanned when following reparse points
+    visited: Mutex<HashSet<(u64, u64)>>,
+    #[cfg(feature = "unicode")]
+    nfc: bool,
+    #[cfg(feature = "archive")]
+    archives: bool,
+    #[cfg(feature = "media")]
+    image_hashes: Option<ImageHashKind>,
+    counters: ScanCounters,
+    error: Option<Error>,
+    lifetime: PhantomData<&'a PathBuf>,
+}
+
+// The work queue shared by the worker threads in a parallel scan
+struct SharedQueue {
+    state: Mutex<QueueState>,
+    ready: Condvar
+}
+
+struct QueueState {
+    q: VecDeque<TreeWork>,
+
+    // the number of work items queued or being processed, when this hits
+    // zero the scan is finished
+    outstanding: usize,
+
+    // set when a worker hits an error
+    stop: bool
+}
+
+impl SharedQueue {
+
+    // blocks until there is work or the scan is finished
+    fn pop<F>(&self, next: F) -> Option<TreeWork>
+    where
+        F: Fn(&mut VecDeque<TreeWork>) -> Option<TreeWork>
+    {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if state.stop || state.outstanding == 0 {
+                return None;
+            }
+            if let Some(work) = next(&mut state.q) {
+                return Some(work);
+            }
+            state = self.ready.wait(state).unwrap();
+        }
+    }
+
+    // queues the children of a finished piece of work
+    fn push<F>(&self, work: Vec<TreeWork>, schedule: F)
+    where
+        F: Fn(&mut VecDeque<TreeWork>, Vec<TreeWork>)
+    {
+        let mut state = self.state.lock().unwrap();
+        state.outstanding += work.len();
+        schedule(&mut state.q, work);
+        state.outstanding -= 1;
+        self.ready.notify_all();
+    }
+
+    // marks a piece of work as finished
+    fn done(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.outstanding -= 1;
+        if state.outstanding == 0 {
+            self.ready.notify_all();
+        }
+    }
+
+    // tells all of the workers to give up
+    fn stop(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.stop = true;
+        self.ready.notify_all();
+    }
+}
+
+impl ScanScope {
+
+    // returns the scope for a subdirectory
+    fn child(&self) -> ScanScope {
+        ScanScope {
+            ignores: self.ignores.clone(),
+            depth: self.depth + 1,
+            dev: self.dev
+        }
+    }
+
+    // returns true if the path is ignored, the innermost ignore file that
+    // has an opinion about the path wins
+    fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        for gi in self.ignores.iter().rev() {
+            let m = gi.matched(path, is_dir);
+            if m.is_ignore() {
+                return true;
+            } else if m.is_whitelist() {
+                return false;
+            }
+        }
+        false
+    }
+}
+
+impl<'a> Default for TreeListBuilder<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> TreeListBuilder<'a> {
+
+    pub fn new() -> Self {
+        Self {
+            fast: false,
+            mmap: false,
+            algorithm: Algorithm::default(),
+            encoding: DigestEncoding::default(),
+            key: None,
+            chunking: None,
+            with_metadata: false,
+            fuzzy: false,
+            min_size: 0,
+            max_size: u64::MAX,
+            size_filter: None,
+            sizes: None,
+            modified_after: None,
+            modified_before: None,
+            extensions: Vec::new(),
+            mime_prefix: None,
+            respect_ignore_files: false,
+            skip_hidden: false,
+            max_depth: usize::MAX,
+            same_file_system: false,
+            order: TraversalOrder::BreadthFirst,
+            special_files: SpecialFilePolicy::Skip,
+            reparse_points: ReparsePolicy::Skip,
+            apple_double: AppleDoublePolicy::Keep,
+            threads: 1,
+            paths: Vec::new(),
+            listed: None,
+            cancel: None,
+            max_files_per_sec: 0,
+            max_bytes_per_sec: 0,
+            io_priority: IoPriority::Normal,
+            limiter: RateLimiter::default(),
+            visited: Mutex::default(),
+            #[cfg(feature = "unicode")]
+            nfc: false,
+            #[cfg(feature = "archive")]
+            archives: false,
+            #[cfg(feature = "media")]
+            image_hashes: None,
+            counters: ScanCounters::default(),
+            error: None,
+            lifetime: PhantomData
+        }
+    }
+
+    pub fn fast(mut self, fast: bool) -> Self {
+        self.fast = fast;
+        self
+    }
+
+    /// Memory map files to hash them, see TreeItemBuilder::mmap
+    pub fn mmap(mut self, mmap: bool) -> Self {
+        self.mmap = mmap;
+        self
+    }
+
+    /// The digest algorithm to hash files with, see TreeItemBuilder::algorithm
+    pub fn algorithm(mut self, algorithm: Algorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    /// How digests are written, see TreeItemBuilder::encoding
+    pub fn encoding(mut self, encoding: DigestEncoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Key the digests with a secret, see TreeItemBuilder::key
+    pub fn key(mut self, key: &[u8]) -> Self {
+        self.key = Some(key.to_vec());
+        self
+    }
+
+    /// Also digest files in chunks of this size, see TreeItemBuilder::chunks.
+    /// This takes either a u64 or a human readable string such as "4MiB".
+    pub fn chunks<B: IntoBytes>(mut self, chunk_size: B) -> Self {
+        match chunk_size.into_bytes() {
+            Ok(c) => self.chunking = Some(Chunking::Fixed(c)),
+            Err(e) => self.error = Some(e)
+        }
+        self
+    }
+
+    /// Also digest files in content defined chunks of about this size, see
+    /// Chunking. This takes either a u64 or a human readable string.
+    pub fn content_chunks<B: IntoBytes>(mut self, avg_size: B) -> Self {
+        match avg_size.into_bytes() {
+            Ok(c) => self.chunking = Some(Chunking::Content(c)),
+            Err(e) => self.error = Some(e)
+        }
+        self
+    }
+
+    /// Chunk files the given way, see TreeItemBuilder::chunking
+    pub fn chunking(mut self, chunking: Chunking) -> Self {
+        self.chunking = Some(chunking);
+        self
+    }
+
+    /// Keep the metadata of each file, see TreeItemBuilder::with_metadata
+    pub fn with_metadata(mut self, with_metadata: bool) -> Self {
+        self.with_metadata = with_metadata;
+        self
+    }
+
+    /// Keep a fuzzy digest of each file, see TreeItemBuilder::fuzzy
+    pub fn fuzzy(mut self, fuzzy: bool) -> Self {
+        self.fuzzy = fuzzy;
+        self
+    }
+
+    /// Normalize the paths of the listed files to Unicode NFC. The paths no
+    /// longer match the names on file systems that store them decomposed
+    /// but still open on the ones that ignore the difference, like macOS.
+    #[cfg(feature = "unicode")]
+    pub fn normalize_unicode(mut self, nfc: bool) -> Self {
+        self.nfc = nfc;
+        self
+    }
+
+    /// Also list the files inside zip, tar and tar.gz archives. The members
+    /// get paths like backup.zip!photos/cat.jpg and are digested the same
+    /// way as files so they match their extracted copies. Archives are found
+    /// by their extension, archives inside archives aren't opened.
+    #[cfg(feature = "archive")]
+    pub fn archives(mut self, archives: bool) -> Self {
+        self.archives = archives;
+        self
+    }
+
+    /// Keep the perceptual hash of every image, see
+    /// TreeItemBuilder::image_hash
+    #[cfg(feature = "media")]
+    pub fn image_hashes(mut self, kind: ImageHashKind) -> Self {
+        self.image_hashes = Some(kind);
+        self
+    }
+
+    /// Only files at least this big are listed. This takes either a u64 or
+    /// a human readable string such as "10MiB".
+    pub fn min_size<B: IntoBytes>(mut self, min: B) -> Self {
+        match min.into_bytes() {
+            Ok(min) => self.min_size = min,
+            Err(e) => self.error = Some(e)
+        }
+        self
+    }
+
+    /// Only files at most this big are listed. This takes either a u64 or
+    /// a human readable string such as "4GiB".
+    pub fn max_size<B: IntoBytes>(mut self, max: B) -> Self {
+        match max.into_bytes() {
+            Ok(max) => self.max_size = max,
+            Err(e) => self.error = Some(e)
+        }
+        self
+    }
+
+    /// Only files whose size might be in the filter are digested and only
+    /// the ones whose digest might be are listed, see TreeIndex::size_filter
+    pub fn size_filter(mut self, filter: SizeFilter) -> Self {
+        self.size_filter = Some(filter);
+        self
+    }
+
+    /// Only files with exactly one of these sizes are digested, see
+    /// TreeIndex::sizes. This is exact where size_filter is approximate but
+    /// it takes more memory for very large indexes.
+    pub fn sizes(mut self, sizes: HashSet<u64>) -> Self {
+        self.sizes = Some(sizes);
+        self
+    }
+
+    /// Only files modified at or after this time are listed
+    pub fn modified_after(mut self, time: SystemTime) -> Self {
+        self.modified_after = Some(time);
+        self
+    }
+
+    /// Only files modified before this time are listed
+    pub fn modified_before(mut self, time: SystemTime) -> Self {
+        self.modified_before = Some(time);
+        self
+    }
+
+    /// Only files with one of these extensions are listed. The comparison
+    /// is case insensitive and the extensions are given without the dot.
+    pub fn extensions<S: AsRef<str>>(mut self, exts: &[S]) -> Self {
+        self.extensions = exts.iter()
+            .map(|e| e.as_ref().trim_start_matches('.').to_lowercase())
+            .collect();
+        self
+    }
+
+    /// Only files whose sniffed MIME type starts with this prefix are listed
+    /// (e.g. "image/"). The type is guessed from the magic bytes at the
+    /// start of the file so this costs an extra small read per file.
+    pub fn mime_prefix(mut self, prefix: &str) -> Self {
+        self.mime_prefix = Some(prefix.to_string());
+        self
+    }
+
+    /// Honor .gitignore and .ignore files found while walking the tree as
+    /// well as the global git excludes file. The .git directories are also
+    /// skipped in this mode.
+    pub fn respect_ignore_files(mut self, respect: bool) -> Self {
+        self.respect_ignore_files = respect;
+        self
+    }
+
+    /// Skip hidden files and don't descend into hidden directories. On Unix
+    /// these are the dotfiles, on Windows it is anything with the hidden
+    /// attribute as well as dotfiles.
+    pub fn skip_hidden(mut self, skip: bool) -> Self {
+        self.skip_hidden = skip;
+        self
+    }
+
+    /// Limits how deep the scan goes. A depth of 1 only lists the files in
+    /// the root directory, 2 includes the files in its subdirectories, etc.
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = depth;
+        self
+    }
+
+    /// Don't descend into directories on a different file system than the
+    /// root, e.g. mount points for network shares or /proc. This is only
+    /// supported on Unix and is ignored elsewhere.
+    pub fn same_file_system(mut self, same: bool) -> Self {
+        self.same_file_system = same;
+        self
+    }
+
+    /// Sets the order the tree is walked in
+    pub fn order(mut self, order: TraversalOrder) -> Self {
+        self.order = order;
+        self
+    }
+
+    /// What to do with special files like FIFOs, sockets, device nodes and
+    /// dangling symlinks, the default is to skip them
+    pub fn special_files(mut self, policy: SpecialFilePolicy) -> Self {
+        self.special_files = policy;
+        self
+    }
+
+    /// What to do with Windows junctions, volume mount points and cloud
+    /// placeholders, the default is to skip them. They are only found on
+    /// Windows.
+    pub fn reparse_points(mut self, policy: ReparsePolicy) -> Self {
+        self.reparse_points = policy;
+        self
+    }
+
+    /// Whether the ._name AppleDouble files and .DS_Store files macOS
+    /// leaves on SMB shares and FAT drives are scanned, by default they are
+    /// like any other file
+    pub fn apple_double(mut self, policy: AppleDoublePolicy) -> Self {
+        self.apple_double = policy;
+        self
+    }
+
+    /// Sets the number of threads used to walk the tree and hash the files.
+    /// The default of 1 does everything on the calling thread, 0 uses one
+    /// thread per CPU. Parallel walking helps most on network file systems
+    /// where reading directories is the bottleneck.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    /// Stops the scan with Error::Interrupted once the token is cancelled.
+    /// The token is checked before each directory and file.
+    pub fn cancel_token(mut self, token: &CancellationToken) -> Self {
+        self.cancel = Some(token.clone());
+        self
+    }
+
+    /// Tells the metrics about the directories, files and bytes the scan
+    /// gets through and how much work is waiting in its queue
+    pub fn metrics(mut self, metrics: Arc<dyn Metrics>) -> Self {
+        self.counters.set_metrics(metrics);
+        self
+    }
+
+    /// Digest at most this many files a second, 0 is no limit. The limits
+    /// keep a scheduled scan from taking over a machine that is in use.
+    pub fn max_files_per_sec(mut self, files: u64) -> Self {
+        self.max_files_per_sec = files;
+        self
+    }
+
+    /// Read at most this many bytes a second, 0 is no limit. This takes
+    /// either a u64 or a human readable string such as "20MiB". The files
+    /// are paced one at a time so one file bigger than the limit is still
+    /// read at full speed.
+    pub fn max_bytes_per_sec<B: IntoBytes>(mut self, bytes: B) -> Self {
+        match bytes.into_bytes() {
+            Ok(bytes) => self.max_bytes_per_sec = bytes,
+            Err(e) => self.error = Some(e)
+        }
+        self
+    }
+
+    /// The IO priority of the threads doing the scan. A serial scan runs on
+    /// the calling thread, which keeps the priority afterwards.
+    pub fn io_priority(mut self, priority: IoPriority) -> Self {
+        self.io_priority = priority;
+        self
+    }
+
+    /// Adds a root directory to scan. This can be called more than once to
+    /// scan several trees into one list. A scan needs a path, paths,
+    /// current_dir or paths_from.
+    pub fn path(mut self, path: &'a Path) -> Self {
+        self.paths.push(path.to_path_buf());
+        self
+    }
+
+    /// Adds the current directory as a root to scan
+    pub fn current_dir(mut self) -> Self {
+        match std::env::current_dir() {
+            Ok(dir) => self.paths.push(dir),
+            Err(e) => self.error = Some(e.into())
+        }
+        self
+    }
+
+    /// Adds several root directories to scan
+    pub fn paths(mut self, paths: &'a [PathBuf]) -> Self {
+        self.paths.extend(paths.iter().cloned());
+        self
+    }
+
+    /// Digests the paths read from the reader instead of walking the roots,
+    /// so the files can be picked by find or any other tool. The paths are
+    /// one per line or, if nul is set, separated by NUL bytes the way
+    /// `find -print0` writes them. Directories in the list are skipped so a
+    /// find that prints them doesn't get their files listed twice. The
+    /// size, time, extension and mime filters still apply, the ignore
+    /// files, hidden files and depth don't.
+    pub fn paths_from<R: Read>(mut self, r: R, nul: bool) -> Self {
+        let sep = if nul { b'\0' } else { b'\n' };
+        let mut listed = Vec::new();
+        for entry in BufReader::new(r).split(sep) {
+            let mut entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    self.error = Some(e.into());
+                    return self;
+                }
+            };
+            if !nul && entry.last() == Some(&b'\r') {
+                entry.pop();
+            }
+            if !entry.is_empty() {
+                listed.push(bytes_path(entry));
+            }
+        }
+        self.listed = Some(listed);
+        self
+    }
+
+    pub fn build(mut self) -> Result<TreeList> {
+        // report any invalid options
+        self.check()?;
+        #[cfg(feature = "tracing")]
+        let span = crate::fs::stats::scan_span().entered();
+        let start = Instant::now();
+        let mut tl = TreeList::default();
+        if self.max_depth > 0 {
+            // seed the work with the roots
+            let work = self.roots()?;
+            let policy = self.special_files;
+            let mut sink = |item| {
+                tl.push(item, policy);
+                Ok(())
+            };
+            if self.thread_count() > 1 {
+                self.build_parallel(work, &mut sink)?;
+
+                // the workers finish in any order so sort to keep the
+                // output stable
+                if self.order == TraversalOrder::SortedDepthFirst {
+                    tl.list.sort_by_cached_key(TreeItem::path);
+                    tl.special.sort_by_cached_key(TreeItem::path);
+                }
+            } else {
+                self.build_serial(work, &mut sink)?;
+            }
+        }
+
+        // record how the files were digested so they can be checked later
+        tl.header = self.header();
+        #[cfg(feature = "unicode")]
+        if self.nfc {
+            // two names that only differ in their normalization are different
+            // files on most file systems, the second one keeps its name
+            let mut seen: HashSet<PathBuf> = tl.list.iter().chain(tl.special.iter())
+                .map(TreeItem::path)
+                .collect();
+            for i in tl.list.iter_mut().chain(tl.special.iter_mut()) {
+                if let Some(p) = nfc_path(&i.path()) {
+                    if seen.insert(p.clone()) {
+                        i.set_path(&p);
+                    } else {
+                        warn!("{} is also listed in NFC, keeping its name", i.path().to_string_lossy());
+                    }
+                }
+            }
+        }
+        tl.stats = self.counters.stats(start.elapsed());
+        #[cfg(feature = "tracing")]
+        tl.stats.record(&span);
+        Ok(tl)
+    }
+
+    /// Scans the same as build but hands each item to the function as soon
+    /// as it has been digested instead of keeping it, so the results can be
+    /// streamed and memory use doesn't grow with the tree. Special files are
+    /// handed over if the policy records them. Parallel scans hand the items
+    /// over in the order they finish. The returned list has the header, the
+    /// special file counts and the stats but no items.
+    pub fn build_each(mut self, f: &mut dyn FnMut(&TreeItem) -> Result<()>) -> Result<TreeList> {
+        self.check()?;
+        #[cfg(feature = "tracing")]
+        let span = crate::fs::stats::scan_span().entered();
+        let start = Instant::now();
+        let mut tl = TreeList::default();
+        if self.max_depth > 0 {
+            let work = self.roots()?;
+            let policy = self.special_files;
+            #[cfg(feature = "unicode")]
+            let mut seen: HashSet<PathBuf> = HashSet::new();
+            let mut sink = |item: TreeItem| {
+                if item.kind != FileKind::File {
+                    *tl.special_counts.entry(item.kind).or_insert(0) += 1;
+                    if policy != SpecialFilePolicy::Record {
+                        return Ok(());
+                    }
+                }
+                // only the names seen so far can clash with the NFC name
+                #[cfg(feature = "unicode")]
+                let item = {
+                    let mut item = item;
+                    if self.nfc {
+                        let path = item.path();
+                        seen.insert(path.clone());
+                        if let Some(p) = nfc_path(&path) {
+                            if seen.insert(p.clone()) {
+                                item.set_path(&p);
+                            } else {
+                                warn!("{} is also listed in NFC, keeping its name", item.path().to_string_lossy());
+                            }
+                        }
+                    }
+                    item
+                };
+                f(&item)
+            };
+            if self.thread_count() > 1 {
+                self.build_parallel(work, &mut sink)?;
+            } else {
+                self.build_serial(work, &mut sink)?;
+            }
+        }
+        tl.header = self.header();
+        tl.stats = self.counters.stats(start.elapsed());
+        #[cfg(feature = "tracing")]
+        tl.stats.record(&span);
+        Ok(tl)
+    }
+
+    // fails on options that couldn't be parsed, a scan without anything to
+    // scan and options that can't be used together
+    fn check(&mut self) -> Result<()> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        match (&self.listed, self.paths.is_empty()) {
+            (None, true) => return Err(Error::MissingSource(
+                "a scan needs a path, paths, current_dir or paths_from".to_string())),
+            (Some(_), false) => return Err(Error::ConflictingOptions(
+                "paths_from can't be used with paths to walk".to_string())),
+            _ => {}
+        }
+        if self.fast && self.chunking.is_some() {
+            return Err(Error::ConflictingOptions("chunk digests can't be used with fast digests".to_string()));
+        }
+        if self.fast && self.fuzzy {
+            return Err(Error::ConflictingOptions("fuzzy digests can't be used with fast digests".to_string()));
+        }
+        if self.min_size > self.max_size {
+            return Err(Error::ConflictingOptions(format!(
+                "the min size {} is more than the max size {}", self.min_size, self.max_size)));
+        }
+        if let (Some(after), Some(before)) = (self.modified_after, self.modified_before) {
+            if after > before {
+                return Err(Error::ConflictingOptions(
+                    "modified_after is later than modified_before so no file can match".to_string()));
+            }
+        }
+        Ok(())
+    }
+
+    // the header of the list, it records how the files were digested
+    pub(crate) fn header(&self) -> TreeIndexHeader {
+        let mut header = TreeIndexHeader::default();
+        header.set_fast(self.fast);
+        header.set_algorithm(self.algorithm);
+        header.set_encoding(self.encoding);
+        header.set_keyed(self.key.is_some());
+        // a key that can't make an id can't make the digests either, the
+        // scan fails on it
+        let key_id = self.key.as_ref().and_then(|k| self.algorithm.key_id(k).ok());
+        header.set_key_id(key_id.as_deref());
+        header.set_chunking(self.chunking);
+        header.set_metadata(self.with_metadata);
+        header.set_fuzzy(self.fuzzy);
+        #[cfg(feature = "archive")]
+        header.set_archives(self.archives);
+        #[cfg(feature = "media")]
+        header.set_image_hashes(self.image_hashes);
+        #[cfg(feature = "unicode")]
+        header.set_nfc(self.nfc);
+        header
+    }
+
+    /// Builds the list from within an async runtime. The scan itself is
+    /// blocking file system work so it runs on tokio's blocking thread pool
+    /// with the same options as build().
+    #[cfg(feature = "async")]
+    pub async fn build_async(self) -> Result<TreeList> {
+        let builder = self.into_owned();
+        tokio::task::spawn_blocking(move || builder.build())
+            .await
+            .map_err(|e| Error::TaskError(e.to_string()))?
+    }
+
+    // the builder owns all of its data so it can be moved to another thread
+    #[cfg(feature = "async")]
+    fn into_owned(self) -> TreeListBuilder<'static> {
+        TreeListBuilder {
+            fast: self.fast,
+            mmap: self.mmap,
+            algorithm: self.algorithm,
+            encoding: self.encoding,
+            key: self.key,
+            chunking: self.chunking,
+            with_metadata: self.with_metadata,
+            fuzzy: self.fuzzy,
+            min_size: self.min_size,
+            max_size: self.max_size,
+            size_filter: self.size_filter,
+            sizes: self.sizes,
+            modified_after: self.modified_after,
+            modified_before: self.modified_before,
+            extensions: self.extensions,
+            mime_prefix: self.mime_prefix,
+            respect_ignore_files: self.respect_ignore_files,
+            skip_hidden: self.skip_hidden,
+            max_depth: self.max_depth,
+            same_file_system: self.same_file_system,
+            order: self.order,
+            special_files: self.special_files,
+            reparse_points: self.reparse_points,
+            apple_double: self.apple_double,
+            threads: self.threads,
+            paths: self.paths,
+            listed: self.listed,
+            cancel: self.cancel,
+            max_files_per_sec: self.max_files_per_sec,
+            max_bytes_per_sec: self.max_bytes_per_sec,
+            io_priority: self.io_priority,
+            limiter: RateLimiter::default(),
+            visited: Mutex::default(),
+            #[cfg(feature = "unicode")]
+            nfc: self.nfc,
+            #[cfg(feature = "archive")]
+            archives: self.archives,
+            #[cfg(feature = "media")]
+            image_hashes: self.image_hashes,
+            counters: self.counters,
+            error: self.error,
+            lifetime: PhantomData
+        }
+    }
+
+    // a scan that can't lower its priority still goes ahead
+    fn set_priority(&self) {
+        if let Err(e) = set_io_priority(self.io_priority) {
+            warn!("failed to set the io priority to {}: {}", self.io_priority, e);
+        }
+    }
+
+    // waits until reading the file keeps the scan under its rate limits,
+    // checking for cancellation while it waits
+    fn throttle(&self, f: &Path) -> Result<()> {
+        if self.max_files_per_sec == 0 && self.max_bytes_per_sec == 0 {
+            return Ok(());
+        }
+        let size = fs::metadata(f).map(|m| m.len()).unwrap_or(0);
+        let read = if self.fast { size.min(2 * FAST_CHUNK) } else { size };
+        let mut wait = self.limiter.take(read, self.max_files_per_sec, self.max_bytes_per_sec);
+        while !wait.is_zero() {
+            let nap = wait.min(Duration::from_millis(100));
+            thread::sleep(nap);
+            wait -= nap;
+            self.check_cancel()?;
+        }
+        Ok(())
+    }
+
+    // returns Error::Interrupted if the scan has been cancelled
+    fn check_cancel(&self) -> Result<()> {
+        match &self.cancel {
+            Some(token) => token.check(),
+            None => Ok(())
+        }
+    }
+
+    // returns the number of worker threads to use
+    fn thread_count(&self) -> usize {
+        match self.threads {
+            0 => thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+            n => n
+        }
+    }
+
+    // returns the initial scan work for each of the roots
+    fn roots(&self) -> Result<Vec<TreeWork>> {
+        if let Some(listed) = &self.listed {
+            return Ok(self.listed_work(listed));
+        }
+
+        // the global git excludes apply to the whole scan
+        let mut scope = ScanScope::default();
+        if self.respect_ignore_files {
+            let (global, err) = Gitignore::global();
+            if let Some(e) = err {
+                warn!("failed to load global git excludes: {}", e);
+                self.counters.error();
+            }
+            if !global.is_empty() {
+                scope.ignores = Arc::new(vec![global]);
+            }
+        }
+
+        let roots = self.paths.clone();
+        let mut work = Vec::new();
+        for root in roots {
+            let mut scope = scope.clone();
+
+            // remember the root's device to stay on the same file system
+            if self.same_file_system {
+                scope.dev = device_id(&root);
+            }
+            work.push(TreeWork::Scan(root, scope));
+        }
+        Ok(work)
+    }
+
+    // walks the tree and hashes the files on the calling thread, the items
+    // go to the sink
+    fn build_serial(&self, work: Vec<TreeWork>, sink: &mut dyn FnMut(TreeItem) -> Result<()>) -> Result<()> {
+        self.set_priority();
+        let mut q: VecDeque<TreeWork> = VecDeque::new();
+        self.schedule(&mut q, work);
+
+        // process the work
+        while let Some(work) = self.next(&mut q) {
+            self.check_cancel()?;
+            match work {
+                TreeWork::Scan(d, scope) => {
+                    let work = self.scan_dir(d, scope)?;
+                    self.schedule(&mut q, work);
+                },
+                TreeWork::Digest(f) => {
+                    if let Some(item) = self.digest(&f)? {
+                        sink(item)?;
+                    }
+                },
+                #[cfg(feature = "archive")]
+                TreeWork::Archive(f) => {
+                    for item in self.digest_archive(&f) {
+                        sink(item)?;
+                    }
+                },
+                TreeWork::Special(f, kind) => {
+                    sink(self.special(f, kind)?)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // walks the tree and hashes the files using a pool of worker threads
+    // that share one work queue. the workers funnel the TreeItems back to
+    // this thread through a channel and on to the sink. the first error
+    // stops all workers.
+    fn build_parallel(&self, work: Vec<TreeWork>, sink: &mut dyn FnMut(TreeItem) -> Result<()>) -> Result<()> {
+        // the workers inherit the priority of this thread on Linux
+        self.set_priority();
+        let shared = SharedQueue {
+            state: Mutex::new(QueueState {
+                q: VecDeque::new(),
+                outstanding: work.len(),
+                stop: false
+            }),
+            ready: Condvar::new()
+        };
+        {
+            let mut state = shared.state.lock().unwrap();
+            self.schedule(&mut state.q, work);
+        }
+
+        let (tx, rx) = mpsc::channel::<Result<TreeItem>>();
+        let mut first_err = None;
+
+        // the workers digest in the span of the scan
+        #[cfg(feature = "tracing")]
+        let span = tracing::Span::current();
+
+        thread::scope(|s| {
+            for _ in 0..self.thread_count() {
+                let tx = tx.clone();
+                let shared = &shared;
+                #[cfg(feature = "tracing")]
+                let span = &span;
+                s.spawn(move || {
+                    #[cfg(feature = "tracing")]
+                    let _scan = span.enter();
+                    self.worker(shared, tx)
+                });
+            }
+            drop(tx);
+
+            // collect the results until all of the workers hang up
+            for result in rx {
+                match result.and_then(&mut *sink) {
+                    Ok(()) => {},
+                    Err(e) => {
+                        if first_err.is_none() {
+                            first_err = Some(e);
+                        }
+                        shared.stop();
+                    }
+                }
+            }
+        });
+
+        match first_err {
+            Some(e) => Err(e),
+            None => Ok(())
+        }
+    }
+
+    // the loop run by each worker thread in build_parallel
+    fn worker(&self, shared: &SharedQueue, tx: mpsc::Sender<Result<TreeItem>>) {
+        while let Some(work) = shared.pop(|q| self.next(q)) {
+            if let Err(e) = self.check_cancel() {
+                let _ = tx.send(Err(e));
+                shared.done();
+                continue;
+            }
+            match work {
+                TreeWork::Scan(d, scope) => {
+                    match self.scan_dir(d, scope) {
+                        Ok(work) => shared.push(work, |q, w| self.schedule(q, w)),
+                        Err(e) => {
+                            let _ = tx.send(Err(e));
+                            shared.done();
+                        }
+                    }
+                },
+                TreeWork::Digest(f) => {
+                    if let Some(result) = self.digest(&f).transpose() {
+                        let _ = tx.send(result);
+                    }
+                    shared.done();
+                },
+                #[cfg(feature = "archive")]
+                TreeWork::Archive(f) => {
+                    for item in self.digest_archive(&f) {
+                        let _ = tx.send(Ok(item));
+                    }
+                    shared.done();
+                },
+                TreeWork::Special(f, kind) => {
+                    let _ = tx.send(self.special(f, kind));
+                    shared.done();
+                }
+            }
+        }
+    }
+
+    // reads a directory and returns the work for its children that pass
+    // the filters
+    fn scan_dir(&self, d: PathBuf, scope: ScanScope) -> Result<Vec<TreeWork>> {
+        debug!("[SCAN] {}", d.to_string_lossy());
+        if self.is_visited(&d) {
+            warn!("{} has already been scanned, skipping it", d.to_string_lossy());
+            return Ok(Vec::new());
+        }
+        self.counters.dir();
+        let scope = self.enter(&d, scope);
+        // the children are joined to d so they keep its form rather than
+        // the extended one the directory was read with
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(os_path(&d))? {
+            entries.push(d.join(entry?.file_name()));
+        }
+        if self.order == TraversalOrder::SortedDepthFirst {
+            entries.sort();
+        }
+        let mut work = Vec::new();
+        for path in entries {
+            let os = os_path(&path);
+            if self.skip_hidden && is_hidden(&path) {
+                if !os.is_dir() {
+                    self.counters.skip();
+                }
+                continue;
+            }
+            if let Some(kind) = self.reparse_kind(&path) {
+                if self.reparse_points == ReparsePolicy::Record && !self.is_ignored(&scope, &path, os.is_dir()) {
+                    work.push(TreeWork::Special(path, kind));
+                } else {
+                    self.skip_reparse(&path, kind);
+                }
+                continue;
+            }
+            if os.is_dir() {
+                if scope.depth + 1 >= self.max_depth {
+                    continue;
+                }
+                if scope.dev.is_some() && device_id(&path) != scope.dev {
+                    debug!("[SKIP] {} is on another file system", path.to_string_lossy());
+                    continue;
+                }
+                if !self.is_ignored(&scope, &path, true) {
+                    work.push(TreeWork::Scan(path, scope.child()));
+                }
+            } else if os.is_file() {
+                let meta = fs::metadata(&os).ok();
+                if !self.is_ignored(&scope, &path, false) && !self.is_skipped_metadata(&path) && self.keep(&path, &meta) {
+                    #[cfg(feature = "archive")]
+                    if self.archives && ArchiveKind::of(&path).is_some() {
+                        work.push(TreeWork::Archive(path.clone()));
+                    }
+                    work.push(TreeWork::Digest(path));
+                } else {
+                    self.counters.skip();
+                }
+            } else if !self.is_ignored(&scope, &path, false) {
+                // not a directory or a regular file, even through a symlink
+                let kind = match fs::symlink_metadata(&os) {
+                    Ok(meta) => FileKind::from(meta.file_type()),
+                    Err(_) => FileKind::Unknown
+                };
+                work.push(TreeWork::Special(path, kind));
+            }
+        }
+        Ok(work)
+    }
+
+    // returns the work for the paths the list was given instead of roots.
+    // a path that doesn't exist is logged and counted as an error.
+    fn listed_work(&self, listed: &[PathBuf]) -> Vec<TreeWork> {
+        let mut work = Vec::new();
+        for path in listed {
+            let path = path.clone();
+            let os = os_path(&path);
+            if let Some(kind) = self.reparse_kind(&path) {
+                if self.reparse_points == ReparsePolicy::Record {
+                    work.push(TreeWork::Special(path, kind));
+                } else {
+                    self.skip_reparse(&path, kind);
+                }
+            } else if os.is_dir() {
+                debug!("[SKIP] {} is a directory", path.to_string_lossy());
+            } else if os.is_file() {
+                let meta = fs::metadata(&os).ok();
+                if !self.is_skipped_metadata(&path) && self.keep(&path, &meta) {
+                    #[cfg(feature = "archive")]
+                    if self.archives && ArchiveKind::of(&path).is_some() {
+                        work.push(TreeWork::Archive(path.clone()));
+                    }
+                    work.push(TreeWork::Digest(path));
+                } else {
+                    self.counters.skip();
+                }
+            } else {
+                match fs::symlink_metadata(&os) {
+                    Ok(meta) => work.push(TreeWork::Special(path, FileKind::from(meta.file_type()))),
+                    Err(e) => {
+                        warn!("failed to read {}: {}", path.to_string_lossy(), e);
+                        self.counters.error();
+                    }
+                }
+            }
+        }
+        work
+    }
+
+    // AppleDouble and .DS_Store files are left out unless they are kept
+    fn is_skipped_metadata(&self, path: &Path) -> bool {
+        self.apple_double != AppleDoublePolicy::Keep && is_mac_metadata(path)
+    }
+
+    // the kind of reparse point the path is, None when they are followed
+    fn reparse_kind(&self, path: &Path) -> Option<FileKind> {
+        if self.reparse_points == ReparsePolicy::Follow {
+            return None;
+        }
+        reparse_kind(path)
+    }
+
+    // true if following reparse points has led back to a directory that
+    // has been scanned, the directory is marked as scanned otherwise
+    fn is_visited(&self, d: &Path) -> bool {
+        if self.reparse_points != ReparsePolicy::Follow {
+            return false;
+        }
+        match file_id(d) {
+            Some(id) => !self.visited.lock().unwrap().insert(id),
+            None => false
+        }
+    }
+
+    // a placeholder left out is counted like a file the filters skipped
+    fn skip_reparse(&self, path: &Path, kind: FileKind) {
+        debug!("[SKIP] {} is a {}", path.to_string_lossy(), kind);
+        if kind == FileKind::Placeholder {
+            self.counters.skip();
+        }
+    }
+
+    // makes the item for a special file or fails if they aren't allowed,
+    // reparse points are only here when they are to be recorded
+    fn special(&self, f: PathBuf, kind: FileKind) -> Result<TreeItem> {
+        debug!("[SPCL] {} {}", kind, f.to_string_lossy());
+        if self.special_files == SpecialFilePolicy::Error && !kind.is_reparse_point() {
+            return Err(Error::NotAFile(f));
+        }
+        Ok(TreeItem::special(&f, kind))
+    }
+
+    // hashes a file, None if its digest can't be in the size filter
+    fn digest(&self, f: &PathBuf) -> Result<Option<TreeItem>> {
+        self.throttle(f)?;
+        let item = self.item_builder(f).build()?;
+        self.counters.file(item.size);
+        if let Some(filter) = &self.size_filter {
+            if !filter.contains_digest(item.size, &item.digest) {
+                debug!("[SKIP] {} has a digest that isn't in the filter", f.to_string_lossy());
+                return Ok(None);
+            }
+        }
+        Ok(Some(item))
+    }
+
+    // hashes the members of an archive that pass the size filters. an
+    // archive that can't be read is logged and counted as an error, the
+    // members read before the problem are kept.
+    #[cfg(feature = "archive")]
+    fn digest_archive(&self, f: &Path) -> Vec<TreeItem> {
+        let mut items = Vec::new();
+        let result = archive::read_members(f, &mut |member, size, r| {
+            if size < self.min_size || size > self.max_size {
+                self.counters.skip();
+                return Ok(());
+            }
+            let path = member_path(f, member);
+            let item = self.item_builder(&path).with_metadata(false).build_reader(r, size)?;
+            self.counters.file(item.size);
+            items.push(item);
+            Ok(())
+        });
+        if let Err(e) = result {
+            warn!("failed to read archive {}: {}", f.to_string_lossy(), e);
+            self.counters.error();
+        }
+        items
+    }
+
+    // a builder that digests files the way the list was told to
+    fn item_builder<'b>(&'b self, f: &'b PathBuf) -> TreeItemBuilder<'b> {
+        let mut builder = TreeItemBuilder::new()
+            .fast(self.fast)
+            .mmap(self.mmap)
+            .algorithm(self.algorithm)
+            .encoding(self.encoding)
+            .with_metadata(self.with_metadata)
+            .fuzzy(self.fuzzy);
+        if let Some(key) = &self.key {
+            builder = builder.key(key);
+        }
+        if let Some(c) = self.chunking {
+            builder = builder.chunking(c);
+        }
+        #[cfg(feature = "media")]
+        if let Some(k) = self.image_hashes {
+            builder = builder.image_hash(k);
+        }
+        builder.path(f)
+    }
+
+    // adds the work to the queue so that it comes out in order
+    fn schedule(&self, q: &mut VecDeque<TreeWork>, work: Vec<TreeWork>) {
+        match self.order {
+            TraversalOrder::BreadthFirst => q.extend(work),
+            _ => q.extend(work.into_iter().rev())
+        }
+    }
+
+    // gets the next piece of work from the queue, breadth first treats the
+    // queue as a FIFO and depth first treats it as a stack
+    fn next(&self, q: &mut VecDeque<TreeWork>) -> Option<TreeWork> {
+        let work = match self.order {
+            TraversalOrder::BreadthFirst => q.pop_front(),
+            _ => q.pop_back()
+        };
+        self.counters.queue(q.len());
+        work
+    }
+
+    // loads the ignore files in the directory, if any, and returns the scope
+    // for the directory's children
+    fn enter(&self, d: &Path, scope: ScanScope) -> ScanScope {
+        if !self.respect_ignore_files {
+            return scope;
+        }
+
+        let mut builder = GitignoreBuilder::new(d);
+        let mut found = false;
+        let candidates = [
+            d.join(".git").join("info").join("exclude"),
+            d.join(".gitignore"),
+            d.join(".ignore")
+        ];
+        for f in candidates.iter() {
+            if f.is_file() {
+                // later files take precedence over earlier ones
+                if let Some(e) = builder.add(f) {
+                    warn!("failed to parse {}: {}", f.to_string_lossy(), e);
+                    self.counters.error();
+                }
+                found = true;
+            }
+        }
+        if !found {
+            return scope;
+        }
+
+        match builder.build() {
+            Ok(gi) => {
+                let mut scope = scope;
+                let mut ignores = (*scope.ignores).clone();
+                ignores.push(gi);
+                scope.ignores = Arc::new(ignores);
+                scope
+            },
+            Err(e) => {
+                warn!("failed to load ignore files in {}: {}", d.to_string_lossy(), e);
+                self.counters.error();
+                scope
+            }
+        }
+    }
+
+    // returns true if the path should be skipped because of ignore files
+    fn is_ignored(&self, scope: &ScanScope, path: &Path, is_dir: bool) -> bool {
+        if !self.respect_ignore_files {
+            return false;
+        }
+        if is_dir && path.file_name().map(|n| n == ".git").unwrap_or(false) {
+            return true;
+        }
+        scope.is_ignored(path, is_dir)
+    }
+
+    // returns true if the file passes all of the filters
+    fn keep(&self, path: &Path, meta: &Option<Metadata>) -> bool {
+        let size = match meta {
+            Some(meta) => meta.len(),
+            None => 0u64
+        };
+        if size < self.min_size || size > self.max_size {
+            return false;
+        }
+        if let Some(sizes) = &self.sizes {
+            if !sizes.contains(&size) {
+                return false;
+            }
+        }
+        if let Some(filter) = &self.size_filter {
+            if !filter.contains(size) {
+                return false;
+            }
+        }
+
+        // files without a readable mtime are skipped when filtering on time
+        if self.modified_after.is_some() || self.modified_before.is_some() {
+            let mtime = match meta.as_ref().map(|m| m.modified()) {
+                Some(Ok(t)) => t,
+                _ => return false
+            };
+            if let Some(after) = self.modified_after {
+                if mtime < after {
+                    return false;
+                }
+            }
+            if let Some(before) = self.modified_before {
+                if mtime >= before {
+                    return false;
+                }
+            }
+        }
+
+        if !self.extensions.is_empty() {
+            let ext = match path.extension() {
+                Some(ext) => ext.to_string_lossy().to_lowercase(),
+                None => return false
+            };
+            if !self.extensions.contains(&ext) {
+                return false;
+            }
+        }
+
+        // sniffing requires reading the file so it is checked last
+        if let Some(prefix) = &self.mime_prefix {
+            match mime::sniff_path(&os_path(path)) {
+                Some(m) if m.starts_with(prefix.as_str()) => {},
+                _ => return false
+            }
+        }
+        true
+    }
+}
+
+// returns true if the file or directory name starts with a dot
+#[cfg(not(windows))]
+fn is_hidden(path: &Path) -> bool {
+    match path.file_name() {
+        Some(name) => name.to_string_lossy().starts_with('.'),
+        None => false
+    }
+}
+
+// returns true if the file has the hidden attribute or starts with a dot
+#[cfg(windows)]
+fn is_hidden(path: &Path) -> bool {
+    use std::os::windows::fs::MetadataExt;
+    const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+    if let Some(name) = path.file_name() {
+        if name.to_string_lossy().starts_with('.') {
+            return true;
+        }
+    }
+    match fs::symlink_metadata(os_path(path)) {
+        Ok(meta) => meta.file_attributes() & FILE_ATTRIBUTE_HIDDEN != 0,
+        Err(_) => false
+    }
+}
+
+// returns the id of the device the path is on
+#[cfg(unix)]
+fn device_id(path: &Path) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    fs::metadata(path).ok().map(|m| m.dev())
+}
+
+#[cfg(not(unix))]
+fn device_id(_path: &Path) -> Option<u64> {
+    None
+}
+
+// turns the bytes of a listed path into a path, unix paths can be any bytes
+#[cfg(unix)]
+fn bytes_path(b: Vec<u8>) -> PathBuf {
+    use std::os::unix::ffi::OsStringExt;
+    PathBuf::from(std::ffi::OsString::from_vec(b))
+}
+
+#[cfg(not(unix))]
+fn bytes_path(b: Vec<u8>) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(&b).into_owned())
+}
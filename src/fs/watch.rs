@@ -0,0 +1,427 @@
+use crate::{
+    error::Error,
+    Result,
+    fs::{
+        Algorithm,
+        Chunking,
+        DigestEncoding,
+        DigestKey,
+        Metrics,
+        TreeIndex,
+        TreeItem,
+        TreeItemBuilder,
+        TreeItemDupes,
+        TreeListBuilder,
+        metrics,
+        reparse_kind,
+        treeitem::escape_path
+    },
+    cancel::CancellationToken
+};
+#[cfg(feature = "media")]
+use crate::fs::ImageHashKind;
+use log::{debug, warn};
+use notify::{Config, Event, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::Duration;
+
+/// An IndexUpdate describes one change made to a watched TreeIndex. The
+/// Display impl produces lines in the index text format so updates can be
+/// appended to an index file and replayed by TreeIndexBuilder::from_reader.
+#[derive(Clone)]
+pub enum IndexUpdate {
+    Added(TreeItem),
+    Modified(TreeItem),
+    Removed(Arc<PathBuf>)
+}
+
+impl Display for IndexUpdate {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error>
+    {
+        match self {
+            IndexUpdate::Added(item) => write!(f, "{}", item),
+            IndexUpdate::Modified(item) => {
+                writeln!(f, "! {}", escape_path(&item.path())?)?;
+                write!(f, "{}", item)
+            },
+            IndexUpdate::Removed(path) => writeln!(f, "! {}", escape_path(path)?)
+        }
+    }
+}
+
+pub struct TreeWatcherBuilder<'a> {
+    fast: bool,
+    settle: Duration,
+    index: TreeIndex,
+    key: Option<Vec<u8>>,
+    path: Option<&'a Path>,
+    ignore: Vec<PathBuf>,
+    metrics: Option<Arc<dyn Metrics>>,
+    cancel: Option<CancellationToken>
+}
+
+// how often waiting for changes checks whether it has been cancelled
+const CANCEL_POLL: Duration = Duration::from_millis(200);
+
+impl<'a> Default for TreeWatcherBuilder<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> TreeWatcherBuilder<'a> {
+
+    pub fn new() -> Self {
+        Self {
+            fast: false,
+            settle: Duration::from_millis(500),
+            index: TreeIndex::default(),
+            key: None,
+            path: None,
+            ignore: Vec::new(),
+            metrics: None,
+            cancel: None
+        }
+    }
+
+    /// Use fast digests for changed files, this should match how the index
+    /// was built
+    pub fn fast(mut self, fast: bool) -> Self {
+        self.fast = fast;
+        self
+    }
+
+    /// How long to wait for the file system to go quiet before digesting
+    /// changed files. This keeps a file that is being written from being
+    /// hashed over and over.
+    pub fn settle(mut self, settle: Duration) -> Self {
+        self.settle = settle;
+        self
+    }
+
+    /// The index to keep up to date
+    pub fn index(mut self, index: TreeIndex) -> Self {
+        self.index = index;
+        self
+    }
+
+    /// The key for keyed indexes, see TreeItemBuilder::key
+    pub fn key(mut self, key: &[u8]) -> Self {
+        self.key = Some(key.to_vec());
+        self
+    }
+
+    /// The root of the tree to watch
+    pub fn path(mut self, path: &'a Path) -> Self {
+        self.path = Some(path);
+        self
+    }
+
+    /// A path to ignore changes to, typically the index file itself when it
+    /// lives inside the watched tree
+    pub fn ignore_path(mut self, path: &Path) -> Self {
+        // events may use either form of the path
+        if let Ok(p) = path.canonicalize() {
+            self.ignore.push(p);
+        }
+        self.ignore.push(path.to_path_buf());
+        self
+    }
+
+    /// Tells the metrics about the files digested, the updates made and
+    /// the size of the index
+    pub fn metrics(mut self, metrics: Arc<dyn Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Stops waiting for changes with Error::Interrupted once the token is
+    /// cancelled
+    pub fn cancel_token(mut self, token: &CancellationToken) -> Self {
+        self.cancel = Some(token.clone());
+        self
+    }
+
+    pub fn build(self) -> Result<TreeWatcher> {
+        let root = match self.path {
+            Some(p) => p.to_path_buf(),
+            None => return Err(Error::MissingSource("a watcher needs a path to watch".to_string()))
+        };
+
+        // index each path by its digest key so changes can be found quickly
+        let mut paths = HashMap::new();
+        for (key, entry) in &self.index.idx {
+            paths.insert(entry.item.path(), key.clone());
+            for d in &entry.dupes {
+                paths.insert(d.path(), key.clone());
+            }
+        }
+
+        // changed files are hashed the same way as the rest of the index
+        let algorithm = self.index.header.algorithm()?;
+        let encoding = self.index.header.encoding()?;
+        let chunking = self.index.header.chunking()?;
+        let metadata = self.index.header.has_metadata();
+        let fuzzy = self.index.header.has_fuzzy();
+        #[cfg(feature = "media")]
+        let image_hashes = self.index.header.image_hashes()?;
+        if self.index.header.is_keyed() != self.key.is_some() {
+            return Err(Error::InvalidArgument(
+                "a key is needed for keyed indexes and only for keyed indexes".to_string()));
+        }
+
+        let (tx, rx) = mpsc::channel();
+        let mut watcher = RecommendedWatcher::new(tx, Config::default())?;
+        watcher.watch(&root, RecursiveMode::Recursive)?;
+        debug!("[WTCH] {}", root.to_string_lossy());
+
+        Ok(TreeWatcher {
+            fast: self.fast,
+            algorithm,
+            encoding,
+            key: self.key,
+            chunking,
+            metadata,
+            fuzzy,
+            #[cfg(feature = "media")]
+            image_hashes,
+            settle: self.settle,
+            index: self.index,
+            paths,
+            ignore: self.ignore,
+            metrics: self.metrics,
+            cancel: self.cancel,
+            rx: Mutex::new(rx),
+            _watcher: watcher
+        })
+    }
+}
+
+/// A TreeWatcher keeps a TreeIndex up to date with a directory tree using
+/// the operating system's file change notifications (inotify, FSEvents or
+/// ReadDirectoryChangesW).
+pub struct TreeWatcher {
+    fast: bool,
+    algorithm: Algorithm,
+    encoding: DigestEncoding,
+    key: Option<Vec<u8>>,
+    chunking: Option<Chunking>,
+    metadata: bool,
+    fuzzy: bool,
+    #[cfg(feature = "media")]
+    image_hashes: Option<ImageHashKind>,
+    settle: Duration,
+    index: TreeIndex,
+    paths: HashMap<PathBuf, DigestKey>,
+    ignore: Vec<PathBuf>,
+    metrics: Option<Arc<dyn Metrics>>,
+    cancel: Option<CancellationToken>,
+    // behind a lock so a shared watcher can be read while it waits
+    rx: Mutex<Receiver<notify::Result<Event>>>,
+    _watcher: RecommendedWatcher
+}
+
+impl TreeWatcher {
+
+    pub fn index(&self) -> &TreeIndex {
+        &self.index
+    }
+
+    pub fn into_index(self) -> TreeIndex {
+        self.index
+    }
+
+    /// Returns the index entry with the path in it, without searching the
+    /// whole index
+    pub fn entry(&self, path: &Path) -> Option<&TreeItemDupes> {
+        self.paths.get(path).and_then(|key| self.index.idx.get(key))
+    }
+
+    /// Blocks until something in the tree changes and the file system has
+    /// settled, then applies the changes to the index and returns them. An
+    /// empty list means the events didn't change the index, e.g. a file was
+    /// touched but its contents are the same. With a cancel token it returns
+    /// Error::Interrupted once the token is cancelled.
+    pub fn wait(&mut self) -> Result<Vec<IndexUpdate>> {
+        let changed = self.changes()?;
+        self.apply(changed)
+    }
+
+    // the waiting half of wait, it leaves the index alone so it only needs
+    // a shared borrow
+    pub(crate) fn changes(&self) -> Result<BTreeSet<PathBuf>> {
+        let mut changed = BTreeSet::new();
+        let rx = self.rx.lock().unwrap();
+
+        // block for the first event then collect events until it is quiet
+        let first = loop {
+            if let Some(token) = &self.cancel {
+                token.check()?;
+            }
+            match rx.recv_timeout(CANCEL_POLL) {
+                Ok(event) => break event,
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(Error::TaskError("the file watcher stopped".to_string()));
+                }
+            }
+        };
+        self.collect(first?, &mut changed);
+        loop {
+            match rx.recv_timeout(self.settle) {
+                Ok(event) => self.collect(event?, &mut changed),
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => break
+            }
+        }
+        Ok(changed)
+    }
+
+    // the digesting half of wait, it brings the index in line with the
+    // changed paths
+    pub(crate) fn apply(&mut self, changed: BTreeSet<PathBuf>) -> Result<Vec<IndexUpdate>> {
+        let mut updates = Vec::new();
+        for path in changed {
+            self.reconcile(&path, &mut updates)?;
+        }
+        if let Some(m) = &self.metrics {
+            m.add(metrics::WATCH_UPDATES, updates.len() as u64);
+            m.set(metrics::INDEX_ENTRIES, self.index.idx.len() as i64);
+        }
+        Ok(updates)
+    }
+
+    fn collect(&self, event: Event, changed: &mut BTreeSet<PathBuf>) {
+        for p in event.paths {
+            if !self.ignore.contains(&p) {
+                changed.insert(p);
+            }
+        }
+    }
+
+    // look at the current state of the path and bring the index in line
+    fn reconcile(&mut self, path: &Path, updates: &mut Vec<IndexUpdate>) -> Result<()> {
+        // reading a junction or a placeholder would walk out of the tree or
+        // download the file, what the index has for it is kept
+        if let Some(kind) = reparse_kind(path) {
+            debug!("[SKIP] {} is a {}", path.to_string_lossy(), kind);
+            return Ok(());
+        }
+        if path.is_file() {
+            let pb = path.to_path_buf();
+            let mut builder = TreeItemBuilder::new()
+                .fast(self.fast)
+                .algorithm(self.algorithm)
+                .encoding(self.encoding)
+                .with_metadata(self.metadata)
+                .fuzzy(self.fuzzy);
+            if let Some(key) = &self.key {
+                builder = builder.key(key);
+            }
+            if let Some(c) = self.chunking {
+                builder = builder.chunking(c);
+            }
+            #[cfg(feature = "media")]
+            if let Some(k) = self.image_hashes {
+                builder = builder.image_hash(k);
+            }
+            let result = builder.path(&pb).build();
+            let item = match result {
+                Ok(item) => item,
+                Err(e) => {
+                    // the file may have been removed again already
+                    warn!("failed to digest {}: {}", path.to_string_lossy(), e);
+                    if let Some(m) = &self.metrics {
+                        m.add(metrics::SCAN_ERRORS, 1);
+                    }
+                    return Ok(());
+                }
+            };
+            if let Some(m) = &self.metrics {
+                m.add(metrics::FILES_SCANNED, 1);
+                m.add(metrics::BYTES_HASHED, item.size);
+            }
+            match self.paths.get(path).cloned() {
+                Some(old) if old == self.index.key(&item) && !self.metadata_changed(&old, &item) => {},
+                Some(old) => {
+                    self.index.remove(&old, path);
+                    self.add(item.clone());
+                    updates.push(IndexUpdate::Modified(item));
+                },
+                None => {
+                    self.add(item.clone());
+                    updates.push(IndexUpdate::Added(item));
+                }
+            }
+        } else if path.is_dir() {
+            // a directory was created or moved into the tree
+            let pb = path.to_path_buf();
+            let mut builder = TreeListBuilder::new()
+                .fast(self.fast)
+                .algorithm(self.algorithm)
+                .encoding(self.encoding)
+                .with_metadata(self.metadata)
+                .fuzzy(self.fuzzy);
+            if let Some(key) = &self.key {
+                builder = builder.key(key);
+            }
+            if let Some(c) = self.chunking {
+                builder = builder.chunking(c);
+            }
+            #[cfg(feature = "media")]
+            if let Some(k) = self.image_hashes {
+                builder = builder.image_hashes(k);
+            }
+            if let Some(m) = &self.metrics {
+                builder = builder.metrics(Arc::clone(m));
+            }
+            let tl = builder.path(&pb).build()?;
+            for item in tl.list {
+                if !self.paths.contains_key(&item.path()) {
+                    self.add(item.clone());
+                    updates.push(IndexUpdate::Added(item));
+                }
+            }
+        } else {
+            // the path is gone, it may have been a file or a whole directory
+            let gone: Vec<PathBuf> = self.paths.keys()
+                .filter(|p| p.as_path() == path || p.starts_with(path))
+                .cloned()
+                .collect();
+            for p in gone {
+                if let Some(key) = self.paths.remove(&p) {
+                    self.index.remove(&key, &p);
+                    updates.push(IndexUpdate::Removed(Arc::new(p)));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // a file with the same contents can still have new metadata
+    fn metadata_changed(&self, key: &DigestKey, item: &TreeItem) -> bool {
+        let entry = match self.index.idx.get(key) {
+            Some(entry) => entry,
+            None => return false
+        };
+        let old = if entry.item.path == item.path {
+            entry.item.metadata.as_ref()
+        } else {
+            entry.dupe_metadata.get(&item.path)
+        };
+        match (old, &item.metadata) {
+            (Some(old), Some(new)) => !old.changes(new).is_empty(),
+            _ => false
+        }
+    }
+
+    fn add(&mut self, item: TreeItem) {
+        self.paths.insert(item.path(), self.index.key(&item));
+        self.index.insert(item);
+    }
+}
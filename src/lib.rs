@@ -1,3 +1,14 @@
+pub mod cancel;
 pub mod cli;
 pub mod error;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "dedup")]
+pub mod fs;
+#[cfg(any(feature = "dedup", feature = "logging"))]
+mod json;
+pub mod lock;
+#[cfg(feature = "python")]
+mod python;
+pub mod units;
 pub type Result<T> = error::Result<T>;
@@ -0,0 +1,49 @@
+use crate::Result;
+use log::info;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::path::Path;
+
+/// Takes an exclusive advisory lock on the file, flock on unix and
+/// LockFileEx on Windows, waiting for whoever holds it to let go. The lock
+/// is released when the file is closed. Other processes are only kept out
+/// if they lock the file too.
+pub fn lock_exclusive(f: &File, path: &Path) -> Result<()> {
+    if !try_lock_exclusive(f)? {
+        info!("waiting for the lock on {}", path.to_string_lossy());
+        f.lock()?;
+    }
+    Ok(())
+}
+
+/// Takes an exclusive advisory lock on the file if nobody else holds one,
+/// returns false if somebody does
+pub fn try_lock_exclusive(f: &File) -> Result<bool> {
+    match f.try_lock() {
+        Ok(()) => Ok(true),
+        Err(TryLockError::WouldBlock) => Ok(false),
+        Err(TryLockError::Error(e)) => Err(e.into())
+    }
+}
+
+/// Opens the file for writing and locks it before anything is written, so
+/// two writers of the same file take turns instead of interleaving. The
+/// file is emptied once the lock is held unless it is opened to append.
+/// Anything that isn't a regular file, like /dev/null, a FIFO or a
+/// terminal, is opened the way File::create opens it, without a lock and
+/// without being emptied, since it can't be truncated.
+pub fn open_locked(path: &Path, append: bool) -> Result<File> {
+    let f = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(append)
+        .truncate(false)
+        .open(path)?;
+    if !f.metadata()?.is_file() {
+        return Ok(f);
+    }
+    lock_exclusive(&f, path)?;
+    if !append {
+        f.set_len(0)?;
+    }
+    Ok(f)
+}
@@ -0,0 +1,126 @@
+// the pyo3 macros convert the PyErr of every PyResult into itself
+#![allow(clippy::useless_conversion)]
+
+use crate::{
+    error::Error,
+    fs::{IndexStats, ItemPath, TreeIndex, TreeIndexBuilder, TreeItemDupes, TreeListBuilder}
+};
+use pyo3::exceptions::{PyKeyboardInterrupt, PyValueError};
+use pyo3::prelude::*;
+use std::fs::File;
+use std::path::PathBuf;
+
+// a file as its digest, size and path
+type Item = (String, u64, PathBuf);
+
+// an entry as its digest, size and paths with the primary first
+type Entry = (String, u64, Vec<PathBuf>);
+
+// a crate error as the python exception for it, io errors become the
+// matching OSError
+fn py_err(e: Error) -> PyErr {
+    match e {
+        Error::IoError(e) => e.into(),
+        Error::Interrupted => PyKeyboardInterrupt::new_err("interrupted"),
+        e => PyValueError::new_err(format!("{:?}", e))
+    }
+}
+
+fn entry(e: &TreeItemDupes) -> Entry {
+    let paths = std::iter::once(e.item.item_path())
+        .chain(e.dupes.iter())
+        .map(|p| p.path())
+        .collect();
+    (e.item.digest.to_string(), e.item.size, paths)
+}
+
+/// Digests the files under root and returns them as (digest, size, path)
+/// tuples. The GIL is released while it scans.
+#[pyfunction]
+#[pyo3(signature = (root, fast = false, threads = 1))]
+fn scan(py: Python<'_>, root: PathBuf, fast: bool, threads: usize) -> PyResult<Vec<Item>> {
+    let tl = py.allow_threads(|| {
+        TreeListBuilder::new()
+            .fast(fast)
+            .threads(threads)
+            .path(&root)
+            .build()
+    }).map_err(py_err)?;
+    Ok(tl.list.into_iter().map(|i| (i.digest.to_string(), i.size, i.path())).collect())
+}
+
+/// An index of a directory tree with the dupes of every file, made by
+/// scanning a tree or by reading an index file. Entries are (digest, size,
+/// paths) tuples with the primary path first.
+#[pyclass(name = "TreeIndex", module = "best_practices")]
+struct PyTreeIndex {
+    index: TreeIndex
+}
+
+#[pymethods]
+impl PyTreeIndex {
+
+    /// Scans the tree at root into an index, the GIL is released while it
+    /// scans
+    #[staticmethod]
+    #[pyo3(signature = (root, fast = false, threads = 1))]
+    fn scan(py: Python<'_>, root: PathBuf, fast: bool, threads: usize) -> PyResult<Self> {
+        let index = py.allow_threads(|| {
+            TreeIndexBuilder::new()
+                .with_dupes(true)
+                .from_scan(TreeListBuilder::new().fast(fast).threads(threads).path(&root))
+                .build()
+        }).map_err(py_err)?;
+        Ok(Self { index })
+    }
+
+    /// Reads an index file in any of the formats treetool writes that can
+    /// be read back
+    #[staticmethod]
+    fn read(py: Python<'_>, path: PathBuf) -> PyResult<Self> {
+        let index = py.allow_threads(|| TreeIndex::read(File::open(&path)?, true)).map_err(py_err)?;
+        Ok(Self { index })
+    }
+
+    /// Writes the index to a file in the text format
+    fn write(&self, path: PathBuf) -> PyResult<()> {
+        let mut f = File::create(path)?;
+        self.index.write(&mut f).map_err(py_err)
+    }
+
+    /// The groups of duplicate files, the ones wasting the most bytes first
+    fn dupes(&self) -> Vec<Entry> {
+        IndexStats::new(&self.index, usize::MAX).top.iter().map(entry).collect()
+    }
+
+    /// The entries whose digest starts with the prefix
+    fn lookup(&self, prefix: &str) -> Vec<Entry> {
+        self.index.find_prefix(prefix).into_iter().map(entry).collect()
+    }
+
+    /// The entry with the path in it, or None if the path isn't in the
+    /// index. Paths are matched as they are in the index.
+    fn dupes_of(&self, path: PathBuf) -> Option<Entry> {
+        let path = ItemPath::new(&path);
+        self.index.idx.values()
+            .find(|e| *e.item.item_path() == path || e.dupes.contains(&path))
+            .map(entry)
+    }
+
+    fn __len__(&self) -> usize {
+        self.index.idx.len()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("<TreeIndex of {} files with {} dupes>", self.index.idx.len(), self.index.count_dupes())
+    }
+}
+
+/// Builds indexes of directory trees and finds the duplicate files in them
+/// with the hashing and indexing of the best-practices crate
+#[pymodule]
+fn best_practices(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(scan, m)?)?;
+    m.add_class::<PyTreeIndex>()?;
+    Ok(())
+}
@@ -0,0 +1,199 @@
+use crate::{
+    error::Error,
+    Result
+};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The unit system used when formatting byte counts. Binary uses powers of
+/// 1024 with IEC suffixes (KiB, MiB, ...), Decimal uses powers of 1000 with
+/// SI suffixes (kB, MB, ...).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Units {
+    #[default]
+    Binary,
+    Decimal
+}
+
+const BINARY_SUFFIXES: [&str; 7] = ["B", "KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+const DECIMAL_SUFFIXES: [&str; 7] = ["B", "kB", "MB", "GB", "TB", "PB", "EB"];
+
+/// This function formats a byte count as a human readable string such as
+/// "1.5 GiB" or "1.6 GB". Counts below one unit are printed exactly.
+pub fn format_bytes(bytes: u64, units: Units) -> String {
+    let (base, suffixes) = match units {
+        Units::Binary => (1024f64, &BINARY_SUFFIXES),
+        Units::Decimal => (1000f64, &DECIMAL_SUFFIXES)
+    };
+    let mut value = bytes as f64;
+    let mut i = 0;
+    while value >= base && i < suffixes.len() - 1 {
+        value /= base;
+        i += 1;
+    }
+    if i == 0 {
+        format!("{} {}", bytes, suffixes[0])
+    } else {
+        format!("{:.1} {}", value, suffixes[i])
+    }
+}
+
+/// This function parses a human readable byte count such as "1.5GiB",
+/// "10 MB", "4k" or "123". IEC suffixes (KiB, MiB, ...) are powers of 1024
+/// and SI suffixes (kB, MB, ...) are powers of 1000. Bare single letter
+/// suffixes (K, M, G, ...) are treated as binary, the same as du and find.
+/// Suffixes are case insensitive.
+pub fn parse_bytes(s: &str) -> Result<u64> {
+    let s = s.trim();
+    let idx = s.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(s.len());
+    let (num, suffix) = s.split_at(idx);
+    let num = num.parse::<f64>()
+        .map_err(|_| Error::InvalidArgument(format!("invalid byte count {}", s)))?;
+
+    let multiplier: u64 = match suffix.trim().to_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kib" => 1 << 10,
+        "m" | "mib" => 1 << 20,
+        "g" | "gib" => 1 << 30,
+        "t" | "tib" => 1 << 40,
+        "p" | "pib" => 1 << 50,
+        "e" | "eib" => 1 << 60,
+        "kb" => 1_000,
+        "mb" => 1_000_000,
+        "gb" => 1_000_000_000,
+        "tb" => 1_000_000_000_000,
+        "pb" => 1_000_000_000_000_000,
+        "eb" => 1_000_000_000_000_000_000,
+        _ => return Err(Error::InvalidArgument(format!("invalid byte count suffix {}", suffix)))
+    };
+
+    let bytes = num * multiplier as f64;
+    if bytes > u64::MAX as f64 {
+        return Err(Error::InvalidArgument(format!("byte count too large {}", s)));
+    }
+    Ok(bytes.round() as u64)
+}
+
+/// This trait is implemented by anything that can be turned into a byte
+/// count, so that builder options can take either a u64 or a human readable
+/// string like "10MiB".
+pub trait IntoBytes {
+    fn into_bytes(self) -> Result<u64>;
+}
+
+impl IntoBytes for u64 {
+    fn into_bytes(self) -> Result<u64> {
+        Ok(self)
+    }
+}
+
+impl IntoBytes for &str {
+    fn into_bytes(self) -> Result<u64> {
+        parse_bytes(self)
+    }
+}
+
+impl IntoBytes for String {
+    fn into_bytes(self) -> Result<u64> {
+        parse_bytes(&self)
+    }
+}
+
+impl IntoBytes for &String {
+    fn into_bytes(self) -> Result<u64> {
+        parse_bytes(self)
+    }
+}
+
+/// This function parses a human readable duration such as "30d", "12h",
+/// "1w" or "1h30m". The supported units are s, m, h, d, w and y (365 days).
+/// A bare number is a number of seconds.
+pub fn parse_duration(s: &str) -> Result<Duration> {
+    let s = s.trim();
+    if s.is_empty() {
+        return Err(Error::InvalidArgument("empty duration".to_string()));
+    }
+    let too_large = || Error::InvalidArgument(format!("duration too large {}", s));
+    let mut secs = 0u64;
+    let mut num = String::new();
+    for c in s.chars() {
+        if c.is_ascii_digit() {
+            num.push(c);
+            continue;
+        }
+        let n = num.parse::<u64>()
+            .map_err(|_| Error::InvalidArgument(format!("invalid duration {}", s)))?;
+        let unit = match c.to_ascii_lowercase() {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            'w' => 7 * 24 * 60 * 60,
+            'y' => 365 * 24 * 60 * 60,
+            _ => return Err(Error::InvalidArgument(format!("invalid duration unit {}", c)))
+        };
+        secs = n.checked_mul(unit).and_then(|n| secs.checked_add(n)).ok_or_else(too_large)?;
+        num.clear();
+    }
+    if !num.is_empty() {
+        let n = num.parse::<u64>()
+            .map_err(|_| Error::InvalidArgument(format!("invalid duration {}", s)))?;
+        secs = secs.checked_add(n).ok_or_else(too_large)?;
+    }
+    Ok(Duration::from_secs(secs))
+}
+
+/// This function parses a human readable age like parse_duration and
+/// returns the point in time that far in the past.
+pub fn parse_age(s: &str) -> Result<SystemTime> {
+    let d = parse_duration(s)?;
+    SystemTime::now().checked_sub(d)
+        .ok_or_else(|| Error::InvalidArgument(format!("age too large {}", s)))
+}
+
+/// This function formats a time as an RFC 3339 timestamp in UTC with
+/// milliseconds, such as "2026-10-14T16:05:25.731Z". Times before 1970 are
+/// formatted as 1970.
+pub fn format_timestamp(t: SystemTime) -> String {
+    let u = UtcTime::from(t);
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+            u.year, u.month, u.day, u.hour, u.minute, u.second, u.millis)
+}
+
+// a point in time as a UTC calendar date and time of day
+pub(crate) struct UtcTime {
+    pub year: i64,
+    pub month: i64,
+    pub day: i64,
+    pub hour: u64,
+    pub minute: u64,
+    pub second: u64,
+    pub millis: u32
+}
+
+impl From<SystemTime> for UtcTime {
+    fn from(t: SystemTime) -> Self {
+        let since = t.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let secs = since.as_secs();
+        let (days, rem) = (secs / 86400, secs % 86400);
+
+        // the civil date of a day count, from Howard Hinnant's date algorithms
+        let z = days as i64 + 719468;
+        let era = z.div_euclid(146097);
+        let doe = z - era * 146097;
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+        UtcTime {
+            year,
+            month,
+            day,
+            hour: rem / 3600,
+            minute: rem % 3600 / 60,
+            second: rem % 60,
+            millis: since.subsec_millis()
+        }
+    }
+}
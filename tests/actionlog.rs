@@ -0,0 +1,133 @@
+use best_practices::fs::{read_action_log, read_action_log_with_header, Action, ActionLog, ActionRecord, ActionResult,
+    TreeFixtureBuilder, TreeIndexHeader};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+// a record with a whole second timestamp, which is all a log keeps
+fn record(action: Action, src: &str, dst: Option<&str>) -> ActionRecord {
+    let mut r = ActionRecord::new(action, Path::new(src), ActionResult::Done).file("00ff", 42);
+    if let Some(dst) = dst {
+        r = r.dst(Path::new(dst));
+    }
+    r.timestamp = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+    r
+}
+
+fn read(text: &str) -> best_practices::Result<Vec<ActionRecord>> {
+    read_action_log(text.as_bytes())
+}
+
+// a log line with the src given as a JSON string
+fn line(src: &str) -> String {
+    format!("{{\"action\":\"delete\",\"src\":{},\"dst\":null,\"result\":\"done\"}}\n", src)
+}
+
+#[test]
+fn written_logs_read_back_the_same() {
+    let tree = TreeFixtureBuilder::new().build().unwrap();
+    let mut weird = record(Action::Move, "a \"quoted\"\\name\n\ttabbed\u{1}é😀", Some("to/b"));
+    weird.result = ActionResult::Failed;
+    let mut dir = record(Action::Rmdir, "empty", None).file("", 0);
+    dir.result = ActionResult::DryRun;
+    let records = [record(Action::Copy, "a", Some("b")), weird, dir];
+
+    let mut header = TreeIndexHeader::default();
+    header.set_fuzzy(true);
+    let mut log = ActionLog::new(Box::new(File::create(tree.join("log")).unwrap()));
+    log.header(&header).unwrap();
+    for r in &records {
+        log.record(r).unwrap();
+    }
+    drop(log);
+
+    let (back_header, back) = read_action_log_with_header(File::open(tree.join("log")).unwrap()).unwrap();
+    assert_eq!(back, records);
+    assert!(back_header.unwrap().has_fuzzy());
+    // the same records without the header
+    let text: String = records.iter().map(ActionRecord::to_string).collect();
+    assert_eq!(read(&text).unwrap(), records);
+    assert_eq!(read_action_log_with_header(text.as_bytes()).unwrap().0, None);
+}
+
+#[cfg(unix)]
+#[test]
+fn paths_that_arent_utf8_survive() {
+    use std::os::unix::ffi::OsStrExt;
+    let path = PathBuf::from(std::ffi::OsStr::from_bytes(b"a\xffb"));
+    let mut r = record(Action::Delete, "x", None);
+    r.src = path.clone();
+    let text = r.to_string();
+    assert!(text.contains(r#""src":"a\\xffb""#), "{}", text);
+    assert_eq!(read(&text).unwrap()[0].src, path);
+}
+
+#[test]
+fn json_escapes_are_read() {
+    let src = |escaped: &str| read(&line(escaped)).map(|r| r[0].src.clone());
+    assert_eq!(src(r#""\"\\\/\b\f\n\r\t""#).unwrap(), PathBuf::from("\"\\/\u{8}\u{c}\n\r\t"));
+    assert_eq!(src(r#""\u00e9\u00C9""#).unwrap(), PathBuf::from("éÉ"));
+    // characters outside the basic plane are a surrogate pair
+    assert_eq!(src(r#""\ud83d\ude00""#).unwrap(), PathBuf::from("😀"));
+    assert_eq!(src("\"😀\"").unwrap(), PathBuf::from("😀"));
+
+    for bad in [r#""\x41""#, r#""\u00e""#, r#""\u+0e9""#, r#""\u00g9""#, r#""\ud83d""#, r#""\ud83dx""#,
+                r#""\ud83d\u0041""#, r#""\ud83d\ue000""#, r#""\ude00""#, r#""open"#] {
+        assert!(src(bad).is_err(), "{}", bad);
+    }
+}
+
+#[test]
+fn only_flat_objects_of_strings_numbers_and_nulls_are_read() {
+    let ok = "{ \"action\" : \"rmdir\" , \"src\":\"d\", \"size\" : 7 ,\"timestamp\":12,\"result\":\"done\" }\n\n";
+    let r = &read(ok).unwrap()[0];
+    assert_eq!((r.action, r.size, r.dst.as_ref()), (Action::Rmdir, 7, None));
+    assert_eq!(r.timestamp, UNIX_EPOCH + Duration::from_secs(12));
+    // logs from before paths were escaped have them as they are
+    assert_eq!(read(&line(r#""a\\xffb""#)).unwrap()[0].src, PathBuf::from("a\\xffb"));
+
+    for bad in [
+        "[]",
+        "{}",
+        "{\"action\":\"delete\",\"src\":\"a\",\"result\":\"done\"} extra",
+        "{\"action\":\"delete\",\"src\":\"a\",\"result\":\"done\",}",
+        "{\"action\":\"delete\" \"src\":\"a\",\"result\":\"done\"}",
+        "{\"action\":\"delete\",\"src\":\"a\",\"result\":\"done\",\"size\":-1}",
+        "{\"action\":\"delete\",\"src\":\"a\",\"result\":\"done\",\"size\":1.5}",
+        "{\"action\":\"delete\",\"src\":\"a\",\"result\":\"done\",\"size\":99999999999999999999}",
+        "{\"action\":\"delete\",\"src\":\"a\",\"result\":\"done\",\"size\":\"1\"}",
+        "{\"action\":\"delete\",\"src\":\"a\",\"result\":\"done\",\"dst\":nul}",
+        "{\"action\":\"delete\",\"src\":\"a\",\"result\":\"done\",\"dst\":true}",
+        "{\"action\":\"delete\",\"src\":{},\"result\":\"done\"}",
+        "{\"action\":\"delete\",\"src\":\"a\"",
+        "{\"action\":\"shred\",\"src\":\"a\",\"result\":\"done\"}",
+        "{\"action\":\"delete\",\"src\":\"a\",\"result\":\"maybe\"}",
+        "{\"action\":\"delete\",\"result\":\"done\"}",
+    ] {
+        assert!(read(bad).is_err(), "{}", bad);
+    }
+}
+
+#[test]
+fn copies_moves_and_directories_are_undone() {
+    let copy = record(Action::Copy, "a", Some("b")).undo().unwrap();
+    assert_eq!((copy.action, copy.src, copy.dst), (Action::Delete, PathBuf::from("b"), None));
+    assert_eq!((copy.digest.as_str(), copy.size), ("00ff", 42));
+
+    let moved = record(Action::Move, "a", Some("b")).undo().unwrap();
+    assert_eq!((moved.action, moved.src, moved.dst), (Action::Move, PathBuf::from("b"), Some(PathBuf::from("a"))));
+    assert_eq!(record(Action::Rmdir, "d", None).undo().unwrap().action, Action::Mkdir);
+    assert_eq!(record(Action::Mkdir, "d", None).undo().unwrap().action, Action::Rmdir);
+
+    for action in [Action::Delete, Action::Dedupe, Action::Hardlink] {
+        assert!(record(action, "a", Some("b")).undo().is_none(), "{}", action);
+    }
+    // a copy or move without a destination has nothing to undo
+    assert!(record(Action::Copy, "a", None).undo().is_none());
+    assert!(record(Action::Move, "a", None).undo().is_none());
+    for result in [ActionResult::DryRun, ActionResult::Failed] {
+        let mut r = record(Action::Copy, "a", Some("b"));
+        r.result = result;
+        assert!(r.undo().is_none(), "{}", result);
+    }
+}
@@ -0,0 +1,133 @@
+use best_practices::fs::{apple_double_path, is_mac_metadata, read_action_log, Action, ActionResult, AppleDoublePolicy,
+    DedupExecutor, DedupExecutorBuilder, ExecutionMode, TreeFixture, TreeFixtureBuilder, TreeIndexBuilder,
+    TreeListBuilder};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+fn tree() -> TreeFixture {
+    TreeFixtureBuilder::new()
+        .file("a", "a")
+        .file("._a", "fork of a")
+        .file("b", "b")
+        .file("._b", "fork of b")
+        .file("c", "c")
+        .file("._c", "fork of c")
+        .file("plain", "plain")
+        .file("d/.DS_Store", "finder")
+        .build()
+        .unwrap()
+}
+
+fn executor(tree: &TreeFixture, policy: AppleDoublePolicy, mode: ExecutionMode) -> DedupExecutor {
+    DedupExecutorBuilder::new()
+        .mode(mode)
+        .apple_double(policy)
+        .log(Box::new(File::create(tree.join("log")).unwrap()))
+        .build()
+        .unwrap()
+}
+
+// the actions in the log with their paths relative to the tree
+fn logged(tree: &TreeFixture) -> Vec<(Action, ActionResult, PathBuf, Option<PathBuf>)> {
+    let rel = |p: &Path| p.strip_prefix(tree.path()).unwrap().to_path_buf();
+    read_action_log(File::open(tree.join("log")).unwrap()).unwrap()
+        .into_iter()
+        .map(|r| (r.action, r.result, rel(&r.src), r.dst.as_deref().map(rel)))
+        .collect()
+}
+
+fn p(s: &str) -> PathBuf {
+    PathBuf::from(s)
+}
+
+#[test]
+fn mac_metadata_is_told_apart_by_name() {
+    for name in ["._a", "dir/._a", "._.DS_Store", ".DS_Store", "dir/.DS_Store", "._._"] {
+        assert!(is_mac_metadata(Path::new(name)), "{}", name);
+    }
+    // the prefix alone has no file it can belong to
+    for name in ["._", "dir/._", "a", "a._b", "_.a", ".DS_Store.bak", "dir/..", "/", ""] {
+        assert!(!is_mac_metadata(Path::new(name)), "{}", name);
+    }
+}
+
+#[test]
+fn apple_double_files_are_named_after_their_file() {
+    assert_eq!(apple_double_path(Path::new("dir/a.txt")), Some(p("dir/._a.txt")));
+    assert_eq!(apple_double_path(Path::new("a")), Some(p("._a")));
+    assert_eq!(apple_double_path(Path::new("._")), Some(p("._._")));
+    for none in ["._a", "dir/.DS_Store", "/", "dir/.."] {
+        assert_eq!(apple_double_path(Path::new(none)), None, "{}", none);
+    }
+}
+
+#[test]
+fn scans_keep_ignore_or_leave_out_mac_metadata() {
+    let tree = tree();
+    let count = |policy| {
+        let scan = TreeListBuilder::new().path(tree.path()).apple_double(policy);
+        let ti = TreeIndexBuilder::new().with_dupes(true).from_scan(scan).build().unwrap();
+        ti.idx.values().map(|v| 1 + v.dupes.len()).sum::<usize>()
+    };
+    assert_eq!(count(AppleDoublePolicy::Keep), 8);
+    assert_eq!(count(AppleDoublePolicy::Ignore), 4);
+    assert_eq!(count(AppleDoublePolicy::Pair), 4);
+}
+
+#[test]
+fn apple_double_files_go_with_deleted_moved_and_copied_files() {
+    let tree = tree();
+    let mut x = executor(&tree, AppleDoublePolicy::Pair, ExecutionMode::Execute);
+    x.delete("", 1, &tree.join("a")).unwrap();
+    x.move_to("", 1, &tree.join("b"), &tree.join("moved/b")).unwrap();
+    x.copy("", 1, &tree.join("c"), &tree.join("copied/c")).unwrap();
+    // a file without one is acted on alone
+    x.delete("", 5, &tree.join("plain")).unwrap();
+    assert_eq!(x.finish().unwrap(), 0);
+
+    assert!(!tree.join("a").exists() && !tree.join("._a").exists());
+    assert!(!tree.join("._b").exists());
+    assert_eq!(fs::read_to_string(tree.join("moved/._b")).unwrap(), "fork of b");
+    assert_eq!(fs::read_to_string(tree.join("._c")).unwrap(), "fork of c");
+    assert_eq!(fs::read_to_string(tree.join("copied/._c")).unwrap(), "fork of c");
+
+    use ActionResult::Done;
+    assert_eq!(logged(&tree), [
+        (Action::Delete, Done, p("a"), None),
+        (Action::Delete, Done, p("._a"), None),
+        (Action::Move, Done, p("b"), Some(p("moved/b"))),
+        (Action::Move, Done, p("._b"), Some(p("moved/._b"))),
+        (Action::Copy, Done, p("c"), Some(p("copied/c"))),
+        (Action::Copy, Done, p("._c"), Some(p("copied/._c"))),
+        (Action::Delete, Done, p("plain"), None),
+    ]);
+}
+
+#[test]
+fn apple_double_files_are_only_paired_when_asked_and_when_the_file_went() {
+    let tree = tree();
+    let mut x = executor(&tree, AppleDoublePolicy::Keep, ExecutionMode::Execute);
+    x.delete("", 1, &tree.join("a")).unwrap();
+    drop(x);
+    assert!(tree.join("._a").exists());
+    assert_eq!(logged(&tree).len(), 1);
+
+    // a file that couldn't be moved keeps its AppleDouble file with it
+    fs::write(tree.join("._missing"), "").unwrap();
+    let mut x = executor(&tree, AppleDoublePolicy::Pair, ExecutionMode::Execute);
+    let failed = x.move_to("", 1, &tree.join("missing"), &tree.join("moved/missing")).unwrap();
+    assert_eq!(failed, ActionResult::Failed);
+    drop(x);
+    assert!(tree.join("._missing").exists());
+    assert_eq!(logged(&tree), [(Action::Move, ActionResult::Failed, p("missing"), Some(p("moved/missing")))]);
+
+    // a dry run logs both and moves neither
+    let mut x = executor(&tree, AppleDoublePolicy::Pair, ExecutionMode::DryRun);
+    x.move_to("", 1, &tree.join("b"), &tree.join("moved/b")).unwrap();
+    drop(x);
+    assert!(tree.join("b").exists() && tree.join("._b").exists());
+    assert_eq!(logged(&tree), [
+        (Action::Move, ActionResult::DryRun, p("b"), Some(p("moved/b"))),
+        (Action::Move, ActionResult::DryRun, p("._b"), Some(p("moved/._b"))),
+    ]);
+}
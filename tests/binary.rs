@@ -0,0 +1,124 @@
+use best_practices::fs::{is_binary, Chunking, DigestEncoding, TreeFixture, TreeFixtureBuilder, TreeIndex, TreeIndexBuilder,
+    TreeListBuilder, BINARY_MAGIC, BINARY_VERSION};
+use std::io::{Cursor, Read};
+
+fn tree() -> TreeFixture {
+    TreeFixtureBuilder::new()
+        .sized("a/one", 5000u64)
+        .dupe("a/copy", "a/one")
+        .dupe("b/copy", "a/one")
+        .file("b/small", "small")
+        .file("empty", "")
+        .weird_names("weird")
+        .build()
+        .unwrap()
+}
+
+fn scan(tree: &TreeFixture, encoding: DigestEncoding) -> TreeIndex {
+    let scan = TreeListBuilder::new()
+        .path(tree.path())
+        .encoding(encoding)
+        .chunking(Chunking::Fixed(1024))
+        .fuzzy(true)
+        .with_metadata(true);
+    TreeIndexBuilder::new().with_dupes(true).from_scan(scan).build().unwrap()
+}
+
+fn read(bytes: Vec<u8>) -> best_practices::Result<TreeIndex> {
+    let mut r: Box<dyn Read> = Box::new(Cursor::new(bytes));
+    TreeIndexBuilder::new().with_dupes(true).from_reader(&mut r).build()
+}
+
+fn text(ti: &TreeIndex) -> String {
+    let mut out = Vec::new();
+    ti.write(&mut out).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+// the entries of the text index, each with its dupe and side lines, sorted
+fn entries(ti: &TreeIndex) -> Vec<String> {
+    let mut entries: Vec<String> = Vec::new();
+    for line in text(ti).lines().skip(1) {
+        match line.chars().next() {
+            Some('-' | '~' | '&' | '@' | '%') => {
+                let last = entries.last_mut().unwrap();
+                last.push('\n');
+                last.push_str(line);
+            }
+            _ => entries.push(line.to_string()),
+        }
+    }
+    entries.sort();
+    entries
+}
+
+fn binary(ti: &TreeIndex) -> Vec<u8> {
+    let mut out = Vec::new();
+    ti.write_binary(&mut out).unwrap();
+    out
+}
+
+#[test]
+fn binary_indexes_read_back_as_the_same_index() {
+    let tree = tree();
+    for encoding in [DigestEncoding::Hex, DigestEncoding::Multihash] {
+        let ti = scan(&tree, encoding);
+        let bytes = binary(&ti);
+        assert!(is_binary(&bytes));
+        assert_eq!(&bytes[..4], BINARY_MAGIC);
+        assert_eq!(bytes[4], BINARY_VERSION);
+        // from_reader tells the formats apart by their first bytes
+        let back = read(bytes.clone()).unwrap();
+        assert_eq!(entries(&back), entries(&ti), "{}", encoding);
+        assert_eq!(back.header.for_text(), ti.header.for_text());
+        assert_eq!(binary(&back), bytes);
+        assert!(back.idx.values().all(|v| v.item.chunks.is_some() && v.item.metadata.is_some()));
+    }
+    assert!(!is_binary(text(&scan(&tree, DigestEncoding::Hex)).as_bytes()));
+}
+
+#[test]
+fn binary_indexes_of_deep_trees_are_well_under_half_the_size() {
+    let mut index = String::from("#treeindex v3 os=linux paths=escaped\n");
+    for i in 0..2000u64 {
+        index.push_str(&format!("{:064x} {} /home/someone/photos/2019/holidays/by-camera/IMG_{:05}.jpg\n", i, i * 1000, i));
+    }
+    let ti = read(index.into_bytes()).unwrap();
+    let (text, binary) = (text(&ti), binary(&ti));
+    assert!(binary.len() * 5 <= text.len() * 2, "{} bytes binary, {} text", binary.len(), text.len());
+}
+
+#[test]
+fn truncated_and_corrupt_binary_indexes_are_refused() {
+    let tree = tree();
+    let bytes = binary(&scan(&tree, DigestEncoding::Hex));
+    for cut in [5, 6, bytes.len() / 2, bytes.len() - 1] {
+        assert!(read(bytes[..cut].to_vec()).is_err(), "cut at {}", cut);
+    }
+
+    let mut version = bytes.clone();
+    version[4] = BINARY_VERSION + 1;
+    assert!(read(version).is_err());
+
+    // the first entry starts right after the header
+    let header_len = bytes[5] as usize;
+    let mut kind = bytes.clone();
+    kind[6 + header_len] = 7;
+    assert!(read(kind).is_err());
+
+    let mut header = b"TIDX\x01".to_vec();
+    let line = b"#treeindex v3 paths=escaped";
+    header.push(line.len() as u8);
+    header.extend_from_slice(line);
+    let mut empty = header.clone();
+    empty.push(0xff);
+    assert!(read(empty).unwrap().idx.is_empty());
+    // a path can't share more than the path before it had
+    let mut shared = header.clone();
+    shared.extend_from_slice(&[0, 1, b'x', 1, 1, 5, 0, 0, 0xff]);
+    assert!(read(shared).is_err());
+    // an entry has at least one path
+    let mut no_paths = header;
+    no_paths.extend_from_slice(&[0, 1, b'x', 1, 0, 0xff]);
+    assert!(read(no_paths).is_err());
+}
@@ -0,0 +1,136 @@
+use best_practices::fs::{Algorithm, ChecksumFormat, TreeFixture, TreeFixtureBuilder, TreeIndex, TreeIndexBuilder,
+    TreeListBuilder};
+use std::io::{Cursor, Read};
+use std::path::{Path, PathBuf};
+
+// what sha256sum and b2sum -l 256 print for "abc"
+const ABC_SHA256: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+const ABC_BLAKE2B: &str = "bddd813c634239723171ef3fee98579b94964e3bb1cb3e427262c8c068d52319";
+
+fn tree() -> TreeFixture {
+    TreeFixtureBuilder::new()
+        .file("abc", "abc")
+        .file("copies/abc", "abc")
+        .file("other", "other")
+        .build()
+        .unwrap()
+}
+
+fn scan(root: &Path, algorithm: Algorithm, fast: bool) -> TreeIndex {
+    TreeIndexBuilder::new()
+        .with_dupes(true)
+        .from_scan(TreeListBuilder::new().path(root).algorithm(algorithm).fast(fast))
+        .build()
+        .unwrap()
+}
+
+fn checksums(ti: &TreeIndex, format: ChecksumFormat) -> String {
+    let mut out = Vec::new();
+    ti.write_checksums(&mut out, format).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+fn read(text: &str, algorithm: Algorithm) -> best_practices::Result<TreeIndex> {
+    let mut r: Box<dyn Read> = Box::new(Cursor::new(text.as_bytes().to_vec()));
+    TreeIndexBuilder::new().with_dupes(true).from_checksums(&mut r, algorithm).build()
+}
+
+// the (path, digest) pairs of the index, sorted by path
+fn pairs(ti: &TreeIndex) -> Vec<(PathBuf, String)> {
+    let mut pairs = Vec::new();
+    for v in ti.idx.values() {
+        pairs.push((v.item.path(), v.item.digest.to_string()));
+        for d in &v.dupes {
+            pairs.push((d.path(), v.item.digest.to_string()));
+        }
+    }
+    pairs.sort();
+    pairs
+}
+
+#[test]
+fn digests_are_the_ones_the_standard_tools_make() {
+    let tree = tree();
+    let sha = scan(tree.path(), Algorithm::Sha256, false);
+    let blake = scan(tree.path(), Algorithm::Blake2b, false);
+    assert_eq!(sha.header.algorithm().unwrap(), Algorithm::Sha256);
+    let digest_of = |ti: &TreeIndex, p: &str| pairs(ti).into_iter().find(|(path, _)| path == &tree.join(p)).unwrap().1;
+    assert_eq!(digest_of(&sha, "abc"), ABC_SHA256);
+    assert_eq!(digest_of(&sha, "copies/abc"), ABC_SHA256);
+    assert_eq!(digest_of(&blake, "abc"), ABC_BLAKE2B);
+}
+
+#[test]
+fn every_path_is_written_sorted_in_either_layout() {
+    let tree = tree();
+    let sha = scan(tree.path(), Algorithm::Sha256, false);
+    let root = tree.path().to_str().unwrap();
+    let gnu = checksums(&sha, ChecksumFormat::Gnu);
+    let lines: Vec<&str> = gnu.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[0], format!("{}  {}/abc", ABC_SHA256, root));
+    assert_eq!(lines[1], format!("{}  {}/copies/abc", ABC_SHA256, root));
+    assert!(lines[2].ends_with(&format!("  {}/other", root)));
+
+    let bsd = checksums(&sha, ChecksumFormat::Bsd);
+    assert_eq!(bsd.lines().next().unwrap(), format!("SHA256 ({}/abc) = {}", root, ABC_SHA256));
+    let blake = checksums(&scan(tree.path(), Algorithm::Blake2b, false), ChecksumFormat::Bsd);
+    assert_eq!(blake.lines().next().unwrap(), format!("BLAKE2b-256 ({}/abc) = {}", root, ABC_BLAKE2B));
+}
+
+#[test]
+fn written_checksums_read_back_the_same() {
+    let tree = tree();
+    for algorithm in [Algorithm::Sha256, Algorithm::Blake2b] {
+        let ti = scan(tree.path(), algorithm, false);
+        for format in [ChecksumFormat::Gnu, ChecksumFormat::Bsd] {
+            // gnu lines don't say which algorithm made them
+            let back = read(&checksums(&ti, format), algorithm).unwrap();
+            assert_eq!(pairs(&back), pairs(&ti), "{} {}", algorithm, format);
+            assert_eq!(back.header.algorithm().unwrap(), algorithm);
+            assert!(back.header.has_sizes());
+            let abc = back.idx.values().find(|v| !v.dupes.is_empty()).unwrap();
+            assert_eq!(abc.item.size, 3);
+        }
+    }
+}
+
+#[test]
+fn paths_with_backslashes_and_newlines_are_escaped() {
+    let mut text = format!("{}  plain\n", ABC_SHA256);
+    text.push_str(&format!("\\{}  back\\\\slash\n", ABC_SHA256));
+    text.push_str(&format!("\\SHA256 (new\\nline) = {}\n", ABC_SHA256));
+    let ti = read(&text, Algorithm::Sha256).unwrap();
+    let paths: Vec<PathBuf> = pairs(&ti).into_iter().map(|(p, _)| p).collect();
+    assert_eq!(paths, [PathBuf::from("back\\slash"), PathBuf::from("new\nline"), PathBuf::from("plain")]);
+    assert_eq!(checksums(&ti, ChecksumFormat::Gnu), format!(
+        "\\{d}  back\\\\slash\n\\{d}  new\\nline\n{d}  plain\n", d = ABC_SHA256));
+}
+
+#[test]
+fn lines_of_either_tool_are_read() {
+    let text = format!("# a comment\n\n{}  a\n{} *binary\nSHA256 (tagged) = {}\n", ABC_SHA256, ABC_SHA256, ABC_SHA256.to_uppercase());
+    let ti = read(&text, Algorithm::Blake2b).unwrap();
+    assert_eq!(ti.header.algorithm().unwrap(), Algorithm::Sha256);
+    assert_eq!(ti.idx.len(), 1);
+    let paths: Vec<PathBuf> = pairs(&ti).into_iter().map(|(p, _)| p).collect();
+    assert_eq!(paths, [PathBuf::from("a"), PathBuf::from("binary"), PathBuf::from("tagged")]);
+    // the files aren't there so their sizes aren't known
+    assert!(!ti.header.has_sizes());
+}
+
+#[test]
+fn bad_and_mixed_lines_are_refused() {
+    assert!(read("not a checksum line\n", Algorithm::Sha256).is_err());
+    assert!(read(&format!("{} path\n", ABC_SHA256), Algorithm::Sha256).is_err());
+    assert!(read(&format!("MD5 (a) = {}\n", ABC_SHA256), Algorithm::Sha256).is_err());
+    let mixed = format!("SHA256 (a) = {}\nBLAKE2b-256 (b) = {}\n", ABC_SHA256, ABC_BLAKE2B);
+    assert!(read(&mixed, Algorithm::Sha256).is_err());
+}
+
+#[test]
+fn fast_digests_arent_written() {
+    let tree = tree();
+    let fast = scan(tree.path(), Algorithm::Sha256, true);
+    assert!(fast.write_checksums(&mut Vec::new(), ChecksumFormat::Gnu).is_err());
+}
@@ -0,0 +1,137 @@
+use best_practices::fs::{similar_files, Algorithm, ChunkDigests, Chunking, TreeFixture, TreeFixtureBuilder, TreeIndex,
+    TreeIndexBuilder, TreeListBuilder};
+use std::fs;
+use std::io::{Cursor, Read};
+
+// bytes that don't repeat, so chunks are only shared where the data is
+fn noise(seed: u64, len: usize) -> Vec<u8> {
+    let mut x = seed;
+    (0..len)
+        .map(|_| {
+            x = x.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (x >> 33) as u8
+        })
+        .collect()
+}
+
+fn shifted() -> TreeFixture {
+    let data = noise(1, 64 * 1024);
+    let mut grown = noise(2, 100);
+    grown.extend_from_slice(&data);
+    TreeFixtureBuilder::new()
+        .file("log", &data)
+        .file("log.grown", grown)
+        .file("unrelated", noise(3, 64 * 1024))
+        .build()
+        .unwrap()
+}
+
+fn scan(tree: &TreeFixture, chunking: Chunking) -> TreeIndex {
+    TreeIndexBuilder::new()
+        .with_dupes(true)
+        .from_scan(TreeListBuilder::new().path(tree.path()).chunking(chunking))
+        .build()
+        .unwrap()
+}
+
+fn chunks(ti: &TreeIndex, tree: &TreeFixture, name: &str) -> ChunkDigests {
+    let v = ti.idx.values().find(|v| v.item.path() == tree.join(name)).unwrap();
+    (**v.item.chunks.as_ref().unwrap()).clone()
+}
+
+#[test]
+fn content_chunks_are_shared_when_the_bytes_shift() {
+    let tree = shifted();
+    let cdc = scan(&tree, Chunking::Content(1024));
+    let (log, grown) = (chunks(&cdc, &tree, "log"), chunks(&cdc, &tree, "log.grown"));
+    assert!(log.shared_bytes(&grown) * 10 >= 64 * 1024 * 9, "{} bytes shared", log.shared_bytes(&grown));
+
+    // fixed chunks all move by the bytes put in front
+    let fixed = scan(&tree, Chunking::Fixed(1024));
+    assert_eq!(chunks(&fixed, &tree, "log").shared_bytes(&chunks(&fixed, &tree, "log.grown")), 0);
+    // and the two kinds of chunks aren't compared
+    assert_eq!(log.shared_bytes(&chunks(&fixed, &tree, "log")), 0);
+}
+
+#[test]
+fn content_chunks_cover_the_file_within_their_limits() {
+    let tree = shifted();
+    let ti = scan(&tree, Chunking::Content(1024));
+    let c = chunks(&ti, &tree, "log");
+    assert_eq!(c.chunking(), Chunking::Content(1024));
+    assert_eq!(c.lengths.len(), c.leaves.len());
+    assert_eq!(c.lengths.iter().sum::<u64>(), 64 * 1024);
+    let (last, rest) = c.lengths.split_last().unwrap();
+    assert!(*last <= 8 * 1024);
+    for l in rest {
+        assert!((256..=8 * 1024).contains(l), "a chunk of {} bytes", l);
+    }
+}
+
+#[test]
+fn similar_files_are_found_and_dupes_and_strangers_left_out() {
+    let tree = shifted();
+    fs::copy(tree.join("log"), tree.join("log.copy")).unwrap();
+    let ti = scan(&tree, Chunking::Content(1024));
+    let similar = similar_files(&ti, 0.5);
+    assert_eq!(similar.len(), 1);
+    let s = &similar[0];
+    assert!(s.ratio > 0.9 && s.ratio <= 1.0, "{}", s.ratio);
+    assert!(s.shared <= 64 * 1024);
+    assert!(s.a.starts_with(tree.path()) && s.b == tree.join("log.grown").into());
+    assert!(s.to_string().ends_with("log.grown\n"));
+    assert!(similar_files(&ti, 0.999).is_empty());
+}
+
+#[test]
+fn fixed_chunks_show_what_changed_in_place() {
+    let tree = TreeFixtureBuilder::new().file("f", noise(4, 10 * 1024)).build().unwrap();
+    let before = scan(&tree, Chunking::Fixed(1024));
+    let old = chunks(&before, &tree, "f");
+    assert_eq!(old.leaves.len(), 10);
+    assert!(old.matches(&tree.join("f"), Algorithm::default(), None).unwrap());
+
+    let mut data = fs::read(tree.join("f")).unwrap();
+    // the changes in the third, fourth and fifth chunks make one region
+    data[3000] ^= 1;
+    data[3100] ^= 1;
+    data[4096] ^= 1;
+    data[8000] ^= 1;
+    data.extend_from_slice(b"more");
+    fs::write(tree.join("f"), &data).unwrap();
+    assert!(!old.matches(&tree.join("f"), Algorithm::default(), None).unwrap());
+    let new = chunks(&scan(&tree, Chunking::Fixed(1024)), &tree, "f");
+    let regions = old.changed_regions(&new, data.len() as u64).unwrap();
+    assert_eq!(regions, [2048..5120, 7168..8192, 10240..10244]);
+
+    let cdc = chunks(&scan(&tree, Chunking::Content(1024)), &tree, "f");
+    assert!(old.changed_regions(&cdc, data.len() as u64).is_none());
+}
+
+#[test]
+fn chunk_digests_survive_the_index_file() {
+    let tree = shifted();
+    for chunking in [Chunking::Fixed(4096), Chunking::Content(1024)] {
+        let ti = scan(&tree, chunking);
+        assert_eq!(ti.header.chunking().unwrap(), Some(chunking));
+        let c = chunks(&ti, &tree, "log");
+        assert_eq!(c.to_string().trim_end().parse::<ChunkDigests>().unwrap(), c);
+
+        let mut text = Vec::new();
+        ti.write(&mut text).unwrap();
+        let mut r: Box<dyn Read> = Box::new(Cursor::new(text));
+        let back = TreeIndexBuilder::new().with_dupes(true).from_reader(&mut r).build().unwrap();
+        assert_eq!(back.header.chunking().unwrap(), Some(chunking));
+        assert_eq!(chunks(&back, &tree, "log"), c);
+    }
+    assert!("~ 0 root a,b".parse::<ChunkDigests>().is_err());
+    assert!("~ 1024 root a,b 1".parse::<ChunkDigests>().is_err());
+    assert!("~ 1024 root".parse::<ChunkDigests>().is_err());
+}
+
+#[test]
+fn tiny_content_chunks_are_refused() {
+    let tree = shifted();
+    let scan = TreeIndexBuilder::new().from_scan(TreeListBuilder::new().path(tree.path()).content_chunks(32u64));
+    assert!(scan.build().is_err());
+}
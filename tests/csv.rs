@@ -0,0 +1,87 @@
+use best_practices::fs::{TreeFixtureBuilder, TreeIndex, TreeIndexBuilder, TreeListBuilder, CSV_HEADER};
+use std::io::{Cursor, Read};
+use std::path::PathBuf;
+
+const A: &str = "0000000000000000000000000000000000000000000000000000000000000001";
+const B: &str = "0000000000000000000000000000000000000000000000000000000000000002";
+
+fn read(text: &str) -> best_practices::Result<TreeIndex> {
+    let mut r: Box<dyn Read> = Box::new(Cursor::new(text.as_bytes().to_vec()));
+    TreeIndexBuilder::new().with_dupes(true).from_reader(&mut r).build()
+}
+
+fn csv(ti: &TreeIndex) -> String {
+    let mut out = Vec::new();
+    ti.write_csv(&mut out).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+// the paths of the index, sorted
+fn paths(ti: &TreeIndex) -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    for v in ti.idx.values() {
+        paths.push(v.item.path());
+        paths.extend(v.dupes.iter().map(|d| d.path()));
+    }
+    paths.sort();
+    paths
+}
+
+#[test]
+fn written_csv_reads_back_the_same() {
+    let tree = TreeFixtureBuilder::new()
+        .file("a", "same")
+        .file("b/a", "same")
+        .file("c", "other")
+        .weird_names("weird")
+        .build()
+        .unwrap();
+    let ti = TreeIndexBuilder::new().with_dupes(true).from_scan(TreeListBuilder::new().path(tree.path())).build().unwrap();
+    let text = csv(&ti);
+    assert_eq!(text.lines().next().unwrap(), CSV_HEADER);
+    let back = read(&text).unwrap();
+    assert_eq!(paths(&back), paths(&ti));
+    assert_eq!(back.idx.len(), ti.idx.len());
+    assert_eq!(csv(&back), text);
+}
+
+#[test]
+fn quoted_fields_keep_their_quotes_commas_and_line_breaks() {
+    let text = format!("{}\n{},1,\"say \"\"hi\"\", bye\",\n{},2,\"two\nlines\",\n", CSV_HEADER, A, B);
+    let ti = read(&text).unwrap();
+    assert_eq!(paths(&ti), [PathBuf::from("say \"hi\", bye"), PathBuf::from("two\nlines")]);
+    assert!(csv(&ti).contains(",\"say \"\"hi\"\", bye\",\n"));
+}
+
+#[test]
+fn a_byte_order_mark_and_blank_lines_are_skipped() {
+    let text = format!("\u{feff}{}\r\n{},1,a,\r\n\r\n{},2,b,\r\n", CSV_HEADER, A, B);
+    let ti = read(&text).unwrap();
+    assert_eq!(paths(&ti), [PathBuf::from("a"), PathBuf::from("b")]);
+}
+
+#[test]
+fn dupes_come_from_the_digests_not_dupe_of() {
+    // dupe_of names a path that isn't in the index and the second row has
+    // none, they are dupes because their digests are the same
+    let text = format!("{}\n{},1,a,elsewhere\n{},1,b,\n", CSV_HEADER, A, A);
+    let ti = read(&text).unwrap();
+    assert_eq!(ti.idx.len(), 1);
+    assert_eq!(paths(&ti), [PathBuf::from("a"), PathBuf::from("b")]);
+    let written = csv(&ti);
+    assert!(written.contains(",1,b,a\n") || written.contains(",1,a,b\n"), "{}", written);
+}
+
+#[test]
+fn bad_rows_are_refused() {
+    // without the header row it isn't read as CSV
+    assert!(read(&format!("{},1,a,\n", A)).is_err());
+    assert!(read(&format!("{},extra\n{},1,a,\n", CSV_HEADER, A)).is_err());
+    assert!(read(&format!("{}\n{},1,a\n", CSV_HEADER, A)).is_err());
+    assert!(read(&format!("{}\n{},1,a,,\n", CSV_HEADER, A)).is_err());
+    assert!(read(&format!("{}\n,1,a,\n", CSV_HEADER)).is_err());
+    assert!(read(&format!("{}\n{},big,a,\n", CSV_HEADER, A)).is_err());
+    assert!(read(&format!("{}\n{},-1,a,\n", CSV_HEADER, A)).is_err());
+    assert!(read(&format!("{}\n{},1,\"a,\n", CSV_HEADER, A)).is_err());
+    assert!(read(&format!("{}\n", CSV_HEADER)).unwrap().idx.is_empty());
+}
@@ -0,0 +1,76 @@
+use best_practices::fs::{TreeIndex, TreeIndexBuilder, TreeIndexDiff, TreeItem};
+use std::io::{Cursor, Read};
+use std::path::PathBuf;
+
+// an index of (digest, size, path) triples
+fn index(files: &[(u64, u64, &str)]) -> TreeIndex {
+    let mut text = String::from("#treeindex v3 os=linux paths=escaped\n");
+    for (digest, size, path) in files {
+        text.push_str(&format!("{:064x} {} {}\n", digest, size, path));
+    }
+    let mut r: Box<dyn Read> = Box::new(Cursor::new(text.into_bytes()));
+    TreeIndexBuilder::new().with_dupes(true).from_reader(&mut r).build().unwrap()
+}
+
+fn diff(old: &[(u64, u64, &str)], new: &[(u64, u64, &str)]) -> TreeIndexDiff {
+    TreeIndexDiff::between(&index(old), &index(new))
+}
+
+fn paths(items: &[TreeItem]) -> Vec<PathBuf> {
+    items.iter().map(TreeItem::path).collect()
+}
+
+fn renames(d: &TreeIndexDiff) -> Vec<(PathBuf, PathBuf)> {
+    d.renamed.iter().map(|(old, new)| (old.path(), new.path())).collect()
+}
+
+#[test]
+fn moved_files_are_renames_once_detected() {
+    let mut d = diff(&[(1, 10, "old/a"), (2, 20, "kept")], &[(1, 10, "new/a"), (2, 20, "kept")]);
+    assert_eq!(paths(&d.missing), [PathBuf::from("old/a")]);
+    assert_eq!(paths(&d.added), [PathBuf::from("new/a")]);
+    assert!(d.renamed.is_empty());
+
+    assert_eq!(d.detect_renames(), 1);
+    assert_eq!(renames(&d), [(PathBuf::from("old/a"), PathBuf::from("new/a"))]);
+    assert!(d.missing.is_empty() && d.added.is_empty());
+    assert_eq!(paths(&d.matched), [PathBuf::from("kept")]);
+    assert!(!d.is_clean());
+    assert_eq!(d.to_string(), "renamed old/a -> new/a\n");
+    // there is nothing left to pair
+    assert_eq!(d.detect_renames(), 0);
+    assert_eq!(d.renamed.len(), 1);
+}
+
+#[test]
+fn a_missing_file_with_the_same_name_is_paired_first() {
+    let mut d = diff(&[(1, 10, "a/x"), (1, 10, "b/report")], &[(1, 10, "c/report")]);
+    assert_eq!(d.detect_renames(), 1);
+    assert_eq!(renames(&d), [(PathBuf::from("b/report"), PathBuf::from("c/report"))]);
+    assert_eq!(paths(&d.missing), [PathBuf::from("a/x")]);
+    assert_eq!(d.to_string(), "renamed b/report -> c/report\nmissing a/x\n");
+}
+
+#[test]
+fn each_missing_file_is_paired_once() {
+    let mut d = diff(&[(1, 10, "a"), (1, 10, "b")], &[(1, 10, "c"), (1, 10, "d"), (1, 10, "e")]);
+    assert_eq!(d.detect_renames(), 2);
+    assert!(d.missing.is_empty());
+    assert_eq!(d.added.len(), 1);
+    let old: Vec<PathBuf> = renames(&d).into_iter().map(|(o, _)| o).collect();
+    assert_eq!(old, [PathBuf::from("a"), PathBuf::from("b")]);
+}
+
+#[test]
+fn different_digests_or_sizes_arent_renames() {
+    let mut d = diff(&[(1, 10, "a"), (2, 20, "b")], &[(3, 10, "c"), (2, 30, "d")]);
+    assert_eq!(d.detect_renames(), 0);
+    assert_eq!(paths(&d.missing), [PathBuf::from("a"), PathBuf::from("b")]);
+    assert_eq!(paths(&d.added), [PathBuf::from("c"), PathBuf::from("d")]);
+    assert_eq!(d.to_string(), "missing a\nmissing b\nnew c\nnew d\n");
+
+    // a path that is in both is modified, not renamed
+    let mut d = diff(&[(1, 10, "a")], &[(2, 10, "a")]);
+    assert_eq!(d.detect_renames(), 0);
+    assert_eq!(paths(&d.modified), [PathBuf::from("a")]);
+}
@@ -0,0 +1,104 @@
+use best_practices::fs::{Algorithm, Digest, DigestEncoding, DigestVariant, ItemDigest};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::mem::size_of;
+
+// counts the bytes each thread holds so the tests can run side by side
+struct Counting;
+
+thread_local! {
+    static HELD: Cell<isize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        HELD.with(|h| h.set(h.get() + layout.size() as isize));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        HELD.with(|h| h.set(h.get() - layout.size() as isize));
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static COUNTING: Counting = Counting;
+
+// the heap bytes the value made by f holds, it is dropped after counting
+fn held<T>(f: impl FnOnce() -> T) -> isize {
+    let before = HELD.with(Cell::get);
+    let value = f();
+    let bytes = HELD.with(Cell::get) - before;
+    drop(value);
+    bytes
+}
+
+fn digests(n: usize) -> Vec<String> {
+    (0..n)
+        .map(|i| {
+            let mut bytes = [0u8; 32];
+            bytes[..8].copy_from_slice(&(i as u64).to_le_bytes());
+            DigestEncoding::Hex.encode(Algorithm::Blake2b, &bytes)
+        })
+        .collect()
+}
+
+#[test]
+fn item_digests_take_half_the_memory_of_hex_strings() {
+    let hex = digests(1000);
+    let strings = held(|| hex.to_vec());
+    let items = held(|| hex.iter().map(|s| ItemDigest::from(s.as_str())).collect::<Vec<ItemDigest>>());
+    assert!(items * 2 <= strings, "{} bytes for item digests, {} for strings", items, strings);
+    assert!(size_of::<ItemDigest>() * 2 <= size_of::<String>() + 64);
+}
+
+#[test]
+fn multihashes_are_kept_as_bytes() {
+    let bytes = [5u8; 32];
+    let mh = DigestEncoding::Multihash.encode_variant(Algorithm::Sha256, DigestVariant::Fast, &bytes);
+    let multihash = held(|| ItemDigest::from(mh.as_str()));
+    assert_eq!(multihash, 0);
+    assert_eq!(
+        ItemDigest::from(mh.as_str()),
+        ItemDigest::Multihash(Algorithm::Sha256, DigestVariant::Fast, Digest::from(bytes))
+    );
+}
+
+#[test]
+fn digests_are_written_back_as_they_were_read() {
+    let bytes = [0xabu8; 32];
+    let mut written = vec![
+        String::new(),
+        DigestEncoding::Hex.encode(Algorithm::Blake2b, &bytes),
+        DigestEncoding::Hex.encode(Algorithm::Blake2b, &bytes).to_uppercase(),
+        "not a digest".to_string()
+    ];
+    for algorithm in [Algorithm::Blake2b, Algorithm::Sha256] {
+        for fast in [false, true] {
+            for keyed in [false, true] {
+                let variant = DigestVariant::new(fast, keyed);
+                written.push(DigestEncoding::Multihash.encode_variant(algorithm, variant, &bytes));
+            }
+        }
+    }
+    for s in &written {
+        assert_eq!(&ItemDigest::from(s.as_str()).to_string(), s);
+    }
+    assert!(matches!(ItemDigest::from(written[2].as_str()), ItemDigest::Text(_)));
+    assert!(ItemDigest::from("").is_empty());
+}
+
+#[test]
+fn the_same_bytes_are_the_same_digest_however_written() {
+    let bytes = [3u8; 32];
+    let hex = ItemDigest::from(DigestEncoding::Hex.encode(Algorithm::Blake2b, &bytes).as_str());
+    let upper = ItemDigest::from(DigestEncoding::Hex.encode(Algorithm::Blake2b, &bytes).to_uppercase().as_str());
+    let fast = ItemDigest::new(DigestEncoding::Multihash, Algorithm::Blake2b, DigestVariant::Fast, Digest::from(bytes));
+    assert_ne!(hex, fast);
+    assert!(hex.same_as(&fast));
+    assert!(upper.same_as(&hex));
+    assert!(!hex.same_as(&ItemDigest::new(DigestEncoding::Hex, Algorithm::Blake2b, DigestVariant::Full, Digest::from([4u8; 32]))));
+    assert!(ItemDigest::from("x").same_as(&ItemDigest::from("x")));
+    assert!(!ItemDigest::from("x").same_as(&ItemDigest::from("y")));
+}
@@ -0,0 +1,157 @@
+use best_practices::fs::{read_action_log_with_header, Action, ActionResult, DedupExecutor, DedupExecutorBuilder,
+    ExecutionMode, TreeFixture, TreeFixtureBuilder, TreeIndex, TreeIndexBuilder, TreeIndexHeader, TreeListBuilder};
+use std::fs::{self, File};
+
+fn tree() -> TreeFixture {
+    TreeFixtureBuilder::new()
+        .file("keep", "data")
+        .file("dup/dupe", "data")
+        .file("link", "data")
+        .file("moving/m", "moved")
+        .file("copy", "copied")
+        .build()
+        .unwrap()
+}
+
+fn scan(tree: &TreeFixture) -> TreeIndex {
+    TreeIndexBuilder::new().with_dupes(true).from_scan(TreeListBuilder::new().path(tree.path())).build().unwrap()
+}
+
+fn digest(ti: &TreeIndex, tree: &TreeFixture, name: &str) -> String {
+    let path = tree.join(name);
+    ti.idx.values().find(|v| v.item.path() == path || v.dupes.iter().any(|d| d.path() == path))
+        .unwrap().item.digest.to_string()
+}
+
+fn executor(tree: &TreeFixture, header: &TreeIndexHeader, mode: ExecutionMode) -> DedupExecutor {
+    DedupExecutorBuilder::new()
+        .mode(mode)
+        .log(Box::new(File::create(tree.join("log")).unwrap()))
+        .header(header)
+        .verify(true)
+        .build()
+        .unwrap()
+}
+
+// does the same actions in every mode and returns what each of them gave
+fn act(x: &mut DedupExecutor, ti: &TreeIndex, tree: &TreeFixture) -> Vec<ActionResult> {
+    let d = |name| digest(ti, tree, name);
+    let results = vec![
+        x.copy(&d("copy"), 6, &tree.join("copy"), &tree.join("out/copy")).unwrap(),
+        x.move_to(&d("moving/m"), 5, &tree.join("moving/m"), &tree.join("to/m")).unwrap(),
+        x.delete(&d("dup/dupe"), 4, &tree.join("dup/dupe")).unwrap(),
+        x.hardlink(&d("keep"), 4, &tree.join("keep"), &tree.join("link")).unwrap(),
+        x.delete(&d("keep"), 4, &tree.join("gone")).unwrap(),
+    ];
+    let root = tree.path().to_path_buf();
+    x.prune(vec![tree.join("dup"), tree.join("moving")], &|d| root.starts_with(d)).unwrap();
+    results
+}
+
+// the actions and results in the log, and whether it has the header
+fn logged(tree: &TreeFixture) -> (bool, Vec<(Action, ActionResult)>) {
+    let (header, records) = read_action_log_with_header(File::open(tree.join("log")).unwrap()).unwrap();
+    (header.is_some(), records.into_iter().map(|r| (r.action, r.result)).collect())
+}
+
+// the files are where the actions put them
+fn check_done(tree: &TreeFixture) {
+    assert_eq!(fs::read_to_string(tree.join("copy")).unwrap(), "copied");
+    assert_eq!(fs::read_to_string(tree.join("out/copy")).unwrap(), "copied");
+    assert_eq!(fs::read_to_string(tree.join("to/m")).unwrap(), "moved");
+    assert!(!tree.join("dup").exists() && !tree.join("moving").exists());
+    assert_eq!(fs::read_to_string(tree.join("link")).unwrap(), "data");
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let ino = |name| fs::metadata(tree.join(name)).unwrap().ino();
+        assert_eq!(ino("keep"), ino("link"));
+    }
+}
+
+// the files are where the fixture put them
+fn check_untouched(tree: &TreeFixture) {
+    for name in ["keep", "dup/dupe", "link", "moving/m", "copy"] {
+        assert!(tree.join(name).is_file(), "{}", name);
+    }
+    assert!(!tree.join("out").exists() && !tree.join("to").exists());
+}
+
+#[test]
+fn executing_does_the_actions_and_logs_each_one() {
+    let tree = tree();
+    let ti = scan(&tree);
+    let mut x = executor(&tree, &ti.header, ExecutionMode::Execute);
+    let results = act(&mut x, &ti, &tree);
+    use ActionResult::*;
+    assert_eq!(results, [Done, Done, Done, Done, Failed]);
+    // the one that failed didn't stop the rest
+    assert_eq!(x.failed(), 1);
+    assert_eq!(x.finish().unwrap(), 1);
+    check_done(&tree);
+
+    let (header, records) = logged(&tree);
+    assert!(header);
+    assert_eq!(records, [(Action::Copy, Done), (Action::Move, Done), (Action::Delete, Done), (Action::Hardlink, Done),
+        (Action::Delete, Failed), (Action::Rmdir, Done), (Action::Rmdir, Done)]);
+}
+
+#[test]
+fn a_dry_run_touches_nothing_and_logs_what_it_would_do() {
+    let tree = tree();
+    let ti = scan(&tree);
+    let mut x = executor(&tree, &ti.header, ExecutionMode::DryRun);
+    let results = act(&mut x, &ti, &tree);
+    assert!(results.iter().all(|r| *r == ActionResult::DryRun));
+    assert_eq!(x.finish().unwrap(), 0);
+    check_untouched(&tree);
+
+    // the directories the delete and move would leave empty are in the log
+    let (_, records) = logged(&tree);
+    assert_eq!(records.len(), 7);
+    assert!(records.iter().all(|r| r.1 == ActionResult::DryRun));
+    assert_eq!(records[5..], [(Action::Rmdir, ActionResult::DryRun), (Action::Rmdir, ActionResult::DryRun)]);
+}
+
+#[test]
+fn a_script_is_written_instead_and_does_the_same_when_run() {
+    let tree = tree();
+    let ti = scan(&tree);
+    let script = tree.join("script.sh");
+    let mut x = executor(&tree, &ti.header, ExecutionMode::Script(script.clone()));
+    let results = act(&mut x, &ti, &tree);
+    assert!(results.iter().all(|r| *r == ActionResult::DryRun));
+    assert_eq!(x.finish().unwrap(), 0);
+    check_untouched(&tree);
+    let (_, records) = logged(&tree);
+    assert_eq!(records.len(), 7);
+
+    let text = fs::read_to_string(&script).unwrap();
+    assert!(text.starts_with("#!/bin/sh\nset -e\n"));
+    assert!(text.contains(&format!("rm -f -- '{}'\n", tree.join("gone").to_str().unwrap())));
+    #[cfg(unix)]
+    {
+        assert!(std::process::Command::new("sh").arg(&script).status().unwrap().success());
+        check_done(&tree);
+    }
+}
+
+#[test]
+fn copies_that_dont_verify_fail() {
+    let tree = tree();
+    let ti = scan(&tree);
+    let mut x = executor(&tree, &ti.header, ExecutionMode::Execute);
+    let wrong = digest(&ti, &tree, "keep");
+    assert_eq!(x.copy(&wrong, 6, &tree.join("copy"), &tree.join("out/copy")).unwrap(), ActionResult::Failed);
+    assert!(!tree.join("out/copy").exists());
+    // a dupe that changed after the scan isn't linked over
+    fs::write(tree.join("link"), "edit").unwrap();
+    assert_eq!(x.hardlink(&wrong, 4, &tree.join("keep"), &tree.join("link")).unwrap(), ActionResult::Failed);
+    assert_eq!(fs::read_to_string(tree.join("link")).unwrap(), "edit");
+    assert_eq!(x.finish().unwrap(), 2);
+
+    let mut keyed = ti.header.clone();
+    keyed.set_keyed(true);
+    assert!(DedupExecutorBuilder::new().header(&keyed).verify(true).build().is_err());
+    assert!(DedupExecutorBuilder::new().header(&keyed).verify(true).key(b"secret").build().is_ok());
+}
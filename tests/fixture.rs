@@ -0,0 +1,112 @@
+use best_practices::fs::{ItemDigest, TreeFixtureBuilder, TreeListBuilder, WEIRD_NAMES};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+#[test]
+fn fixtures_make_the_files_they_list() {
+    let tree = TreeFixtureBuilder::new()
+        .file("a/b/small", "contents")
+        .sized("big", "4KiB")
+        .sized("odd", 1001u64)
+        .dir("empty/dir")
+        .build()
+        .unwrap();
+    let files: Vec<_> = tree.files().iter().map(|p| p.to_str().unwrap()).collect();
+    assert_eq!(files, ["a/b/small", "big", "odd"]);
+    assert_eq!(fs::read(tree.join("a/b/small")).unwrap(), b"contents");
+    assert_eq!(fs::metadata(tree.join("big")).unwrap().len(), 4096);
+    assert_eq!(fs::metadata(tree.join("odd")).unwrap().len(), 1001);
+    assert!(tree.join("empty/dir").is_dir());
+    assert_eq!(fs::read_dir(tree.join("empty/dir")).unwrap().count(), 0);
+}
+
+#[test]
+fn generated_contents_come_from_the_path() {
+    let spec = || TreeFixtureBuilder::new().sized("x", 10_000u64).sized("y", 10_000u64);
+    let (one, two) = (spec().build().unwrap(), spec().build().unwrap());
+    assert_ne!(one.path(), two.path());
+    assert_eq!(fs::read(one.join("x")).unwrap(), fs::read(two.join("x")).unwrap());
+    assert_ne!(fs::read(one.join("x")).unwrap(), fs::read(one.join("y")).unwrap());
+}
+
+#[test]
+fn dupes_and_hard_links_are_copies_a_scan_finds() {
+    let tree = TreeFixtureBuilder::new()
+        .sized("orig", 5000u64)
+        .dupe("copies/one", "orig")
+        .dupe("copies/two", "orig")
+        .hardlink("link", "orig")
+        .sized("other", 5000u64)
+        .build()
+        .unwrap();
+    let orig = fs::read(tree.join("orig")).unwrap();
+    for copy in ["copies/one", "copies/two", "link"] {
+        assert_eq!(fs::read(tree.join(copy)).unwrap(), orig, "{}", copy);
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let ino = |p: &str| fs::metadata(tree.join(p)).unwrap().ino();
+        assert_eq!(ino("link"), ino("orig"));
+        assert_ne!(ino("copies/one"), ino("orig"));
+    }
+
+    let list = TreeListBuilder::new().path(tree.path()).build().unwrap();
+    let mut digests: HashMap<&ItemDigest, usize> = HashMap::new();
+    for item in &list.list {
+        *digests.entry(&item.digest).or_default() += 1;
+    }
+    let mut counts: Vec<usize> = digests.into_values().collect();
+    counts.sort_unstable();
+    assert_eq!(counts, [1, 4]);
+}
+
+#[test]
+fn weird_names_are_made_as_they_are() {
+    let tree = TreeFixtureBuilder::new().weird_names("weird").build().unwrap();
+    assert_eq!(tree.files().len(), WEIRD_NAMES.len());
+    for name in WEIRD_NAMES {
+        let path = tree.join("weird").join(name);
+        assert_eq!(fs::read(&path).unwrap(), format!("{}\n", name).as_bytes(), "{:?}", name);
+    }
+    let list = TreeListBuilder::new().path(tree.path()).build().unwrap();
+    assert_eq!(list.list.len(), WEIRD_NAMES.len());
+}
+
+#[cfg(unix)]
+#[test]
+fn names_that_are_not_utf8_are_made() {
+    use std::os::unix::ffi::OsStrExt;
+    let tree = TreeFixtureBuilder::new().non_utf8_name("raw", b"bad\xffname").build().unwrap();
+    assert_eq!(tree.files(), [PathBuf::from("raw").join(std::ffi::OsStr::from_bytes(b"bad\xffname"))]);
+    assert_eq!(fs::read(tree.join(&tree.files()[0])).unwrap(), b"bad\xffname");
+}
+
+#[test]
+fn paths_have_to_stay_in_the_tree() {
+    assert!(TreeFixtureBuilder::new().file("../out", "x").build().is_err());
+    assert!(TreeFixtureBuilder::new().file(std::env::temp_dir().join("abs"), "x").build().is_err());
+    assert!(TreeFixtureBuilder::new().file("", "x").build().is_err());
+    assert!(TreeFixtureBuilder::new().dupe("copy", "missing").build().is_err());
+    assert!(TreeFixtureBuilder::new().sized("bad", "lots").build().is_err());
+}
+
+#[test]
+fn fixtures_are_removed_when_dropped() {
+    let parent = TreeFixtureBuilder::new().build().unwrap();
+    let tree = TreeFixtureBuilder::new()
+        .in_dir(parent.path())
+        .file("a/b", "x")
+        .build()
+        .unwrap();
+    let root = tree.path().to_path_buf();
+    assert!(root.starts_with(parent.path()));
+    assert!(root.join("a/b").is_file());
+    drop(tree);
+    assert!(!root.exists());
+    assert_eq!(fs::read_dir(parent.path()).unwrap().count(), 0);
+    let parent_root = parent.path().to_path_buf();
+    drop(parent);
+    assert!(!parent_root.exists());
+}
@@ -0,0 +1,127 @@
+use best_practices::fs::{fuzzy_groups, DedupPlannerBuilder, FuzzyDigest, TreeFixture, TreeFixtureBuilder, TreeIndex,
+    TreeIndexBuilder, TreeListBuilder};
+use std::io::{Cursor, Read};
+use std::path::PathBuf;
+
+const WORDS: [&str; 16] = ["the", "index", "of", "files", "under", "a", "tree", "is", "kept", "with", "digests",
+    "and", "paths", "so", "dupes", "show"];
+
+// a document of words picked by the seed
+fn document(seed: u64, words: usize) -> String {
+    let mut x = seed;
+    let mut doc = String::new();
+    for n in 0..words {
+        x = x.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        doc.push_str(WORDS[(x >> 60) as usize]);
+        doc.push(if n % 12 == 11 { '\n' } else { ' ' });
+    }
+    doc
+}
+
+// the document with a few words changed here and there
+fn edited(doc: &str) -> String {
+    let mut words: Vec<&str> = doc.split(' ').collect();
+    for i in [100, 1000, 2000] {
+        words[i] = "edited";
+    }
+    words.join(" ")
+}
+
+fn tree() -> TreeFixture {
+    let doc = document(1, 3000);
+    TreeFixtureBuilder::new()
+        .file("report.txt", &doc)
+        .file("report-v2.txt", edited(&doc))
+        .file("copy/report.txt", &doc)
+        .file("other.txt", document(2, 3000))
+        .build()
+        .unwrap()
+}
+
+fn scan(tree: &TreeFixture) -> TreeIndex {
+    TreeIndexBuilder::new()
+        .with_dupes(true)
+        .from_scan(TreeListBuilder::new().path(tree.path()).fuzzy(true))
+        .build()
+        .unwrap()
+}
+
+fn digest(ti: &TreeIndex, tree: &TreeFixture, name: &str) -> FuzzyDigest {
+    let v = ti.idx.values().find(|v| v.item.path() == tree.join(name)).unwrap();
+    (**v.item.fuzzy.as_ref().unwrap()).clone()
+}
+
+#[test]
+fn minor_edits_score_high_and_other_files_low() {
+    let tree = tree();
+    let ti = scan(&tree);
+    assert!(ti.header.has_fuzzy());
+    let (report, v2, other) = (digest(&ti, &tree, "report.txt"), digest(&ti, &tree, "report-v2.txt"),
+                               digest(&ti, &tree, "other.txt"));
+    assert_eq!(report.compare(&report), 100);
+    let edited = report.compare(&v2);
+    assert!((50..100).contains(&edited), "an edited copy scored {}", edited);
+    assert_eq!(v2.compare(&report), edited);
+    assert!(report.compare(&other) < 20, "another file scored {}", report.compare(&other));
+}
+
+#[test]
+fn digests_read_back_as_they_were_written() {
+    let tree = tree();
+    let ti = scan(&tree);
+    let d = digest(&ti, &tree, "report.txt");
+    assert!(d.sig1.len() <= 64 && d.sig2.len() <= 32 && !d.sig1.is_empty());
+    assert_eq!(d.to_string(), format!("& {}:{}:{}\n", d.block_size, d.sig1, d.sig2));
+    assert_eq!(d.to_string().parse::<FuzzyDigest>().unwrap(), d);
+    assert_eq!("96:AXGBicFlgVNhBGcL6wCrFQE:AXGHsNhxLsr2s".parse::<FuzzyDigest>().unwrap().block_size, 96);
+
+    let mut text = Vec::new();
+    ti.write(&mut text).unwrap();
+    let mut r: Box<dyn Read> = Box::new(Cursor::new(text));
+    let back = TreeIndexBuilder::new().with_dupes(true).from_reader(&mut r).build().unwrap();
+    assert_eq!(digest(&back, &tree, "report.txt"), d);
+
+    for bad in ["2:abc:abc", "96:ab!c:abc", "96:abc", "96:abc:abc:abc", "x:abc:abc"] {
+        assert!(bad.parse::<FuzzyDigest>().is_err(), "{}", bad);
+    }
+}
+
+#[test]
+fn block_sizes_too_far_apart_dont_compare() {
+    let a: FuzzyDigest = "96:AXGBicFlgVNhBGcL6wCrFQE:AXGHsNhxLsr2s".parse().unwrap();
+    let b: FuzzyDigest = "384:AXGBicFlgVNhBGcL6wCrFQE:AXGHsNhxLsr2s".parse().unwrap();
+    let c: FuzzyDigest = "192:AXGHsNhxLsr2s:AXGHsN".parse().unwrap();
+    assert_eq!(a.compare(&b), 0);
+    // the second signature of a is for the block size of c
+    assert!(a.compare(&c) > 0);
+    assert_eq!(a.compare(&c), c.compare(&a));
+}
+
+#[test]
+fn near_dupes_are_grouped_above_the_threshold() {
+    let tree = tree();
+    let ti = scan(&tree);
+    let groups = fuzzy_groups(&ti, 50);
+    assert_eq!(groups.len(), 1);
+    // the identical copy is a dupe already so its group has one path for it
+    let files: Vec<PathBuf> = groups[0].files.iter().map(|p| (**p).clone()).collect();
+    assert_eq!(files.len(), 2);
+    assert!(files.contains(&tree.join("report-v2.txt")));
+    assert!(groups[0].score >= 50);
+    assert!(groups[0].to_string().starts_with("2 similar files, score at least "));
+    assert!(fuzzy_groups(&ti, 100).is_empty());
+
+    let strict = DedupPlannerBuilder::new().min_fuzzy_score(100).build().unwrap();
+    assert!(strict.near_dupes(&ti).is_empty());
+    let default = DedupPlannerBuilder::new().build().unwrap();
+    assert_eq!(default.min_fuzzy_score, 50);
+    assert_eq!(default.near_dupes(&ti).len(), 1);
+    assert!(DedupPlannerBuilder::new().min_fuzzy_score(101).build().is_err());
+}
+
+#[test]
+fn fuzzy_digests_need_the_whole_file() {
+    let tree = tree();
+    let scan = TreeIndexBuilder::new().from_scan(TreeListBuilder::new().path(tree.path()).fuzzy(true).fast(true));
+    assert!(scan.build().is_err());
+}
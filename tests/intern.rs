@@ -0,0 +1,98 @@
+use best_practices::fs::{ItemPath, TreeItem};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+
+// counts the bytes each thread holds, the arena is shared so the tests take
+// turns with it
+struct Counting;
+
+thread_local! {
+    static HELD: Cell<isize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        HELD.with(|h| h.set(h.get() + layout.size() as isize));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        HELD.with(|h| h.set(h.get() - layout.size() as isize));
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static COUNTING: Counting = Counting;
+
+static ARENA: Mutex<()> = Mutex::new(());
+
+// the heap bytes the value made by f holds, it is dropped after counting
+fn held<T>(f: impl FnOnce() -> T) -> isize {
+    let before = HELD.with(Cell::get);
+    let value = f();
+    let bytes = HELD.with(Cell::get) - before;
+    drop(value);
+    bytes
+}
+
+fn deep_paths(round: usize, n: usize) -> Vec<PathBuf> {
+    (0..n)
+        .map(|i| PathBuf::from(format!("/home/someone/projects/{}/src/fs/backends/remote/file{}.rs", round, i)))
+        .collect()
+}
+
+// items used to keep each path whole behind an Arc, the deeper the tree the
+// more is saved
+#[test]
+fn interned_paths_of_a_deep_tree_take_a_third_less_memory() {
+    let _turn = ARENA.lock().unwrap_or_else(PoisonError::into_inner);
+    let paths = deep_paths(0, 1000);
+    let whole = held(|| paths.iter().cloned().map(Arc::new).collect::<Vec<Arc<PathBuf>>>());
+    let interned = held(|| paths.iter().map(ItemPath::from).collect::<Vec<ItemPath>>());
+    assert!(interned * 3 <= whole * 2, "{} bytes for interned paths, {} for whole ones", interned, whole);
+}
+
+#[test]
+fn paths_are_freed_with_their_last_item() {
+    let _turn = ARENA.lock().unwrap_or_else(PoisonError::into_inner);
+    let before = HELD.with(Cell::get);
+    for round in 0..20 {
+        let items: Vec<TreeItem> = deep_paths(round, 500).iter().map(|p| TreeItem::new("", p, 1)).collect();
+        let copies = items.clone();
+        drop(items);
+        assert_eq!(copies[7].path(), deep_paths(round, 500)[7]);
+    }
+    let after_many = HELD.with(Cell::get) - before;
+    let kept: Vec<TreeItem> = deep_paths(20, 500).iter().map(|p| TreeItem::new("", p, 1)).collect();
+    let after_one_more = HELD.with(Cell::get) - before;
+    // the freed nodes are used again, so twenty rounds leave no more behind
+    // than the one round still held
+    assert!(after_many <= after_one_more, "{} bytes left after the rounds, {} with one held", after_many, after_one_more);
+    drop(kept);
+}
+
+#[test]
+fn interned_paths_compare_by_id_and_come_back_whole() {
+    let _turn = ARENA.lock().unwrap_or_else(PoisonError::into_inner);
+    let a = ItemPath::new(Path::new("dir/a"));
+    assert_eq!(a, ItemPath::new(Path::new("dir/a")));
+    assert_ne!(a, ItemPath::new(Path::new("dir/b")));
+    assert_ne!(a, ItemPath::new(Path::new("dir//a")));
+    for p in ["dir/a", "dir//a", "dir/./a", "/dir/a/", "a"] {
+        assert_eq!(ItemPath::new(Path::new(p)).path().as_os_str(), p);
+    }
+    let set: HashSet<ItemPath> = ["x/1", "x/2", "x/1"].iter().map(|p| ItemPath::new(Path::new(p))).collect();
+    assert_eq!(set.len(), 2);
+    assert!(ItemPath::default().is_empty());
+    assert!(!a.is_empty());
+
+    let mut item = TreeItem::new("", Path::new("dir/a"), 1);
+    assert_eq!(item.item_path(), &a);
+    item.set_path(Path::new("dir/c"));
+    assert_eq!(item.path(), Path::new("dir/c"));
+    assert_eq!(a.path(), Path::new("dir/a"));
+}
@@ -0,0 +1,98 @@
+use best_practices::fs::{extended_path, is_reserved_name, longpath::needs_extended, os_path};
+use std::path::Path;
+
+#[test]
+fn device_names_are_reserved() {
+    for name in ["CON", "con", "Aux", "NUL", "prn", "COM1", "com9", "LPT1", "lpt0", "COM\u{b9}", "CONIN$", "conout$"] {
+        assert!(is_reserved_name(name), "{}", name);
+    }
+}
+
+#[test]
+fn device_names_with_extensions_are_reserved() {
+    for name in ["aux.txt", "CON.tar.gz", "nul .log", "com1.", "LPT3 .x"] {
+        assert!(is_reserved_name(name), "{}", name);
+    }
+}
+
+#[test]
+fn other_names_are_not_reserved() {
+    for name in ["", "CONSOLE", "COM10", "COM", "LPT", "xcon", "con_", "auxiliary.txt", ".con", "nul-device"] {
+        assert!(!is_reserved_name(name), "{}", name);
+    }
+}
+
+#[test]
+fn absolute_paths_get_the_prefix() {
+    assert_eq!(extended_path("C:\\dir\\file.txt", "D:\\"), "\\\\?\\C:\\dir\\file.txt");
+    assert_eq!(extended_path("c:/dir/file.txt", "D:\\"), "\\\\?\\C:\\dir\\file.txt");
+    assert_eq!(extended_path("C:\\", "D:\\"), "\\\\?\\C:\\");
+}
+
+#[test]
+fn unc_paths_get_the_unc_prefix() {
+    assert_eq!(extended_path("\\\\server\\share\\dir\\f", "C:\\"), "\\\\?\\UNC\\server\\share\\dir\\f");
+    assert_eq!(extended_path("//server/share/f", "C:\\"), "\\\\?\\UNC\\server\\share\\f");
+}
+
+#[test]
+fn verbatim_paths_are_left_alone() {
+    for p in ["\\\\?\\C:\\a\\..\\b", "\\\\?\\UNC\\s\\share\\x", "\\\\.\\pipe\\name"] {
+        assert_eq!(extended_path(p, "C:\\"), p);
+    }
+}
+
+#[test]
+fn relative_paths_are_made_absolute() {
+    assert_eq!(extended_path("dir\\f", "C:\\work"), "\\\\?\\C:\\work\\dir\\f");
+    assert_eq!(extended_path("..\\f", "C:\\work\\sub"), "\\\\?\\C:\\work\\f");
+    assert_eq!(extended_path("\\top\\f", "D:\\work"), "\\\\?\\D:\\top\\f");
+    assert_eq!(extended_path("\\top", "\\\\server\\share\\work"), "\\\\?\\UNC\\server\\share\\top");
+    assert_eq!(extended_path("C:f", "C:\\work"), "\\\\?\\C:\\work\\f");
+    assert_eq!(extended_path("E:f", "C:\\work"), "\\\\?\\E:\\f");
+}
+
+#[test]
+fn dots_are_taken_out_but_not_past_the_root() {
+    assert_eq!(extended_path("C:\\a\\.\\b\\..\\c", "C:\\"), "\\\\?\\C:\\a\\c");
+    assert_eq!(extended_path("C:\\..\\..\\a", "C:\\"), "\\\\?\\C:\\a");
+    assert_eq!(extended_path("C:\\a\\\\b\\", "C:\\"), "\\\\?\\C:\\a\\b");
+}
+
+#[test]
+fn trailing_dots_and_spaces_are_kept() {
+    assert_eq!(extended_path("C:\\dir.\\file. ", "C:\\"), "\\\\?\\C:\\dir.\\file. ");
+    assert_eq!(extended_path("C:\\dir \\...", "C:\\"), "\\\\?\\C:\\dir \\...");
+    assert_eq!(extended_path("C:\\d\\CON", "C:\\"), "\\\\?\\C:\\d\\CON");
+    assert_eq!(extended_path("C:\\d\\aux.txt", "C:\\"), "\\\\?\\C:\\d\\aux.txt");
+}
+
+#[test]
+fn paths_that_need_the_prefix() {
+    let long = format!("C:\\{}", "a\\".repeat(130));
+    for p in [long.as_str(), "C:\\d\\CON", "dir\\aux.txt", "C:\\d\\name.", "d/name ", "trailing\\dir.\\f"] {
+        assert!(needs_extended(p), "{}", p);
+    }
+}
+
+#[test]
+fn paths_that_dont_need_the_prefix() {
+    for p in ["C:\\dir\\file.txt", "dir\\.\\f", "..\\f", "C:", "C:\\", "\\\\?\\C:\\d\\CON", "\\\\server\\share\\f", ""] {
+        assert!(!needs_extended(p), "{}", p);
+    }
+}
+
+#[cfg(not(windows))]
+#[test]
+fn other_platforms_use_paths_as_they_are() {
+    for p in ["dir/CON", "name.", "name ", "/a/b"] {
+        assert_eq!(os_path(Path::new(p)), Path::new(p));
+    }
+}
+
+#[cfg(windows)]
+#[test]
+fn windows_paths_that_need_it_get_the_prefix() {
+    assert_eq!(os_path(Path::new("C:\\d\\CON")), Path::new("\\\\?\\C:\\d\\CON"));
+    assert_eq!(os_path(Path::new("C:\\d\\file.txt")), Path::new("C:\\d\\file.txt"));
+}
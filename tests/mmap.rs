@@ -0,0 +1,32 @@
+use best_practices::fs::{TreeFixtureBuilder, TreeItemBuilder};
+use std::fs::File;
+use std::time::{Duration, SystemTime};
+
+// sizes around the fast chunk, a fast digest hashes all of the files up to
+// it and only the head and tail of bigger ones
+const SIZES: &[u64] = &[0, 1, 4095, 1_048_575, 1_048_576, 1_048_577, 3_000_001];
+
+#[test]
+fn mapped_and_streamed_digests_are_the_same() {
+    let mut builder = TreeFixtureBuilder::new();
+    for size in SIZES {
+        builder = builder.sized(format!("f{}", size), *size);
+    }
+    let tree = builder.build().unwrap();
+
+    // files modified just now are streamed even when mapping is asked for
+    let old = SystemTime::now() - Duration::from_secs(3600);
+    for f in tree.files() {
+        File::options().write(true).open(tree.join(f)).unwrap().set_modified(old).unwrap();
+    }
+
+    for f in tree.files() {
+        let path = tree.join(f);
+        for fast in [false, true] {
+            let digest = |mmap| TreeItemBuilder::new().fast(fast).mmap(mmap).path(&path).build().unwrap();
+            let (mapped, streamed) = (digest(true), digest(false));
+            assert_eq!(mapped.digest, streamed.digest, "{} fast {}", f.to_string_lossy(), fast);
+            assert_eq!(mapped.size, streamed.size);
+        }
+    }
+}
@@ -0,0 +1,26 @@
+use best_practices::fs::{decode_multihash_variant, Algorithm, Digest, DigestEncoding, DigestVariant};
+
+#[test]
+fn variants_have_codes_of_their_own() {
+    let bytes = [7u8; 32];
+    let mut seen = Vec::new();
+    for algorithm in [Algorithm::Blake2b, Algorithm::Sha256] {
+        for fast in [false, true] {
+            for keyed in [false, true] {
+                let variant = DigestVariant::new(fast, keyed);
+                let mh = DigestEncoding::Multihash.encode_variant(algorithm, variant, &bytes);
+                assert_eq!(decode_multihash_variant(&mh).unwrap(), (algorithm, variant, bytes.to_vec()));
+                assert!(!seen.contains(&mh), "{} is used twice", mh);
+                seen.push(mh);
+            }
+        }
+    }
+}
+
+#[test]
+fn every_variant_is_the_same_digest() {
+    let bytes = [9u8; 32];
+    let hex = DigestEncoding::Hex.encode(Algorithm::Blake2b, &bytes);
+    let fast = DigestEncoding::Multihash.encode_variant(Algorithm::Blake2b, DigestVariant::Fast, &bytes);
+    assert_eq!(hex.parse::<Digest>().unwrap(), fast.parse::<Digest>().unwrap());
+}
@@ -0,0 +1,86 @@
+use best_practices::fs::{prune_empty_dirs, subdirs, DedupPlannerBuilder, TreeFixtureBuilder};
+use std::path::Path;
+
+#[test]
+fn globs_match_absolute_and_relative_paths() {
+    let cwd = std::env::current_dir().unwrap();
+    let here = format!("{}/backups/**", cwd.to_str().unwrap());
+    let planner = DedupPlannerBuilder::new().protect("/backups/**").protect(&here).protect("*.keep").build().unwrap();
+    assert!(planner.is_protected(Path::new("/backups/2020/x.jpg")));
+    assert!(planner.is_protected(&cwd.join("backups/x.jpg")));
+    assert!(planner.is_protected(Path::new("backups/x.jpg")));
+    assert!(planner.is_protected(Path::new("./photos/../backups/x.jpg")));
+    assert!(planner.is_protected(Path::new("/photos/a.keep")));
+    assert!(planner.is_protected(Path::new("a.keep")));
+    assert!(!planner.is_protected(Path::new("/photos/backups/x.jpg")));
+    assert!(!planner.is_protected(Path::new("/backups/../photos/x.jpg")));
+    assert!(!planner.is_protected(Path::new("photos/x.jpg")));
+}
+
+#[test]
+fn relative_and_absolute_paths_protect_each_other() {
+    let cwd = std::env::current_dir().unwrap();
+    let absolute = cwd.join("kept");
+    let planner = DedupPlannerBuilder::new()
+        .protect("photos")
+        .protect("./docs/../music/")
+        .protect(absolute.to_str().unwrap())
+        .build()
+        .unwrap();
+    assert!(planner.is_protected(&cwd.join("photos/a.jpg")));
+    assert!(planner.is_protected(Path::new("photos/a.jpg")));
+    assert!(planner.is_protected(Path::new("./photos")));
+    assert!(planner.is_protected(Path::new("other/../photos/a.jpg")));
+    assert!(planner.is_protected(&cwd.join("music/b.mp3")));
+    assert!(planner.is_protected(Path::new("music/b.mp3")));
+    assert!(planner.is_protected(Path::new("kept/c")));
+    assert!(planner.is_protected(&absolute.join("c")));
+    assert!(!planner.is_protected(Path::new("docs/d")));
+    assert!(!planner.is_protected(Path::new("photos2/a.jpg")));
+    assert!(!planner.is_protected(Path::new("photos/../other/a.jpg")));
+    assert!(!planner.is_protected(Path::new("/photos/a.jpg")));
+    assert_eq!(planner.protected_paths()[0], cwd.join("photos"));
+}
+
+#[test]
+fn pruning_leaves_protected_dirs_and_globs() {
+    let tree = TreeFixtureBuilder::new()
+        .dir("backups/2020")
+        .dir("photos/old")
+        .dir("gone")
+        .dir("music/empty")
+        .dir("docs/drop")
+        .file("docs/keep/x", "x")
+        .build()
+        .unwrap();
+    let root = tree.path();
+    let planner = DedupPlannerBuilder::new()
+        .protect(&format!("{}/backups/**", root.to_str().unwrap()))
+        .protect(root.join("photos").to_str().unwrap())
+        .protect(root.join("gone/deeper").to_str().unwrap())
+        .build()
+        .unwrap();
+    let is_protected = |d: &Path| root.starts_with(d) || planner.is_protected_dir(d);
+    let mut pruned = prune_empty_dirs(subdirs(root).unwrap(), &is_protected, false).unwrap();
+    pruned.sort();
+    assert_eq!(pruned, [root.join("docs/drop"), root.join("music"), root.join("music/empty")]);
+    for kept in ["backups/2020", "photos/old", "gone", "docs/keep"] {
+        assert!(root.join(kept).is_dir(), "{} was pruned", kept);
+    }
+}
+
+#[test]
+fn drive_letter_paths_dont_panic() {
+    let planner = DedupPlannerBuilder::new().protect("/data/**").build().unwrap();
+    planner.is_protected(Path::new("C:\\data\\x"));
+    planner.is_protected(Path::new("\\\\server\\share\\data\\x"));
+}
+
+#[cfg(windows)]
+#[test]
+fn globs_match_under_any_drive() {
+    let planner = DedupPlannerBuilder::new().protect("/data/**").build().unwrap();
+    assert!(planner.is_protected(Path::new("C:\\data\\x")));
+    assert!(planner.is_protected(Path::new("\\\\?\\D:\\data\\sub\\y")));
+    assert!(!planner.is_protected(Path::new("C:\\other\\data\\x")));
+}
@@ -0,0 +1,21 @@
+use best_practices::fs::{ReparsePolicy, TreeListBuilder};
+
+// a link back up the tree is the same loop a junction makes on Windows
+#[cfg(unix)]
+#[test]
+fn following_a_loop_scans_each_directory_once() {
+    use best_practices::fs::TreeFixtureBuilder;
+
+    let tree = TreeFixtureBuilder::new()
+        .file("sub/f", "data")
+        .symlink("sub/up", "..")
+        .build()
+        .unwrap();
+    let list = TreeListBuilder::new()
+        .path(tree.path())
+        .reparse_points(ReparsePolicy::Follow)
+        .build()
+        .unwrap();
+    assert_eq!(list.list.len(), 1);
+    assert_eq!(list.list[0].path().file_name().unwrap(), "f");
+}
@@ -0,0 +1,130 @@
+use best_practices::fs::{TreeIndex, TreeItem};
+use proptest::collection::{btree_set, vec};
+use proptest::prelude::*;
+use std::collections::BTreeMap;
+use std::io::Cursor;
+use std::path::PathBuf;
+
+// a file name of any bytes but the separator and NUL, which no file system
+// allows
+#[cfg(unix)]
+fn name() -> impl Strategy<Value = std::ffi::OsString> {
+    use std::os::unix::ffi::OsStringExt;
+    vec(any::<u8>().prop_filter("separator or NUL", |b| *b != b'/' && *b != 0), 1..12)
+        .prop_map(std::ffi::OsString::from_vec)
+}
+
+// other platforms can only write paths that are valid Unicode
+#[cfg(not(unix))]
+fn name() -> impl Strategy<Value = std::ffi::OsString> {
+    "[^/\\\\\u{0}]{1,12}".prop_map(std::ffi::OsString::from)
+}
+
+// paths with a few components, some of them absolute, biased towards the
+// characters that mean something in the text format
+fn path() -> impl Strategy<Value = PathBuf> {
+    let special = prop_oneof![
+        Just(" ".into()), Just("\n".into()), Just("\r".into()), Just("\t".into()),
+        Just("\\".into()), Just("\\x41".into()), Just("- ".into()), Just("! ".into()),
+        Just("# ".into()), Just("~ ".into()), Just("@ ".into()), Just("\u{e9}".into()),
+        Just("\u{1f600}".into())
+    ];
+    let component = prop_oneof![3 => name(), 1 => special];
+    (any::<bool>(), vec(component, 1..4)).prop_map(|(absolute, components)| {
+        let mut path = PathBuf::from(if absolute { "/" } else { "" });
+        for c in components {
+            path.push(c);
+        }
+        path
+    })
+}
+
+// digests and sizes with one or more paths each, no path is in two entries
+fn entries() -> impl Strategy<Value = Vec<(String, u64, Vec<PathBuf>)>> {
+    btree_set(path(), 1..24).prop_flat_map(|paths| {
+        let paths: Vec<PathBuf> = paths.into_iter().collect();
+        let n = paths.len();
+        (Just(paths), vec(0..4usize, n), vec(any::<u64>(), 4))
+    }).prop_map(|(paths, groups, sizes)| {
+        let mut entries: BTreeMap<usize, Vec<PathBuf>> = BTreeMap::new();
+        for (path, group) in paths.into_iter().zip(groups) {
+            entries.entry(group).or_default().push(path);
+        }
+        entries.into_iter()
+            .map(|(group, paths)| (format!("{:064x}", group + 1), sizes[group], paths))
+            .collect()
+    })
+}
+
+fn index(entries: &[(String, u64, Vec<PathBuf>)]) -> TreeIndex {
+    let mut ti = TreeIndex::default();
+    for (digest, size, paths) in entries {
+        for path in paths {
+            ti.insert(TreeItem::new(digest, path, *size));
+        }
+    }
+    ti
+}
+
+// every digest with its size and all of its paths
+fn contents(ti: &TreeIndex) -> BTreeMap<String, (u64, Vec<PathBuf>)> {
+    ti.idx.values()
+        .map(|v| {
+            let paths = std::iter::once(v.item.item_path()).chain(&v.dupes).map(|p| p.path()).collect();
+            (v.item.digest.to_string(), (v.item.size, paths))
+        })
+        .collect()
+}
+
+proptest! {
+    #[test]
+    fn text_index_round_trips(entries in entries()) {
+        let ti = index(&entries);
+        let mut text = Vec::new();
+        ti.write(&mut text).unwrap();
+
+        let read = TreeIndex::read(Cursor::new(&text), true).unwrap();
+        prop_assert_eq!(contents(&read), contents(&ti));
+        let fast = TreeIndex::read_fast(Cursor::new(&text), true).unwrap();
+        prop_assert_eq!(contents(&fast), contents(&ti));
+
+        // writing what was read gives the same text
+        let mut again = Vec::new();
+        read.write(&mut again).unwrap();
+        prop_assert_eq!(contents(&TreeIndex::read(Cursor::new(&again), true).unwrap()), contents(&ti));
+    }
+
+    #[test]
+    fn every_path_is_one_line(entries in entries()) {
+        let ti = index(&entries);
+        let mut text = Vec::new();
+        ti.write(&mut text).unwrap();
+        let paths: usize = entries.iter().map(|(_, _, p)| p.len()).sum();
+        prop_assert_eq!(text.iter().filter(|b| **b == b'\n').count(), paths + 1);
+    }
+}
+
+#[test]
+fn csv_index_keeps_line_breaks_in_paths() {
+    let entries = vec![
+        (format!("{:064x}", 1), 10, vec![PathBuf::from("a\r\nb"), PathBuf::from("c\rd"), PathBuf::from("trail\r")]),
+        (format!("{:064x}", 2), 20, vec![PathBuf::from("e\n"), PathBuf::from("x,y\"z"), PathBuf::from("\r\n")])
+    ];
+    let ti = index(&entries);
+    let mut csv = Vec::new();
+    ti.write_csv(&mut csv).unwrap();
+    let read = TreeIndex::read(Cursor::new(&csv), true).unwrap();
+    assert_eq!(contents(&read), contents(&ti));
+}
+
+#[cfg(unix)]
+#[test]
+fn ndjson_paths_are_escaped() {
+    use std::os::unix::ffi::OsStrExt;
+    let path = PathBuf::from(std::ffi::OsStr::from_bytes(b"a\xffb\\c\nd"));
+    let ti = index(&[(format!("{:064x}", 1), 10, vec![path])]);
+    let mut json = Vec::new();
+    ti.write_ndjson(&mut json).unwrap();
+    let json = String::from_utf8(json).unwrap();
+    assert!(json.contains(r#""paths":["a\\xffb\\\\c\\nd"]"#), "{}", json);
+}
@@ -0,0 +1,118 @@
+use best_practices::fs::{posix_quote, powershell_quote, Action, ActionRecord, ActionResult, ScriptShell, ScriptWriter,
+    TreeFixtureBuilder};
+use std::fs::{self, File};
+use std::path::Path;
+
+fn record(action: Action, src: &Path, dst: Option<&Path>) -> ActionRecord {
+    let r = ActionRecord::new(action, src, ActionResult::Done);
+    match dst {
+        Some(dst) => r.dst(dst),
+        None => r
+    }
+}
+
+// the script for the records, without its first line
+fn script(shell: ScriptShell, records: &[ActionRecord]) -> String {
+    let tree = TreeFixtureBuilder::new().build().unwrap();
+    let mut w = ScriptWriter::new(Box::new(File::create(tree.join("script")).unwrap()), shell).unwrap();
+    for r in records {
+        w.action(r).unwrap();
+    }
+    w.finish().unwrap();
+    let text = fs::read_to_string(tree.join("script")).unwrap();
+    text.split_once('\n').unwrap().1.to_string()
+}
+
+#[test]
+fn quotes_are_closed_or_doubled() {
+    assert_eq!(posix_quote("plain"), "'plain'");
+    assert_eq!(posix_quote(""), "''");
+    assert_eq!(posix_quote("it's"), r"'it'\''s'");
+    assert_eq!(posix_quote("$HOME `x` \"y\" \\"), r#"'$HOME `x` "y" \'"#);
+    assert_eq!(posix_quote("-rf\nx"), "'-rf\nx'");
+
+    assert_eq!(powershell_quote("it's"), "'it''s'");
+    assert_eq!(powershell_quote("\u{2018}a\u{2019}\u{201a}\u{201b}"), "'\u{2018}\u{2018}a\u{2019}\u{2019}\u{201a}\u{201a}\u{201b}\u{201b}'");
+    assert_eq!(powershell_quote("$env:x `n \"y\""), "'$env:x `n \"y\"'");
+    assert_eq!(powershell_quote("-Force\nx"), "'-Force\nx'");
+}
+
+#[cfg(unix)]
+#[test]
+fn the_shell_reads_quoted_strings_back() {
+    for s in ["it's", "-n", "a\nb", "$(echo no) `x` \"q\" \\", "'", "''", "\u{2019}"] {
+        let out = std::process::Command::new("sh").arg("-c").arg(format!("printf %s {}", posix_quote(s))).output().unwrap();
+        assert_eq!(String::from_utf8(out.stdout).unwrap(), s);
+    }
+}
+
+#[test]
+fn options_are_ended_before_the_paths() {
+    let (dash, other) = (Path::new("-rf"), Path::new("d/-x"));
+    let records = [
+        record(Action::Copy, dash, Some(other)),
+        record(Action::Move, dash, Some(Path::new("d/-y"))),
+        record(Action::Hardlink, dash, Some(other)),
+        record(Action::Delete, dash, None),
+        record(Action::Rmdir, Path::new("-e"), None),
+    ];
+    assert_eq!(script(ScriptShell::Posix, &records), "set -e\nmkdir -p -- 'd'\ncp -p -- '-rf' 'd/-x'\nmv -- '-rf' 'd/-y'\n\
+        ln -f -- '-rf' 'd/-x'\nrm -f -- '-rf'\nrmdir -- '-e'\n");
+    assert_eq!(script(ScriptShell::PowerShell, &records), "New-Item -ItemType Directory -Force -Path 'd' | Out-Null\n\
+        Copy-Item -LiteralPath '-rf' -Destination 'd/-x'\nMove-Item -LiteralPath '-rf' -Destination 'd/-y'\n\
+        New-Item -ItemType HardLink -Force -Path 'd/-x' -Target '-rf' | Out-Null\nRemove-Item -LiteralPath '-rf' -Force\n\
+        Remove-Item -LiteralPath '-e'\n");
+}
+
+#[test]
+fn de_duping_is_a_comment_that_a_newline_cant_end() {
+    let r = record(Action::Dedupe, Path::new("a\nrm -rf /"), Some(Path::new("b")));
+    assert_eq!(script(ScriptShell::Posix, &[r]), "set -e\n# can't be done from a script: dedupe a rm -rf / b\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn the_script_does_what_the_records_say() {
+    let tree = TreeFixtureBuilder::new()
+        .file("-a", "a")
+        .file("it's", "b")
+        .file("line\nbreak", "c")
+        .dir("empty")
+        .build()
+        .unwrap();
+    let records = [
+        record(Action::Copy, &tree.join("-a"), Some(&tree.join("new dir/-a"))),
+        record(Action::Move, &tree.join("it's"), Some(&tree.join("new dir/it's"))),
+        record(Action::Delete, &tree.join("line\nbreak"), None),
+        record(Action::Rmdir, &tree.join("empty"), None),
+        record(Action::Mkdir, &tree.join("made"), None),
+    ];
+    let path = tree.join("script.sh");
+    let mut w = ScriptWriter::new(Box::new(File::create(&path).unwrap()), ScriptShell::Posix).unwrap();
+    for r in &records {
+        w.action(r).unwrap();
+    }
+    w.finish().unwrap();
+    assert!(std::process::Command::new("sh").arg(&path).status().unwrap().success());
+
+    assert_eq!(fs::read_to_string(tree.join("-a")).unwrap(), "a");
+    assert_eq!(fs::read_to_string(tree.join("new dir/-a")).unwrap(), "a");
+    assert!(!tree.join("it's").exists());
+    assert_eq!(fs::read_to_string(tree.join("new dir/it's")).unwrap(), "b");
+    assert!(!tree.join("line\nbreak").exists());
+    assert!(!tree.join("empty").exists());
+    assert!(tree.join("made").is_dir());
+}
+
+#[cfg(unix)]
+#[test]
+fn paths_that_arent_unicode_are_refused() {
+    use std::os::unix::ffi::OsStrExt;
+    let path = Path::new(std::ffi::OsStr::from_bytes(b"a\xffb"));
+    for shell in [ScriptShell::Posix, ScriptShell::PowerShell] {
+        let mut w = ScriptWriter::new(Box::new(Vec::new()), shell).unwrap();
+        assert!(w.action(&record(Action::Delete, path, None)).is_err());
+        assert!(w.action(&record(Action::Copy, Path::new("ok"), Some(path))).is_err());
+        assert!(w.action(&record(Action::Mkdir, path, None)).is_err());
+    }
+}
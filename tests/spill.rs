@@ -0,0 +1,80 @@
+use best_practices::fs::{PathArena, TreeIndex, TreeIndexBuilder};
+use std::io::{Cursor, Read};
+use std::path::{Path, PathBuf};
+
+// several digests with their paths out of name order, so an entry whose
+// paths were sorted by name would come out differently
+fn text_index() -> Vec<u8> {
+    let mut text = String::from("#treeindex v3 os=linux paths=escaped\n");
+    for (group, paths) in [(1, ["z/9", "a/1", "m/5"]), (2, ["q", "b", "y"]), (3, ["c/c", "c/a", "c/b"])] {
+        for p in paths {
+            text.push_str(&format!("{:064x} {} {}\n", group, group * 10, p));
+        }
+    }
+    // an entry of its own between the dupes of the first one
+    text.push_str(&format!("{:064x} 10 k\n", 1));
+    text.push_str(&format!("{:064x} 40 solo\n", 4));
+    text.into_bytes()
+}
+
+fn build(spill: Option<u64>) -> TreeIndex {
+    let mut r: Box<dyn Read> = Box::new(Cursor::new(text_index()));
+    let mut builder = TreeIndexBuilder::new().with_dupes(true);
+    if let Some(budget) = spill {
+        builder = builder.spill(budget);
+    }
+    builder.from_reader(&mut r).build().unwrap()
+}
+
+#[test]
+fn spilled_entries_keep_the_order_paths_were_seen_in() {
+    let ti = build(Some(1));
+    let entry = ti.idx.values().find(|e| e.item.size == 10).unwrap();
+    assert_eq!(entry.item.path().to_str(), Some("z/9"));
+    let dupes: Vec<PathBuf> = entry.dupes.iter().map(|d| d.path()).collect();
+    assert_eq!(dupes, [Path::new("a/1"), Path::new("m/5"), Path::new("k")]);
+}
+
+#[test]
+fn spilled_and_in_memory_indexes_are_identical() {
+    let memory = build(None);
+    for budget in [1, 200, 1000] {
+        let spilled = build(Some(budget));
+        let mut a = Vec::new();
+        let mut b = Vec::new();
+        memory.write_binary(&mut a).unwrap();
+        spilled.write_binary(&mut b).unwrap();
+        assert_eq!(a, b, "binary with a budget of {}", budget);
+
+        let mut a = Vec::new();
+        let mut b = Vec::new();
+        memory.write_csv(&mut a).unwrap();
+        spilled.write_csv(&mut b).unwrap();
+        assert_eq!(a, b, "csv with a budget of {}", budget);
+    }
+}
+
+#[test]
+fn arena_paths_come_back_as_they_went_in() {
+    let mut arena = PathArena::new();
+    for p in ["a/b", "a//b", "a/./b", "/a/b/", "a/b"] {
+        let id = arena.intern(Path::new(p)).unwrap();
+        assert_eq!(arena.path(id).as_os_str(), p);
+    }
+    assert_eq!(arena.intern(Path::new("a//b")).unwrap(), arena.intern(Path::new("a//b")).unwrap());
+}
+
+#[test]
+fn memory_limits_are_only_for_build_to() {
+    let mut r: Box<dyn Read> = Box::new(Cursor::new(text_index()));
+    assert!(TreeIndexBuilder::new().memory_limit(1).from_reader(&mut r).build().is_err());
+
+    let mut r: Box<dyn Read> = Box::new(Cursor::new(text_index()));
+    let mut limited = Vec::new();
+    TreeIndexBuilder::new().with_dupes(true).memory_limit(1).from_reader(&mut r).build_to(&mut limited).unwrap();
+    let mut r: Box<dyn Read> = Box::new(Cursor::new(limited));
+    let (mut a, mut b) = (Vec::new(), Vec::new());
+    TreeIndexBuilder::new().with_dupes(true).from_reader(&mut r).build().unwrap().write_binary(&mut a).unwrap();
+    build(None).write_binary(&mut b).unwrap();
+    assert_eq!(a, b);
+}
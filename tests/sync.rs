@@ -0,0 +1,74 @@
+use best_practices::fs::{SyncOp, SyncPlan, SyncPlannerBuilder, TreeIndex, TreeIndexBuilder};
+use std::io::{Cursor, Read};
+use std::path::PathBuf;
+
+// an index with dupes of (digest, path) pairs, each digest is its own size
+fn index(header: &str, files: &[(u64, &str)]) -> TreeIndex {
+    let mut text = format!("#treeindex v3 os=linux paths=escaped{}\n", header);
+    for (digest, path) in files {
+        text.push_str(&format!("{:064x} {} {}\n", digest, digest, path));
+    }
+    let mut r: Box<dyn Read> = Box::new(Cursor::new(text.into_bytes()));
+    TreeIndexBuilder::new().with_dupes(true).from_reader(&mut r).build().unwrap()
+}
+
+fn plan(content_addressed: bool, source: &[(u64, &str)], dest: &[(u64, &str)]) -> SyncPlan {
+    SyncPlannerBuilder::new()
+        .content_addressed(content_addressed)
+        .build()
+        .unwrap()
+        .plan(&index("", source), &index("", dest))
+        .unwrap()
+}
+
+fn path(p: &str) -> PathBuf {
+    PathBuf::from(p)
+}
+
+#[test]
+fn renamed_files_are_moved() {
+    let p = plan(true, &[(1, "new/name")], &[(1, "old/name")]);
+    assert_eq!(p.ops, [SyncOp::Move { from: path("old/name"), to: path("new/name") }]);
+    assert_eq!(p.copy_bytes(), 0);
+
+    // without looking at the content it is sent again
+    let p = plan(false, &[(1, "new/name")], &[(1, "old/name")]);
+    assert_eq!(p.ops, [SyncOp::Copy { path: path("new/name"), size: 1 }, SyncOp::Delete { path: path("old/name") }]);
+}
+
+#[test]
+fn another_copy_of_a_kept_file_is_duplicated() {
+    let p = plan(true, &[(1, "a"), (1, "c")], &[(1, "a")]);
+    assert_eq!(p.ops, [SyncOp::Duplicate { from: path("a"), to: path("c") }]);
+    assert!(plan(true, &[(1, "a")], &[(1, "a")]).is_empty());
+}
+
+#[test]
+fn moves_come_first_then_copies_then_duplicates_then_deletes() {
+    let source = [(1, "b"), (2, "n"), (1, "x"), (4, "same")];
+    let dest = [(1, "a"), (3, "old"), (4, "same")];
+    let p = plan(true, &source, &dest);
+    assert_eq!(p.ops, [
+        SyncOp::Move { from: path("a"), to: path("b") },
+        SyncOp::Copy { path: path("n"), size: 2 },
+        SyncOp::Duplicate { from: path("b"), to: path("x") },
+        SyncOp::Delete { path: path("old") }
+    ]);
+    assert_eq!(p.copy_bytes(), 2);
+    assert_eq!(p.to_string(), "move\ta\tb\ncopy\tn\ndup\tb\tx\ndelete\told\n");
+}
+
+#[test]
+fn fast_and_differently_keyed_indexes_are_refused() {
+    let planner = SyncPlannerBuilder::new().content_addressed(true).build().unwrap();
+    let files = [(1, "a")];
+    let refused = |source: &str, dest: &str| planner.plan(&index(source, &files), &index(dest, &files)).is_err();
+    assert!(refused(" fast=true", ""));
+    assert!(refused("", " fast=true"));
+    assert!(refused(" keyed=true keyid=0011223344556677", ""));
+    assert!(refused(" keyed=true keyid=0011223344556677", " keyed=true keyid=7766554433221100"));
+    assert!(refused(" keyed=true", " keyed=true"));
+    assert!(refused(" algo=sha256", ""));
+    assert!(!refused(" keyed=true keyid=0011223344556677", " keyed=true keyid=0011223344556677"));
+    assert!(!refused("", ""));
+}
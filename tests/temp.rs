@@ -0,0 +1,14 @@
+use best_practices::fs::{TempFile, TreeFixtureBuilder};
+use std::fs;
+use std::io::Write;
+
+#[test]
+fn temp_files_for_long_names_fit_in_the_directory() {
+    let tree = TreeFixtureBuilder::new().build().unwrap();
+    let target = tree.join(format!("{}.idx", "é".repeat(125)));
+    let mut tmp = TempFile::next_to(&target).unwrap();
+    assert!(tmp.path().file_name().unwrap().len() <= 255);
+    tmp.write_all(b"index").unwrap();
+    tmp.persist(&target).unwrap();
+    assert_eq!(fs::read(&target).unwrap(), b"index");
+}